@@ -53,7 +53,7 @@ if __name__ == "__main__":
     # 
     rec = Counter()
     for _ in range(1000):
-        y = ris(4)
+        y = ris_wrs(4)
         rec[y] += 1
     
     xs = sorted(list(rec.keys()))
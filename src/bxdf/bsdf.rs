@@ -1,4 +1,5 @@
 use super::{Bxdf, BxdfType, Spectrum, BxdfSample};
+use crate::geometry::interaction::GeometryInfo;
 use cgmath::{Vector3, InnerSpace, Point2};
 
 pub struct Bsdf {
@@ -8,9 +9,31 @@ pub struct Bsdf {
     pub ts: Vector3<f64>, // t axis in the shading coordinate
     pub bxdfs: Vec<Box<dyn Bxdf>>,
     pub n_bxdfs: usize, // the number of bxdfs 
+    pub eta: f64, // the refractive index inside over outside, 1 for surfaces that do not transmit
 }
 
 impl Bsdf {
+    /// the bsdf in the shading frame of the hit, its s axis follows dpdu
+    pub fn new(geo: &GeometryInfo, bxdfs: Vec<Box<dyn Bxdf>>) -> Self {
+        let (ss, ts) = geo.shading_frame();
+        let n_bxdfs = bxdfs.len();
+
+        Bsdf {
+            ns: geo.shading.n,
+            ng: geo.n,
+            ss,
+            ts,
+            bxdfs,
+            n_bxdfs,
+            eta: 1.0,
+        }
+    }
+
+    pub fn with_eta(mut self, eta: f64) -> Self {
+        self.eta = eta;
+        self
+    }
+
     pub fn local_to_world(&self, v: Vector3<f64>) -> Vector3<f64> {
         let (ss, ts, ns) = (self.ss, self.ts, self.ns);
 
@@ -47,13 +70,11 @@ impl Bsdf{
         // transform wi to world space
         bxdf_sample.wi = self.local_to_world(bxdf_sample.wi);
 
-        // compute pdf
+        // the bxdf failed to sample a direction, otherwise the pdf and the brdf value are the ones of
+        // all the bxdfs in world space
         let wo = self.local_to_world(wo);
-        if !bxdf_sample.is_delta {
+        if !bxdf_sample.is_delta && bxdf_sample.pdf != 0.0 {
             bxdf_sample.pdf = self.pdf(wo, bxdf_sample.wi);
-        }
-        // compute brdf value in world space
-        if !bxdf_sample.is_delta {
             bxdf_sample.rho = self.f(wo, bxdf_sample.wi);
         }
 
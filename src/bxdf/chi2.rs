@@ -0,0 +1,220 @@
+//! a chi-square test of a sampling routine against the pdf it reports, the sampled directions are
+//! binned over (theta, phi) and the counts compared with the integral of the pdf over each bin
+
+use std::f64::consts::PI;
+
+use cgmath::{Point2, Vector3};
+
+use crate::{sampler::rng::Rng, utils::spherical_direction};
+
+const THETA_RES: usize = 10;
+const PHI_RES: usize = 2 * THETA_RES;
+// the subintervals of the midpoint rule integrating the pdf over a bin, per dimension, its nodes stay
+// off the edges of the bins, where the pdfs of hemispherical samplers jump
+const INTEGRATION_RES: usize = 32;
+// the bins expecting fewer samples are pooled together
+const MIN_EXPECTED: f64 = 5.0;
+const SIGNIFICANCE: f64 = 0.01;
+
+fn bin(w: Vector3<f64>) -> usize {
+    let theta = w.z.clamp(-1.0, 1.0).acos();
+    let mut phi = w.y.atan2(w.x);
+    if phi < 0.0 {
+        phi += 2.0 * PI;
+    }
+
+    let i = ((theta / PI * THETA_RES as f64) as usize).min(THETA_RES - 1);
+    let j = ((phi / (2.0 * PI) * PHI_RES as f64) as usize).min(PHI_RES - 1);
+    i * PHI_RES + j
+}
+
+// the probability of each bin, the integral of pdf sin(theta) with the composite midpoint rule
+fn expected_frequencies(pdf: &dyn Fn(Vector3<f64>) -> f64) -> Vec<f64> {
+    let (d_theta, d_phi) = (PI / THETA_RES as f64, 2.0 * PI / PHI_RES as f64);
+    let (h_theta, h_phi) = (d_theta / INTEGRATION_RES as f64, d_phi / INTEGRATION_RES as f64);
+
+    let mut frequencies = vec![0.0; THETA_RES * PHI_RES];
+    for i in 0..THETA_RES {
+        for j in 0..PHI_RES {
+            let mut sum = 0.0;
+            for k in 0..INTEGRATION_RES {
+                let theta = i as f64 * d_theta + (k as f64 + 0.5) * h_theta;
+                let (sin_theta, cos_theta) = theta.sin_cos();
+                for l in 0..INTEGRATION_RES {
+                    let phi = j as f64 * d_phi + (l as f64 + 0.5) * h_phi;
+                    sum += pdf(spherical_direction(sin_theta, cos_theta, phi)) * sin_theta;
+                }
+            }
+            frequencies[i * PHI_RES + j] = sum * h_theta * h_phi;
+        }
+    }
+    frequencies
+}
+
+// the natural logarithm of the gamma function, with the approximation of Lanczos
+fn ln_gamma(x: f64) -> f64 {
+    let coefficients = [
+        76.18009172947146, -86.50532032941677, 24.01409824083091,
+        -1.231739572450155, 0.1208650973866179e-2, -0.5395239384953e-5,
+    ];
+    let tmp = x + 5.5;
+    let tmp = tmp - (x + 0.5) * tmp.ln();
+    let series = coefficients.iter().enumerate().fold(1.000000000190015, |sum, (i, c)| sum + c / (x + 1.0 + i as f64));
+    -tmp + (2.5066282746310005 * series / x).ln()
+}
+
+// the regularized lower incomplete gamma function P(a, x), by its series below a + 1 and by the
+// continued fraction of its complement above
+fn regularized_gamma(a: f64, x: f64) -> f64 {
+    if x <= 0.0 {
+        return 0.0;
+    }
+
+    let prefactor = (-x + a * x.ln() - ln_gamma(a)).exp();
+    if x < a + 1.0 {
+        let (mut term, mut sum, mut ap) = (1.0 / a, 1.0 / a, a);
+        for _ in 0..1000 {
+            ap += 1.0;
+            term *= x / ap;
+            sum += term;
+            if term.abs() < sum.abs() * 1e-15 {
+                break;
+            }
+        }
+        sum * prefactor
+    } else {
+        // modified lentz
+        let tiny = 1e-300;
+        let mut b = x + 1.0 - a;
+        let mut c = 1.0 / tiny;
+        let mut d = 1.0 / b;
+        let mut h = d;
+        for i in 1..1000 {
+            let an = -(i as f64) * (i as f64 - a);
+            b += 2.0;
+            d = an * d + b;
+            if d.abs() < tiny {
+                d = tiny;
+            }
+            c = b + an / c;
+            if c.abs() < tiny {
+                c = tiny;
+            }
+            d = 1.0 / d;
+            let delta = d * c;
+            h *= delta;
+            if (delta - 1.0).abs() < 1e-15 {
+                break;
+            }
+        }
+        1.0 - prefactor * h
+    }
+}
+
+/// the p-value of the hypothesis that the directions drawn by sample are distributed as pdf, a
+/// sample may fail and return None, which the pdf must account for by integrating to less than 1
+pub fn chi2_p_value(
+    sample: &mut dyn FnMut(Point2<f64>) -> Option<Vector3<f64>>,
+    pdf: &dyn Fn(Vector3<f64>) -> f64,
+    sample_count: usize,
+    seed: u64,
+) -> Result<f64, String> {
+    let mut rng = Rng::new();
+    rng.set_sequence(seed, 0);
+
+    let mut observed = vec![0.0; THETA_RES * PHI_RES];
+    for _ in 0..sample_count {
+        let u = Point2::new(rng.uniform_f64(), rng.uniform_f64());
+        if let Some(w) = sample(u) {
+            if !(w.x.is_finite() && w.y.is_finite() && w.z.is_finite()) {
+                return Err(format!("sampled the direction {:?}", w));
+            }
+            observed[bin(w)] += 1.0;
+        }
+    }
+
+    let expected: Vec<f64> = expected_frequencies(pdf).iter().map(|p| p * sample_count as f64).collect();
+
+    // pool the bins expecting few samples, in increasing order of their expectations
+    let mut order: Vec<usize> = (0..expected.len()).collect();
+    order.sort_by(|&a, &b| expected[a].total_cmp(&expected[b]));
+
+    let (mut pooled_expected, mut pooled_observed) = (0.0, 0.0);
+    let (mut statistic, mut dof) = (0.0, 0);
+    for &k in &order {
+        if expected[k] == 0.0 {
+            if observed[k] > sample_count as f64 * 1e-5 {
+                return Err(format!("{} samples fell in the bin {} where the pdf is zero", observed[k], k));
+            }
+        } else if expected[k] < MIN_EXPECTED {
+            pooled_expected += expected[k];
+            pooled_observed += observed[k];
+        } else if pooled_expected > 0.0 && pooled_expected < MIN_EXPECTED {
+            // the pool is too small on its own, so it takes the bin in
+            pooled_expected += expected[k];
+            pooled_observed += observed[k];
+        } else {
+            let diff = observed[k] - expected[k];
+            statistic += diff * diff / expected[k];
+            dof += 1;
+        }
+    }
+    if pooled_expected > 0.0 {
+        let diff = pooled_observed - pooled_expected;
+        statistic += diff * diff / pooled_expected;
+        dof += 1;
+    }
+
+    // the counts sum to sample_count, which removes a degree of freedom
+    if dof < 2 {
+        return Err(format!("only {} bins are populated", dof));
+    }
+    let dof = dof - 1;
+
+    Ok(1.0 - regularized_gamma(dof as f64 / 2.0, statistic / 2.0))
+}
+
+/// fails when any of the test_count tests sharing this significance level rejects the hypothesis,
+/// the level of a single test is lowered with the sidak correction
+pub fn assert_chi2(
+    name: &str,
+    sample: &mut dyn FnMut(Point2<f64>) -> Option<Vector3<f64>>,
+    pdf: &dyn Fn(Vector3<f64>) -> f64,
+    sample_count: usize,
+    test_count: usize,
+) {
+    let significance = 1.0 - (1.0 - SIGNIFICANCE).powf(1.0 / test_count as f64);
+    match chi2_p_value(sample, pdf, sample_count, 0) {
+        Ok(p_value) => assert!(p_value >= significance, "{}: rejected with a p-value of {} < {}", name, p_value, significance),
+        Err(msg) => panic!("{}: {}", name, msg),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_regularized_gamma() {
+        // the chi-square cdf with 2 degrees of freedom is 1 - exp(-x / 2)
+        for x in [0.5, 2.0, 7.0] {
+            assert!((regularized_gamma(1.0, x / 2.0) - (1.0 - (-x / 2.0).exp())).abs() < 1e-10);
+        }
+        // the median of a chi-square with 10 degrees of freedom
+        assert!((regularized_gamma(5.0, 9.341818 / 2.0) - 0.5).abs() < 1e-5);
+    }
+
+    #[test]
+    fn test_chi2_rejects_a_wrong_pdf() {
+        // cosine weighted samples
+        let mut sample = |u: Point2<f64>| {
+            let (r, phi) = (u[0].sqrt(), 2.0 * PI * u[1]);
+            Some(Vector3::new(r * phi.cos(), r * phi.sin(), (1.0 - u[0]).sqrt()))
+        };
+        let cosine = |w: Vector3<f64>| w.z.max(0.0) / PI;
+        let uniform = |w: Vector3<f64>| if w.z > 0.0 { 0.5 / PI } else { 0.0 };
+
+        assert!(chi2_p_value(&mut sample, &cosine, 100000, 0).unwrap() > 0.01);
+        assert!(chi2_p_value(&mut sample, &uniform, 100000, 0).unwrap() < 1e-6);
+    }
+}
@@ -1,12 +1,60 @@
-use cgmath::{Vector3, InnerSpace};
+use cgmath::Vector3;
 
-use crate::{spectrum::Spectrum, utils::{cos_theta, is_nan}};
+use crate::{spectrum::Spectrum, utils::{cos_theta, refract}};
 
 use super::{BxdfType, Bxdf, BxdfSample};
 
-pub trait Fresnel {
-    /// returns (f_value, sin_theta_t, cos_theta_t)
-    fn evaluate(&self, cos_theta_i: f64) -> (f64, f64, f64);
+pub trait Fresnel: Send + Sync {
+    /// the reflected fraction of the light arriving at cos_theta_i, from outside when it is positive
+    fn evaluate(&self, cos_theta_i: f64) -> Spectrum;
+}
+
+/// the fresnel reflectance of the interface between the dielectrics eta_a (outside) and eta_b (inside),
+/// returns (fresnel, sin_theta_t, cos_theta_t), cos_theta_t is nan on total internal reflection
+pub fn fr_dielectric(cos_theta_i: f64, eta_a: f64, eta_b: f64) -> (f64, f64, f64) {
+    // compute the fresnel term, and the refracted direction(if it exists)
+    assert!((-1.0..=1.0).contains(&cos_theta_i));
+    let (eta_i, eta_t) = if cos_theta_i > 0.0 { (eta_a, eta_b) } else { (eta_b, eta_a) };
+
+    let cos_theta_i = cos_theta_i.abs();
+    let sin_theta_i = (1.0 - cos_theta_i * cos_theta_i).max(0.0).sqrt();
+    let sin_theta_t = eta_i / eta_t * sin_theta_i;
+    if sin_theta_t > 1.0 {
+        // total internal reflection
+        return (1.0, sin_theta_t, f64::NAN);
+    }
+
+    let cos_theta_t = (1.0 - sin_theta_t * sin_theta_t).max(0.0).sqrt();
+    assert!((0.0..=1.0).contains(&cos_theta_t));
+
+    let fresnel_parl:f64= (eta_t * cos_theta_i - eta_i * cos_theta_t)/
+                        (eta_t * cos_theta_i + eta_i * cos_theta_t);
+    let fresnel_perp:f64= (eta_i * cos_theta_i - eta_t * cos_theta_t)/
+                        (eta_i * cos_theta_i + eta_t * cos_theta_t);
+    let fresnel = (fresnel_parl * fresnel_parl + fresnel_perp * fresnel_perp) / 2.0;
+
+    (fresnel, sin_theta_t, cos_theta_t)
+}
+
+/// the fresnel reflectance of a conductor of complex index of refraction eta + i k, seen from the
+/// outside which is a vacuum, for a single channel
+pub fn fr_conductor(cos_theta_i: f64, eta: f64, k: f64) -> f64 {
+    let cos2 = cos_theta_i.clamp(-1.0, 1.0).powi(2);
+    let sin2 = 1.0 - cos2;
+    let (eta2, k2) = (eta * eta, k * k);
+
+    let t0 = eta2 - k2 - sin2;
+    let a2_plus_b2 = (t0 * t0 + 4.0 * eta2 * k2).sqrt();
+    let t1 = a2_plus_b2 + cos2;
+    let a = (0.5 * (a2_plus_b2 + t0)).max(0.0).sqrt();
+    let t2 = 2.0 * cos_theta_i.abs() * a;
+    let rs = (t1 - t2) / (t1 + t2);
+
+    let t3 = cos2 * a2_plus_b2 + sin2 * sin2;
+    let t4 = t2 * sin2;
+    let rp = rs * (t3 - t4) / (t3 + t4);
+
+    0.5 * (rp + rs)
 }
 
 pub struct FresnelSpecular {
@@ -25,20 +73,6 @@ impl FresnelSpecular {
             t,
         }
     }
-    fn refract(&self, wi: Vector3<f64>, sin_theta_t: f64, cos_theta_t: f64) -> Vector3<f64> {
-        if cos_theta_t.is_nan() {
-            panic!()
-        }
-        let wo_parl = Vector3::new(-wi.x, -wi.y, 0.0).normalize() * sin_theta_t;
-        let wo_perp = Vector3::new(0.0, 0.0, -wi.z).normalize() * cos_theta_t;
-        let wo = wo_parl + wo_perp;
-
-        if is_nan(wo) {
-            panic!("refracted direction is nan")
-        }
-
-        wo
-    }
 }
 
 impl Bxdf for FresnelSpecular {
@@ -47,7 +81,8 @@ impl Bxdf for FresnelSpecular {
     }
 
     fn sample_f(&self, wo: cgmath::Vector3<f64>, sample: cgmath::Point2<f64>) -> BxdfSample {
-        let (fresnel_term, sin_theta_t, cos_theta_t)= self.evaluate(cos_theta(wo));
+        // wo is outside when it is on the side of the normal
+        let (fresnel_term, _, _) = fr_dielectric(wo.z.clamp(-1.0, 1.0), self.eta_a, self.eta_b);
         if sample.x < fresnel_term {
             // reflect 
             let wi = Vector3::new(-wo.x, -wo.y, wo.z);
@@ -63,10 +98,16 @@ impl Bxdf for FresnelSpecular {
         } else {
             // refract
             let pdf = 1.0 - fresnel_term;
-            let mut ratio2 = (self.eta_a * self.eta_a) / (self.eta_b * self.eta_b);
-            if wo.z <= 0.0 { ratio2 = 1.0 / ratio2; }
+            let (eta_i, eta_t) = if wo.z > 0.0 { (self.eta_a, self.eta_b) } else { (self.eta_b, self.eta_a) };
+            let ratio2 = (eta_i * eta_i) / (eta_t * eta_t);
 
-            let wi = self.refract(wo, sin_theta_t, cos_theta_t);
+            // total internal reflection takes the whole sample space, but at the critical angle refract may
+            // round to it where the fresnel term did not, the sample then fails
+            let n = Vector3::new(0.0, 0.0, wo.z.signum());
+            let wi = match refract(wo, n, eta_i / eta_t) {
+                Some(wi) => wi,
+                None => return BxdfSample { rho: Spectrum::black(), wi: Vector3::new(-wo.x, -wo.y, wo.z), pdf: 0.0, is_delta: self.is_delta() },
+            };
 
             let rho = self.t * (1.0-fresnel_term) * ratio2 / cos_theta(wi).abs();
 
@@ -92,34 +133,45 @@ impl Bxdf for FresnelSpecular {
     }
 }
 
-impl Fresnel for FresnelSpecular {
-    fn evaluate(&self, cos_theta_i: f64) -> (f64, f64, f64) {
-        // returns (fresnel, sin_theta_t, cos_theta_t)
-        // compute the fresnel term, and the refracted direction(if it exists)
-        assert!(cos_theta_i >= -1.0 && cos_theta_i <= 1.0);
-        let (eta_i, eta_t) = if cos_theta_i > 0.0 { (self.eta_a, self.eta_b) } else { (self.eta_b, self.eta_a )};
-    
-        let cos_theta_i = cos_theta_i.abs();
-        let sin_theta_i = (1.0 - cos_theta_i * cos_theta_i).max(0.0).sqrt();
-        let sin_theta_t = eta_i / eta_t * sin_theta_i;
-        if sin_theta_t > 1.0 {
-            // total internal reflection
-            return (1.0, sin_theta_t, f64::NAN);
-        }
+/// the fresnel reflectance of a dielectric interface, for the microfacets of rough dielectrics
+pub struct FresnelDielectric {
+    eta_a: f64, // refractive index outside
+    eta_b: f64, // refractive index inside
+}
 
-        let cos_theta_t = (1.0 - sin_theta_t * sin_theta_t).max(0.0).sqrt();
-        assert!(cos_theta_t >= 0.0 && cos_theta_t <= 1.0);
+impl FresnelDielectric {
+    pub fn new(eta_a: f64, eta_b: f64) -> Self {
+        Self { eta_a, eta_b }
+    }
+}
 
-        let fresnel_parl:f64= (eta_t * cos_theta_i - eta_i * cos_theta_t)/
-                            (eta_t * cos_theta_i + eta_i * cos_theta_t);
-        let fresnel_perp:f64= (eta_i * cos_theta_i - eta_t * cos_theta_t)/
-                            (eta_i * cos_theta_i + eta_t * cos_theta_t);
-        let fresnel = (fresnel_parl * fresnel_parl + fresnel_perp * fresnel_perp) / 2.0;
+impl Fresnel for FresnelDielectric {
+    fn evaluate(&self, cos_theta_i: f64) -> Spectrum {
+        Spectrum::from(fr_dielectric(cos_theta_i, self.eta_a, self.eta_b).0)
+    }
+}
 
-        (fresnel, sin_theta_t, cos_theta_t)
+/// the fresnel reflectance of a metal, the index of refraction eta + i k is given per channel
+pub struct FresnelConductor {
+    eta: Spectrum,
+    k: Spectrum,
+}
+
+impl FresnelConductor {
+    pub fn new(eta: Spectrum, k: Spectrum) -> Self {
+        Self { eta, k }
     }
 }
 
+impl Fresnel for FresnelConductor {
+    fn evaluate(&self, cos_theta_i: f64) -> Spectrum {
+        Spectrum::new(
+            fr_conductor(cos_theta_i, self.eta.r, self.k.r),
+            fr_conductor(cos_theta_i, self.eta.g, self.k.g),
+            fr_conductor(cos_theta_i, self.eta.b, self.k.b),
+        )
+    }
+}
 
 pub struct FresnelNoOp { }
 
@@ -131,8 +183,8 @@ impl FresnelNoOp {
 }
 
 impl Fresnel for FresnelNoOp {
-    fn evaluate(&self, _cos_theta_i: f64) -> (f64, f64, f64) {
-        (1.0, 1.0, 0.0)
+    fn evaluate(&self, _cos_theta_i: f64) -> Spectrum {
+        Spectrum::new(1.0, 1.0, 1.0)
     }
 }
 
@@ -151,10 +203,89 @@ impl FresnelSchlick {
 }
 
 impl Fresnel for FresnelSchlick {
-    fn evaluate(&self, cos_theta_i: f64) -> (f64, f64, f64) {
+    fn evaluate(&self, cos_theta_i: f64) -> Spectrum {
         let r0 = (self.eta_a - self.eta_b).powf(2.0) / (self.eta_a + self.eta_b).powf(2.0);
         let r = r0 + (1.0 - r0) * (1.0 - cos_theta_i).powf(5.0);
 
-        (r, 1.0, 0.0)
+        Spectrum::from(r)
+    }
+}
+#[cfg(test)]
+mod tests {
+    use cgmath::{InnerSpace, Point2};
+
+    use crate::sampler::rng::Rng;
+
+    use super::*;
+
+    #[test]
+    fn test_fresnel_conductor() {
+        // a conductor without absorption is a dielectric seen from the outside
+        for cos in [1.0, 0.8, 0.3, 0.05] {
+            let (dielectric, _, _) = fr_dielectric(cos, 1.0, 1.5);
+            assert!((fr_conductor(cos, 1.5, 0.0) - dielectric).abs() < 1e-12);
+        }
+
+        // normal incidence, and the grazing limit
+        let gold = FresnelConductor::new(Spectrum::new(0.143, 0.374, 1.442), Spectrum::new(3.983, 2.385, 1.603));
+        let r = gold.evaluate(1.0);
+        let (eta, k) = (0.143f64, 3.983f64);
+        assert!((r.r - ((eta - 1.0).powi(2) + k * k) / ((eta + 1.0).powi(2) + k * k)).abs() < 1e-12);
+        assert!(r.r > r.b);
+        assert!((gold.evaluate(0.0).g - 1.0).abs() < 1e-12);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_fresnel_specular() {
+        let bxdf = FresnelSpecular::new(1.0, 1.5, Spectrum::new(1.0, 1.0, 1.0), Spectrum::new(1.0, 1.0, 1.0));
+        let mut rng = Rng::new();
+
+        // from the outside, at normal incidence, from the inside, and beyond the critical angle inside
+        for wo in [Vector3::new(0.3, 0.4, 0.8), Vector3::new(0.0, 0.0, 1.0), Vector3::new(-0.2, 0.1, -0.9), Vector3::new(0.8, 0.0, -0.5)] {
+            let wo = wo.normalize();
+            let (fresnel, _, _) = fr_dielectric(wo.z, 1.0, 1.5);
+            let (eta_i, eta_t) = if wo.z > 0.0 { (1.0, 1.5) } else { (1.5, 1.0) };
+
+            let n = 10000;
+            let mut reflected = 0;
+            for _ in 0..n {
+                let sample = bxdf.sample_f(wo, Point2::new(rng.uniform_f64(), rng.uniform_f64()));
+                let sin_o = (wo.x * wo.x + wo.y * wo.y).sqrt();
+                let sin_i = (sample.wi.x * sample.wi.x + sample.wi.y * sample.wi.y).sqrt();
+
+                // the light is either mirrored or refracted following snell's law
+                let energy = if sample.wi.z * wo.z > 0.0 {
+                    reflected += 1;
+                    assert!((sample.wi - Vector3::new(-wo.x, -wo.y, wo.z)).x.abs() < 1e-12);
+                    1.0
+                } else {
+                    assert!((eta_i * sin_o - eta_t * sin_i).abs() < 1e-9);
+                    assert!(sin_o == 0.0 || (sample.wi.x * wo.y - sample.wi.y * wo.x).abs() < 1e-9);
+                    eta_t * eta_t / (eta_i * eta_i)
+                };
+                // every sample carries the light it scatters, without the eta^2 of the refracted radiance
+                let weight = sample.rho.g * cos_theta(sample.wi) / sample.pdf * energy;
+                assert!((weight - 1.0).abs() < 1e-9);
+            }
+
+            let fraction = reflected as f64 / n as f64;
+            assert!((fraction - fresnel).abs() < 4.0 * (fresnel * (1.0 - fresnel) / n as f64).sqrt() + 1e-12);
+        }
+    }
+
+    #[test]
+    fn test_fresnel_specular_critical_angle() {
+        // a direction inside, a rounding error away from the critical angle, for which the fresnel term lets
+        // some light refract while refract finds a total internal reflection
+        let (eta_b, cos) = (1.3817052208421563, 0.6900692189345293);
+        let bxdf = FresnelSpecular::new(1.0, eta_b, Spectrum::new(1.0, 1.0, 1.0), Spectrum::new(1.0, 1.0, 1.0));
+        let wo = Vector3::new((1.0f64 - cos * cos).sqrt(), 0.0, -cos);
+        let (fresnel, _, _) = fr_dielectric(wo.z, 1.0, eta_b);
+        assert!(fresnel < 1.0 && refract(wo, Vector3::new(0.0, 0.0, -1.0), eta_b).is_none());
+
+        // the refraction then fails instead of panicking
+        let sample = bxdf.sample_f(wo, Point2::new(1.0 - 1e-9, 0.5));
+        assert_eq!(sample.pdf, 0.0);
+        assert!(sample.rho.is_black());
+    }
+}
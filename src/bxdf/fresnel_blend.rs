@@ -1,11 +1,13 @@
 use std::f64::consts::PI;
 
-use cgmath::InnerSpace;
+use cgmath::{EuclideanSpace, InnerSpace, Point2, Vector3};
 
-use crate::spectrum::Spectrum;
+use crate::{spectrum::Spectrum, utils::{cos_theta, reflect}};
 
-use super::{microfacet::MicrofacetDistribution, Bxdf, BxdfType};
+use super::{microfacet::MicrofacetDistribution, Bxdf, BxdfSample, BxdfType, cosine_sample_hemisphere};
 
+/// the Ashikhmin and Shirley model of a glossy coating over a diffuse base, the diffuse light is what
+/// the coating lets through
 pub struct FresnelBlend {
     distribution: MicrofacetDistribution,
     ks: Spectrum,
@@ -23,25 +25,99 @@ impl FresnelBlend {
 
     fn schlick(&self, cos_theta: f64) -> Spectrum {
         let pow5 = |v: f64| { (v * v) * (v * v) * v };
-        self.ks + (Spectrum::new(1.0, 1.0, 1.0) - self.ks) * (1.0 - pow5(cos_theta))
+        self.ks + (Spectrum::new(1.0, 1.0, 1.0) - self.ks) * pow5(1.0 - cos_theta)
     }
 }
 
 impl Bxdf for FresnelBlend {
-    fn f(&self, wo: cgmath::Vector3<f64>, wi: cgmath::Vector3<f64>) -> Spectrum {
+    fn f(&self, wo: Vector3<f64>, wi: Vector3<f64>) -> Spectrum {
         let pow5 = |v: f64| {(v * v) * (v * v) * v };
-        let diffuse = (28.0 * self.kd) / (23.0 * PI) * (Spectrum::new(1.0, 1.0, 1.0)-self.ks) * 
-                (1.0 - pow5(1.0 - wi.z.abs()/2.0)) * (1.0 - pow5(1.0 - wo.z.abs()/2.0));
-
-        let wh = (wi + wo).normalize();
-        assert!(!wh.x.is_nan());
-        let specular = self.distribution.d(wh) * self.schlick(wi.dot(wh)) / 
-                (4.0 * wh.dot(wi) * wo.z.abs().max(wi.z.abs()));
-        
+        let diffuse = (28.0 * self.kd) / (23.0 * PI) * (Spectrum::new(1.0, 1.0, 1.0)-self.ks) *
+                (1.0 - pow5(1.0 - cos_theta(wi) / 2.0)) * (1.0 - pow5(1.0 - cos_theta(wo) / 2.0));
+
+        let wh = wi + wo;
+        if wh.magnitude2() == 0.0 || cos_theta(wo) == 0.0 || cos_theta(wi) == 0.0 {
+            return Spectrum::black();
+        }
+        let wh = wh.normalize();
+        let specular = self.distribution.d(wh) * self.schlick(wi.dot(wh).abs()) /
+                (4.0 * wi.dot(wh).abs() * cos_theta(wo).max(cos_theta(wi)));
+
         diffuse + specular
     }
 
+    fn sample_f(&self, wo: Vector3<f64>, sample: Point2<f64>) -> BxdfSample {
+        // half of the samples are cosine weighted for the diffuse base, the others follow the coating
+        let wi = if sample.x < 0.5 {
+            let mut wi = cosine_sample_hemisphere(Point2::new(2.0 * sample.x, sample.y)).to_vec();
+            if wo.z < 0.0 {
+                wi.z = -wi.z;
+            }
+            wi
+        } else {
+            let wh = self.distribution.sample_wh(wo, Point2::new(2.0 * (sample.x - 0.5), sample.y));
+            reflect(wo, wh)
+        };
+
+        if wo.z * wi.z <= 0.0 {
+            return BxdfSample { rho: Spectrum::black(), wi, pdf: 0.0, is_delta: false };
+        }
+
+        BxdfSample {
+            rho: self.f(wo, wi),
+            wi,
+            pdf: self.pdf(wo, wi),
+            is_delta: false,
+        }
+    }
+
+    fn pdf(&self, wo: Vector3<f64>, wi: Vector3<f64>) -> f64 {
+        if wo.z * wi.z <= 0.0 {
+            return 0.0;
+        }
+
+        let wh = (wo + wi).normalize();
+        let specular = self.distribution.pdf(wo, wh) / (4.0 * wo.dot(wh));
+        0.5 * (cos_theta(wi) / PI + specular)
+    }
+
     fn types(&self) -> i32 {
         BxdfType::Glossy | BxdfType::Reflection
     }
-}
\ No newline at end of file
+
+    fn is_delta(&self) -> bool {
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::bxdf::{microfacet::MicrofacetModel, validation::{albedo, assert_sample_f_matches_pdf}};
+
+    use super::*;
+
+    #[test]
+    fn test_fresnel_blend() {
+        let models = [MicrofacetModel::TrowbridgeReitz, MicrofacetModel::Beckmann];
+        let alphas = [0.2, 0.6];
+        let wos = [Vector3::new(0.1, 0.0, 0.9).normalize(), Vector3::new(-0.7, 0.5, -0.3).normalize()];
+        let test_count = models.len() * alphas.len() * wos.len();
+
+        for model in models {
+            for alpha in alphas {
+                let distribution = MicrofacetDistribution::new(model, alpha, alpha, true);
+                let bxdf = FresnelBlend::new(distribution, Spectrum::new(0.04, 0.04, 0.04), Spectrum::new(1.0, 1.0, 1.0));
+
+                for wo in wos {
+                    let name = format!("fresnel blend {:?} alpha {} wo {:?}", model, alpha, wo);
+                    assert_sample_f_matches_pdf(&name, &bxdf, wo, 200000, test_count);
+
+                    // the white coating over the white base does not create energy
+                    let a = albedo(&bxdf, wo, 200000, &|_| 1.0);
+                    assert!(a.g < 1.01, "{}: albedo {}", name, a.g);
+                    assert!(a.g > 0.5, "{}: albedo {}", name, a.g);
+                }
+            }
+        }
+    }
+}
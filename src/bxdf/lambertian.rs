@@ -24,16 +24,19 @@ impl Bxdf for LambertianReflection {
         self.reflectance * INV_PI
     }
 
-    fn sample_f(&self, _wo: Vector3<f64>, sample: Point2<f64>) -> BxdfSample {
-            let p = cosine_sample_hemisphere(sample);
-            let wi = p.to_vec();
+    fn sample_f(&self, wo: Vector3<f64>, sample: Point2<f64>) -> BxdfSample {
+            // the light is reflected on the side of wo
+            let mut wi = cosine_sample_hemisphere(sample).to_vec();
+            if wo.z < 0.0 {
+                wi.z = -wi.z;
+            }
 
             if wi[0].is_nan() || wi[1].is_nan() || wi[2].is_nan() {
                 println!("Not a Number in lambertian");
             }
 
             let rho = self.reflectance * INV_PI;
-            let pdf = INV_PI * cos_theta(wi);
+            let pdf = self.pdf(wo, wi);
 
             BxdfSample {
                 rho,
@@ -43,8 +46,12 @@ impl Bxdf for LambertianReflection {
             }
     }
 
-    fn pdf(&self, _wo: Vector3<f64>, wi: Vector3<f64>) -> f64 {
-        INV_PI * cos_theta(wi)
+    fn pdf(&self, wo: Vector3<f64>, wi: Vector3<f64>) -> f64 {
+        if wo.z * wi.z > 0.0 {
+            INV_PI * cos_theta(wi)
+        } else {
+            0.0
+        }
     }
 
     fn types(&self) -> i32 {
@@ -55,3 +62,26 @@ impl Bxdf for LambertianReflection {
         false
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use cgmath::InnerSpace;
+
+    use crate::bxdf::validation::{albedo, assert_sample_f_matches_pdf};
+
+    use super::*;
+
+    #[test]
+    fn test_lambertian() {
+        let bxdf = LambertianReflection::new(Spectrum::new(1.0, 0.5, 0.25));
+        let wos = [Vector3::new(0.0, 0.0, 1.0), Vector3::new(0.5, 0.3, 0.2).normalize(), Vector3::new(-0.2, 0.6, -0.7).normalize()];
+
+        for wo in wos {
+            assert_sample_f_matches_pdf(&format!("lambertian wo {:?}", wo), &bxdf, wo, 100000, wos.len());
+
+            // cosine sampling estimates the reflectance without variance
+            let a = albedo(&bxdf, wo, 1000, &|_| 1.0);
+            assert!((a.r - 1.0).abs() < 1e-9 && (a.g - 0.5).abs() < 1e-9 && (a.b - 0.25).abs() < 1e-9);
+        }
+    }
+}
@@ -0,0 +1,468 @@
+//! a dielectric coat over a base, the light is followed by random walks through the layer between
+//! them as pbrt-v4 does, so the lobes of the base are refracted, tinted and scattered by the coat
+//! without an analytic model of their interplay
+
+use std::f64::consts::PI;
+
+use cgmath::{EuclideanSpace, Point2, Vector3};
+
+use crate::{
+    medium::phase::{HenyeyGreenstein, PhaseFunction},
+    sampler::{lowdiscrepancy::hash, rng::Rng},
+    spectrum::Spectrum,
+    utils::{cos_theta, refract},
+};
+
+use super::{
+    Bxdf, BxdfSample, BxdfType, cosine_sample_hemisphere,
+    fresnel::{fr_dielectric, FresnelDielectric},
+    microfacet::{MicrofacetDistribution, MicrofacetReflection, MicrofacetTransmission},
+};
+
+const INV_PI: f64 = 1.0 / PI;
+
+fn power_heuristic(pdf_f: f64, pdf_g: f64) -> f64 {
+    (pdf_f * pdf_f) / (pdf_f * pdf_f + pdf_g * pdf_g)
+}
+
+// the transmittance through the height dz of the layer along w, its extinction coefficient is 1
+fn tr(dz: f64, w: Vector3<f64>) -> f64 {
+    if dz.abs() <= f64::MIN_POSITIVE {
+        return 1.0;
+    }
+    (-(dz / w.z).abs()).exp()
+}
+
+// the dielectric interface of the coat, eta is the index inside over the one outside
+enum Interface {
+    Smooth { eta: f64 },
+    Rough { eta: f64, reflection: MicrofacetReflection, transmission: MicrofacetTransmission },
+}
+
+impl Interface {
+    fn eta(&self) -> f64 {
+        match self {
+            Interface::Smooth { eta } | Interface::Rough { eta, .. } => *eta,
+        }
+    }
+
+    fn is_specular(&self) -> bool {
+        matches!(self, Interface::Smooth { .. })
+    }
+
+    fn f(&self, wo: Vector3<f64>, wi: Vector3<f64>) -> Spectrum {
+        match self {
+            Interface::Smooth { .. } => Spectrum::black(),
+            Interface::Rough { reflection, transmission, .. } => {
+                if wo.z * wi.z > 0.0 { reflection.f(wo, wi) } else { transmission.f(wo, wi) }
+            }
+        }
+    }
+
+    // the pdf of the lobe that reflects, or transmits, alone
+    fn pdf(&self, wo: Vector3<f64>, wi: Vector3<f64>, transmit: bool) -> f64 {
+        match self {
+            Interface::Smooth { .. } => 0.0,
+            Interface::Rough { reflection, transmission, .. } => {
+                if transmit { transmission.pdf(wo, wi) } else { reflection.pdf(wo, wi) }
+            }
+        }
+    }
+
+    // samples the lobe that reflects, or transmits, alone, the values are those of radiance
+    fn sample(&self, wo: Vector3<f64>, u: Point2<f64>, transmit: bool) -> Option<BxdfSample> {
+        let sample = match self {
+            Interface::Smooth { eta } => {
+                let (fresnel, _, _) = fr_dielectric(wo.z.clamp(-1.0, 1.0), 1.0, *eta);
+                if transmit {
+                    // the ratio of the index on the side of wo over the one on the other side
+                    let eta_ratio = if wo.z > 0.0 { 1.0 / eta } else { *eta };
+                    let n = Vector3::new(0.0, 0.0, wo.z.signum());
+                    let wi = refract(wo, n, eta_ratio)?;
+                    let value = (1.0 - fresnel) * eta_ratio * eta_ratio / cos_theta(wi);
+                    BxdfSample { rho: Spectrum::new(value, value, value), wi, pdf: 1.0, is_delta: true }
+                } else {
+                    let wi = Vector3::new(-wo.x, -wo.y, wo.z);
+                    let value = fresnel / cos_theta(wi);
+                    BxdfSample { rho: Spectrum::new(value, value, value), wi, pdf: 1.0, is_delta: true }
+                }
+            }
+            Interface::Rough { reflection, transmission, .. } => {
+                if transmit { transmission.sample_f(wo, u) } else { reflection.sample_f(wo, u) }
+            }
+        };
+
+        if sample.pdf == 0.0 || sample.rho.is_black() || sample.wi.z == 0.0 {
+            return None;
+        }
+        Some(sample)
+    }
+}
+
+// the reflection lobes of the base, combined as a bsdf combines its bxdfs
+struct Base {
+    bxdfs: Vec<Box<dyn Bxdf>>,
+}
+
+impl Base {
+    fn is_specular(&self) -> bool {
+        self.bxdfs.iter().all(|bxdf| bxdf.is_delta())
+    }
+
+    fn f(&self, wo: Vector3<f64>, wi: Vector3<f64>) -> Spectrum {
+        let mut f = Spectrum::black();
+        if wo.z * wi.z <= 0.0 {
+            return f;
+        }
+        for bxdf in self.bxdfs.iter().filter(|bxdf| (bxdf.types() & BxdfType::Reflection) != 0) {
+            f += bxdf.f(wo, wi);
+        }
+        f
+    }
+
+    fn pdf(&self, wo: Vector3<f64>, wi: Vector3<f64>) -> f64 {
+        if wo.z * wi.z <= 0.0 {
+            return 0.0;
+        }
+        self.bxdfs.iter().map(|bxdf| bxdf.pdf(wo, wi)).sum::<f64>() / self.bxdfs.len() as f64
+    }
+
+    fn sample(&self, wo: Vector3<f64>, u: Point2<f64>) -> Option<BxdfSample> {
+        let n = self.bxdfs.len();
+        let index = ((u.x * n as f64) as usize).min(n - 1);
+        let u = Point2::new(u.x * n as f64 - index as f64, u.y);
+
+        let mut sample = self.bxdfs[index].sample_f(wo, u);
+        // the base is opaque, the light it transmits is lost
+        if sample.pdf == 0.0 || wo.z * sample.wi.z <= 0.0 {
+            return None;
+        }
+        if sample.is_delta {
+            sample.pdf /= n as f64;
+        } else {
+            sample.pdf = self.pdf(wo, sample.wi);
+            sample.rho = self.f(wo, sample.wi);
+        }
+
+        if sample.pdf == 0.0 || sample.rho.is_black() {
+            return None;
+        }
+        Some(sample)
+    }
+}
+
+/// an opaque base under a dielectric coat of index eta, the layer between them has the given
+/// thickness and scatters with the given albedo, it only absorbs when the albedo is black
+///
+/// f is estimated by n_samples random walks of at most max_depth bounces, seeded by wo and wi so that
+/// it is a function of them, and the directions are sampled from an approximation of the lobes whose
+/// pdf is exact
+pub struct LayeredBxdf {
+    top: Interface,
+    base: Base,
+    thickness: f64,
+    albedo: Spectrum,
+    phase: HenyeyGreenstein,
+    max_depth: usize,
+    n_samples: usize,
+}
+
+impl LayeredBxdf {
+    /// a smooth coat when there is no distribution
+    pub fn new(eta: f64, distribution: Option<MicrofacetDistribution>, base: Vec<Box<dyn Bxdf>>, thickness: f64, albedo: Spectrum) -> Self {
+        let top = match distribution {
+            None => Interface::Smooth { eta },
+            Some(distribution) => Interface::Rough {
+                eta,
+                reflection: MicrofacetReflection::new(distribution, Box::new(FresnelDielectric::new(1.0, eta)), Spectrum::new(1.0, 1.0, 1.0)),
+                transmission: MicrofacetTransmission::new(distribution, Spectrum::new(1.0, 1.0, 1.0), 1.0, eta),
+            },
+        };
+
+        Self {
+            top,
+            base: Base { bxdfs: base },
+            thickness: thickness.max(f64::MIN_POSITIVE),
+            albedo,
+            phase: HenyeyGreenstein::new(0.0),
+            max_depth: 10,
+            n_samples: 1,
+        }
+    }
+
+    /// the asymmetry of the henyey greenstein phase function of the layer
+    pub fn with_g(mut self, g: f64) -> Self {
+        self.phase = HenyeyGreenstein::new(g);
+        self
+    }
+
+    pub fn with_random_walk(mut self, max_depth: usize, n_samples: usize) -> Self {
+        self.max_depth = max_depth;
+        self.n_samples = n_samples.max(1);
+        self
+    }
+
+    // the probability that a sample follows the reflection of the coat instead of the cosine
+    fn coat_probability(&self, wo: Vector3<f64>) -> f64 {
+        match self.top {
+            Interface::Smooth { eta } => fr_dielectric(wo.z.clamp(-1.0, 1.0), 1.0, eta).0,
+            Interface::Rough { .. } => 0.5,
+        }
+    }
+
+    // f for wo and wi above the coat, the walks enter through the coat towards wo and leave it
+    // towards wi, and are connected to wi at every bounce
+    fn random_walk(&self, wo: Vector3<f64>, wi: Vector3<f64>) -> Spectrum {
+        let bits = |w: Vector3<f64>| [w.x.to_bits(), w.y.to_bits(), w.z.to_bits()];
+        let mut rng = Rng::new();
+        rng.set_sequence(hash(&bits(wo)), hash(&bits(wi)));
+        let mut r = || rng.uniform_f64();
+
+        let thickness = self.thickness;
+        // the radiance refracted towards wi is the importance refracted from it, with eta^2 undone
+        let eta2 = self.top.eta() * self.top.eta();
+
+        let mut f = Spectrum::black();
+        for _ in 0..self.n_samples {
+            let wos = match self.top.sample(wo, Point2::new(r(), r()), true) {
+                Some(wos) => wos,
+                None => continue,
+            };
+            let mut wis = match self.top.sample(wi, Point2::new(r(), r()), true) {
+                Some(wis) => wis,
+                None => continue,
+            };
+            wis.rho = wis.rho * eta2;
+
+            let mut beta = wos.rho * cos_theta(wos.wi) / wos.pdf;
+            let mut z = thickness;
+            let mut w = wos.wi;
+
+            for depth in 0..self.max_depth {
+                // russian roulette
+                if depth > 3 && beta.max_component() < 0.25 {
+                    let q = (1.0 - beta.max_component()).max(0.0);
+                    if r() < q {
+                        break;
+                    }
+                    beta /= 1.0 - q;
+                }
+
+                if self.albedo.is_black() {
+                    // the layer only absorbs, the walk goes straight to the other interface
+                    z = if z == thickness { 0.0 } else { thickness };
+                    beta = beta * tr(thickness, w);
+                } else {
+                    let dz = -(1.0 - r()).ln() * w.z.abs();
+                    let zp = if w.z > 0.0 { z + dz } else { z - dz };
+                    if zp == z {
+                        continue;
+                    }
+
+                    if 0.0 < zp && zp < thickness {
+                        // scatter in the layer, and connect to wi through the coat
+                        let p = self.phase.p(-w, -wis.wi);
+                        let mut weight = 1.0;
+                        if !self.top.is_specular() {
+                            weight = power_heuristic(wis.pdf, p);
+                        }
+                        f += beta * self.albedo * wis.rho * (p * weight * tr(zp - thickness, wis.wi) / wis.pdf);
+
+                        let (wp, p) = self.phase.sample_p(-w, Point2::new(r(), r()));
+                        if p == 0.0 || wp.z == 0.0 {
+                            break;
+                        }
+                        beta *= self.albedo;
+                        w = wp;
+                        z = zp;
+
+                        // connect the scattered direction to wi when it heads for the coat
+                        if w.z > 0.0 && !self.top.is_specular() {
+                            let f_exit = self.top.f(-w, wi);
+                            if !f_exit.is_black() {
+                                let weight = power_heuristic(p, self.top.pdf(-w, wi, true));
+                                f += beta * f_exit * (tr(zp - thickness, w) * weight);
+                            }
+                        }
+                        continue;
+                    }
+                    z = zp.clamp(0.0, thickness);
+                }
+
+                if z == thickness {
+                    // the coat reflects the light back into the layer
+                    let sample = match self.top.sample(-w, Point2::new(r(), r()), false) {
+                        Some(sample) => sample,
+                        None => break,
+                    };
+                    beta = beta * sample.rho * (cos_theta(sample.wi) / sample.pdf);
+                    w = sample.wi;
+                } else {
+                    // connect the base to wi through the coat
+                    if !self.base.is_specular() {
+                        let mut weight = 1.0;
+                        if !self.top.is_specular() {
+                            weight = power_heuristic(wis.pdf, self.base.pdf(-w, -wis.wi));
+                        }
+                        f += beta * self.base.f(-w, -wis.wi) * wis.rho * (cos_theta(wis.wi) * weight * tr(thickness, wis.wi) / wis.pdf);
+                    }
+
+                    let sample = match self.base.sample(-w, Point2::new(r(), r())) {
+                        Some(sample) => sample,
+                        None => break,
+                    };
+                    beta = beta * sample.rho * (cos_theta(sample.wi) / sample.pdf);
+                    w = sample.wi;
+
+                    // and connect the reflected direction to wi
+                    if !self.top.is_specular() {
+                        let f_exit = self.top.f(-w, wi);
+                        if !f_exit.is_black() {
+                            let mut weight = 1.0;
+                            if !sample.is_delta {
+                                weight = power_heuristic(sample.pdf, self.top.pdf(-w, wi, true));
+                            }
+                            f += beta * f_exit * (tr(thickness, w) * weight);
+                        }
+                    }
+                }
+            }
+        }
+
+        f / self.n_samples as f64
+    }
+}
+
+impl Bxdf for LayeredBxdf {
+    fn f(&self, wo: Vector3<f64>, wi: Vector3<f64>) -> Spectrum {
+        // the coat is on both sides of the surface
+        let (wo, wi) = if wo.z < 0.0 { (-wo, -wi) } else { (wo, wi) };
+        if wo.z == 0.0 || wi.z <= 0.0 {
+            return Spectrum::black();
+        }
+
+        self.top.f(wo, wi) + self.random_walk(wo, wi)
+    }
+
+    fn sample_f(&self, wo: Vector3<f64>, sample: Point2<f64>) -> BxdfSample {
+        let flip = wo.z < 0.0;
+        let wo = if flip { -wo } else { wo };
+        let flipped = |w: Vector3<f64>| if flip { -w } else { w };
+        let blocked = BxdfSample { rho: Spectrum::black(), wi: flipped(-wo), pdf: 0.0, is_delta: false };
+        if wo.z == 0.0 {
+            return blocked;
+        }
+
+        let p = self.coat_probability(wo);
+        if sample.x < p {
+            let u = Point2::new(sample.x / p, sample.y);
+            match &self.top {
+                // the mirror reflection of the smooth coat
+                Interface::Smooth { .. } => {
+                    let wi = Vector3::new(-wo.x, -wo.y, wo.z);
+                    let value = p / cos_theta(wi);
+                    return BxdfSample { rho: Spectrum::new(value, value, value), wi: flipped(wi), pdf: p, is_delta: true };
+                }
+                Interface::Rough { reflection, .. } => {
+                    let wi = reflection.sample_f(wo, u).wi;
+                    if wi.z <= 0.0 {
+                        return blocked;
+                    }
+                    return BxdfSample { rho: self.f(wo, wi), wi: flipped(wi), pdf: self.pdf(wo, wi), is_delta: false };
+                }
+            }
+        }
+
+        // the light that goes through the coat leaves it roughly as from a diffuse base
+        let u = Point2::new(((sample.x - p) / (1.0 - p)).min(1.0 - f64::EPSILON), sample.y);
+        let wi = cosine_sample_hemisphere(u).to_vec();
+        if wi.z <= 0.0 {
+            return blocked;
+        }
+        BxdfSample { rho: self.f(wo, wi), wi: flipped(wi), pdf: self.pdf(wo, wi), is_delta: false }
+    }
+
+    fn pdf(&self, wo: Vector3<f64>, wi: Vector3<f64>) -> f64 {
+        let (wo, wi) = if wo.z < 0.0 { (-wo, -wi) } else { (wo, wi) };
+        if wo.z == 0.0 || wi.z <= 0.0 {
+            return 0.0;
+        }
+
+        let p = self.coat_probability(wo);
+        let coat = match &self.top {
+            Interface::Smooth { .. } => 0.0,
+            Interface::Rough { reflection, .. } => reflection.pdf(wo, wi),
+        };
+        p * coat + (1.0 - p) * INV_PI * wi.z
+    }
+
+    fn types(&self) -> i32 {
+        BxdfType::Reflection | BxdfType::Glossy
+    }
+
+    fn is_delta(&self) -> bool {
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::InnerSpace;
+
+    use crate::bxdf::{lambertian::LambertianReflection, microfacet::MicrofacetModel, validation::{albedo, assert_sample_f_matches_pdf}};
+
+    use super::*;
+
+    fn white_base() -> Vec<Box<dyn Bxdf>> {
+        vec![Box::new(LambertianReflection::new(Spectrum::new(1.0, 1.0, 1.0)))]
+    }
+
+    #[test]
+    fn test_layered() {
+        let wos = [Vector3::new(0.0, 0.0, 1.0), Vector3::new(0.5, -0.3, 0.6).normalize(), Vector3::new(0.2, 0.7, -0.3).normalize()];
+        let alphas = [0.1, 0.5];
+        let test_count = wos.len() * alphas.len();
+
+        for wo in wos {
+            // the smooth coat over a white base in a white furnace, the lost light is what the walks
+            // cut short, the coat itself neither absorbs nor creates light
+            let layered = LayeredBxdf::new(1.5, None, white_base(), 0.01, Spectrum::black()).with_random_walk(64, 1);
+            let a = albedo(&layered, wo, 20000, &|_| 1.0);
+            assert!(a.g < 1.02 && a.g > 0.9, "smooth coat wo {:?}: albedo {}", wo, a.g);
+
+            // a thick absorbing layer darkens it
+            let absorbing = LayeredBxdf::new(1.5, None, white_base(), 1.0, Spectrum::black()).with_random_walk(64, 1);
+            let b = albedo(&absorbing, wo, 20000, &|_| 1.0);
+            assert!(b.g < 0.5 * a.g, "absorbing coat wo {:?}: albedo {}", wo, b.g);
+
+            // and a scattering one less than an absorbing one of the same thickness
+            let scattering = LayeredBxdf::new(1.5, None, white_base(), 1.0, Spectrum::new(0.8, 0.8, 0.8)).with_random_walk(64, 1);
+            let c = albedo(&scattering, wo, 20000, &|_| 1.0);
+            assert!(c.g > b.g && c.g < a.g, "scattering coat wo {:?}: albedo {}", wo, c.g);
+
+            for alpha in alphas {
+                let distribution = MicrofacetDistribution::new(MicrofacetModel::TrowbridgeReitz, alpha, alpha, true);
+                let layered = LayeredBxdf::new(1.5, Some(distribution), white_base(), 0.01, Spectrum::black()).with_random_walk(64, 1);
+                let name = format!("rough coat alpha {} wo {:?}", alpha, wo);
+                assert_sample_f_matches_pdf(&name, &layered, wo, 100000, test_count);
+
+                // the single scattering microfacets lose the light they trap under the rough coat
+                let a = albedo(&layered, wo, 20000, &|_| 1.0);
+                assert!(a.g < 1.02 && a.g > 0.5, "{}: albedo {}", name, a.g);
+            }
+        }
+    }
+
+    #[test]
+    fn test_layered_is_reciprocal() {
+        let distribution = MicrofacetDistribution::new(MicrofacetModel::TrowbridgeReitz, 0.3, 0.3, true);
+        let base: Vec<Box<dyn Bxdf>> = vec![Box::new(LambertianReflection::new(Spectrum::new(0.7, 0.5, 0.3)))];
+        let layered = LayeredBxdf::new(1.5, Some(distribution), base, 0.1, Spectrum::new(0.5, 0.5, 0.5)).with_random_walk(32, 4000);
+
+        let (wo, wi) = (Vector3::new(0.2, 0.1, 0.9).normalize(), Vector3::new(-0.6, 0.3, 0.5).normalize());
+        let (f, f_swapped) = (layered.f(wo, wi), layered.f(wi, wo));
+        for c in 0..3 {
+            assert!((f[c] - f_swapped[c]).abs() < 0.05 * f[c], "f(wo, wi) {:?} but f(wi, wo) {:?}", f, f_swapped);
+        }
+    }
+}
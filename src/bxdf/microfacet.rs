@@ -2,56 +2,99 @@ use std::f64::consts::PI;
 
 use cgmath::{InnerSpace, Point2, Vector3};
 
-use crate::{utils::{cos2_phi, cos2_theta, cos_theta, reflect, sin2_phi, spherical_direction, tan2_theta}, spectrum::Spectrum};
+use crate::{utils::{cos2_phi, cos2_theta, cos_phi, cos_theta, erf, erf_inv, reflect, refract, sin2_phi, sin_phi, spherical_direction, tan2_theta}, spectrum::Spectrum};
 
-use super::{fresnel::Fresnel, Bxdf, BxdfSample, BxdfType};
+use super::{fresnel::{Fresnel, fr_dielectric}, Bxdf, BxdfSample, BxdfType};
 
+/// the shapes of the microfacet normal distributions
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum MicrofacetModel {
+    TrowbridgeReitz,
+    Beckmann,
+}
+
+/// sample_visible draws the normals seen from wo instead of the whole distribution, which has a
+/// much lower variance at grazing angles
+#[derive(Clone, Copy)]
 pub enum MicrofacetDistribution {
     TrowbridgeReitz {
         alpha_x: f64,
         alpha_y: f64,
-    }
+        sample_visible: bool,
+    },
+    Beckmann {
+        alpha_x: f64,
+        alpha_y: f64,
+        sample_visible: bool,
+    },
 }
 
 impl MicrofacetDistribution {
-    pub fn d(&self, wh: Vector3<f64>) -> f64 {
-        match self {
-            Self::TrowbridgeReitz { alpha_x, alpha_y } => {
-                let tan2_theta = tan2_theta(wh);
-                if tan2_theta.is_infinite() {
-                    return 0.0;
-                }
+    pub fn new(model: MicrofacetModel, alpha_x: f64, alpha_y: f64, sample_visible: bool) -> Self {
+        match model {
+            MicrofacetModel::TrowbridgeReitz => Self::TrowbridgeReitz { alpha_x, alpha_y, sample_visible },
+            MicrofacetModel::Beckmann => Self::Beckmann { alpha_x, alpha_y, sample_visible },
+        }
+    }
+
+    fn alpha(&self) -> (f64, f64) {
+        match *self {
+            Self::TrowbridgeReitz { alpha_x, alpha_y, .. } | Self::Beckmann { alpha_x, alpha_y, .. } => (alpha_x, alpha_y),
+        }
+    }
 
-                let sin2_phi = sin2_phi(wh);
-                let cos2_phi = cos2_phi(wh);
-                let cos2_theta = cos2_theta(wh);
-                let cos4_theta = cos2_theta * cos2_theta;
+    fn sample_visible(&self) -> bool {
+        match *self {
+            Self::TrowbridgeReitz { sample_visible, .. } | Self::Beckmann { sample_visible, .. } => sample_visible,
+        }
+    }
 
-                let e = 1.0 + tan2_theta * 
-                        (cos2_phi / (alpha_x*alpha_x) +  sin2_phi / (alpha_y*alpha_y));
+    pub fn d(&self, wh: Vector3<f64>) -> f64 {
+        let tan2_theta = tan2_theta(wh);
+        if tan2_theta.is_infinite() {
+            return 0.0;
+        }
 
-                let inv_d = PI * alpha_x * alpha_y * cos4_theta * e * e;
+        let (alpha_x, alpha_y) = self.alpha();
+        let cos2_theta = cos2_theta(wh);
+        let cos4_theta = cos2_theta * cos2_theta;
+        let slope2 = tan2_theta * (cos2_phi(wh) / (alpha_x * alpha_x) + sin2_phi(wh) / (alpha_y * alpha_y));
 
-                1.0 / inv_d
+        match self {
+            Self::TrowbridgeReitz { .. } => {
+                let e = 1.0 + slope2;
+                1.0 / (PI * alpha_x * alpha_y * cos4_theta * e * e)
             }
+            Self::Beckmann { .. } => (-slope2).exp() / (PI * alpha_x * alpha_y * cos4_theta),
         }
     }
 
     fn lambda(&self, w: Vector3<f64>) -> f64 {
+        let tan2_theta = tan2_theta(w);
+        if tan2_theta.is_infinite() {
+            return 0.0;
+        }
+
+        let (alpha_x, alpha_y) = self.alpha();
+        let alpha2 = cos2_phi(w) * alpha_x * alpha_x + sin2_phi(w) * alpha_y * alpha_y;
+
         match self {
-            Self::TrowbridgeReitz { alpha_x, alpha_y } => {
-                let tan2_theta = tan2_theta(w);
-                if tan2_theta.is_infinite() {
+            Self::TrowbridgeReitz { .. } => (-1.0 + (1.0 + alpha2 * tan2_theta).sqrt()) / 2.0,
+            Self::Beckmann { .. } => {
+                // the rational approximation of the masking of a gaussian surface
+                let a = 1.0 / (alpha2 * tan2_theta).sqrt();
+                if a >= 1.6 {
                     return 0.0;
                 }
-
-                let alpha2 = cos2_phi(w) * alpha_x * alpha_x + sin2_phi(w) * alpha_y * alpha_y;
-
-                (-1.0 + (1.0 + alpha2 * tan2_theta).sqrt()) / 2.0
+                (1.0 - 1.259 * a + 0.396 * a * a) / (3.535 * a + 2.181 * a * a)
             }
         }
     }
 
+    pub fn g1(&self, w: Vector3<f64>) -> f64 {
+        1.0 / (1.0 + self.lambda(w))
+    }
+
     pub fn g(&self, wo: Vector3<f64>, wi: Vector3<f64>) -> f64 {
         1.0 / (1.0 + self.lambda(wo) + self.lambda(wi))
     }
@@ -64,48 +107,138 @@ impl MicrofacetDistribution {
         roughness
     }
 
-    // sample the Distribution function
+    /// sample a microfacet normal on the side of wo
     pub fn sample_wh(&self, wo: Vector3<f64>, u: Point2<f64>) -> Vector3<f64> {
-        match self {
-            Self::TrowbridgeReitz { alpha_x, alpha_y } => {
-                let cos_theta;
-                let mut phi;
-
-                if alpha_x == alpha_y {
-                    // isotropic
-                    let tan_theta2 = alpha_x * alpha_x * u[0] / (1.0 - u[0]);
-                    cos_theta = 1.0 / (1.0 + tan_theta2).sqrt();
-                    phi = 2.0 * PI * u[1];
-                } else {
-                    // anisotropic
-                    phi = (alpha_y / alpha_x * (2.0 * PI * u[1] + 0.5 * PI).tan()).atan();
-                    if u[1] > 0.5 {
-                        phi += PI;
-                    }
+        let (alpha_x, alpha_y) = self.alpha();
+
+        if self.sample_visible() {
+            // the visible normals of the upper side, mirrored for a wo below the surface
+            let flip = wo.z < 0.0;
+            let w = if flip { -wo } else { wo };
+            let wh = match self {
+                Self::TrowbridgeReitz { .. } => trowbridge_reitz_sample_visible(w, alpha_x, alpha_y, u),
+                Self::Beckmann { .. } => beckmann_sample_visible(w, alpha_x, alpha_y, u),
+            };
+            return if flip { -wh } else { wh };
+        }
 
-                    let sin_phi = phi.sin();
-                    let cos_phi = phi.cos();
-                    let alpha_x2 = alpha_x * alpha_x;
-                    let alpha_y2 = alpha_y * alpha_y;
-                    let alpha2 = 1.0 / (cos_phi * cos_phi / alpha_x2 + sin_phi * sin_phi / alpha_y2);
+        // the azimuth of an anisotropic distribution, which sees the alpha of its direction
+        let (phi, alpha2) = if alpha_x == alpha_y {
+            (2.0 * PI * u[1], alpha_x * alpha_x)
+        } else {
+            let mut phi = (alpha_y / alpha_x * (2.0 * PI * u[1] + 0.5 * PI).tan()).atan();
+            if u[1] > 0.5 {
+                phi += PI;
+            }
 
-                    let tan_theta2 = alpha2 * u[0] / (1.0 - u[0]);
-                    cos_theta = 1.0 / (1.0 + tan_theta2).sqrt();
-                }
+            let (sin_phi, cos_phi) = phi.sin_cos();
+            let alpha2 = 1.0 / (cos_phi * cos_phi / (alpha_x * alpha_x) + sin_phi * sin_phi / (alpha_y * alpha_y));
+            (phi, alpha2)
+        };
+
+        let tan2_theta = match self {
+            Self::TrowbridgeReitz { .. } => alpha2 * u[0] / (1.0 - u[0]),
+            Self::Beckmann { .. } => -alpha2 * (1.0 - u[0]).ln(),
+        };
+        let cos_theta = 1.0 / (1.0 + tan2_theta).sqrt();
+        let sin_theta = (1.0 - cos_theta * cos_theta).clamp(0.0, 1.0).sqrt();
+        let wh = spherical_direction(sin_theta, cos_theta, phi);
+
+        // the microfacet normal is on the side of wo
+        if wo.z < 0.0 { -wh } else { wh }
+    }
 
-                let sin_theta = (1.0 - cos_theta * cos_theta).clamp(0.0, 1.0).sqrt();
-                let wh = spherical_direction(sin_theta, cos_theta, phi);
+    /// the pdf of sampling wh with sample_wh, over solid angle, which draws the normals on the side of
+    /// wo, wh may be given on either side
+    pub fn pdf(&self, wo: Vector3<f64>, wh: Vector3<f64>) -> f64  {
+        if !self.sample_visible() {
+            return self.d(wh) * cos_theta(wh);
+        }
 
-                wh
-            }
+        // the normals facing away from wo are not visible
+        let cos_oh = if wo.z * wh.z < 0.0 { -wo.dot(wh) } else { wo.dot(wh) };
+        if wo.z == 0.0 || cos_oh <= 0.0 {
+            return 0.0;
         }
+        self.d(wh) * self.g1(wo) * cos_oh / cos_theta(wo)
     }
+}
+
+// Heitz's sampling of the visible normals of the Trowbridge-Reitz distribution, as the projection of
+// a uniform sample of the hemisphere stretched to an isotropic unit roughness
+fn trowbridge_reitz_sample_visible(wo: Vector3<f64>, alpha_x: f64, alpha_y: f64, u: Point2<f64>) -> Vector3<f64> {
+    let vh = Vector3::new(alpha_x * wo.x, alpha_y * wo.y, wo.z).normalize();
 
+    let len2 = vh.x * vh.x + vh.y * vh.y;
+    let t1 = if len2 > 0.0 { Vector3::new(-vh.y, vh.x, 0.0) / len2.sqrt() } else { Vector3::new(1.0, 0.0, 0.0) };
+    let t2 = vh.cross(t1);
 
-    // return the pdf of sampling wh
-    pub fn pdf(&self, wh: Vector3<f64>) -> f64  {
-        self.d(wh) * cos_theta(wh).abs()
+    // a disk sample warped to the projected area of the half sphere seen from vh
+    let r = u[0].sqrt();
+    let phi = 2.0 * PI * u[1];
+    let p1 = r * phi.cos();
+    let s = 0.5 * (1.0 + vh.z);
+    let p2 = (1.0 - s) * (1.0 - p1 * p1).sqrt() + s * r * phi.sin();
+    let nh = t1 * p1 + t2 * p2 + vh * (1.0 - p1 * p1 - p2 * p2).max(0.0).sqrt();
+
+    Vector3::new(alpha_x * nh.x, alpha_y * nh.y, nh.z.max(1e-6)).normalize()
+}
+
+// the visible normals of the Beckmann distribution, from the slope distribution of a stretched
+// unit roughness surface seen from wo
+fn beckmann_sample_visible(wo: Vector3<f64>, alpha_x: f64, alpha_y: f64, u: Point2<f64>) -> Vector3<f64> {
+    let stretched = Vector3::new(alpha_x * wo.x, alpha_y * wo.y, wo.z).normalize();
+    let (slope_x, slope_y) = beckmann_sample_11(stretched.z, u);
+
+    // rotate to the azimuth of wo and unstretch
+    let (cos_phi, sin_phi) = (cos_phi(stretched), sin_phi(stretched));
+    let (slope_x, slope_y) = (cos_phi * slope_x - sin_phi * slope_y, sin_phi * slope_x + cos_phi * slope_y);
+    Vector3::new(-alpha_x * slope_x, -alpha_y * slope_y, 1.0).normalize()
+}
+
+// the slopes of the visible normals of the unit Beckmann distribution seen at cos_theta_i along x
+fn beckmann_sample_11(cos_theta_i: f64, u: Point2<f64>) -> (f64, f64) {
+    // at normal incidence the slopes are a gaussian
+    if cos_theta_i > 0.9999 {
+        let r = (-(1.0 - u[0]).ln()).sqrt();
+        let (sin_phi, cos_phi) = (2.0 * PI * u[1]).sin_cos();
+        return (r * cos_phi, r * sin_phi);
     }
+
+    let sin_theta_i = (1.0 - cos_theta_i * cos_theta_i).max(0.0).sqrt();
+    let tan_theta_i = sin_theta_i / cos_theta_i;
+    let cot_theta_i = 1.0 / tan_theta_i;
+
+    // invert the cdf of the slope along x with newton steps guarded by bisection, from a fitted guess
+    let (mut a, mut c) = (-1.0, erf(cot_theta_i));
+    let sample_x = u[0].max(1e-6);
+    let theta_i = cos_theta_i.acos();
+    let fit = 1.0 + theta_i * (-0.876 + theta_i * (0.4265 - 0.0594 * theta_i));
+    let mut b = c - (1.0 + c) * (1.0 - sample_x).powf(fit);
+
+    let inv_sqrt_pi = 1.0 / PI.sqrt();
+    let normalization = 1.0 / (1.0 + c + inv_sqrt_pi * tan_theta_i * (-cot_theta_i * cot_theta_i).exp());
+
+    for _ in 0..9 {
+        if !(b >= a && b <= c) {
+            b = 0.5 * (a + c);
+        }
+        let inv_erf = erf_inv(b);
+        let value = normalization * (1.0 + b + inv_sqrt_pi * tan_theta_i * (-inv_erf * inv_erf).exp()) - sample_x;
+        let derivative = normalization * (1.0 - inv_erf * tan_theta_i);
+        if value.abs() < 1e-5 {
+            break;
+        }
+
+        if value > 0.0 {
+            c = b;
+        } else {
+            a = b;
+        }
+        b -= value / derivative;
+    }
+
+    (erf_inv(b), erf_inv(2.0 * u[1].max(1e-6) - 1.0))
 }
 
 // Torrance-Sparrow Model
@@ -128,22 +261,19 @@ impl MicrofacetReflection {
 
 impl Bxdf for MicrofacetReflection {
     fn f(&self, wo: cgmath::Vector3<f64>, wi: cgmath::Vector3<f64>) -> crate::spectrum::Spectrum {
-        let wh = (wo + wi).normalize();
         let cos_o = cos_theta(wo);
         let cos_i = cos_theta(wi);
+        let wh = wo + wi;
+        if cos_o == 0.0 || cos_i == 0.0 || wh.magnitude2() == 0.0 {
+            return Spectrum::black();
+        }
+        let wh = wh.normalize();
 
-        let (fresnel_term, _, _) = self.fresnel.evaluate(wo.dot(wh));
-
-        let res = self.reflectance * fresnel_term * self.distribution.g(wo, wi) * self.distribution.d(wh) / (4.0 * cos_o * cos_i);
-
-        // if fresnel_term > 0.6 {
-        //     println!("fresnel: {:?}", fresnel_term);
-        // }
-        // println!("g: {:?}", self.distribution.g(wo, wi));
-        // println!("d: {:?}", self.distribution.d(wh));
-        // println!("res: {:?}", res);
+        // the fresnel term sees the light from the side of the surface it arrives on
+        let cos_h = if wh.z < 0.0 { -wo.dot(wh) } else { wo.dot(wh) };
+        let fresnel_term = self.fresnel.evaluate(cos_h);
 
-        res
+        self.reflectance * fresnel_term * self.distribution.g(wo, wi) * self.distribution.d(wh) / (4.0 * cos_o * cos_i)
     }
 
     fn sample_f(&self, wo: Vector3<f64>, sample: Point2<f64>) -> super::BxdfSample {
@@ -161,11 +291,11 @@ impl Bxdf for MicrofacetReflection {
                 is_delta: false,
             }
         } else {
-            // blocked 
+            // the microfacet reflects the light below the surface
             BxdfSample {
                 rho: Spectrum::black(),
                 wi,
-                pdf: 1.0,
+                pdf: 0.0,
                 is_delta: false,
             }
         }
@@ -178,7 +308,7 @@ impl Bxdf for MicrofacetReflection {
         }
 
         let wh = (wo + wi).normalize();
-        let pdf_wh = self.distribution.pdf(wh);
+        let pdf_wh = self.distribution.pdf(wo, wh);
 
         pdf_wh / (4.0 * wo.dot(wh).max(1e-3))
     }
@@ -190,4 +320,241 @@ impl Bxdf for MicrofacetReflection {
     fn is_delta(&self) -> bool {
         false
     }
-}
\ No newline at end of file
+}
+/// the Walter et al. model of the light refracted by the microfacets of a rough dielectric
+pub struct MicrofacetTransmission {
+    distribution: MicrofacetDistribution,
+    transmittance: Spectrum,
+    eta_a: f64, // refractive index outside
+    eta_b: f64, // refractive index inside
+}
+
+impl MicrofacetTransmission {
+    pub fn new(distribution: MicrofacetDistribution, transmittance: Spectrum, eta_a: f64, eta_b: f64) -> Self {
+        Self {
+            distribution,
+            transmittance,
+            eta_a,
+            eta_b,
+        }
+    }
+
+    // the ratio of the index on the side of wi over the one on the side of wo
+    fn eta(&self, wo: Vector3<f64>) -> f64 {
+        if wo.z > 0.0 { self.eta_b / self.eta_a } else { self.eta_a / self.eta_b }
+    }
+
+    // the generalized half vector of a refraction, on the outside, None if wo and wi do not refract
+    // through the same microfacet
+    fn half_vector(&self, wo: Vector3<f64>, wi: Vector3<f64>) -> Option<Vector3<f64>> {
+        if wo.z * wi.z >= 0.0 {
+            return None;
+        }
+        let wh = wo + wi * self.eta(wo);
+        if wh.magnitude2() == 0.0 {
+            return None;
+        }
+        let wh = wh.normalize();
+        let wh = if wh.z < 0.0 { -wh } else { wh };
+        if wo.dot(wh) * wi.dot(wh) > 0.0 {
+            return None;
+        }
+        Some(wh)
+    }
+}
+
+impl Bxdf for MicrofacetTransmission {
+    fn f(&self, wo: Vector3<f64>, wi: Vector3<f64>) -> Spectrum {
+        let wh = match self.half_vector(wo, wi) {
+            Some(wh) => wh,
+            None => return Spectrum::black(),
+        };
+        let eta = self.eta(wo);
+
+        let (fresnel, _, _) = fr_dielectric(wo.dot(wh).clamp(-1.0, 1.0), self.eta_a, self.eta_b);
+        let sqrt_denom = wo.dot(wh) + eta * wi.dot(wh);
+        // the radiance is compressed into the smaller solid angle by 1 / eta^2
+        let factor = 1.0 / eta;
+
+        self.transmittance * (1.0 - fresnel) * (
+            self.distribution.d(wh) * self.distribution.g(wo, wi) * eta * eta * wi.dot(wh).abs() * wo.dot(wh).abs() * factor * factor /
+            (cos_theta(wi) * cos_theta(wo) * sqrt_denom * sqrt_denom)
+        ).abs()
+    }
+
+    fn sample_f(&self, wo: Vector3<f64>, sample: Point2<f64>) -> BxdfSample {
+        let blocked = BxdfSample { rho: Spectrum::black(), wi: -wo, pdf: 0.0, is_delta: false };
+        if wo.z == 0.0 {
+            return blocked;
+        }
+
+        let wh = self.distribution.sample_wh(wo, sample);
+        if wo.dot(wh) < 0.0 {
+            return blocked;
+        }
+        match refract(wo, wh, 1.0 / self.eta(wo)) {
+            Some(wi) => BxdfSample {
+                rho: self.f(wo, wi),
+                wi,
+                pdf: self.pdf(wo, wi),
+                is_delta: false,
+            },
+            None => blocked,
+        }
+    }
+
+    fn pdf(&self, wo: Vector3<f64>, wi: Vector3<f64>) -> f64 {
+        let wh = match self.half_vector(wo, wi) {
+            Some(wh) => wh,
+            None => return 0.0,
+        };
+        let eta = self.eta(wo);
+
+        // the jacobian of the refraction from wh to wi
+        let sqrt_denom = wo.dot(wh) + eta * wi.dot(wh);
+        let dwh_dwi = (eta * eta * wi.dot(wh) / (sqrt_denom * sqrt_denom)).abs();
+        self.distribution.pdf(wo, wh) * dwh_dwi
+    }
+
+    fn types(&self) -> i32 {
+        BxdfType::Transmission | BxdfType::Glossy
+    }
+
+    fn is_delta(&self) -> bool {
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{bxdf::{chi2::assert_chi2, fresnel::{FresnelDielectric, FresnelNoOp}, validation::{albedo, assert_sample_f_matches_pdf}}, sampler::{Sampler, uniform_sampler::UniformSampler}};
+
+    use super::*;
+
+    // the integral of f |cos| over the hemisphere opposite to wo, with the midpoint rule
+    fn transmitted(bxdf: &MicrofacetTransmission, wo: Vector3<f64>) -> f64 {
+        let n = 500;
+        let mut sum = 0.0;
+        for i in 0..n {
+            for j in 0..n {
+                let cos = (i as f64 + 0.5) / n as f64;
+                let phi = 2.0 * PI * (j as f64 + 0.5) / n as f64;
+                let wi = spherical_direction((1.0 - cos * cos).sqrt(), -cos * wo.z.signum(), phi);
+                sum += bxdf.f(wo, wi).g * cos * 2.0 * PI / (n * n) as f64;
+            }
+        }
+        sum
+    }
+
+    #[test]
+    fn test_microfacet_transmission() {
+        let distribution = MicrofacetDistribution::new(MicrofacetModel::TrowbridgeReitz, 0.3, 0.15, true);
+        let bxdf = MicrofacetTransmission::new(distribution, Spectrum::new(1.0, 1.0, 1.0), 1.0, 1.5);
+        let mut sampler = UniformSampler::new(1, 3);
+
+        for wo in [Vector3::new(0.3, 0.2, 0.9).normalize(), Vector3::new(-0.4, 0.1, -0.8).normalize()] {
+            // the sampled directions cross the surface, carry their pdf and estimate the transmission
+            let n = 100000;
+            let mut estimate = 0.0;
+            for _ in 0..n {
+                let sample = bxdf.sample_f(wo, sampler.get_2d());
+                if sample.pdf == 0.0 {
+                    continue;
+                }
+                assert!(sample.wi.z * wo.z < 0.0);
+                assert!((sample.pdf - bxdf.pdf(wo, sample.wi)).abs() < 1e-6 * sample.pdf.max(1.0));
+                estimate += sample.rho.g * sample.wi.z.abs() / sample.pdf / n as f64;
+            }
+            let expected = transmitted(&bxdf, wo);
+            assert!(expected > 0.1);
+            assert!((estimate - expected).abs() < 0.02 * expected);
+        }
+        // reciprocal up to the eta^2 the radiance gains when entering the glass
+        let wo = Vector3::new(0.3, 0.2, 0.9).normalize();
+        let wi = Vector3::new(-0.15, -0.1, -0.95).normalize();
+        let forward = bxdf.f(wo, wi).g;
+        let backward = bxdf.f(wi, wo).g;
+        assert!(forward > 0.0);
+        assert!((forward * 1.5f64.powi(2) - backward).abs() < 1e-9 * backward);
+    }
+
+    #[test]
+    fn test_sample_wh_chi2() {
+        let models = [MicrofacetModel::TrowbridgeReitz, MicrofacetModel::Beckmann];
+        let alphas = [(0.3, 0.3), (0.7, 0.7), (0.2, 0.6)];
+        let wos = [Vector3::new(0.0, 0.0, 1.0), Vector3::new(0.6, -0.3, 0.5).normalize(), Vector3::new(-0.9, 0.2, -0.1).normalize()];
+        let test_count = models.len() * 2 * alphas.len() * wos.len();
+
+        for model in models {
+            for sample_visible in [true, false] {
+                for (alpha_x, alpha_y) in alphas {
+                    for wo in wos {
+                        let distribution = MicrofacetDistribution::new(model, alpha_x, alpha_y, sample_visible);
+                        let name = format!("{:?} visible {} alpha ({}, {}) wo {:?}", model, sample_visible, alpha_x, alpha_y, wo);
+                        assert_chi2(
+                            &name,
+                            &mut |u| Some(distribution.sample_wh(wo, u)),
+                            // sample_wh only draws the normals facing wo
+                            &|wh| if wh.z * wo.z > 0.0 { distribution.pdf(wo, wh) } else { 0.0 },
+                            200000,
+                            test_count,
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_microfacet_reflection() {
+        let models = [MicrofacetModel::TrowbridgeReitz, MicrofacetModel::Beckmann];
+        let alphas = [(0.1, 0.1), (0.5, 0.5), (0.15, 0.6)];
+        let wos = [Vector3::new(0.2, 0.1, 0.9).normalize(), Vector3::new(-0.6, -0.5, -0.4).normalize()];
+        let test_count = models.len() * 2 * alphas.len() * wos.len();
+
+        for model in models {
+            for sample_visible in [true, false] {
+                for (alpha_x, alpha_y) in alphas {
+                    let distribution = MicrofacetDistribution::new(model, alpha_x, alpha_y, sample_visible);
+                    let bxdf = MicrofacetReflection::new(distribution, Box::new(FresnelNoOp::new()), Spectrum::new(1.0, 1.0, 1.0));
+
+                    for wo in wos {
+                        let name = format!("reflection {:?} visible {} alpha ({}, {}) wo {:?}", model, sample_visible, alpha_x, alpha_y, wo);
+                        assert_sample_f_matches_pdf(&name, &bxdf, wo, 100000, test_count);
+
+                        // a white furnace, the single scattering loses the light masked by the microfacets
+                        let a = albedo(&bxdf, wo, 50000, &|_| 1.0);
+                        assert!(a.g < 1.01, "{}: albedo {}", name, a.g);
+                        assert!(a.g > if alpha_x.max(alpha_y) < 0.2 { 0.95 } else { 0.5 }, "{}: albedo {}", name, a.g);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_rough_dielectric() {
+        let models = [MicrofacetModel::TrowbridgeReitz, MicrofacetModel::Beckmann];
+        let wos = [Vector3::new(0.3, 0.2, 0.9).normalize(), Vector3::new(0.5, -0.1, -0.6).normalize(), Vector3::new(0.8, 0.1, 0.2).normalize()];
+        let test_count = models.len() * wos.len();
+        let white = Spectrum::new(1.0, 1.0, 1.0);
+
+        for model in models {
+            let distribution = MicrofacetDistribution::new(model, 0.3, 0.3, true);
+            let reflection = MicrofacetReflection::new(distribution, Box::new(FresnelDielectric::new(1.0, 1.5)), white);
+            let transmission = MicrofacetTransmission::new(distribution, white, 1.0, 1.5);
+
+            for wo in wos {
+                let name = format!("transmission {:?} wo {:?}", model, wo);
+                assert_sample_f_matches_pdf(&name, &transmission, wo, 200000, test_count);
+
+                // without the eta^2 the radiance gains or loses across the interface, the light is
+                // reflected or refracted but not created
+                let eta = if wo.z > 0.0 { 1.5 } else { 1.0 / 1.5 };
+                let a = albedo(&reflection, wo, 100000, &|_| 1.0) + albedo(&transmission, wo, 100000, &|_| eta * eta);
+                assert!(a.g < 1.01, "{}: albedo {}", name, a.g);
+                assert!(a.g > 0.7, "{}: albedo {}", name, a.g);
+            }
+        }
+    }
+}
@@ -3,7 +3,13 @@ pub mod bsdf;
 pub mod fresnel;
 pub mod microfacet;
 pub mod perfect_specular;
-// pub mod fresnel_blend;
+pub mod principled;
+pub mod layered;
+#[cfg(test)]
+pub mod chi2;
+#[cfg(test)]
+pub mod validation;
+pub mod fresnel_blend;
 
 use std::{ops::{BitOr, BitAnd}, f64::consts::PI};
 
@@ -19,7 +25,7 @@ pub struct BxdfSample {
     pub is_delta: bool
 }
 
-pub trait Bxdf {
+pub trait Bxdf: Send + Sync {
     fn f(&self, wo: Vector3<f64>, wi: Vector3<f64>) -> Spectrum;
     fn sample_f(&self, wo: Vector3<f64>, sample: Point2<f64>) -> BxdfSample {
         // // here we will use uniform sampling
@@ -37,9 +43,10 @@ pub trait Bxdf {
 
         let mut wi = p.to_vec();
 
-        // if the bxdf is transmissive
-        if (self.types() & BxdfType::Reflection) == 0 {
-            wi = -wi;
+        // a reflection stays on the side of wo, a transmission crosses to the other one
+        let reflective = (self.types() & BxdfType::Reflection) != 0;
+        if (wo.z < 0.0) == reflective {
+            wi.z = -wi.z;
         }
 
         if (self.types() & BxdfType::Reflection) != 0 && (self.types() & BxdfType::Transmission) != 0 {
@@ -55,8 +62,13 @@ pub trait Bxdf {
             is_delta: self.is_delta()
         }
     }
-    fn pdf(&self, _wo: Vector3<f64>, wi: Vector3<f64>) -> f64 {
-        INV_PI * cos_theta(wi)
+    fn pdf(&self, wo: Vector3<f64>, wi: Vector3<f64>) -> f64 {
+        let reflective = (self.types() & BxdfType::Reflection) != 0;
+        if (wo.z * wi.z > 0.0) == reflective {
+            INV_PI * cos_theta(wi)
+        } else {
+            0.0
+        }
     }
     fn types(&self) -> i32;
     fn is_delta(&self) -> bool;
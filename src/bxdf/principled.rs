@@ -0,0 +1,257 @@
+//! the lobes of the Disney principled bsdf, as given by Burley, the specular lobes are the
+//! microfacet ones driven by a PrincipledFresnel
+
+use std::f64::consts::PI;
+
+use cgmath::{InnerSpace, Point2, Vector3};
+
+use crate::{spectrum::Spectrum, utils::{cos_theta, reflect, spherical_direction}};
+
+use super::{Bxdf, BxdfSample, BxdfType, fresnel::{Fresnel, fr_dielectric}};
+
+const INV_PI: f64 = 1.0 / PI;
+
+fn schlick_weight(cos_theta: f64) -> f64 {
+    let m = (1.0 - cos_theta).clamp(0.0, 1.0);
+    (m * m) * (m * m) * m
+}
+
+fn fr_schlick(r0: Spectrum, cos_theta: f64) -> Spectrum {
+    r0 + (Spectrum::new(1.0, 1.0, 1.0) - r0) * schlick_weight(cos_theta)
+}
+
+/// the reflectance at normal incidence of a dielectric of relative index eta
+pub fn schlick_r0_from_eta(eta: f64) -> f64 {
+    ((eta - 1.0) / (eta + 1.0)).powi(2)
+}
+
+/// the diffuse lobe, darkened at grazing angles where the specular reflection takes the light
+pub struct PrincipledDiffuse {
+    reflectance: Spectrum,
+}
+
+impl PrincipledDiffuse {
+    pub fn new(reflectance: Spectrum) -> Self {
+        Self { reflectance }
+    }
+}
+
+impl Bxdf for PrincipledDiffuse {
+    fn f(&self, wo: Vector3<f64>, wi: Vector3<f64>) -> Spectrum {
+        let (fo, fi) = (schlick_weight(cos_theta(wo)), schlick_weight(cos_theta(wi)));
+        self.reflectance * (INV_PI * (1.0 - fo / 2.0) * (1.0 - fi / 2.0))
+    }
+
+    fn types(&self) -> i32 {
+        BxdfType::Reflection | BxdfType::Diffuse
+    }
+
+    fn is_delta(&self) -> bool {
+        false
+    }
+}
+
+/// the retro-reflection of the rough diffuse surfaces
+pub struct PrincipledRetro {
+    reflectance: Spectrum,
+    roughness: f64,
+}
+
+impl PrincipledRetro {
+    pub fn new(reflectance: Spectrum, roughness: f64) -> Self {
+        Self { reflectance, roughness }
+    }
+}
+
+impl Bxdf for PrincipledRetro {
+    fn f(&self, wo: Vector3<f64>, wi: Vector3<f64>) -> Spectrum {
+        let wh = wi + wo;
+        if wh.magnitude2() == 0.0 {
+            return Spectrum::black();
+        }
+        let cos_theta_d = wi.dot(wh.normalize());
+
+        let (fo, fi) = (schlick_weight(cos_theta(wo)), schlick_weight(cos_theta(wi)));
+        let rr = 2.0 * self.roughness * cos_theta_d * cos_theta_d;
+        self.reflectance * (INV_PI * rr * (fo + fi + fo * fi * (rr - 1.0)))
+    }
+
+    fn types(&self) -> i32 {
+        BxdfType::Reflection | BxdfType::Diffuse
+    }
+
+    fn is_delta(&self) -> bool {
+        false
+    }
+}
+
+/// the grazing reflection of cloth like surfaces
+pub struct PrincipledSheen {
+    reflectance: Spectrum,
+}
+
+impl PrincipledSheen {
+    pub fn new(reflectance: Spectrum) -> Self {
+        Self { reflectance }
+    }
+}
+
+impl Bxdf for PrincipledSheen {
+    fn f(&self, wo: Vector3<f64>, wi: Vector3<f64>) -> Spectrum {
+        let wh = wi + wo;
+        if wh.magnitude2() == 0.0 {
+            return Spectrum::black();
+        }
+        let cos_theta_d = wi.dot(wh.normalize());
+
+        self.reflectance * schlick_weight(cos_theta_d)
+    }
+
+    fn types(&self) -> i32 {
+        BxdfType::Reflection | BxdfType::Diffuse
+    }
+
+    fn is_delta(&self) -> bool {
+        false
+    }
+}
+
+// the generalized Trowbridge-Reitz distribution of exponent 1, whose long tail gives the clearcoat
+// its haze
+fn gtr1(cos_theta: f64, alpha: f64) -> f64 {
+    let alpha2 = alpha * alpha;
+    (alpha2 - 1.0) / (PI * alpha2.ln() * (1.0 + (alpha2 - 1.0) * cos_theta * cos_theta))
+}
+
+fn smith_g_ggx(cos_theta: f64, alpha: f64) -> f64 {
+    let (alpha2, cos2) = (alpha * alpha, cos_theta * cos_theta);
+    1.0 / (cos_theta + (alpha2 + cos2 - alpha2 * cos2).sqrt())
+}
+
+/// a clear, colorless coat of polyurethane over the surface, with a fixed index of 1.5
+pub struct PrincipledClearcoat {
+    weight: f64,
+    gloss: f64, // the alpha of the GTR1 distribution
+}
+
+impl PrincipledClearcoat {
+    pub fn new(weight: f64, gloss: f64) -> Self {
+        Self { weight, gloss }
+    }
+}
+
+impl Bxdf for PrincipledClearcoat {
+    fn f(&self, wo: Vector3<f64>, wi: Vector3<f64>) -> Spectrum {
+        let wh = wi + wo;
+        if wh.magnitude2() == 0.0 {
+            return Spectrum::black();
+        }
+        let wh = wh.normalize();
+
+        let dr = gtr1(cos_theta(wh), self.gloss);
+        let fr = 0.04 + 0.96 * schlick_weight(wo.dot(wh).abs());
+        let gr = smith_g_ggx(cos_theta(wo), 0.25) * smith_g_ggx(cos_theta(wi), 0.25);
+
+        let value = self.weight * gr * fr * dr / 4.0;
+        Spectrum::new(value, value, value)
+    }
+
+    fn sample_f(&self, wo: Vector3<f64>, sample: Point2<f64>) -> BxdfSample {
+        let blocked = BxdfSample { rho: Spectrum::black(), wi: -wo, pdf: 0.0, is_delta: false };
+        if wo.z == 0.0 {
+            return blocked;
+        }
+
+        // invert the cdf of gtr1 cos(theta_h)
+        let alpha2 = self.gloss * self.gloss;
+        let cos_theta = ((1.0 - alpha2.powf(1.0 - sample.x)) / (1.0 - alpha2)).max(0.0).sqrt();
+        let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+        let mut wh = spherical_direction(sin_theta, cos_theta, 2.0 * PI * sample.y);
+        if wo.z < 0.0 {
+            wh = -wh;
+        }
+
+        let wi = reflect(wo, wh);
+        if wo.z * wi.z <= 0.0 {
+            return blocked;
+        }
+
+        BxdfSample {
+            rho: self.f(wo, wi),
+            wi,
+            pdf: self.pdf(wo, wi),
+            is_delta: false,
+        }
+    }
+
+    fn pdf(&self, wo: Vector3<f64>, wi: Vector3<f64>) -> f64 {
+        if wo.z * wi.z <= 0.0 {
+            return 0.0;
+        }
+
+        let wh = (wi + wo).normalize();
+        gtr1(cos_theta(wh), self.gloss) * cos_theta(wh) / (4.0 * wo.dot(wh))
+    }
+
+    fn types(&self) -> i32 {
+        BxdfType::Reflection | BxdfType::Glossy
+    }
+
+    fn is_delta(&self) -> bool {
+        false
+    }
+}
+
+/// the reflectance of the specular lobe, a dielectric turning into the tinted Schlick reflectance
+/// r0 of a metal as metallic goes to 1
+pub struct PrincipledFresnel {
+    r0: Spectrum,
+    metallic: f64,
+    eta: f64,
+}
+
+impl PrincipledFresnel {
+    pub fn new(r0: Spectrum, metallic: f64, eta: f64) -> Self {
+        Self { r0, metallic, eta }
+    }
+}
+
+impl Fresnel for PrincipledFresnel {
+    fn evaluate(&self, cos_theta_i: f64) -> Spectrum {
+        let (dielectric, _, _) = fr_dielectric(cos_theta_i.clamp(-1.0, 1.0), 1.0, self.eta);
+        let dielectric = Spectrum::new(dielectric, dielectric, dielectric);
+        dielectric * (1.0 - self.metallic) + fr_schlick(self.r0, cos_theta_i.abs()) * self.metallic
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::bxdf::validation::{albedo, assert_sample_f_matches_pdf};
+
+    use super::*;
+
+    #[test]
+    fn test_principled_lobes() {
+        let wos = [Vector3::new(0.0, 0.0, 1.0), Vector3::new(0.6, 0.2, 0.4).normalize(), Vector3::new(-0.3, 0.3, -0.8).normalize()];
+        let glosses = [0.1, 0.01];
+        let test_count = wos.len() * (1 + glosses.len());
+
+        for wo in wos {
+            // the diffuse lobes share the cosine sampling of the bxdfs
+            let diffuse = PrincipledDiffuse::new(Spectrum::new(1.0, 1.0, 1.0));
+            assert_sample_f_matches_pdf(&format!("diffuse wo {:?}", wo), &diffuse, wo, 100000, test_count);
+            // the grazing darkening only loses light
+            let a = albedo(&diffuse, wo, 100000, &|_| 1.0);
+            assert!(a.g < 1.0 && a.g > 0.5);
+
+            for gloss in glosses {
+                let clearcoat = PrincipledClearcoat::new(1.0, gloss);
+                let name = format!("clearcoat gloss {} wo {:?}", gloss, wo);
+                assert_sample_f_matches_pdf(&name, &clearcoat, wo, 100000, test_count);
+                // the coat reflects a few percent of the light, scaled by a quarter as burley does
+                let a = albedo(&clearcoat, wo, 100000, &|_| 1.0);
+                assert!(a.g > 0.005 && a.g < 0.25, "{}: albedo {}", name, a.g);
+            }
+        }
+    }
+}
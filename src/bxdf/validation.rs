@@ -0,0 +1,52 @@
+//! the statistical checks the tests of the bxdfs share, the sampled directions against the pdf and
+//! the reflected energy against the white furnace
+
+use cgmath::{Point2, Vector3};
+
+use crate::{sampler::rng::Rng, spectrum::Spectrum};
+
+use super::{Bxdf, chi2::assert_chi2};
+
+fn close(a: f64, b: f64) -> bool {
+    (a - b).abs() <= 1e-6 * a.abs().max(b.abs()).max(1.0)
+}
+
+/// the chi-square test of the directions drawn by sample_f against pdf, every sample must also
+/// carry the values pdf and f give for its direction, the failed samples have a zero pdf
+pub fn assert_sample_f_matches_pdf(name: &str, bxdf: &dyn Bxdf, wo: Vector3<f64>, sample_count: usize, test_count: usize) {
+    assert!(!bxdf.is_delta(), "{}: a delta bxdf has no pdf to test against", name);
+
+    let mut sample = |u: Point2<f64>| {
+        let sample = bxdf.sample_f(wo, u);
+        if sample.pdf == 0.0 {
+            return None;
+        }
+
+        let (pdf, f) = (bxdf.pdf(wo, sample.wi), bxdf.f(wo, sample.wi));
+        assert!(close(sample.pdf, pdf), "{}: sampled the pdf {} of {:?}, whose pdf is {}", name, sample.pdf, sample.wi, pdf);
+        assert!(
+            close(sample.rho.r, f.r) && close(sample.rho.g, f.g) && close(sample.rho.b, f.b),
+            "{}: sampled the value {:?} of {:?}, whose f is {:?}", name, sample.rho, sample.wi, f
+        );
+        Some(sample.wi)
+    };
+    assert_chi2(name, &mut sample, &|wi| bxdf.pdf(wo, wi), sample_count, test_count);
+}
+
+/// the fraction of the light arriving from every direction that the bxdf scatters towards wo, the
+/// integral of f |cos| over wi estimated with sample_f, which is at most 1 in a white furnace
+///
+/// scale weighs each sampled direction, such as the eta^2 a refracted radiance gains
+pub fn albedo(bxdf: &dyn Bxdf, wo: Vector3<f64>, sample_count: usize, scale: &dyn Fn(Vector3<f64>) -> f64) -> Spectrum {
+    let mut rng = Rng::new();
+    rng.set_sequence(1, 0);
+
+    let mut sum = Spectrum::black();
+    for _ in 0..sample_count {
+        let sample = bxdf.sample_f(wo, Point2::new(rng.uniform_f64(), rng.uniform_f64()));
+        if sample.pdf > 0.0 {
+            sum += sample.rho * (sample.wi.z.abs() * scale(sample.wi) / sample.pdf);
+        }
+    }
+    sum / sample_count as f64
+}
@@ -0,0 +1,274 @@
+use cgmath::{InnerSpace, Point2, Point3, Vector3};
+
+use crate::{
+    spectrum::Spectrum,
+    geometry::ray::Ray,
+    scene::Scene,
+    primitive::ObjectIds,
+};
+
+// the bsdf samples estimating the albedo of the first hit, on a fixed grid so that it is noiseless
+const ALBEDO_SAMPLES: usize = 4;
+
+/// the auxiliary outputs of the film, for denoising and compositing
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum AovKind {
+    Albedo,
+    Normal,
+    Depth,
+    Position,
+    PrimitiveId,
+    MaterialId,
+    Direct,
+    Indirect,
+}
+
+impl AovKind {
+    pub fn from_name(name: &str) -> Option<Self> {
+        let kind = match name {
+            "albedo" => Self::Albedo,
+            "normal" => Self::Normal,
+            "depth" => Self::Depth,
+            "position" => Self::Position,
+            "primitive_id" => Self::PrimitiveId,
+            "material_id" => Self::MaterialId,
+            "direct" => Self::Direct,
+            "indirect" => Self::Indirect,
+            _ => return None,
+        };
+        Some(kind)
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Albedo => "albedo",
+            Self::Normal => "normal",
+            Self::Depth => "depth",
+            Self::Position => "position",
+            Self::PrimitiveId => "primitive_id",
+            Self::MaterialId => "material_id",
+            Self::Direct => "direct",
+            Self::Indirect => "indirect",
+        }
+    }
+}
+
+/// the auxiliary outputs of a pixel, or of a single camera ray
+///
+/// all but direct and indirect describe the first surface hit that is not only the boundary of a medium,
+/// direct is the emission seen by the camera and the light reaching that surface straight from the lights
+///
+/// the albedo, the normal and the light are averaged over the samples of a pixel, the depth, position and
+/// ids are the ones of its first sample, as their means would not belong to any surface
+#[derive(Clone, Copy, Debug)]
+pub struct Aov {
+    pub albedo: Spectrum,
+    pub normal: Vector3<f64>, // the shading normal
+    pub depth: f64, // the distance from the camera, infinite if nothing is hit
+    pub position: Point3<f64>,
+    pub ids: ObjectIds,
+    pub direct: Spectrum,
+    pub indirect: Spectrum,
+}
+
+impl Default for Aov {
+    fn default() -> Self {
+        Aov {
+            albedo: Spectrum::black(),
+            normal: Vector3::new(0.0, 0.0, 0.0),
+            depth: f64::INFINITY,
+            position: Point3::new(0.0, 0.0, 0.0),
+            ids: ObjectIds::default(),
+            direct: Spectrum::black(),
+            indirect: Spectrum::black(),
+        }
+    }
+}
+
+impl Aov {
+    /// the outputs of the first surface hit by the ray, which is left untouched
+    pub fn first_hit(ray: &Ray, scene: &Scene) -> Self {
+        let mut r = Ray::new_in_medium(ray.o, ray.d, ray.time, ray.t_max, ray.medium.clone());
+
+        loop {
+            let isect = match scene.intersect(&mut r) {
+                Some(isect) => isect,
+                None => return Aov::default(),
+            };
+
+            let mut aov = Aov {
+                normal: isect.geo.n,
+                depth: (isect.geo.p - ray.o).magnitude(),
+                position: isect.geo.p,
+                ids: isect.ids,
+                ..Aov::default()
+            };
+            if isect.hit_light {
+                return aov;
+            }
+
+            match &isect.material {
+                Some(material) => {
+                    let bsdf = material.compute_scattering(&isect);
+                    let wo = -r.d.normalize();
+
+                    // the directional albedo toward the camera, by sampling the bsdf on a stratified grid
+                    for i in 0..ALBEDO_SAMPLES {
+                        for j in 0..ALBEDO_SAMPLES {
+                            let u = Point2::new((i as f64 + 0.5) / ALBEDO_SAMPLES as f64, (j as f64 + 0.5) / ALBEDO_SAMPLES as f64);
+                            let sample = bsdf.sample_f(wo, u);
+                            if sample.pdf > 0.0 {
+                                aov.albedo += sample.rho * sample.wi.dot(bsdf.ns).abs() / sample.pdf;
+                            }
+                        }
+                    }
+                    aov.albedo /= (ALBEDO_SAMPLES * ALBEDO_SAMPLES) as f64;
+                    aov.normal = bsdf.ns;
+
+                    return aov;
+                },
+                None => {
+                    // the surface only bounds a medium
+                    let medium = isect.get_medium(r.d, &r.medium);
+                    r = Ray::new_in_medium(isect.geo.p, r.d, r.time, f64::INFINITY, medium);
+                }
+            }
+        }
+    }
+
+    /// add the averaged outputs of another sample of the pixel
+    pub fn accumulate(&mut self, other: &Aov) {
+        self.albedo += other.albedo;
+        self.normal += other.normal;
+        self.direct += other.direct;
+        self.indirect += other.indirect;
+    }
+
+    /// divide the averaged outputs by the number of samples accumulated
+    pub fn average(&mut self, n_sample: usize) {
+        let n = n_sample as f64;
+        self.albedo /= n;
+        self.normal /= n;
+        self.direct /= n;
+        self.indirect /= n;
+    }
+
+    /// the value of an output, as many channels as it has
+    pub fn channels(&self, kind: AovKind) -> Vec<f64> {
+        match kind {
+            AovKind::Albedo => vec![self.albedo.r, self.albedo.g, self.albedo.b],
+            AovKind::Normal => vec![self.normal.x, self.normal.y, self.normal.z],
+            AovKind::Depth => vec![self.depth],
+            AovKind::Position => vec![self.position.x, self.position.y, self.position.z],
+            AovKind::PrimitiveId => vec![self.ids.primitive as f64],
+            AovKind::MaterialId => vec![self.ids.material as f64],
+            AovKind::Direct => vec![self.direct.r, self.direct.g, self.direct.b],
+            AovKind::Indirect => vec![self.indirect.r, self.indirect.g, self.indirect.b],
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, sync::Arc};
+
+    use cgmath::Vector3;
+
+    use super::*;
+    use crate::{
+        render, WorldSetting,
+        camera::{film::Film, perspective::PerspectiveCamera, tonemap::ToneMapper},
+        integrator::{
+            Integrator, path_integrator::PathIntegrator, volpath_integrator::VolPathIntegrator, bdpt_integrator::BdptIntegrator,
+            test_scene::{TestScene, matte, camera_with_film, lit_sphere, WIDTH, HEIGHT},
+        },
+        sampler::uniform_sampler::UniformSampler,
+    };
+
+    #[test]
+    fn test_aovs() {
+        let kinds = vec![AovKind::Albedo, AovKind::Normal, AovKind::Depth, AovKind::PrimitiveId, AovKind::Direct, AovKind::Indirect];
+        let path = env::temp_dir().join("rbrt_test_aovs.exr");
+        let film = Film::new(path.to_str().unwrap(), WIDTH, HEIGHT, ToneMapper::default()).with_aovs(kinds.clone());
+        let camera = camera_with_film(film, None);
+
+        let sampler = Box::new(UniformSampler::new(16, 3));
+        let integrator: Arc<Box<dyn Integrator>> = Arc::new(Box::new(PathIntegrator::new(5, true)));
+        let setting = WorldSetting::new(16, 2, integrator, sampler, 3);
+        render(setting.integrator.clone(), Arc::clone(&camera), Arc::new(lit_sphere()), &setting, false);
+
+        {
+            let radiance_map = camera.film.radiance_map.lock().unwrap();
+            let aov_map = camera.film.aov_map.lock().unwrap();
+
+            // the center of the image sees the front of the sphere
+            let center = aov_map[HEIGHT / 2 * WIDTH + WIDTH / 2];
+            assert!((center.depth - 4.0).abs() < 0.05, "depth {}", center.depth);
+            // the pixel lies half a pixel off the axis, a quarter of the radius at that distance
+            assert!(center.normal.z < -0.95, "normal {:?}", center.normal);
+            assert!((center.albedo.r - 0.5).abs() < 0.01, "albedo {:?}", center.albedo);
+            assert_eq!(center.ids, ObjectIds { primitive: 1, material: 1 });
+
+            // the corner sees nothing
+            assert!(aov_map[0].depth.is_infinite());
+            assert_eq!(aov_map[0].ids, ObjectIds::default());
+
+            // a lone sphere never lights itself, and the light is split without loss
+            assert!(aov_map.iter().any(|aov| !aov.direct.is_black()));
+            assert!(aov_map.iter().all(|aov| aov.indirect.is_black()));
+            for (radiance, aov) in radiance_map.iter().zip(aov_map.iter()) {
+                let sum = aov.direct + aov.indirect;
+                assert!((radiance.r - sum.r).abs() < 1e-9 && (radiance.g - sum.g).abs() < 1e-9 && (radiance.b - sum.b).abs() < 1e-9);
+            }
+        }
+
+        // every output is a layer of the exr
+        camera.film.write_to_image(1.0);
+        let image = exr::prelude::read_all_flat_layers_from_file(&path).unwrap();
+        let mut names: Vec<String> = image.layer_data.iter()
+            .map(|layer| layer.attributes.layer_name.as_ref().unwrap().to_string())
+            .collect();
+        std::fs::remove_file(&path).unwrap();
+
+        let mut expected: Vec<String> = kinds.iter().map(|kind| kind.name().to_string()).collect();
+        expected.push("radiance".to_string());
+        names.sort();
+        expected.sort();
+        assert_eq!(names, expected);
+    }
+
+    #[test]
+    fn test_split_light() {
+        // the sphere on the floor lights it and is lit by it, which only the indirect light holds
+        let split = |integrator: &dyn Fn(Arc<PerspectiveCamera>) -> Box<dyn Integrator>| {
+            let film = Film::new("unused.png", WIDTH, HEIGHT, ToneMapper::default()).with_aovs(vec![AovKind::Direct, AovKind::Indirect]);
+            let camera = camera_with_film(film, None);
+            let scene = TestScene::new()
+                .sphere(matte(0.8))
+                .floor(matte(0.8))
+                .disk_light(Vector3::new(0.0, 3.0, 5.0), 1.0, 4.0)
+                .build();
+
+            let sampler = Box::new(UniformSampler::new(4, 3));
+            let setting = WorldSetting::new(4, 2, Arc::new(integrator(Arc::clone(&camera))), sampler, 3);
+            render(setting.integrator.clone(), Arc::clone(&camera), Arc::new(scene), &setting, false);
+            (camera.film.pixels(0.25), camera.film.aovs(0.25))
+        };
+
+        let integrators: [&dyn Fn(Arc<PerspectiveCamera>) -> Box<dyn Integrator>; 3] = [
+            &|_| Box::new(PathIntegrator::new(5, true)),
+            &|_| Box::new(VolPathIntegrator::new(5)),
+            &|camera| Box::new(BdptIntegrator::new(5, camera)),
+        ];
+        for integrator in integrators {
+            // the splats of bdpt are split too, and nothing is lost
+            let (pixels, aovs) = split(integrator);
+            assert!(aovs.iter().any(|aov| !aov.direct.is_black()));
+            assert!(aovs.iter().any(|aov| !aov.indirect.is_black()));
+            for (radiance, aov) in pixels.iter().zip(aovs.iter()) {
+                let sum = aov.direct + aov.indirect;
+                assert!((radiance.r - sum.r).abs() < 1e-9 && (radiance.g - sum.g).abs() < 1e-9 && (radiance.b - sum.b).abs() < 1e-9);
+            }
+        }
+    }
+}
@@ -1,44 +1,213 @@
-use crate::spectrum::Spectrum;
+use crate::{spectrum::Spectrum, sampler::lowdiscrepancy::hash};
+
+use super::{tonemap::ToneMapper, aov::{Aov, AovKind}};
 
 use cgmath::Point2;
 
 use std::fs::File;
-use std::io::BufWriter;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::collections::BTreeMap;
 use std::sync::Mutex;
 
-use image::RgbImage;
+use image::codecs::hdr::HdrEncoder;
+use image::{Rgb, Rgb32FImage, RgbImage};
+use exr::prelude::{AnyChannel, AnyChannels, Encoding, FlatSamples, Image, ImageAttributes, IntegerBounds, Layer, LayerAttributes, SmallVec, WritableImage};
+
+/// radiance added to the film at a raster position
+#[derive(Clone, Copy, Debug)]
+pub struct Splat {
+    pub p_raster: Point2<f64>,
+    pub radiance: Spectrum,
+    pub direct: bool, // whether it is direct lighting, for the auxiliary outputs
+}
 
 pub struct Film {
     pub filename: String,
     pub resolution: Point2<usize>,
     pub radiance_map: Mutex<Vec<Spectrum>>,
+    pub splat_map: Mutex<Vec<Spectrum>>, // the radiance added from anywhere on the film, such as by light tracing
+    direct_splat_map: Mutex<Vec<Spectrum>>, // the part of the splats that is direct lighting
+    pending_splats: Mutex<(usize, BTreeMap<usize, Vec<Splat>>)>, // the next tile to splat, and the tiles finished before it
+    pub tone_mapper: ToneMapper, // only used when writing low dynamic range images
+    pub aovs: Vec<AovKind>, // the auxiliary outputs written with the radiance
+    pub aov_map: Mutex<Vec<Aov>>, // empty without auxiliary outputs
 }
 
 impl Film {
-    pub fn new(path: &str, width: usize, height: usize) -> Self {
+    pub fn new(path: &str, width: usize, height: usize, tone_mapper: ToneMapper) -> Self {
         let radiance_map = vec![Spectrum::new(0.0, 0.0, 0.0); width * height];
         Film {
             filename: String::from(path),
             resolution: Point2::new(width, height),
+            splat_map: Mutex::new(radiance_map.clone()),
+            direct_splat_map: Mutex::new(radiance_map.clone()),
+            pending_splats: Mutex::new((0, BTreeMap::new())),
             radiance_map: Mutex::new(radiance_map),
+            tone_mapper,
+            aovs: Vec::new(),
+            aov_map: Mutex::new(Vec::new()),
         }
     }
 
-    pub fn record(&self, i: usize, j: usize, radiance: Spectrum) {
+    /// record the auxiliary outputs, which are written as layers of the image for .exr and else as images
+    /// of their own, named after the output
+    pub fn with_aovs(mut self, aovs: Vec<AovKind>) -> Self {
+        let n_pixels = if aovs.is_empty() { 0 } else { self.resolution.x * self.resolution.y };
+        self.aovs = aovs;
+        self.aov_map = Mutex::new(vec![Aov::default(); n_pixels]);
+        self
+    }
+
+    /// split the film into tiles of at most tile_size x tile_size pixels,
+    /// ordered from the upper left corner rightwards and downwards
+    pub fn tiles(&self, tile_size: usize) -> Vec<FilmTile> {
+        let tile_size = tile_size.max(1);
+        let mut tiles = Vec::new();
+
+        for y0 in (0..self.resolution.y).step_by(tile_size) {
+            for x0 in (0..self.resolution.x).step_by(tile_size) {
+                let x1 = (x0 + tile_size).min(self.resolution.x);
+                let y1 = (y0 + tile_size).min(self.resolution.y);
+                tiles.push(FilmTile::new(tiles.len(), Point2::new(x0, y0), Point2::new(x1, y1)));
+            }
+        }
+
+        tiles
+    }
+
+    /// add the radiance of a finished tile to the film, taking the lock only once per tile
+    pub fn merge_tile(&self, tile: FilmTile) {
         let mut radiance_map = self.radiance_map.lock().unwrap();
-        radiance_map[i * self.resolution.x + j] += radiance;
+
+        for i in tile.p_min.y..tile.p_max.y {
+            for j in tile.p_min.x..tile.p_max.x {
+                radiance_map[i * self.resolution.x + j] += tile.radiance_map[tile.offset(i, j)];
+            }
+        }
+        drop(radiance_map);
+
+        // the tiles only hold the auxiliary outputs the film asks for
+        if !tile.aov_map.is_empty() {
+            let mut aov_map = self.aov_map.lock().unwrap();
+            for i in tile.p_min.y..tile.p_max.y {
+                for j in tile.p_min.x..tile.p_max.x {
+                    aov_map[i * self.resolution.x + j] = tile.aov_map[tile.offset(i, j)];
+                }
+            }
+        }
+
+        // the splats of the tiles are added in their order, so that the image does not depend on the threads
+        let mut pending = self.pending_splats.lock().unwrap();
+        let (next, finished) = &mut *pending;
+        finished.insert(tile.index, tile.splats);
+        while let Some(splats) = finished.remove(next) {
+            for splat in splats {
+                self.add_splat(splat.p_raster, splat.radiance);
+                if splat.direct {
+                    add_to_pixel(&self.direct_splat_map, self.resolution, splat.p_raster, splat.radiance);
+                }
+            }
+            *next += 1;
+        }
+    }
+
+    /// add radiance to the pixel containing the raster position, it is shared between threads
+    /// so it should only be used for the contributions that do not belong to the pixel being rendered
+    pub fn add_splat(&self, p_raster: Point2<f64>, radiance: Spectrum) {
+        add_to_pixel(&self.splat_map, self.resolution, p_raster, radiance);
     }
 
-    pub fn write_to_image(&self) {
+    /// the final radiance of every pixel, the splats are scaled by splat_scale
+    /// (usually the inverse of the samples per pixel)
+    pub fn pixels(&self, splat_scale: f64) -> Vec<Spectrum> {
+        let radiance_map = self.radiance_map.lock().unwrap();
+        let splat_map = self.splat_map.lock().unwrap();
+
+        radiance_map.iter().zip(splat_map.iter()).map(|(&radiance, &splat)| radiance + splat * splat_scale).collect()
+    }
+
+    /// the auxiliary outputs of every pixel, with the splats scaled by splat_scale added to its direct and indirect light
+    pub fn aovs(&self, splat_scale: f64) -> Vec<Aov> {
+        let aov_map = self.aov_map.lock().unwrap();
+        let splat_map = self.splat_map.lock().unwrap();
+        let direct_splat_map = self.direct_splat_map.lock().unwrap();
+
+        aov_map.iter().zip(splat_map.iter().zip(direct_splat_map.iter())).map(|(&aov, (&splat, &direct))| Aov {
+            direct: aov.direct + direct * splat_scale,
+            indirect: aov.indirect + (splat - direct) * splat_scale,
+            ..aov
+        }).collect()
+    }
+
+    /// write the film to disk, the format is chosen by the extension of the filename.
+    ///
+    /// .exr, .hdr and .pfm keep the linear radiance values, any other extension is written
+    /// as a tone mapped 8-bit png
+    pub fn write_to_image(&self, splat_scale: f64) {
+        let extension = Path::new(&self.filename)
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .map(|ext| ext.to_lowercase());
+
+        let radiance_map = self.pixels(splat_scale);
+        if !self.aovs.is_empty() && extension.as_deref() == Some("exr") {
+            self.write_exr_layers(&radiance_map, &self.aovs(splat_scale));
+            return;
+        }
+        self.write(&self.filename, extension.as_deref(), &radiance_map, true);
+
+        // every auxiliary output next to the image, in the same format
+        let aov_map = self.aovs(splat_scale);
+        for &kind in &self.aovs {
+            let values: Vec<Spectrum> = aov_map.iter().map(|aov| {
+                let channels = aov.channels(kind);
+                match channels[..] {
+                    [v] => Spectrum::new(v, v, v),
+                    _ => Spectrum::new(channels[0], channels[1], channels[2]),
+                }
+            }).collect();
+            let path = self.aov_path(kind);
+
+            match extension.as_deref() {
+                Some("exr") | Some("hdr") | Some("pfm") => self.write(&path, extension.as_deref(), &values, false),
+                // the radiance is tone mapped, the other outputs are brought into [0, 1]
+                _ if matches!(kind, AovKind::Direct | AovKind::Indirect) => self.write_png(&path, &values, true),
+                _ => self.write_png(&path, &displayable(kind, &values), false),
+            }
+        }
+    }
+
+    // the file of an auxiliary output, the name of the output is appended to the one of the image
+    fn aov_path(&self, kind: AovKind) -> String {
+        let path = Path::new(&self.filename);
+        let stem = path.file_stem().and_then(|stem| stem.to_str()).unwrap_or("");
+        let name = match path.extension().and_then(|ext| ext.to_str()) {
+            Some(ext) => format!("{}_{}.{}", stem, kind.name(), ext),
+            None => format!("{}_{}", stem, kind.name()),
+        };
+        path.with_file_name(name).to_string_lossy().into_owned()
+    }
+
+    fn write(&self, path: &str, extension: Option<&str>, map: &[Spectrum], tone_map: bool) {
+        match extension {
+            Some("exr") => self.write_exr(path, map),
+            Some("hdr") => self.write_hdr(path, map),
+            Some("pfm") => self.write_pfm(path, map),
+            _ => self.write_png(path, map, tone_map),
+        }
+    }
+
+    fn write_png(&self, path: &str, radiance_map: &[Spectrum], tone_map: bool) {
         let mut image = RgbImage::new(self.resolution.x as u32, self.resolution.y as u32);
-        let file = File::create(&self.filename).unwrap();
+        let file = File::create(path).unwrap();
         let mut writer = BufWriter::new(file);
-        
-        let radiance_map = self.radiance_map.lock().unwrap();
 
         for i in 0..self.resolution.y {
             for j in 0..self.resolution.x {
-                let pixel = radiance_map[i * self.resolution.x + j].to_pixel();
+                // tone mapping is applied only when the radiance is quantized
+                let radiance = radiance_map[i * self.resolution.x + j];
+                let pixel = if tone_map { self.tone_mapper.map(radiance) } else { radiance }.to_pixel();
                 let rgb = pixel.to_rgb();
                 image.put_pixel(j as u32, i as u32, rgb);
             }
@@ -46,4 +215,254 @@ impl Film {
 
         image.write_to(&mut writer, image::ImageOutputFormat::Png).unwrap();
     }
+
+    fn write_exr(&self, path: &str, radiance_map: &[Spectrum]) {
+        let mut image = Rgb32FImage::new(self.resolution.x as u32, self.resolution.y as u32);
+        let file = File::create(path).unwrap();
+        let mut writer = BufWriter::new(file);
+
+        for i in 0..self.resolution.y {
+            for j in 0..self.resolution.x {
+                let radiance = radiance_map[i * self.resolution.x + j];
+                image.put_pixel(j as u32, i as u32, Rgb([radiance.r as f32, radiance.g as f32, radiance.b as f32]));
+            }
+        }
+
+        image.write_to(&mut writer, image::ImageOutputFormat::OpenExr).unwrap();
+    }
+
+    fn write_hdr(&self, path: &str, radiance_map: &[Spectrum]) {
+        let file = File::create(path).unwrap();
+        let writer = BufWriter::new(file);
+
+        // the rgbe encoding can not represent negative values
+        let data: Vec<Rgb<f32>> = radiance_map
+            .iter()
+            .map(|radiance| Rgb([radiance.r.max(0.0) as f32, radiance.g.max(0.0) as f32, radiance.b.max(0.0) as f32]))
+            .collect();
+
+        HdrEncoder::new(writer)
+            .encode(&data, self.resolution.x, self.resolution.y)
+            .unwrap();
+    }
+
+    fn write_pfm(&self, path: &str, radiance_map: &[Spectrum]) {
+        let file = File::create(path).unwrap();
+        let mut writer = BufWriter::new(file);
+
+        // a negative scale marks the data as little endian
+        write!(writer, "PF\n{} {}\n-1.0\n", self.resolution.x, self.resolution.y).unwrap();
+
+        // pfm stores the scanlines from bottom to top
+        for i in (0..self.resolution.y).rev() {
+            for j in 0..self.resolution.x {
+                let radiance = radiance_map[i * self.resolution.x + j];
+                for value in [radiance.r, radiance.g, radiance.b] {
+                    writer.write_all(&(value as f32).to_le_bytes()).unwrap();
+                }
+            }
+        }
+
+        writer.flush().unwrap();
+    }
+
+    // the radiance and every auxiliary output as the layers of one exr
+    fn write_exr_layers(&self, radiance_map: &[Spectrum], aov_map: &[Aov]) {
+        let size = (self.resolution.x, self.resolution.y);
+        let rgb = |name: &str, values: Vec<[f64; 3]>| {
+            let channels = ["R", "G", "B"].iter().enumerate()
+                .map(|(c, channel)| AnyChannel::new(*channel, FlatSamples::F32(values.iter().map(|v| v[c] as f32).collect())))
+                .collect();
+            Layer::new(size, LayerAttributes::named(name), Encoding::FAST_LOSSLESS, AnyChannels::sort(channels))
+        };
+
+        let mut layers: SmallVec<[_; 2]> = SmallVec::new();
+        layers.push(rgb("radiance", radiance_map.iter().map(|s| [s.r, s.g, s.b]).collect()));
+
+        for &kind in &self.aovs {
+            let layer = match kind {
+                // the usual channels of the compositing software
+                AovKind::Depth => {
+                    let depth = AnyChannel::new("Z", FlatSamples::F32(aov_map.iter().map(|aov| aov.depth as f32).collect()));
+                    Layer::new(size, LayerAttributes::named(kind.name()), Encoding::FAST_LOSSLESS, AnyChannels::sort(SmallVec::from_elem(depth, 1)))
+                },
+                AovKind::PrimitiveId | AovKind::MaterialId => {
+                    let ids = aov_map.iter().map(|aov| if kind == AovKind::PrimitiveId { aov.ids.primitive } else { aov.ids.material }).collect();
+                    let id = AnyChannel::new("id", FlatSamples::U32(ids));
+                    Layer::new(size, LayerAttributes::named(kind.name()), Encoding::FAST_LOSSLESS, AnyChannels::sort(SmallVec::from_elem(id, 1)))
+                },
+                _ => rgb(kind.name(), aov_map.iter().map(|aov| {
+                    let channels = aov.channels(kind);
+                    [channels[0], channels[1], channels[2]]
+                }).collect()),
+            };
+            layers.push(layer);
+        }
+
+        let attributes = ImageAttributes::new(IntegerBounds::from_dimensions(size));
+        Image::from_layers(attributes, layers).write().to_file(&self.filename).unwrap();
+    }
+}
+
+// add radiance to the pixel of a map containing the raster position, nothing if it is off the film
+fn add_to_pixel(map: &Mutex<Vec<Spectrum>>, resolution: Point2<usize>, p_raster: Point2<f64>, radiance: Spectrum) {
+    let (x, y) = (p_raster.x as usize, p_raster.y as usize);
+    if x >= resolution.x || y >= resolution.y {
+        return;
+    }
+    map.lock().unwrap()[y * resolution.x + x] += radiance;
+}
+
+// bring an auxiliary output into [0, 1] for an 8-bit image, the positions and depths over their range
+// among the pixels and the ids as distinct colors
+fn displayable(kind: AovKind, values: &[Spectrum]) -> Vec<Spectrum> {
+    let finite = values.iter().flat_map(|v| [v.r, v.g, v.b]).filter(|v| v.is_finite());
+    let (min, max) = finite.fold((f64::INFINITY, f64::NEG_INFINITY), |(min, max), v| (min.min(v), max.max(v)));
+    let normalize = |v: f64| if v.is_finite() && max > min { (v - min) / (max - min) } else { 0.0 };
+
+    values.iter().map(|&v| match kind {
+        AovKind::Normal => Spectrum::new(v.r * 0.5 + 0.5, v.g * 0.5 + 0.5, v.b * 0.5 + 0.5),
+        AovKind::Depth | AovKind::Position => Spectrum::new(normalize(v.r), normalize(v.g), normalize(v.b)),
+        AovKind::PrimitiveId | AovKind::MaterialId if v.r > 0.0 => {
+            let h = hash(&[v.r as u64]);
+            Spectrum::new((h & 0xff) as f64 / 255.0, ((h >> 8) & 0xff) as f64 / 255.0, ((h >> 16) & 0xff) as f64 / 255.0)
+        },
+        _ => Spectrum::new(v.r.max(0.0), v.g.max(0.0), v.b.max(0.0)),
+    }).collect()
+}
+
+/// a rectangular block of the film owned by a single worker while it is rendered
+pub struct FilmTile {
+    pub index: usize, // the order of the tile in the film
+    pub p_min: Point2<usize>, // upper left pixel, inclusive
+    pub p_max: Point2<usize>, // lower right pixel, exclusive
+    pub splats: Vec<Splat>, // the radiance the samples of the tile add anywhere on the film
+    radiance_map: Vec<Spectrum>,
+    aov_map: Vec<Aov>, // empty until an auxiliary output is recorded
+}
+
+impl FilmTile {
+    pub fn new(index: usize, p_min: Point2<usize>, p_max: Point2<usize>) -> Self {
+        let n_pixels = (p_max.x - p_min.x) * (p_max.y - p_min.y);
+        FilmTile {
+            index,
+            p_min,
+            p_max,
+            splats: Vec::new(),
+            radiance_map: vec![Spectrum::black(); n_pixels],
+            aov_map: Vec::new(),
+        }
+    }
+
+    // i and j are the row and column of the pixel in the whole film
+    pub fn record(&mut self, i: usize, j: usize, radiance: Spectrum) {
+        let offset = self.offset(i, j);
+        self.radiance_map[offset] += radiance;
+    }
+
+    pub fn record_aov(&mut self, i: usize, j: usize, aov: Aov) {
+        if self.aov_map.is_empty() {
+            self.aov_map = vec![Aov::default(); self.radiance_map.len()];
+        }
+        let offset = self.offset(i, j);
+        self.aov_map[offset] = aov;
+    }
+
+    fn offset(&self, i: usize, j: usize) -> usize {
+        debug_assert!(self.p_min.y <= i && i < self.p_max.y);
+        debug_assert!(self.p_min.x <= j && j < self.p_max.x);
+        (i - self.p_min.y) * (self.p_max.x - self.p_min.x) + (j - self.p_min.x)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use image::codecs::hdr::HdrDecoder;
+
+    const WIDTH: usize = 3;
+    const HEIGHT: usize = 2;
+
+    // every pixel is told apart by its value, most of them above 1
+    fn radiance(i: usize, j: usize) -> Spectrum {
+        Spectrum::new(0.5 + i as f64, 1.0 + 2.0 * j as f64, 16.0)
+    }
+
+    // write the film to a temporary file and read its bytes back
+    fn written(extension: &str) -> Vec<u8> {
+        let path = env::temp_dir().join(format!("rbrt_test_film.{}", extension));
+        let film = Film::new(path.to_str().unwrap(), WIDTH, HEIGHT, ToneMapper::default());
+        {
+            let mut radiance_map = film.radiance_map.lock().unwrap();
+            for i in 0..HEIGHT {
+                for j in 0..WIDTH {
+                    radiance_map[i * WIDTH + j] = radiance(i, j);
+                }
+            }
+        }
+
+        film.write_to_image(1.0);
+        let bytes = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        bytes
+    }
+
+    fn assert_close(value: f32, expected: f64, tolerance: f64) {
+        assert!((value as f64 - expected).abs() <= tolerance * expected, "{} != {}", value, expected);
+    }
+
+    #[test]
+    fn test_exr_round_trip() {
+        let image = image::load_from_memory_with_format(&written("exr"), image::ImageFormat::OpenExr).unwrap().to_rgb32f();
+        assert_eq!((image.width() as usize, image.height() as usize), (WIDTH, HEIGHT));
+
+        for i in 0..HEIGHT {
+            for j in 0..WIDTH {
+                let pixel = image.get_pixel(j as u32, i as u32);
+                let expected = radiance(i, j);
+                assert_eq!(pixel.0, [expected.r as f32, expected.g as f32, expected.b as f32]);
+            }
+        }
+    }
+
+    #[test]
+    fn test_hdr_round_trip() {
+        let bytes = written("hdr");
+        let decoder = HdrDecoder::new(bytes.as_slice()).unwrap();
+        let metadata = decoder.metadata();
+        assert_eq!((metadata.width as usize, metadata.height as usize), (WIDTH, HEIGHT));
+
+        // rgbe shares the exponent of the largest channel, which leaves 8 bits to the mantissa of all of them
+        let pixels = decoder.read_image_hdr().unwrap();
+        for i in 0..HEIGHT {
+            for j in 0..WIDTH {
+                let pixel = pixels[i * WIDTH + j];
+                let expected = radiance(i, j);
+                assert_close(pixel[0], expected.r, 0.05);
+                assert_close(pixel[1], expected.g, 0.05);
+                assert_close(pixel[2], expected.b, 0.05);
+            }
+        }
+    }
+
+    #[test]
+    fn test_pfm_round_trip() {
+        let bytes = written("pfm");
+        let header = format!("PF\n{} {}\n-1.0\n", WIDTH, HEIGHT);
+        assert!(bytes.starts_with(header.as_bytes()));
+
+        let data = &bytes[header.len()..];
+        assert_eq!(data.len(), WIDTH * HEIGHT * 3 * 4);
+        let values: Vec<f32> = data.chunks(4).map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]])).collect();
+
+        // the first scanline of the file is the bottom row of the image
+        for i in 0..HEIGHT {
+            for j in 0..WIDTH {
+                let offset = ((HEIGHT - 1 - i) * WIDTH + j) * 3;
+                let expected = radiance(i, j);
+                assert_eq!(values[offset..offset + 3], [expected.r as f32, expected.g as f32, expected.b as f32]);
+            }
+        }
+    }
 }
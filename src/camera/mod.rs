@@ -1,12 +1,30 @@
+pub mod aov;
 pub mod film;
 pub mod perspective;
 pub mod pixel;
+pub mod tonemap;
 
 use super::geometry::ray::Ray;
-use cgmath::Point2;
+use cgmath::{Point2, Point3, Vector3};
 
 pub trait Camera {
     fn generate_ray(&self, sample: CameraSample) -> Ray;
+    /// the importance the camera gives to the ray leaving it, and the raster position the ray goes
+    /// through, None if it misses the film
+    fn we(&self, ray: &Ray) -> Option<(f64, Point2<f64>)>;
+    /// the densities (pdf_pos, pdf_dir) of generate_ray producing the ray, over the whole film
+    fn pdf_we(&self, ray: &Ray) -> (f64, f64);
+    /// sample a point of the camera seen from p_ref, for the paths that start from the lights
+    fn sample_wi(&self, p_ref: Point3<f64>) -> Option<ImportanceSample>;
+}
+
+/// the connection of a point to the camera
+pub struct ImportanceSample {
+    pub position: Point3<f64>,
+    pub wi: Vector3<f64>, // the direction from the point to the camera
+    pub importance: f64,
+    pub pdf: f64, // pdf in solid angle, seen from the point
+    pub p_raster: Point2<f64>,
 }
 
 pub struct CameraSample {
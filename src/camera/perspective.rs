@@ -1,11 +1,13 @@
 use std::f64::INFINITY;
+use std::sync::Arc;
 
-use cgmath::{EuclideanSpace, Point2, Point3, Vector3};
+use cgmath::{EuclideanSpace, InnerSpace, Point2, Point3, Vector3};
 
-use super::super::geometry::ray::Ray;
+use super::super::geometry::ray::{Ray, RayDifferential};
 use super::super::geometry::transform::Transform;
 use super::film::Film;
-use super::{Camera, CameraSample};
+use crate::medium::Medium;
+use super::{Camera, CameraSample, ImportanceSample};
 
 #[allow(dead_code)]
 pub struct PerspectiveCamera {
@@ -14,6 +16,8 @@ pub struct PerspectiveCamera {
     shutter_open: f64,
     shutter_close: f64,
     pub film: Film,
+    medium: Option<Arc<dyn Medium>>, // the medium the camera is in
+    film_area: f64, // the area of the film on the plane z = 1 of the camera space
 }
 
 impl PerspectiveCamera {
@@ -24,6 +28,7 @@ impl PerspectiveCamera {
         shutter_close: f64,
         fov: f64,
         film: Film,
+        medium: Option<Arc<dyn Medium>>,
     ) -> Self {
         // first compute screen to raster
         let res = film.resolution;
@@ -36,32 +41,95 @@ impl PerspectiveCamera {
             * Transform::translate(Vector3::new(-screen_window.0.x, -screen_window.1.y, 0.0));
 
         let camera_to_screen = Transform::perspective(fov, 1.0, 1e6); // the near and far plane are set arbitrarily
+        let raster_to_camera = (screen_to_raster * camera_to_screen).inverse();
+
+        let to_plane = |x: f64, y: f64| {
+            let p = raster_to_camera.transform_point3(Point3::new(x, y, 0.0));
+            p / p.z
+        };
+        let (p_min, p_max) = (to_plane(0.0, 0.0), to_plane(res.x as f64, res.y as f64));
+        let film_area = ((p_max.x - p_min.x) * (p_max.y - p_min.y)).abs();
 
         PerspectiveCamera {
             camera_to_world,
-            raster_to_camera: (screen_to_raster * camera_to_screen).inverse(), // to be implelmented,
+            raster_to_camera,
             shutter_open,
             shutter_close,
             film,
+            medium,
+            film_area,
         }
     }
+
+    // the cosine of the direction with the viewing direction, and the raster position it goes through
+    fn raster_position(&self, d: Vector3<f64>) -> Option<(f64, Point2<f64>)> {
+        let d = self.camera_to_world.inverse().transform_vector3(d).normalize();
+        if d.z <= 0.0 {
+            return None;
+        }
+
+        let p_raster = self.raster_to_camera.inverse().transform_point3(Point3::from_vec(d / d.z));
+        let res = self.film.resolution;
+        if p_raster.x < 0.0 || p_raster.x >= res.x as f64 || p_raster.y < 0.0 || p_raster.y >= res.y as f64 {
+            return None;
+        }
+        Some((d.z, Point2::new(p_raster.x, p_raster.y)))
+    }
 }
 
 impl Camera for PerspectiveCamera {
     fn generate_ray(&self, sample: CameraSample) -> crate::geometry::ray::Ray {
-        let p_camera = self.raster_to_camera.transform_point3(Point3::new(
-            sample.p_film.x,
-            sample.p_film.y,
-            0.0,
-        ));
-        // cast ray in the camera space
+        let to_camera = |x: f64, y: f64| self.raster_to_camera.transform_point3(Point3::new(x, y, 0.0)).to_vec();
+        let p_camera = to_camera(sample.p_film.x, sample.p_film.y);
+
+        // cast ray in the camera space, the differential rays go through the next pixels
         let r = Ray {
             o: Point3::new(0.0, 0.0, 0.0),
-            d: p_camera.to_vec(),
+            d: p_camera,
             time: sample.time,
             t_max: INFINITY,
+            medium: self.medium.clone(),
+            differential: Some(RayDifferential {
+                rx_origin: Point3::new(0.0, 0.0, 0.0),
+                ry_origin: Point3::new(0.0, 0.0, 0.0),
+                rx_direction: to_camera(sample.p_film.x + 1.0, sample.p_film.y).normalize(),
+                ry_direction: to_camera(sample.p_film.x, sample.p_film.y + 1.0).normalize(),
+            }),
         };
 
         self.camera_to_world.transform_ray(&r)
     }
+
+    fn we(&self, ray: &Ray) -> Option<(f64, Point2<f64>)> {
+        // the pinhole has a unit area, the importance is normalized over the film
+        let (cos_theta, p_raster) = self.raster_position(ray.d)?;
+        let cos2 = cos_theta * cos_theta;
+        Some((1.0 / (self.film_area * cos2 * cos2), p_raster))
+    }
+
+    fn pdf_we(&self, ray: &Ray) -> (f64, f64) {
+        match self.raster_position(ray.d) {
+            Some((cos_theta, _)) => (1.0, 1.0 / (self.film_area * cos_theta * cos_theta * cos_theta)),
+            None => (0.0, 0.0),
+        }
+    }
+
+    fn sample_wi(&self, p_ref: Point3<f64>) -> Option<ImportanceSample> {
+        let position = self.camera_to_world.transform_point3(Point3::new(0.0, 0.0, 0.0));
+        let normal = self.camera_to_world.transform_vector3(Vector3::new(0.0, 0.0, 1.0)).normalize();
+        let distance2 = (position - p_ref).magnitude2();
+        if distance2 == 0.0 {
+            return None;
+        }
+        let wi = (position - p_ref).normalize();
+
+        let (importance, p_raster) = self.we(&Ray::new(position, -wi, 0.0, f64::INFINITY))?;
+        Some(ImportanceSample {
+            position,
+            wi,
+            importance,
+            pdf: distance2 / normal.dot(wi).abs(),
+            p_raster,
+        })
+    }
 }
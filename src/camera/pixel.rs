@@ -24,9 +24,11 @@ impl Pixel {
     }
 
     pub fn to_rgb(&self) -> Rgb<u8> {
-        let x = (self.rgb.x * 255.0) as u8;
-        let y = (self.rgb.y * 255.0) as u8;
-        let z = (self.rgb.z * 255.0) as u8;
+        // values out of [0, 1] are clamped instead of wrapping around
+        let quantize = |v: f64| (v.clamp(0.0, 1.0) * 255.0).round() as u8;
+        let x = quantize(self.rgb.x);
+        let y = quantize(self.rgb.y);
+        let z = quantize(self.rgb.z);
 
         Rgb([x, y, z])
     }
@@ -0,0 +1,152 @@
+use crate::spectrum::Spectrum;
+
+/// curves compressing the linear radiance into [0, 1], applied per channel
+#[derive(Clone, Copy, Debug)]
+pub enum ToneMapping {
+    Linear,
+    Reinhard,
+    ExtendedReinhard {
+        white: f64, // the smallest radiance mapped to pure white
+    },
+    Aces,
+    Uncharted2 {
+        white: f64, // the smallest radiance mapped to pure white, before the exposure bias
+    },
+}
+
+impl ToneMapping {
+    pub fn evaluate(&self, x: f64) -> f64 {
+        let x = x.max(0.0);
+        match self {
+            Self::Linear => x,
+            Self::Reinhard => x / (1.0 + x),
+            Self::ExtendedReinhard { white } => {
+                x * (1.0 + x / (white * white)) / (1.0 + x)
+            },
+            Self::Aces => {
+                // Narkowicz's fit of the ACES reference rendering transform
+                let (a, b, c, d, e) = (2.51, 0.03, 2.43, 0.59, 0.14);
+                (x * (a * x + b)) / (x * (c * x + d) + e)
+            },
+            Self::Uncharted2 { white } => {
+                // Hable's filmic curve, with the exposure bias used in the original presentation,
+                // applied to the white point too so that white is still mapped to 1
+                const EXPOSURE_BIAS: f64 = 2.0;
+                uncharted2_partial(x * EXPOSURE_BIAS) / uncharted2_partial(white * EXPOSURE_BIAS)
+            }
+        }
+    }
+}
+
+fn uncharted2_partial(x: f64) -> f64 {
+    let (a, b, c, d, e, f) = (0.15, 0.50, 0.10, 0.20, 0.02, 0.30);
+    ((x * (a * x + c * b) + d * e) / (x * (a * x + b) + d * f)) - e / f
+}
+
+/// maps the linear radiance of the film to display referred values in [0, 1]
+#[derive(Clone, Copy, Debug)]
+pub struct ToneMapper {
+    pub operator: ToneMapping,
+    pub exposure: f64, // in stops (EV), the radiance is scaled by 2^exposure before tone mapping
+}
+
+impl ToneMapper {
+    pub fn new(operator: ToneMapping, exposure: f64) -> Self {
+        ToneMapper { operator, exposure }
+    }
+
+    pub fn map(&self, radiance: Spectrum) -> Spectrum {
+        let scale = self.exposure.exp2();
+        let map = |v: f64| self.operator.evaluate(v * scale).clamp(0.0, 1.0);
+
+        Spectrum::new(map(radiance.r), map(radiance.g), map(radiance.b))
+    }
+}
+
+impl Default for ToneMapper {
+    fn default() -> Self {
+        ToneMapper::new(ToneMapping::Linear, 0.0)
+    }
+}
+
+/// the sRGB opto-electronic transfer function, v is a linear value in [0, 1]
+pub fn srgb_oetf(v: f64) -> f64 {
+    if v <= 0.0031308 {
+        12.92 * v
+    } else {
+        1.055 * v.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+/// the inverse of srgb_oetf, decodes an sRGB value in [0, 1] to a linear one
+pub fn srgb_eotf(v: f64) -> f64 {
+    if v <= 0.04045 {
+        v / 12.92
+    } else {
+        ((v + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_range() {
+        let operators = [
+            ToneMapping::Linear,
+            ToneMapping::Reinhard,
+            ToneMapping::ExtendedReinhard { white: 4.0 },
+            ToneMapping::Aces,
+            ToneMapping::Uncharted2 { white: 5.6 },
+        ];
+
+        for operator in operators {
+            let mapper = ToneMapper::new(operator, 0.0);
+            let mut last = 0.0;
+            for i in 0..1000 {
+                let v = mapper.map(Spectrum::new(i as f64 * 0.02, 0.0, 0.0)).r;
+                assert!((0.0..=1.0).contains(&v));
+                // the curves should never decrease
+                assert!(v >= last - 1e-12, "{:?} is not monotonic", operator);
+                last = v;
+            }
+        }
+    }
+
+    #[test]
+    fn test_white_point() {
+        let white = 4.0;
+        let extended = ToneMapping::ExtendedReinhard { white };
+        assert!((extended.evaluate(white) - 1.0).abs() < 1e-9);
+
+        let filmic = ToneMapping::Uncharted2 { white };
+        assert!((filmic.evaluate(white) - 1.0).abs() < 1e-9);
+        assert!(filmic.evaluate(white / 2.0) < 1.0);
+    }
+
+    #[test]
+    fn test_exposure() {
+        let mapper = ToneMapper::new(ToneMapping::Linear, 1.0);
+        let s = mapper.map(Spectrum::new(0.25, 0.1, 0.4));
+        assert!((s.r - 0.5).abs() < 1e-9);
+        assert!((s.g - 0.2).abs() < 1e-9);
+        assert!((s.b - 0.8).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_srgb_oetf() {
+        assert_eq!(srgb_oetf(0.0), 0.0);
+        assert!((srgb_oetf(1.0) - 1.0).abs() < 1e-9);
+        // the linear and the power segments meet at the threshold
+        let t = 0.0031308;
+        assert!((12.92 * t - (1.055 * f64::powf(t, 1.0 / 2.4) - 0.055)).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_srgb_eotf() {
+        for v in [0.0, 0.002, 0.01, 0.2, 0.7, 1.0] {
+            assert!((srgb_eotf(srgb_oetf(v)) - v).abs() < 1e-9);
+        }
+    }
+}
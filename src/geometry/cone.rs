@@ -0,0 +1,88 @@
+use std::f64::consts::PI;
+
+use cgmath::{InnerSpace, Point3, Vector3};
+
+use super::{bound3::Bound3, transform::Transform};
+
+/// the set of directions within angle acos(cos_theta) around w
+#[derive(Debug, Clone, Copy)]
+pub struct DirectionCone {
+    pub w: Vector3<f64>,
+    pub cos_theta: f64,
+}
+
+impl DirectionCone {
+    pub fn new(w: Vector3<f64>, cos_theta: f64) -> Self {
+        DirectionCone { w: w.normalize(), cos_theta }
+    }
+
+    pub fn entire_sphere() -> Self {
+        DirectionCone { w: Vector3::new(0.0, 0.0, 1.0), cos_theta: -1.0 }
+    }
+
+    /// the cone of directions from p that reach the bound
+    pub fn bound_subtended_directions(b: &Bound3, p: Point3<f64>) -> Self {
+        let mut center = Point3::new(0.0, 0.0, 0.0);
+        let mut radius = 0.0;
+        b.bounding_sphere(&mut center, &mut radius);
+
+        let distance2 = (p - center).magnitude2();
+        if distance2 < radius * radius {
+            return Self::entire_sphere();
+        }
+
+        let sin2_theta_max = radius * radius / distance2;
+        let cos_theta_max = (1.0 - sin2_theta_max).max(0.0).sqrt();
+        DirectionCone::new(center - p, cos_theta_max)
+    }
+
+    /// the smallest cone containing both cones
+    pub fn union(a: &DirectionCone, b: &DirectionCone) -> Self {
+        let theta_a = a.cos_theta.clamp(-1.0, 1.0).acos();
+        let theta_b = b.cos_theta.clamp(-1.0, 1.0).acos();
+        let theta_d = a.w.dot(b.w).clamp(-1.0, 1.0).acos();
+
+        // one of the cones already contains the other
+        if (theta_d + theta_b).min(PI) <= theta_a {
+            return *a;
+        }
+        if (theta_d + theta_a).min(PI) <= theta_b {
+            return *b;
+        }
+
+        let theta_o = (theta_a + theta_d + theta_b) / 2.0;
+        if theta_o >= PI {
+            return Self::entire_sphere();
+        }
+
+        // rotate a's axis towards b's so the new cone touches both boundaries
+        let theta_r = theta_o - theta_a;
+        let wr = a.w.cross(b.w);
+        if wr.magnitude2() == 0.0 {
+            return Self::entire_sphere();
+        }
+        let w = Transform::rotate(theta_r.to_degrees(), wr.normalize()).transform_vector3(a.w);
+
+        DirectionCone::new(w, theta_o.cos())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_union_contains_both() {
+        let a = DirectionCone::new(Vector3::new(0.0, 0.0, 1.0), (0.3f64).cos());
+        let b = DirectionCone::new(Vector3::new(1.0, 0.0, 0.0), (0.2f64).cos());
+        let c = DirectionCone::union(&a, &b);
+
+        for cone in [a, b] {
+            let theta = cone.cos_theta.acos();
+            let theta_d = c.w.dot(cone.w).clamp(-1.0, 1.0).acos();
+            assert!(theta_d + theta <= c.cos_theta.acos() + 1e-9);
+        }
+        // the union is as tight as possible
+        assert!((c.cos_theta.acos() - (0.3 + PI / 2.0 + 0.2) / 2.0).abs() < 1e-9);
+    }
+}
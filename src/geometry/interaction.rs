@@ -1,17 +1,90 @@
 use std::sync::Arc;
 
-use cgmath::{Point3, Vector3};
-use crate::{material::Material, spectrum::Spectrum, light::Light};
+use cgmath::{EuclideanSpace, InnerSpace, Point2, Point3, Vector3};
+use crate::{material::Material, spectrum::Spectrum, light::Light, medium::{Medium, MediumInterface}, utils::{is_nan, perpendicular}, geometry::ray::{Ray, RayDifferential}, primitive::ObjectIds};
 
 
+#[derive(Clone)]
 pub struct GeometryInfo {
     pub p: Point3<f64>, // hit point, in world space
     pub n: Vector3<f64>, // surface normal
     pub t: f64,  // the parametric distance along the ray
     pub wo: Vector3<f64>, // normalized reverse direction of incoming ray
+    pub uv: Point2<f64>, // surface parameterization of p
+    pub dpdu: Vector3<f64>,
+    pub dpdv: Vector3<f64>,
+    pub dndu: Vector3<f64>,
+    pub dndv: Vector3<f64>,
+    pub shading: ShadingGeometry, // perturbed by interpolated normals, the geometric one otherwise
+}
+
+/// the local frame the bsdf is evaluated in
+#[derive(Clone, Copy)]
+pub struct ShadingGeometry {
+    pub n: Vector3<f64>,
+    pub dpdu: Vector3<f64>,
+    pub dpdv: Vector3<f64>,
+    pub dndu: Vector3<f64>,
+    pub dndv: Vector3<f64>,
+}
+
+fn face_forward(v: Vector3<f64>, w: Vector3<f64>) -> Vector3<f64> {
+    if v.dot(w) < 0.0 { -v } else { v }
+}
+
+impl GeometryInfo {
+    /// the normal does not change over the surface, use with_normal_derivatives for curved surfaces
+    pub fn new(p: Point3<f64>, n: Vector3<f64>, t: f64, wo: Vector3<f64>, uv: Point2<f64>, dpdu: Vector3<f64>, dpdv: Vector3<f64>) -> Self {
+        let zero = Vector3::new(0.0, 0.0, 0.0);
+        GeometryInfo {
+            p,
+            n,
+            t,
+            wo,
+            uv,
+            dpdu,
+            dpdv,
+            dndu: zero,
+            dndv: zero,
+            shading: ShadingGeometry { n, dpdu, dpdv, dndu: zero, dndv: zero },
+        }
+    }
+
+    pub fn with_normal_derivatives(mut self, dndu: Vector3<f64>, dndv: Vector3<f64>) -> Self {
+        self.dndu = dndu;
+        self.dndv = dndv;
+        self.shading.dndu = dndu;
+        self.shading.dndv = dndv;
+        self
+    }
+
+    /// replace the shading frame, the shading normal is flipped to the side of the geometric one, unless
+    /// the shading orientation is authoritative in which case the geometric normal is flipped instead
+    pub fn set_shading_geometry(&mut self, shading: ShadingGeometry, orientation_is_authoritative: bool) {
+        self.shading = shading;
+        self.shading.n = shading.n.normalize();
+        if orientation_is_authoritative {
+            self.n = face_forward(self.n, self.shading.n);
+        } else {
+            self.shading.n = face_forward(self.shading.n, self.n);
+        }
+    }
+
+    /// an orthonormal tangent frame (s, t) around the shading normal, s follows dpdu when possible
+    pub fn shading_frame(&self) -> (Vector3<f64>, Vector3<f64>) {
+        let ns = self.shading.n;
+        let s = self.shading.dpdu - ns * ns.dot(self.shading.dpdu);
+        if s.magnitude2() < 1e-16 {
+            return perpendicular(ns);
+        }
+
+        let s = s.normalize();
+        (s, ns.cross(s))
+    }
 }
 
 
+#[derive(Clone)]
 pub struct SurfaceInteraction {
     pub geo: GeometryInfo,
     pub time: f64,
@@ -19,4 +92,181 @@ pub struct SurfaceInteraction {
     pub hit_light: bool,
     pub radiance: Option<Spectrum>,
     pub light: Option<Arc<dyn Light>>,
-}
\ No newline at end of file
+    pub medium_interface: Option<MediumInterface>, // None if the surface does not change the medium
+    pub differentials: Option<SurfaceDifferentials>, // None if the ray carried no differentials
+    pub ids: ObjectIds,
+}
+
+/// how the hit point and its uv change from one pixel to the next on the film
+#[derive(Clone, Copy)]
+pub struct SurfaceDifferentials {
+    pub dpdx: Vector3<f64>,
+    pub dpdy: Vector3<f64>,
+    pub dudx: f64,
+    pub dvdx: f64,
+    pub dudy: f64,
+    pub dvdy: f64,
+}
+
+impl SurfaceInteraction {
+    /// estimate the footprint of the pixel on the surface from the differential rays, which are
+    /// intersected with the tangent plane of the hit
+    pub fn compute_differentials(&mut self, ray: &Ray) {
+        self.differentials = None;
+        let diff = match &ray.differential {
+            Some(diff) => diff,
+            None => return,
+        };
+
+        let (p, n) = (self.geo.p, self.geo.n);
+        let d = n.dot(p.to_vec());
+        let tx = -(n.dot(diff.rx_origin.to_vec()) - d) / n.dot(diff.rx_direction);
+        let ty = -(n.dot(diff.ry_origin.to_vec()) - d) / n.dot(diff.ry_direction);
+        if !tx.is_finite() || !ty.is_finite() {
+            return;
+        }
+        let dpdx = diff.rx_origin + diff.rx_direction * tx - p;
+        let dpdy = diff.ry_origin + diff.ry_direction * ty - p;
+
+        // solve dp = dpdu du + dpdv dv in the two coordinates the normal is the least aligned with
+        let dim = if n.x.abs() > n.y.abs() && n.x.abs() > n.z.abs() {
+            [1, 2]
+        } else if n.y.abs() > n.z.abs() {
+            [0, 2]
+        } else {
+            [0, 1]
+        };
+        let (dpdu, dpdv) = (self.geo.dpdu, self.geo.dpdv);
+        let a = [[dpdu[dim[0]], dpdv[dim[0]]], [dpdu[dim[1]], dpdv[dim[1]]]];
+        let solve = |b: [f64; 2]| -> (f64, f64) {
+            let det = a[0][0] * a[1][1] - a[0][1] * a[1][0];
+            if det.abs() < 1e-12 {
+                return (0.0, 0.0);
+            }
+            let u = (a[1][1] * b[0] - a[0][1] * b[1]) / det;
+            let v = (a[0][0] * b[1] - a[1][0] * b[0]) / det;
+            if u.is_finite() && v.is_finite() { (u, v) } else { (0.0, 0.0) }
+        };
+        let (dudx, dvdx) = solve([dpdx[dim[0]], dpdx[dim[1]]]);
+        let (dudy, dvdy) = solve([dpdy[dim[0]], dpdy[dim[1]]]);
+
+        self.differentials = Some(SurfaceDifferentials { dpdx, dpdy, dudx, dvdx, dudy, dvdy });
+    }
+
+    /// the differentials of the ray leaving the surface in the specular direction wi, following
+    /// the perfect reflection or refraction of the incoming differential rays
+    pub fn specular_differential(&self, ray: &Ray, wi: Vector3<f64>, eta: f64) -> Option<RayDifferential> {
+        let (diff, sd) = match (&ray.differential, &self.differentials) {
+            (Some(diff), Some(sd)) => (diff, sd),
+            _ => return None,
+        };
+
+        let wo = self.geo.wo;
+        let mut ns = self.geo.shading.n;
+        let mut dndx = self.geo.shading.dndu * sd.dudx + self.geo.shading.dndv * sd.dvdx;
+        let mut dndy = self.geo.shading.dndu * sd.dudy + self.geo.shading.dndv * sd.dvdy;
+        let dwodx = -diff.rx_direction - wo;
+        let dwody = -diff.ry_direction - wo;
+
+        let (rx_direction, ry_direction) = if wo.dot(self.geo.n) * wi.dot(self.geo.n) > 0.0 {
+            let ddndx = dwodx.dot(ns) + wo.dot(dndx);
+            let ddndy = dwody.dot(ns) + wo.dot(dndy);
+            (
+                wi - dwodx + (dndx * wo.dot(ns) + ns * ddndx) * 2.0,
+                wi - dwody + (dndy * wo.dot(ns) + ns * ddndy) * 2.0,
+            )
+        } else {
+            // eta is the ratio of the indices inside over outside, flipped when leaving the inside
+            let mut eta = 1.0 / eta;
+            if wo.dot(ns) < 0.0 {
+                eta = 1.0 / eta;
+                ns = -ns;
+                dndx = -dndx;
+                dndy = -dndy;
+            }
+            let ddndx = dwodx.dot(ns) + wo.dot(dndx);
+            let ddndy = dwody.dot(ns) + wo.dot(dndy);
+            let mu = eta * wo.dot(ns) - wi.dot(ns).abs();
+            let dmudx = (eta - (eta * eta * wo.dot(ns)) / wi.dot(ns).abs()) * ddndx;
+            let dmudy = (eta - (eta * eta * wo.dot(ns)) / wi.dot(ns).abs()) * ddndy;
+            (
+                wi - dwodx * eta + (dndx * mu + ns * dmudx),
+                wi - dwody * eta + (dndy * mu + ns * dmudy),
+            )
+        };
+
+        let differential = RayDifferential {
+            rx_origin: self.geo.p + sd.dpdx,
+            ry_origin: self.geo.p + sd.dpdy,
+            rx_direction: rx_direction.normalize(),
+            ry_direction: ry_direction.normalize(),
+        };
+        if is_nan(differential.rx_direction) || is_nan(differential.ry_direction) {
+            return None;
+        }
+        Some(differential)
+    }
+
+    /// the medium a ray leaving the surface in direction w travels through,
+    /// current is the medium of the ray that reached the surface
+    pub fn get_medium(&self, w: Vector3<f64>, current: &Option<Arc<dyn Medium>>) -> Option<Arc<dyn Medium>> {
+        match &self.medium_interface {
+            Some(mi) if w.dot(self.geo.n) > 0.0 => mi.outside.clone(),
+            Some(mi) => mi.inside.clone(),
+            None => current.clone(),
+        }
+    }
+}
+#[cfg(test)]
+mod tests {
+    use crate::geometry::{shape::{disk::Disk, Shape}, transform::Transform};
+    use crate::utils::reflect;
+
+    use super::*;
+
+    fn hit(ray: &Ray) -> SurfaceInteraction {
+        // a disk of radius 10 in the plane z = 5, facing -z
+        let object_to_world = Transform::translate(Vector3::new(0.0, 0.0, 5.0)) * Transform::rotate_x(180.0);
+        let disk = Disk::new(object_to_world.clone(), object_to_world.inverse(), 10.0);
+
+        let mut isect = SurfaceInteraction {
+            geo: disk.intersect(ray).unwrap(),
+            time: 0.0,
+            material: None,
+            hit_light: false,
+            radiance: None,
+            light: None,
+            medium_interface: None,
+            differentials: None,
+            ids: Default::default(),
+        };
+        isect.compute_differentials(ray);
+        isect
+    }
+
+    #[test]
+    fn test_differentials_on_plane() {
+        let mut ray = Ray::new(Point3::new(1.3, 0.2, 0.0), Vector3::new(0.1, -0.2, 1.0), 0.0, f64::INFINITY);
+        let rx_direction = Vector3::new(0.1001, -0.2, 1.0).normalize();
+        let ry_direction = Vector3::new(0.1, -0.1999, 1.0).normalize();
+        ray.differential = Some(RayDifferential { rx_origin: ray.o, ry_origin: ray.o, rx_direction, ry_direction });
+        let isect = hit(&ray);
+        let sd = isect.differentials.unwrap();
+
+        // the offsets reach the hits of the neighbouring rays
+        let px = hit(&Ray::new(ray.o, rx_direction, 0.0, f64::INFINITY)).geo;
+        let py = hit(&Ray::new(ray.o, ry_direction, 0.0, f64::INFINITY)).geo;
+        assert!((isect.geo.p + sd.dpdx - px.p).magnitude() < 1e-9);
+        assert!((isect.geo.p + sd.dpdy - py.p).magnitude() < 1e-9);
+
+        // the uv of the disk is not linear, so only compare to first order
+        assert!((sd.dudx - (px.uv.x - isect.geo.uv.x)).abs() < 1e-3 * sd.dudx.abs());
+        assert!((sd.dvdy - (py.uv.y - isect.geo.uv.y)).abs() < 1e-3 * sd.dvdy.abs());
+
+        // a flat mirror reflects the differential rays as they are
+        let wi = reflect(isect.geo.wo, isect.geo.n);
+        let reflected = isect.specular_differential(&ray, wi, 1.0).unwrap();
+        assert!((reflected.rx_direction - reflect(-rx_direction, isect.geo.n)).magnitude() < 1e-9);
+        assert!((reflected.ry_direction - reflect(-ry_direction, isect.geo.n)).magnitude() < 1e-9);
+    }
+}
@@ -1,4 +1,5 @@
 pub mod bound3;
+pub mod cone;
 pub mod interaction;
 pub mod ray;
 pub mod shape;
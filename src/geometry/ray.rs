@@ -1,4 +1,8 @@
-use cgmath::{Point3, Vector3};
+use std::sync::Arc;
+
+use cgmath::{InnerSpace, Point3, Vector3};
+
+use crate::medium::Medium;
 
 pub trait Beam {
     fn at(&self, t: f64) -> Point3<f64>;
@@ -9,7 +13,17 @@ pub struct Ray {
     pub d: Vector3<f64>,
     pub time: f64,
     pub t_max: f64,
-    // pub medium:
+    pub medium: Option<Arc<dyn Medium>>, // the medium containing the origin, None for vacuum
+    pub differential: Option<RayDifferential>, // the neighbouring rays one pixel away on the film
+}
+
+/// the rays offset by one pixel along the x and y axes of the film, their directions are normalized
+#[derive(Clone, Copy)]
+pub struct RayDifferential {
+    pub rx_origin: Point3<f64>,
+    pub ry_origin: Point3<f64>,
+    pub rx_direction: Vector3<f64>,
+    pub ry_direction: Vector3<f64>,
 }
 
 impl Ray {
@@ -18,7 +32,31 @@ impl Ray {
             o, 
             d, 
             time, 
-            t_max
+            t_max,
+            medium: None,
+            differential: None,
+        }
+    }
+
+    pub fn new_in_medium(o: Point3<f64>, d: Vector3<f64>, time: f64, t_max: f64, medium: Option<Arc<dyn Medium>>) -> Self {
+        Ray {
+            o,
+            d,
+            time,
+            t_max,
+            medium,
+            differential: None,
+        }
+    }
+
+    /// shrink the offsets of the differential rays, to account for several samples per pixel
+    pub fn scale_differentials(&mut self, s: f64) {
+        let (o, d) = (self.o, self.d.normalize());
+        if let Some(diff) = &mut self.differential {
+            diff.rx_origin = o + (diff.rx_origin - o) * s;
+            diff.ry_origin = o + (diff.ry_origin - o) * s;
+            diff.rx_direction = (d + (diff.rx_direction - d) * s).normalize();
+            diff.ry_direction = (d + (diff.ry_direction - d) * s).normalize();
         }
     }
 }
@@ -29,6 +67,3 @@ impl Beam for Ray {
     }
 }
 
-// pub struct RayDifferential {
-//     pub o: Point3<f64>
-// }
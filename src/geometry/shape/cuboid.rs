@@ -1,4 +1,4 @@
-use cgmath::{InnerSpace, Point3, Vector3};
+use cgmath::{InnerSpace, Point2, Point3, Vector3};
 
 use crate::geometry::{bound3::Bound3, interaction::GeometryInfo, ray::Beam, transform::Transform};
 
@@ -65,27 +65,38 @@ impl Shape for Cuboid {
                 let local_r = self.world_to_object.transform_ray(r);
                 let local_p = local_r.at(t);
                 let local_n;
+                // every face is parameterized over the two other axes, (u, v) = (0, 0) at the negative corner
+                let (u_axis, v_axis);
 
                 if 1e-3 > (local_p.x.abs() - self.half_x).abs() {
                     // p is on the x plane
                     if local_p.x > 0.0 { local_n = Vector3::new(1.0, 0.0, 0.0); } 
                     else { local_n = Vector3::new(-1.0, 0.0, 0.0); }
+                    (u_axis, v_axis) = (1, 2);
                 } else if 1e-3 > (local_p.y.abs() - self.half_y).abs() {
                     // p is on the y plane
                     if local_p.y > 0.0 { local_n = Vector3::new(0.0, 1.0, 0.0); }
                     else { local_n = Vector3::new(0.0, -1.0, 0.0); }
+                    (u_axis, v_axis) = (0, 2);
                 } else {
                     // p is on the z plane
                     if local_p.z > 0.0 { local_n = Vector3::new(0.0, 0.0, 1.0); }
                     else { local_n = Vector3::new(0.0, 0.0, -1.0); }
+                    (u_axis, v_axis) = (0, 1);
                 }
 
-                Some(GeometryInfo {
-                    p: self.object_to_world.transform_point3(local_p),
-                    n: self.object_to_world.transform_normal(local_n),
-                    t,
-                    wo: -r.d.normalize()
-                })
+                let half = [self.half_x, self.half_y, self.half_z];
+                let uv = Point2::new(
+                    (local_p[u_axis] / half[u_axis] + 1.0) * 0.5,
+                    (local_p[v_axis] / half[v_axis] + 1.0) * 0.5,
+                );
+                let mut dpdu = Vector3::new(0.0, 0.0, 0.0);
+                dpdu[u_axis] = 2.0 * half[u_axis];
+                let mut dpdv = Vector3::new(0.0, 0.0, 0.0);
+                dpdv[v_axis] = 2.0 * half[v_axis];
+
+                let geo = GeometryInfo::new(local_p, local_n, t, -local_r.d.normalize(), uv, dpdu, dpdv);
+                Some(self.object_to_world.transform_geometry_info(&geo))
             }
         }
     }
@@ -95,6 +106,10 @@ impl Shape for Cuboid {
         let local_r = self.world_to_object.transform_ray(r);
         let bound = self.object_bound();
         match bound.intersect_p(&local_r) {
+            // rays starting inside or on the cuboid hit it on the way out, t.1 is clamped to t_max
+            Some(t) if bound.expand(1e-6).contains(local_r.o) => {
+                if t.1 < local_r.t_max { Some(t.1) } else { None }
+            },
             Some(t) => Some(t.0),
             None => None,
         }
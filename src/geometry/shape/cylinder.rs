@@ -60,8 +60,16 @@ impl Shape for Cylinder {
         // get the solution t
         let p = r.at(t);
         let n = Vector3::new(p.x, p.y, 0.0);
-        
-        let geo = GeometryInfo { p, n, t, wo: -r.d.normalize() };
+
+        // u goes around the z axis and v along it
+        let mut phi = p.y.atan2(p.x);
+        if phi < 0.0 { phi += 2.0 * PI; }
+        let uv = Point2::new(phi / (2.0 * PI), (p.z - self.z_min) / (self.z_max - self.z_min));
+        let dpdu = Vector3::new(-p.y, p.x, 0.0) * 2.0 * PI;
+        let dpdv = Vector3::new(0.0, 0.0, self.z_max - self.z_min);
+
+        let geo = GeometryInfo::new(p, n, t, -r.d.normalize(), uv, dpdu, dpdv)
+            .with_normal_derivatives(dpdu / self.radius, Vector3::new(0.0, 0.0, 0.0));
 
         // transform the interation back to the world coordinate
         let geo = self.object_to_world.transform_geometry_info(&geo);
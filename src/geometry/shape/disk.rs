@@ -2,7 +2,7 @@ use std::f64::consts::PI;
 
 use cgmath::{Point2, Vector3, Point3, InnerSpace, EuclideanSpace};
 
-use crate::geometry::{transform::Transform, bound3::Bound3, ray::{Ray, Beam}, interaction::GeometryInfo};
+use crate::geometry::{transform::Transform, bound3::Bound3, cone::DirectionCone, ray::{Ray, Beam}, interaction::GeometryInfo};
 
 use super::Shape;
 
@@ -52,7 +52,19 @@ impl Shape for Disk {
 
         let n = Vector3::new(0.0, 0.0, 1.0);
 
-        let geo = GeometryInfo{ p, n, t, wo: -r.d.normalize() };
+        // u goes around the center and v from the rim to the center
+        let dist = p.to_vec().magnitude();
+        let mut phi = p.y.atan2(p.x);
+        if phi < 0.0 { phi += 2.0 * PI; }
+        let uv = Point2::new(phi / (2.0 * PI), 1.0 - dist / self.radius);
+        let dpdu = Vector3::new(-p.y, p.x, 0.0) * 2.0 * PI;
+        let dpdv = if dist > 0.0 {
+            Vector3::new(p.x, p.y, 0.0) * -self.radius / dist
+        } else {
+            Vector3::new(-self.radius, 0.0, 0.0)
+        };
+
+        let geo = GeometryInfo::new(p, n, t, -r.d.normalize(), uv, dpdu, dpdv);
 
         let geo = self.object_to_world.transform_geometry_info(&geo);
 
@@ -93,4 +105,9 @@ impl Shape for Disk {
 
         (p, n, area_pdf)
     }
+
+    fn normal_bounds(&self) -> DirectionCone {
+        let n = self.object_to_world.transform_normal(Vector3::new(0.0, 0.0, 1.0));
+        DirectionCone::new(n, 1.0)
+    }
 }
@@ -6,6 +6,7 @@ pub mod cuboid;
 use cgmath::{Point2, Point3, Vector3};
 
 use super::bound3::Bound3;
+use super::cone::DirectionCone;
 use super::interaction::GeometryInfo;
 use super::ray::Ray;
 
@@ -20,4 +21,9 @@ pub trait Shape: Sync + Send {
 
     // return point and vector in world space
     fn uniform_sample_point(&self, u: Point2<f64>) -> (Point3<f64>, Vector3<f64>, f64);
+
+    // the directions the surface normals point to, in world space
+    fn normal_bounds(&self) -> DirectionCone {
+        DirectionCone::entire_sphere()
+    }
 }
\ No newline at end of file
@@ -63,7 +63,19 @@ impl Shape for Sphere {
         // got a valid solution, compute interaction parameters
         let p = r.at(t);
         let n = p.to_vec().normalize();
-        let geo = GeometryInfo { p, n, t, wo: -r.d.normalize() };
+
+        // the parameterization of pbrt, u goes around the z axis and v from the south pole to the north
+        let mut phi = p.y.atan2(p.x);
+        if phi < 0.0 { phi += 2.0 * PI; }
+        let cos_theta = (p.z / self.radius).clamp(-1.0, 1.0);
+        let theta = cos_theta.acos();
+        let uv = Point2::new(phi / (2.0 * PI), 1.0 - theta / PI);
+        let dpdu = Vector3::new(-p.y, p.x, 0.0) * 2.0 * PI;
+        let dpdv = Vector3::new(p.z * phi.cos(), p.z * phi.sin(), -self.radius * theta.sin()) * -PI;
+
+        // the normal is p / radius, so are its derivatives
+        let geo = GeometryInfo::new(p, n, t, -r.d.normalize(), uv, dpdu, dpdv)
+            .with_normal_derivatives(dpdu / self.radius, dpdv / self.radius);
    
         // convert the interaction in the object space to world space
 
@@ -113,3 +125,39 @@ impl Shape for Sphere {
         (p, n, 1.0 / self.area())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sphere_partial_derivatives() {
+        let radius = 2.0;
+        let object_to_world = Transform::translate(Vector3::new(1.0, -1.0, 3.0));
+        let sphere = Sphere::new(object_to_world.clone(), object_to_world.inverse(), radius);
+
+        let r = Ray::new(Point3::new(-4.0, 0.3, 3.8), Vector3::new(1.0, -0.4, 0.1), 0.0, f64::INFINITY);
+        let geo = sphere.intersect(&r).unwrap();
+
+        // the point of the surface at (u, v)
+        let at = |u: f64, v: f64| {
+            let (theta, phi) = (PI * (1.0 - v), 2.0 * PI * u);
+            object_to_world.transform_point3(Point3::new(
+                radius * theta.sin() * phi.cos(),
+                radius * theta.sin() * phi.sin(),
+                radius * theta.cos(),
+            ))
+        };
+        assert!((at(geo.uv.x, geo.uv.y) - geo.p).magnitude() < 1e-9);
+
+        let h = 1e-6;
+        let dpdu = (at(geo.uv.x + h, geo.uv.y) - at(geo.uv.x - h, geo.uv.y)) / (2.0 * h);
+        let dpdv = (at(geo.uv.x, geo.uv.y + h) - at(geo.uv.x, geo.uv.y - h)) / (2.0 * h);
+        assert!((dpdu - geo.dpdu).magnitude() < 1e-5);
+        assert!((dpdv - geo.dpdv).magnitude() < 1e-5);
+
+        // the normal points out of the sphere, along dpdu x dpdv
+        assert!(geo.dpdu.cross(geo.dpdv).normalize().dot(geo.n) > 1.0 - 1e-9);
+        assert!((geo.shading.n - geo.n).magnitude() < 1e-12);
+    }
+}
@@ -1,6 +1,6 @@
 use super::bound3::Bound3;
 use super::interaction::*;
-use super::ray::Ray;
+use super::ray::{Ray, RayDifferential};
 use cgmath::{InnerSpace, Matrix4, Point3, SquareMatrix, Vector3, Vector4};
 use std::ops::Mul;
 
@@ -37,14 +37,18 @@ impl Transform {
 
     pub fn transform_normal(&self, n: Vector3<f64>) -> Vector3<f64> {
         // n must be normalized, which is different from that in pbrt
-        // do not transpose the inverse matrix explicitly (change iteration method instead).
         // just multiplying the matrix may result in a non-normalized vector, so normalize it in the end.
+        self.transform_normal_unnormalized(n).normalize()
+    }
+
+    // for the derivatives of normals, whose length matters
+    fn transform_normal_unnormalized(&self, n: Vector3<f64>) -> Vector3<f64> {
+        // do not transpose the inverse matrix explicitly (change iteration method instead).
         Vector3::new(
             self.m_inv[0][0] * n.x + self.m_inv[0][1] * n.y + self.m_inv[0][2] * n.z,
             self.m_inv[1][0] * n.x + self.m_inv[1][1] * n.y + self.m_inv[1][2] * n.z,
             self.m_inv[2][0] * n.x + self.m_inv[2][1] * n.y + self.m_inv[2][2] * n.z,
         )
-        .normalize()
     }
 
     pub fn transform_ray(&self, r: &Ray) -> Ray {
@@ -53,6 +57,13 @@ impl Transform {
             d: self.transform_vector3(r.d),
             time: r.time,
             t_max: r.t_max,
+            medium: r.medium.clone(),
+            differential: r.differential.map(|diff| RayDifferential {
+                rx_origin: self.transform_point3(diff.rx_origin),
+                ry_origin: self.transform_point3(diff.ry_origin),
+                rx_direction: self.transform_vector3(diff.rx_direction).normalize(),
+                ry_direction: self.transform_vector3(diff.ry_direction).normalize(),
+            }),
         }
     }
 
@@ -69,12 +80,24 @@ impl Transform {
     pub fn transform_geometry_info(&self, geo: &GeometryInfo) -> GeometryInfo {
         let p = self.transform_point3(geo.p);
         let n = self.transform_normal(geo.n);
-        let wo = self.transform_vector3(geo.wo);
+        let wo = self.transform_vector3(geo.wo).normalize();
         GeometryInfo {
             p,
             n,
             wo,
             t: geo.t,
+            uv: geo.uv,
+            dpdu: self.transform_vector3(geo.dpdu),
+            dpdv: self.transform_vector3(geo.dpdv),
+            dndu: self.transform_normal_unnormalized(geo.dndu),
+            dndv: self.transform_normal_unnormalized(geo.dndv),
+            shading: ShadingGeometry {
+                n: self.transform_normal(geo.shading.n),
+                dpdu: self.transform_vector3(geo.shading.dpdu),
+                dpdv: self.transform_vector3(geo.shading.dpdv),
+                dndu: self.transform_normal_unnormalized(geo.shading.dndu),
+                dndv: self.transform_normal_unnormalized(geo.shading.dndv),
+            },
         }
     }
 }
@@ -0,0 +1,669 @@
+use std::sync::Arc;
+
+use cgmath::{InnerSpace, Point2, Point3, Vector3};
+
+use crate::{
+    spectrum::Spectrum,
+    geometry::ray::Ray,
+    scene::Scene,
+    sampler::Sampler,
+    bxdf::{bsdf::Bsdf, BxdfType},
+    camera::{Camera, film::Splat, perspective::PerspectiveCamera},
+    light::Light,
+};
+use super::{Integrator, importance_scale};
+
+/// a bidirectional path tracer, every camera subpath is connected to a subpath traced from the
+/// lights with all the strategies combined by multiple importance sampling
+///
+/// the paths that reach the camera directly from the lights are splatted on the film of the camera,
+/// the surfaces that only bound media are passed through and the media are ignored
+pub struct BdptIntegrator {
+    pub max_depth: usize,
+    camera: Arc<PerspectiveCamera>,
+}
+
+impl BdptIntegrator {
+    pub fn new(max_depth: usize, camera: Arc<PerspectiveCamera>) -> Self {
+        BdptIntegrator { max_depth, camera }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum VertexKind {
+    Camera,
+    Light,
+    Surface,
+}
+
+/// a vertex of a subpath, the densities are those of sampling it from the previous vertex (fwd)
+/// and from the next one (rev), per unit area except for the infinite lights
+struct Vertex {
+    kind: VertexKind,
+    beta: Spectrum, // the throughput of the subpath up to the vertex
+    p: Point3<f64>,
+    ng: Vector3<f64>, // zero for the vertices that are not on a surface
+    ns: Vector3<f64>,
+    wo: Vector3<f64>, // towards the previous vertex
+    time: f64,
+    bsdf: Option<Bsdf>,
+    light: Option<Arc<dyn Light>>, // None for the camera and the escaped rays
+    le: Spectrum, // the radiance emitted towards the previous vertex of a camera subpath
+    importance: bool, // whether the vertex belongs to a subpath traced from the lights
+    delta: bool,
+    pdf_fwd: f64,
+    pdf_rev: f64,
+}
+
+impl Vertex {
+    fn new(kind: VertexKind, p: Point3<f64>, time: f64, beta: Spectrum) -> Self {
+        let zero = Vector3::new(0.0, 0.0, 0.0);
+        Vertex {
+            kind,
+            beta,
+            p,
+            ng: zero,
+            ns: zero,
+            wo: zero,
+            time,
+            bsdf: None,
+            light: None,
+            le: Spectrum::black(),
+            importance: false,
+            delta: false,
+            pdf_fwd: 0.0,
+            pdf_rev: 0.0,
+        }
+    }
+
+    fn with_normal(mut self, ng: Vector3<f64>, ns: Vector3<f64>) -> Self {
+        self.ng = ng;
+        self.ns = ns;
+        self
+    }
+
+    fn with_light(mut self, light: Option<Arc<dyn Light>>, le: Spectrum) -> Self {
+        self.light = light;
+        self.le = le;
+        self
+    }
+
+    fn is_on_surface(&self) -> bool {
+        self.ng != Vector3::new(0.0, 0.0, 0.0)
+    }
+
+    fn is_light(&self) -> bool {
+        self.kind == VertexKind::Light || (self.kind == VertexKind::Surface && self.light.is_some())
+    }
+
+    fn is_infinite_light(&self) -> bool {
+        self.kind == VertexKind::Light && self.light.as_ref().is_none_or(|light| light.is_infinite())
+    }
+
+    fn is_delta_light(&self) -> bool {
+        self.kind == VertexKind::Light && self.light.as_ref().is_some_and(|light| light.is_delta())
+    }
+
+    // the vertices with only specular lobes can not be reached by a connection
+    fn is_connectible(&self) -> bool {
+        match self.kind {
+            VertexKind::Camera | VertexKind::Light => true,
+            VertexKind::Surface => self.bsdf.as_ref().is_some_and(|bsdf| {
+                bsdf.bxdfs.iter().any(|bxdf| bxdf.types() & BxdfType::Specular as i32 == 0)
+            }),
+        }
+    }
+
+    /// the bsdf of a surface vertex scattering from the previous vertex to next
+    fn f(&self, next: &Vertex) -> Spectrum {
+        let wi = (next.p - self.p).normalize();
+        match &self.bsdf {
+            Some(bsdf) => bsdf.f(self.wo, wi) * self.importance_correction(bsdf, wi),
+            None => Spectrum::black(),
+        }
+    }
+
+    fn importance_correction(&self, bsdf: &Bsdf, wi: Vector3<f64>) -> f64 {
+        if self.importance { importance_scale(bsdf, self.wo, wi) } else { 1.0 }
+    }
+
+    /// turn a solid angle density at the vertex into the area density at next
+    fn convert_density(&self, pdf: f64, next: &Vertex) -> f64 {
+        if next.is_infinite_light() {
+            return pdf;
+        }
+
+        let w = next.p - self.p;
+        let distance2 = w.magnitude2();
+        if distance2 == 0.0 {
+            return 0.0;
+        }
+
+        if next.is_on_surface() {
+            pdf * next.ng.dot(w / distance2.sqrt()).abs() / distance2
+        } else {
+            pdf / distance2
+        }
+    }
+
+    /// the area density of the vertex sampling next, having been reached from prev
+    fn pdf(&self, scene: &Scene, camera: &PerspectiveCamera, prev: Option<&Vertex>, next: &Vertex) -> f64 {
+        if self.kind == VertexKind::Light {
+            return self.pdf_light(scene, next);
+        }
+
+        let wn = next.p - self.p;
+        if wn.magnitude2() == 0.0 {
+            return 0.0;
+        }
+        let wn = wn.normalize();
+
+        let pdf = match self.kind {
+            VertexKind::Camera => camera.pdf_we(&Ray::new(self.p, wn, self.time, f64::INFINITY)).1,
+            _ => match (&self.bsdf, prev) {
+                (Some(bsdf), Some(prev)) => bsdf.pdf((prev.p - self.p).normalize(), wn),
+                _ => 0.0,
+            },
+        };
+
+        self.convert_density(pdf, next)
+    }
+
+    /// the area density of the light at the vertex emitting towards v
+    fn pdf_light(&self, scene: &Scene, v: &Vertex) -> f64 {
+        let w = v.p - self.p;
+        let distance2 = w.magnitude2();
+        if distance2 == 0.0 {
+            return 0.0;
+        }
+        let w = w / distance2.sqrt();
+
+        let pdf = if self.is_infinite_light() {
+            // the rays of the infinite lights start on a disk as large as the scene
+            match scene.lightlist.infinite_lights.first() {
+                Some(light) => light.pdf_le(self.p, self.ng, w).0,
+                None => 0.0,
+            }
+        } else {
+            match &self.light {
+                Some(light) => light.pdf_le(self.p, self.ng, w).1 / distance2,
+                None => 0.0,
+            }
+        };
+
+        if v.is_on_surface() {
+            pdf * v.ng.dot(w).abs()
+        } else {
+            pdf
+        }
+    }
+
+    /// the area density of the subpaths from the lights starting at the vertex, towards v
+    fn pdf_light_origin(&self, scene: &Scene, v: &Vertex) -> f64 {
+        let w = v.p - self.p;
+        if w.magnitude2() == 0.0 {
+            return 0.0;
+        }
+        let w = w.normalize();
+
+        if self.is_infinite_light() {
+            return infinite_light_density(scene, w);
+        }
+
+        match &self.light {
+            Some(light) => light.pdf_le(self.p, self.ng, w).0 * scene.lightlist.pmf_emitter(light),
+            None => 0.0,
+        }
+    }
+}
+
+// the density of the infinite lights emitting along w, over the directions
+fn infinite_light_density(scene: &Scene, w: Vector3<f64>) -> f64 {
+    scene.lightlist.infinite_lights.iter()
+        .map(|light| scene.lightlist.pmf_emitter(light) * light.pdf_escaped(-w))
+        .sum()
+}
+
+// both points can see each other
+fn unoccluded(scene: &Scene, p0: Point3<f64>, p1: Point3<f64>, time: f64) -> bool {
+    let ray = Ray::new(p0, p1 - p0, time, 1.0 - 0.0001);
+    scene.intersect_p(&ray).is_none()
+}
+
+/// the geometric term between two vertices, zero if they are occluded
+fn g(scene: &Scene, v0: &Vertex, v1: &Vertex) -> f64 {
+    let d = v0.p - v1.p;
+    let distance2 = d.magnitude2();
+    if distance2 == 0.0 || !unoccluded(scene, v0.p, v1.p, v0.time) {
+        return 0.0;
+    }
+
+    let d = d / distance2.sqrt();
+    let mut g = 1.0 / distance2;
+    if v0.is_on_surface() {
+        g *= v0.ns.dot(d).abs();
+    }
+    if v1.is_on_surface() {
+        g *= v1.ns.dot(d).abs();
+    }
+    g
+}
+
+impl BdptIntegrator {
+    /// extend the subpath ending at the last vertex of path along the ray, pdf_fwd is the solid angle
+    /// density of the ray, at most max_vertices are added
+    #[allow(clippy::too_many_arguments)]
+    fn random_walk(
+        &self,
+        scene: &Scene,
+        sampler: &mut dyn Sampler,
+        ray: &mut Ray,
+        mut beta: Spectrum,
+        mut pdf_fwd: f64,
+        max_vertices: usize,
+        importance: bool,
+        path: &mut Vec<Vertex>,
+    ) {
+        let mut bounces = 0;
+        while bounces < max_vertices {
+            let isect = match scene.intersect(ray) {
+                Some(isect) => isect,
+                None => {
+                    // only the camera subpaths can find the infinite lights
+                    if !importance {
+                        let d = ray.d.normalize();
+                        let mut vertex = Vertex::new(VertexKind::Light, ray.o + d, ray.time, beta)
+                            .with_light(None, scene.le_escaped(ray));
+                        vertex.pdf_fwd = pdf_fwd;
+                        path.push(vertex);
+                    }
+                    break;
+                }
+            };
+
+            // the lights are not scattering, they end the subpaths
+            if isect.hit_light {
+                if !importance {
+                    let le = isect.radiance.unwrap_or(Spectrum::black());
+                    let mut vertex = Vertex::new(VertexKind::Surface, isect.geo.p, ray.time, beta)
+                        .with_normal(isect.geo.n, isect.geo.n)
+                        .with_light(isect.light.clone(), le);
+                    vertex.wo = -ray.d.normalize();
+                    vertex.pdf_fwd = path.last().unwrap().convert_density(pdf_fwd, &vertex);
+                    path.push(vertex);
+                }
+                break;
+            }
+
+            let material = match &isect.material {
+                Some(material) => material,
+                None => {
+                    // the surface only bounds a medium, the ray goes on without counting a vertex
+                    let differential = ray.differential;
+                    *ray = Ray::new(isect.geo.p, ray.d, ray.time, f64::INFINITY);
+                    ray.differential = differential;
+                    continue;
+                }
+            };
+
+            let bsdf = material.compute_scattering(&isect);
+            let wo = -ray.d.normalize();
+            let mut vertex = Vertex::new(VertexKind::Surface, isect.geo.p, ray.time, beta).with_normal(bsdf.ng, bsdf.ns);
+            vertex.wo = wo;
+            vertex.importance = importance;
+            vertex.pdf_fwd = path.last().unwrap().convert_density(pdf_fwd, &vertex);
+            bounces += 1;
+
+            if bounces >= max_vertices {
+                vertex.bsdf = Some(bsdf);
+                path.push(vertex);
+                break;
+            }
+
+            let sample = bsdf.sample_f(wo, sampler.get_2d());
+            if sample.pdf <= 0.0 || sample.rho.is_black() {
+                vertex.bsdf = Some(bsdf);
+                path.push(vertex);
+                break;
+            }
+
+            let wi = sample.wi;
+            beta *= sample.rho * wi.dot(bsdf.ns).abs() / sample.pdf;
+            beta = beta * vertex.importance_correction(&bsdf, wi);
+
+            pdf_fwd = sample.pdf;
+            let mut pdf_rev = bsdf.pdf(wi, wo);
+            if sample.is_delta {
+                vertex.delta = true;
+                pdf_fwd = 0.0;
+                pdf_rev = 0.0;
+            }
+
+            let prev = path.last_mut().unwrap();
+            prev.pdf_rev = vertex.convert_density(pdf_rev, prev);
+
+            *ray = Ray::new(isect.geo.p, wi, ray.time, f64::INFINITY);
+            vertex.bsdf = Some(bsdf);
+            path.push(vertex);
+        }
+    }
+
+    fn camera_subpath(&self, ray: &Ray, scene: &Scene, sampler: &mut dyn Sampler, max_vertices: usize) -> Vec<Vertex> {
+        let mut ray = Ray::new(ray.o, ray.d.normalize(), ray.time, f64::INFINITY);
+        let (_, pdf_dir) = self.camera.pdf_we(&ray);
+
+        let mut path = vec![Vertex::new(VertexKind::Camera, ray.o, ray.time, Spectrum::new(1.0, 1.0, 1.0))];
+        self.random_walk(scene, sampler, &mut ray, Spectrum::new(1.0, 1.0, 1.0), pdf_dir, max_vertices - 1, false, &mut path);
+        path
+    }
+
+    fn light_subpath(&self, scene: &Scene, sampler: &mut dyn Sampler, time: f64, max_vertices: usize) -> Vec<Vertex> {
+        let (light, pmf) = match scene.lightlist.sample_emitter(sampler.get_1d()) {
+            Some(picked) => picked,
+            None => return Vec::new(),
+        };
+
+        let (u_pos, u_dir) = (sampler.get_2d(), sampler.get_2d());
+        let emission = light.sample_le(u_pos, u_dir, time);
+        if emission.pdf_pos == 0.0 || emission.pdf_dir == 0.0 || emission.le.is_black() {
+            return Vec::new();
+        }
+
+        let mut ray = emission.ray;
+        let mut vertex = Vertex::new(VertexKind::Light, ray.o, time, emission.le)
+            .with_normal(emission.normal, emission.normal)
+            .with_light(Some(light.clone()), emission.le);
+        vertex.pdf_fwd = emission.pdf_pos * pmf;
+
+        // the lights at a point emit the same in every direction
+        let cos_theta = if light.is_delta() { 1.0 } else { emission.normal.dot(ray.d).abs() };
+        let beta = emission.le * cos_theta / (pmf * emission.pdf_pos * emission.pdf_dir);
+
+        let d = ray.d;
+        let mut path = vec![vertex];
+        self.random_walk(scene, sampler, &mut ray, beta, emission.pdf_dir, max_vertices - 1, true, &mut path);
+
+        // the rays of the infinite lights are sampled by their direction first, then by their origin
+        if light.is_infinite() {
+            if path.len() > 1 {
+                let cos_theta = if path[1].is_on_surface() { path[1].ng.dot(d).abs() } else { 1.0 };
+                path[1].pdf_fwd = emission.pdf_pos * cos_theta;
+            }
+            path[0].pdf_fwd = infinite_light_density(scene, d);
+        }
+
+        path
+    }
+
+    /// the contribution of the path made of the first s vertices of the light subpath and the first t
+    /// of the camera subpath, along with the raster position of the paths that are splatted (t = 1)
+    fn connect(
+        &self,
+        scene: &Scene,
+        sampler: &mut dyn Sampler,
+        light_path: &[Vertex],
+        camera_path: &[Vertex],
+        s: usize,
+        t: usize,
+    ) -> (Spectrum, Option<Point2<f64>>) {
+        // the escaped rays can only be seen, not connected to
+        if t > 1 && s != 0 && camera_path[t - 1].kind == VertexKind::Light {
+            return (Spectrum::black(), None);
+        }
+
+        let mut sampled = None;
+        let mut p_raster = None;
+        let radiance = if s == 0 {
+            let pt = &camera_path[t - 1];
+            if pt.is_light() { pt.le * pt.beta } else { Spectrum::black() }
+        } else if t == 1 {
+            let qs = &light_path[s - 1];
+            match self.camera.sample_wi(qs.p) {
+                Some(is) if qs.is_connectible() && is.pdf > 0.0 && is.importance > 0.0 => {
+                    let mut vertex = Vertex::new(VertexKind::Camera, is.position, qs.time, Spectrum::new(1.0, 1.0, 1.0) * (is.importance / is.pdf));
+                    vertex.wo = -is.wi;
+
+                    let mut radiance = qs.beta * qs.f(&vertex) * vertex.beta;
+                    if qs.is_on_surface() {
+                        radiance = radiance * is.wi.dot(qs.ns).abs();
+                    }
+                    if !radiance.is_black() && !unoccluded(scene, qs.p, is.position, qs.time) {
+                        radiance = Spectrum::black();
+                    }
+
+                    p_raster = Some(is.p_raster);
+                    sampled = Some(vertex);
+                    radiance
+                }
+                _ => Spectrum::black(),
+            }
+        } else if s == 1 {
+            let pt = &camera_path[t - 1];
+            // the light is picked as for the subpaths from the lights, which the weights rely on
+            let picked = scene.lightlist.sample_emitter(sampler.get_1d());
+            let u_light = sampler.get_2d();
+            match picked {
+                Some((light, pmf)) if pt.is_connectible() => {
+                    let light_sample = light.sample_li(pt.p, u_light);
+                    let pdf = light_sample.pdf_area_to_solid(pt.p);
+
+                    if pdf > 0.0 && !light_sample.le.is_black() {
+                        let n = if light.is_delta() || light.is_infinite() { Vector3::new(0.0, 0.0, 0.0) } else { light_sample.normal };
+                        let mut vertex = Vertex::new(VertexKind::Light, light_sample.position, pt.time, light_sample.le / (pdf * pmf))
+                            .with_normal(n, n)
+                            .with_light(Some(light.clone()), light_sample.le);
+                        vertex.pdf_fwd = vertex.pdf_light_origin(scene, pt);
+
+                        let wi = (light_sample.position - pt.p).normalize();
+                        let mut radiance = pt.beta * pt.f(&vertex) * vertex.beta;
+                        if pt.is_on_surface() {
+                            radiance = radiance * wi.dot(pt.ns).abs();
+                        }
+                        if !radiance.is_black() && !unoccluded(scene, pt.p, light_sample.position, pt.time) {
+                            radiance = Spectrum::black();
+                        }
+
+                        sampled = Some(vertex);
+                        radiance
+                    } else {
+                        Spectrum::black()
+                    }
+                }
+                _ => Spectrum::black(),
+            }
+        } else {
+            let (qs, pt) = (&light_path[s - 1], &camera_path[t - 1]);
+            if qs.is_connectible() && pt.is_connectible() {
+                let radiance = qs.beta * qs.f(pt) * pt.f(qs) * pt.beta;
+                if radiance.is_black() { radiance } else { radiance * g(scene, qs, pt) }
+            } else {
+                Spectrum::black()
+            }
+        };
+
+        if radiance.is_black() {
+            return (radiance, None);
+        }
+
+        let weight = self.mis_weight(scene, light_path, camera_path, sampled.as_ref(), s, t);
+        (radiance * weight, p_raster)
+    }
+
+    /// the balance heuristic over all the strategies that could have sampled the same path, sampled
+    /// replaces the last vertex of the subpath it was sampled for (s = 1 or t = 1)
+    fn mis_weight(&self, scene: &Scene, light_path: &[Vertex], camera_path: &[Vertex], sampled: Option<&Vertex>, s: usize, t: usize) -> f64 {
+        if s + t == 2 {
+            return 1.0;
+        }
+
+        let camera = self.camera.as_ref();
+        let qs = match s {
+            0 => None,
+            1 => sampled,
+            _ => Some(&light_path[s - 1]),
+        };
+        let pt = if t == 1 { sampled.unwrap() } else { &camera_path[t - 1] };
+        let qs_minus = if s > 1 { Some(&light_path[s - 2]) } else { None };
+        let pt_minus = if t > 1 { Some(&camera_path[t - 2]) } else { None };
+
+        // the densities (fwd, rev) and delta flags of the vertices, updated for the connection
+        let mut camera_pdfs: Vec<(f64, f64, bool)> = camera_path[..t].iter().map(|v| (v.pdf_fwd, v.pdf_rev, v.delta)).collect();
+        let mut light_pdfs: Vec<(f64, f64, bool)> = light_path[..s].iter().map(|v| (v.pdf_fwd, v.pdf_rev, v.delta)).collect();
+        camera_pdfs[t - 1] = (pt.pdf_fwd, pt.pdf_rev, false);
+        if let Some(qs) = qs {
+            light_pdfs[s - 1] = (qs.pdf_fwd, qs.pdf_rev, false);
+        }
+
+        camera_pdfs[t - 1].1 = match qs {
+            Some(qs) => qs.pdf(scene, camera, qs_minus, pt),
+            None => pt.pdf_light_origin(scene, pt_minus.unwrap()),
+        };
+        if let Some(pt_minus) = pt_minus {
+            camera_pdfs[t - 2].1 = match qs {
+                Some(qs) => pt.pdf(scene, camera, Some(qs), pt_minus),
+                None => pt.pdf_light(scene, pt_minus),
+            };
+        }
+        if let Some(qs) = qs {
+            light_pdfs[s - 1].1 = pt.pdf(scene, camera, pt_minus, qs);
+            if let Some(qs_minus) = qs_minus {
+                light_pdfs[s - 2].1 = qs.pdf(scene, camera, Some(pt), qs_minus);
+            }
+        }
+
+        // the delta densities are left out of the ratios
+        let remap0 = |pdf: f64| if pdf != 0.0 { pdf } else { 1.0 };
+        let mut sum_ri = 0.0;
+
+        let mut ri = 1.0;
+        for i in (1..t).rev() {
+            ri *= remap0(camera_pdfs[i].1) / remap0(camera_pdfs[i].0);
+            if !camera_pdfs[i].2 && !camera_pdfs[i - 1].2 {
+                sum_ri += ri;
+            }
+        }
+
+        let delta_light = match s {
+            0 => false,
+            1 => qs.unwrap().is_delta_light(),
+            _ => light_path[0].is_delta_light(),
+        };
+        let mut ri = 1.0;
+        for i in (0..s).rev() {
+            ri *= remap0(light_pdfs[i].1) / remap0(light_pdfs[i].0);
+            let delta_prev = if i > 0 { light_pdfs[i - 1].2 } else { delta_light };
+            if !light_pdfs[i].2 && !delta_prev {
+                sum_ri += ri;
+            }
+        }
+
+        1.0 / (1.0 + sum_ri)
+    }
+}
+
+impl Integrator for BdptIntegrator {
+    fn li(&self, ray: &mut Ray, scene: &Scene, sampler: &mut dyn Sampler) -> Spectrum {
+        let mut splats = Vec::new();
+        let radiance = self.li_splat(ray, scene, sampler, &mut splats);
+
+        // outside of the tiles, the splats go to the film at once
+        for splat in splats {
+            self.camera.film.add_splat(splat.p_raster, splat.radiance);
+        }
+
+        radiance
+    }
+
+    fn li_splat(&self, ray: &mut Ray, scene: &Scene, sampler: &mut dyn Sampler, splats: &mut Vec<Splat>) -> Spectrum {
+        let (direct, indirect) = self.li_split(ray, scene, sampler, splats);
+        direct + indirect
+    }
+
+    fn li_split(&self, ray: &mut Ray, scene: &Scene, sampler: &mut dyn Sampler, splats: &mut Vec<Splat>) -> (Spectrum, Spectrum) {
+        let camera_path = self.camera_subpath(ray, scene, sampler, self.max_depth + 2);
+        let light_path = self.light_subpath(scene, sampler, ray.time, self.max_depth + 1);
+
+        let mut direct = Spectrum::black();
+        let mut indirect = Spectrum::black();
+        for t in 1..=camera_path.len() {
+            for s in 0..=light_path.len() {
+                // the bounces of the path
+                if s + t < 2 || (s == 1 && t == 1) || s + t - 2 > self.max_depth {
+                    continue;
+                }
+
+                // the emission seen and the light scattered once toward the camera are direct
+                let is_direct = s + t - 2 <= 1;
+                let (contribution, p_raster) = self.connect(scene, sampler, &light_path, &camera_path, s, t);
+                if t == 1 {
+                    if let Some(p_raster) = p_raster {
+                        splats.push(Splat { p_raster, radiance: contribution, direct: is_direct });
+                    }
+                } else if is_direct {
+                    direct += contribution;
+                } else {
+                    indirect += contribution;
+                }
+            }
+        }
+
+        (direct, indirect)
+    }
+
+    fn can_split(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        integrator::{path_integrator::PathIntegrator, test_scene::{TestScene, matte, lit_sphere, camera, render_pixels, WIDTH, HEIGHT}},
+        geometry::{shape::disk::Disk, transform::Transform},
+        light::point::PointLight,
+        material::mirror::Mirror,
+        texture::constant::ConstantTexture,
+    };
+
+    #[test]
+    fn test_bdpt_deterministic_across_threads() {
+        let bdpt = |n_thread| {
+            let camera = camera();
+            let integrator = BdptIntegrator::new(5, Arc::clone(&camera));
+            render_pixels(camera, lit_sphere(), Box::new(integrator), 4, n_thread)
+        };
+
+        // the splats of the tiles are merged in the same order whatever the number of threads
+        let single = bdpt(1);
+        assert!(single.iter().any(|s| !s.is_black()));
+        assert_eq!(single, bdpt(3));
+    }
+
+    #[test]
+    fn test_bdpt_mirror_caustic() {
+        // a point light 2 units above the floor, under a mirror at y = 2, which the light reflects onto the
+        // floor as if a second light stood at its mirror image at y = 3. the paths through the mirror only
+        // reach the camera by the light subpaths splatted on the film
+        let light = |y: f64| Arc::new(PointLight::new(Point3::new(0.0, y, 5.0), Spectrum::new(4.0, 4.0, 4.0)));
+        let object_to_world = Transform::translate(Vector3::new(0.0, 2.0, 5.0)) * Transform::rotate(90.0, Vector3::new(1.0, 0.0, 0.0));
+        let mirror = Disk::new(object_to_world.clone(), object_to_world.inverse(), 20.0);
+        let scene = TestScene::new()
+            .floor(matte(0.5))
+            .shape(Box::new(mirror), Some(Arc::new(Mirror::new(Arc::new(ConstantTexture::new(Spectrum::new(1.0, 1.0, 1.0)))))), None)
+            .light(light(1.0))
+            .build();
+
+        // two bounces leave out the light going back and forth between the floor and the mirror
+        let reference = TestScene::new().floor(matte(0.5)).light(light(1.0)).light(light(3.0)).build();
+        let reference = render_pixels(camera(), reference, Box::new(PathIntegrator::new(1, true)), 64, 2);
+        let camera = camera();
+        let bdpt = render_pixels(Arc::clone(&camera), scene, Box::new(BdptIntegrator::new(2, camera)), 256, 2);
+
+        // the lower half of the image sees the floor
+        let floor = |pixels: &[Spectrum]| pixels[HEIGHT / 2 * WIDTH..].iter().map(|s| s.luminance()).sum::<f64>();
+        let (bdpt, reference) = (floor(&bdpt), floor(&reference));
+        assert!(reference > 0.0);
+        assert!((bdpt - reference).abs() < 0.03 * reference, "bdpt {} reference {}", bdpt, reference);
+    }
+}
@@ -15,12 +15,13 @@ impl DirectIntegrator {
 }
 
 impl Integrator for DirectIntegrator {
-    fn li(&self, ray: &mut crate::geometry::ray::Ray, scene: &Scene, sampler: &Arc<dyn Sampler>) -> crate::spectrum::Spectrum {
+    fn li(&self, ray: &mut crate::geometry::ray::Ray, scene: &Scene, sampler: &mut dyn Sampler) -> crate::spectrum::Spectrum {
         let mut lo = Spectrum::new(0.0, 0.0, 0.0);
         let mut throughput = Spectrum::new(1.0, 1.0, 1.0);
         let mut specular = false;
 
-        for depth in 0..self.max_depth {
+        let mut depth = 0;
+        while depth < self.max_depth {
             match scene.intersect(ray) {
                 Some(isect) => {
                     if isect.hit_light {
@@ -33,18 +34,20 @@ impl Integrator for DirectIntegrator {
                             Some(mat) => {
                                 if !mat.is_specular() {
                                     let u = sampler.get_2d();
-                                    // let (light, light_pdf) = scene.lightlist.importance_sample_light(u);
-                                    let (light, light_pdf) = scene.lightlist.uniform_pick_light(sampler.get_2d().x);
+                                    let (light, light_pdf) = match scene.lightlist.sample(isect.geo.p, isect.geo.n, sampler.get_1d()) {
+                                        Some(picked) => picked,
+                                        None => break,
+                                    };
 
-                                    let p_light = light.sample_li(&isect, u);
+                                    let p_light = light.sample_li(isect.geo.p, u);
                                     let light_pdf = light_pdf * p_light.pdf;
 
                                     let wi = (p_light.position - isect.geo.p).normalize();
                                     let wo = -ray.d.normalize();
-                                    let cos_theta = isect.geo.n.dot(wi);
+                                    let bsdf = mat.compute_scattering(&isect);
+                                    let cos_theta = bsdf.ns.dot(wi).abs();
                                     let cos_alpha = wi.dot(-p_light.normal).max(0.0);
                                     let r2 = (p_light.position - isect.geo.p).magnitude2();
-                                    let bsdf = mat.compute_scattering(&isect);
 
                                     if r2 > 0.0 && light_pdf > 0.0 && !p_light.le.is_black() && visibility_test(&isect, p_light.position, scene) {
                                         lo += throughput * bsdf.f(wo, wi) * p_light.le * cos_theta * cos_alpha / (light_pdf * r2);
@@ -59,26 +62,41 @@ impl Integrator for DirectIntegrator {
                                     let wo = -ray.d.normalize();
                                     let bsdf_sample = bsdf.sample_f(wo, sampler.get_2d());
                                     let (rho, wi, pdf) = (bsdf_sample.rho, bsdf_sample.wi, bsdf_sample.pdf);
+                                    if pdf <= 0.0 || rho.is_black() {
+                                        break;
+                                    }
 
-                                    let cosine = wi.dot(isect.geo.n).abs();
+                                    let cosine = wi.dot(bsdf.ns).abs();
                                     throughput *= rho * cosine / pdf;
 
-                                    // spawn the new ray
+                                    // spawn the new ray, which keeps track of the pixel footprint
+                                    let differential = isect.specular_differential(ray, wi, bsdf.eta);
                                     *ray = Ray::new(isect.geo.p, wi, ray.time, f64::INFINITY);
+                                    ray.differential = differential;
                                 }
                                 
                             }
                             None => {
-                                panic!();
+                                // a medium boundary, the ray goes on without counting a bounce
+                                let medium = isect.get_medium(ray.d, &ray.medium);
+                                let differential = ray.differential;
+                                *ray = Ray::new_in_medium(isect.geo.p, ray.d, ray.time, f64::INFINITY, medium);
+                                ray.differential = differential;
+                                continue;
                             }
                         }
                     }
                 },
                 None => {
-                    // returns the background color
-                    // lo += Spectrum::skyblue(ray.d.y) * throughput
+                    // the infinite lights seen directly or through specular vertices
+                    if depth == 0 || specular {
+                        lo += throughput * scene.le_escaped(ray);
+                    }
+                    break;
                 }
             }
+
+            depth += 1;
         }
 
         lo
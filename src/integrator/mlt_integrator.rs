@@ -0,0 +1,262 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::{Arc, Mutex, atomic::{AtomicUsize, Ordering}},
+    thread,
+};
+
+use cgmath::Point2;
+use indicatif::{ProgressBar, ProgressStyle};
+
+use crate::{
+    spectrum::Spectrum,
+    geometry::ray::Ray,
+    scene::Scene,
+    sampler::{Sampler, mlt::MltSampler, rng::Rng, distribution::Distribution1D, lowdiscrepancy::hash},
+    camera::{Camera, CameraSample, perspective::PerspectiveCamera},
+    WorldSetting,
+};
+use super::{Integrator, path_integrator::PathIntegrator};
+
+/// primary sample space metropolis light transport, markov chains mutate the random numbers consumed by
+/// the path tracer, including the film position, and splat the paths they visit on the film
+///
+/// the chains start from paths picked among the bootstrap paths, whose mean brightness normalizes the
+/// image, and the film gets as many mutations as samples per pixel
+pub struct MltIntegrator {
+    path: PathIntegrator,
+    pub n_bootstrap: usize,
+    pub n_chains: usize,
+    pub sigma: f64, // the standard deviation of the small steps
+    pub large_step_probability: f64,
+    camera: Arc<PerspectiveCamera>,
+}
+
+impl MltIntegrator {
+    pub fn new(max_depth: usize, b_mis: bool, camera: Arc<PerspectiveCamera>) -> Self {
+        MltIntegrator {
+            path: PathIntegrator::new(max_depth, b_mis),
+            n_bootstrap: 100000,
+            n_chains: 1000,
+            sigma: 0.01,
+            large_step_probability: 0.3,
+            camera,
+        }
+    }
+
+    /// the paths sampled to normalize the image and the number of markov chains
+    pub fn with_chains(mut self, n_bootstrap: usize, n_chains: usize) -> Self {
+        self.n_bootstrap = n_bootstrap.max(1);
+        self.n_chains = n_chains.max(1);
+        self
+    }
+
+    /// the bounces before russian roulette may end the paths of the path tracer
+    pub fn with_russian_roulette(mut self, rr_depth: usize) -> Self {
+        self.path = self.path.with_russian_roulette(rr_depth);
+        self
+    }
+
+    /// the size of the small steps and how often the samples are drawn anew
+    pub fn with_mutations(mut self, sigma: f64, large_step_probability: f64) -> Self {
+        self.sigma = sigma;
+        self.large_step_probability = large_step_probability;
+        self
+    }
+
+    /// the radiance of the path given by the samples and the raster position it goes through
+    fn l(&self, scene: &Scene, sampler: &mut MltSampler) -> (Spectrum, Point2<f64>) {
+        sampler.start_pixel_sample(Point2::new(0, 0), 0);
+        let resolution = self.camera.film.resolution;
+        let u = sampler.get_pixel_2d();
+        let p_raster = Point2::new(u.x * resolution.x as f64, u.y * resolution.y as f64);
+
+        let mut ray = self.camera.generate_ray(CameraSample::new(p_raster, 0.0));
+        let radiance = self.path.li(&mut ray, scene, sampler);
+        // the chains can not leave the paths of an invalid value
+        if radiance.luminance().is_finite() && radiance.luminance() > 0.0 {
+            (radiance, p_raster)
+        } else {
+            (Spectrum::black(), p_raster)
+        }
+    }
+
+    // the pixel containing the raster position
+    fn pixel_index(&self, p_raster: Point2<f64>) -> usize {
+        let resolution = self.camera.film.resolution;
+        let x = (p_raster.x as usize).min(resolution.x - 1);
+        let y = (p_raster.y as usize).min(resolution.y - 1);
+        y * resolution.x + x
+    }
+
+    // the sampler of a bootstrap path, the chains starting from it replay its first iteration
+    fn bootstrap_sampler(&self, seed: u64, index: usize) -> MltSampler {
+        MltSampler::new(hash(&[seed, index as u64]), self.sigma, self.large_step_probability)
+    }
+
+    /// run a chain of n_mutations, returns the radiance it splats by pixel, scaled by b
+    fn run_chain(&self, scene: &Scene, seed: u64, chain: usize, n_mutations: usize, bootstrap: &Distribution1D, b: f64) -> HashMap<usize, Spectrum> {
+        // the chains draw from sequences after those of the bootstrap paths
+        let mut rng = Rng::new();
+        rng.set_sequence(hash(&[seed, (self.n_bootstrap + chain) as u64]), 0);
+
+        // start from a bootstrap path, picked proportionally to its brightness
+        let (index, _) = bootstrap.sample_discrete(rng.uniform_f64());
+        let mut sampler = self.bootstrap_sampler(seed, index);
+        let (mut l_current, mut p_current) = self.l(scene, &mut sampler);
+
+        let mut splats = HashMap::new();
+        let mut splat = |p_raster: Point2<f64>, radiance: Spectrum| {
+            *splats.entry(self.pixel_index(p_raster)).or_insert(Spectrum::black()) += radiance;
+        };
+
+        for _ in 0..n_mutations {
+            sampler.start_iteration();
+            let (l_proposed, p_proposed) = self.l(scene, &mut sampler);
+
+            // both the current and the proposed paths contribute, weighted by the acceptance
+            let accept = (l_proposed.luminance() / l_current.luminance()).min(1.0);
+            if accept > 0.0 {
+                splat(p_proposed, l_proposed * (accept * b / l_proposed.luminance()));
+            }
+            splat(p_current, l_current * ((1.0 - accept) * b / l_current.luminance()));
+
+            if rng.uniform_f64() < accept {
+                l_current = l_proposed;
+                p_current = p_proposed;
+                sampler.accept();
+            } else {
+                sampler.reject();
+            }
+        }
+
+        splats
+    }
+}
+
+impl Integrator for MltIntegrator {
+    fn li(&self, _ray: &mut Ray, _scene: &Scene, _sampler: &mut dyn Sampler) -> Spectrum {
+        unreachable!("the metropolis light transport renders the whole film at once")
+    }
+
+    fn render(&self, scene: &Scene, setting: &WorldSetting) -> bool {
+        let n_thread = setting.n_thread;
+
+        // the brightness of independent paths, the chains start from them
+        let next = AtomicUsize::new(0);
+        let weights = Mutex::new(vec![0.0; self.n_bootstrap]);
+        thread::scope(|s| {
+            for _ in 0..n_thread {
+                let (next, weights) = (&next, &weights);
+                s.spawn(move || loop {
+                    let index = next.fetch_add(1, Ordering::Relaxed);
+                    if index >= self.n_bootstrap {
+                        break;
+                    }
+                    let mut sampler = self.bootstrap_sampler(setting.seed, index);
+                    let weight = self.l(scene, &mut sampler).0.luminance();
+                    weights.lock().unwrap()[index] = weight;
+                });
+            }
+        });
+        let weights = weights.into_inner().unwrap();
+        let b = weights.iter().sum::<f64>() / self.n_bootstrap as f64;
+        if b == 0.0 {
+            eprintln!("warning: no bootstrap path carries light, the image is black");
+            return true;
+        }
+        let bootstrap = Distribution1D::new(weights);
+
+        // the film divides the splats by the samples per pixel
+        let resolution = self.camera.film.resolution;
+        let n_mutations = setting.n_sample * resolution.x * resolution.y;
+        let n_chains = self.n_chains.min(n_mutations);
+        let chain_mutations = n_mutations.div_ceil(n_chains);
+
+        let bar = ProgressBar::new(n_chains as u64);
+        bar.set_message(format!("{} threads running...", n_thread));
+        bar.set_style(ProgressStyle::with_template("{msg}  {bar:40.cyan/blue} {pos:>7}/{len:7} chains [{elapsed_precise}]")
+            .unwrap()
+            .progress_chars("=>-"));
+
+        // the splats of the chains are added in their order, so that the image does not depend on the threads
+        let next = AtomicUsize::new(0);
+        let pending = Mutex::new((0, BTreeMap::new()));
+        thread::scope(|s| {
+            for _ in 0..n_thread {
+                let (next, pending, bootstrap, bar) = (&next, &pending, &bootstrap, bar.clone());
+                s.spawn(move || loop {
+                    let chain = next.fetch_add(1, Ordering::Relaxed);
+                    if chain >= n_chains {
+                        break;
+                    }
+                    let n = chain_mutations.min(n_mutations.saturating_sub(chain * chain_mutations));
+                    let splats = self.run_chain(scene, setting.seed, chain, n, bootstrap, b);
+
+                    let mut guard = pending.lock().unwrap();
+                    let (next_merged, finished) = &mut *guard;
+                    finished.insert(chain, splats);
+                    while let Some(splats) = finished.remove(next_merged) {
+                        for (index, radiance) in splats {
+                            let p = Point2::new((index % resolution.x) as f64 + 0.5, (index / resolution.x) as f64 + 0.5);
+                            self.camera.film.add_splat(p, radiance);
+                        }
+                        *next_merged += 1;
+                    }
+                    bar.inc(1);
+                });
+            }
+        });
+        bar.finish();
+
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        integrator::test_scene::{TestScene, matte, camera, render_pixels, WIDTH, HEIGHT},
+        geometry::{shape::cuboid::Cuboid, transform::Transform},
+    };
+    use cgmath::Vector3;
+
+    // a floor under a ceiling with a narrow slit along z, the light above the ceiling only reaches the room
+    // through the slit
+    fn slit_room() -> Scene {
+        let slab = |x: f64| Box::new(Cuboid::new(10.0, 0.1, 10.0, Transform::translate(Vector3::new(x, 1.0, 5.0))));
+        TestScene::new()
+            .floor(matte(0.5))
+            .shape(slab(-10.1), Some(matte(0.5)), None)
+            .shape(slab(10.1), Some(matte(0.5)), None)
+            .disk_light(Vector3::new(0.0, 3.0, 5.0), 1.0, 4.0)
+            .build()
+    }
+
+    fn luminance(pixels: &[Spectrum]) -> f64 {
+        pixels.iter().map(|s| s.luminance()).sum()
+    }
+
+    #[test]
+    fn test_mlt_slit() {
+        let mlt = |n_thread| {
+            let camera = camera();
+            let integrator = MltIntegrator::new(5, true, Arc::clone(&camera)).with_chains(100000, 100);
+            render_pixels(camera, slit_room(), Box::new(integrator), 64, n_thread)
+        };
+
+        // the chains are splatted in the same order whatever the number of threads
+        let single = mlt(1);
+        assert_eq!(single, mlt(3));
+
+        let path = render_pixels(camera(), slit_room(), Box::new(PathIntegrator::new(5, true)), 256, 2);
+        let (mlt_total, path_total) = (luminance(&single), luminance(&path));
+        assert!((mlt_total - path_total).abs() < 0.05 * path_total, "mlt {} path {}", mlt_total, path_total);
+
+        // most of the light lands on the strip of floor under the slit, where the chains have to stay
+        let strip = |pixels: &[Spectrum]| (HEIGHT / 2..HEIGHT).map(|i| luminance(&pixels[i * WIDTH + 7..i * WIDTH + 13])).sum::<f64>();
+        let (mlt, path) = (strip(&single), strip(&path));
+        assert!(path > 0.5 * path_total, "strip {} image {}", path, path_total);
+        assert!((mlt - path).abs() < 0.1 * path, "mlt {} path {}", mlt, path);
+    }
+}
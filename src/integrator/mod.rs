@@ -1,21 +1,69 @@
 pub mod path_integrator;
-// pub mod wrs_direct_integrator;
+pub mod wrs_direct_integrator;
 pub mod direct_integrator;
+pub mod volpath_integrator;
+pub mod bdpt_integrator;
+pub mod sppm_integrator;
+pub mod mlt_integrator;
+#[cfg(test)]
+pub mod test_scene;
 
-use std::sync::Arc;
+use cgmath::{InnerSpace, Point3, Vector3};
 
-use cgmath::{InnerSpace, Point3};
-
-use crate::{geometry::{ray::Ray, interaction::SurfaceInteraction}, spectrum::Spectrum, scene::Scene, sampler::Sampler};
+use crate::{camera::film::Splat, geometry::{ray::Ray, interaction::SurfaceInteraction}, spectrum::Spectrum, scene::Scene, sampler::Sampler, bxdf::bsdf::Bsdf, WorldSetting};
 
 pub trait Integrator: Sync + Send {
-    fn li(&self, ray: &mut Ray, scene: &Scene, sampler: &Arc<dyn Sampler>) -> Spectrum;
+    fn li(&self, ray: &mut Ray, scene: &Scene, sampler: &mut dyn Sampler) -> Spectrum;
+    /// li for the integrators whose paths also reach other pixels of the film, such as by tracing them
+    /// from the lights, the radiance they add there is pushed to splats with its raster position
+    fn li_splat(&self, ray: &mut Ray, scene: &Scene, sampler: &mut dyn Sampler, _splats: &mut Vec<Splat>) -> Spectrum {
+        self.li(ray, scene, sampler)
+    }
+    /// the radiance of li_splat split into the direct lighting, the emission seen by the ray and the light
+    /// reaching its first hit straight from the lights, and the rest, the integrators that can not tell
+    /// them apart return all of it as direct
+    fn li_split(&self, ray: &mut Ray, scene: &Scene, sampler: &mut dyn Sampler, splats: &mut Vec<Splat>) -> (Spectrum, Spectrum) {
+        (self.li_splat(ray, scene, sampler, splats), Spectrum::black())
+    }
+    /// whether li_split tells the direct lighting from the rest
+    fn can_split(&self) -> bool {
+        false
+    }
+    /// the integrators that can not estimate the pixels one by one render the whole film themselves
+    /// and return true, the others are rendered through li
+    fn render(&self, _scene: &Scene, _setting: &WorldSetting) -> bool {
+        false
+    }
+}
+
+fn power_heuristic(n1: i32, p1: f64, n2: i32, p2: f64) -> f64 {
+    let np1 = n1 as f64 * p1;
+    let np2 = n2 as f64 * p2;
+    (np1 * np1) / (np1 * np1 + np2 * np2)
+}
+
+// the bxdfs scale the refracted radiance by 1 / eta^2 and ignore that the shading normal differs from
+// the geometric one, neither holds for the importance carried by the paths traced from the lights
+fn importance_scale(bsdf: &Bsdf, wo: Vector3<f64>, wi: Vector3<f64>) -> f64 {
+    let denom = (wo.dot(bsdf.ng) * wi.dot(bsdf.ns)).abs();
+    if denom == 0.0 {
+        return 0.0;
+    }
+    let shading = (wo.dot(bsdf.ns) * wi.dot(bsdf.ng)).abs() / denom;
+
+    let refraction = if wo.dot(bsdf.ng) * wi.dot(bsdf.ng) >= 0.0 {
+        1.0
+    } else if wo.dot(bsdf.ng) > 0.0 {
+        bsdf.eta * bsdf.eta
+    } else {
+        1.0 / (bsdf.eta * bsdf.eta)
+    };
+
+    shading * refraction
 }
 
 fn visibility_test(isect: &SurfaceInteraction, sample_p: Point3<f64>, scene: &Scene) -> bool {
         let shadow_ray = Ray::new(isect.geo.p, sample_p-isect.geo.p, isect.time, 1.0-0.0001);
-        // back facing surfaces do not get lit
-        if shadow_ray.d.dot(isect.geo.n) < 0.0 { return false; }
         // test intersection 
         match scene.intersect_p(&shadow_ray) {
             Some(_t) => false,
@@ -1,61 +1,60 @@
-use core::panic;
-use std::{f64::INFINITY, sync::Arc};
-
 use cgmath::InnerSpace;
 
-use crate::{spectrum::Spectrum, geometry::{ray::Ray, interaction::SurfaceInteraction}, scene::Scene, sampler::Sampler, bxdf::bsdf::Bsdf};
-use super::{Integrator, visibility_test};
+use crate::{spectrum::Spectrum, geometry::{ray::Ray, interaction::SurfaceInteraction}, scene::Scene, sampler::Sampler, bxdf::bsdf::Bsdf, camera::film::Splat};
+use super::{Integrator, visibility_test, power_heuristic};
+
+// the bounces a path makes before russian roulette may end it
+const RR_DEPTH: usize = 3;
 
 pub struct PathIntegrator {
-    pub max_depth: usize,
+    pub max_depth: usize, // usize::MAX leaves the length of the paths to russian roulette
     pub b_mis: bool,
+    pub rr_depth: usize,
 }
 
 impl PathIntegrator {
     pub fn new(max_depth: usize, b_mis: bool) -> Self {
-        PathIntegrator { max_depth, b_mis }
+        PathIntegrator { max_depth, b_mis, rr_depth: RR_DEPTH }
     }
-}
 
-fn power_heuristic(n1: i32, p1: f64, n2: i32, p2: f64) -> f64 {
-    let np1 = n1 as f64 * p1;
-    let np2 = n2 as f64 * p2;
-    (np1 * np1) / (np1 * np1 + np2 * np2)
+    /// the bounces before russian roulette starts, usize::MAX never ends the paths randomly
+    pub fn with_russian_roulette(mut self, rr_depth: usize) -> Self {
+        self.rr_depth = rr_depth;
+        self
+    }
 }
 
-fn multiple_importance_sampling(scene: &Scene, sampler: &Arc<dyn Sampler>, bsdf: &Bsdf, isect: &SurfaceInteraction, ray: &Ray, throughput: Spectrum) -> Spectrum {
+pub(super) fn multiple_importance_sampling(scene: &Scene, sampler: &mut dyn Sampler, bsdf: &Bsdf, isect: &SurfaceInteraction, ray: &Ray, throughput: Spectrum) -> Spectrum {
     let mut res = Spectrum::black();
 
     {
-        // let (light, light_pdf) = scene.lightlist.importance_sample_light(sampler.get_2d());
-        let (light, light_pdf) = scene.lightlist.uniform_pick_light(sampler.get_2d().x);
-
-        // sample light
-        let light_sample = light.sample_li(&isect, sampler.get_2d());
-        let l_pdf = light_sample.pdf_area_to_solid(&isect) * light_pdf;
-        let li = light_sample.le;
-
-        res += if l_pdf > 0.0 && !li.is_black() && visibility_test(&isect, light_sample.position, scene) {
-            let wi = -light_sample.dir.normalize();
-            let wo = -ray.d.normalize();
-            let rho = bsdf.f(wo, wi);
-            let b_pdf = bsdf.pdf(wo, wi);
-            let cosine = wi.dot(isect.geo.n).abs();
-
-            let weight = if light_sample.is_delta { 
-                power_heuristic(1, l_pdf, 1, b_pdf)
-            } else {
-                1.0
-            };
+        // sample light, no light may be able to lit the point in which case only the bsdf is sampled
+        let picked = scene.lightlist.sample(isect.geo.p, isect.geo.n, sampler.get_1d());
+        let u_light = sampler.get_2d();
 
-            weight * li * throughput * rho * cosine / l_pdf
-        } else {
-            Spectrum::black()
-        };
+        if let Some((light, light_pdf)) = picked {
+            let light_sample = light.sample_li(isect.geo.p, u_light);
+            let l_pdf = light_sample.pdf_area_to_solid(isect.geo.p) * light_pdf;
+            let li = light_sample.le;
 
-        if light_sample.is_delta {
-            // if the sampled light is delta light, do not sample the bsdf cause there's no need
-            return res;
+            res += if l_pdf > 0.0 && !li.is_black() && visibility_test(isect, light_sample.position, scene) {
+                let wi = -light_sample.dir.normalize();
+                let wo = -ray.d.normalize();
+                let rho = bsdf.f(wo, wi);
+                let b_pdf = bsdf.pdf(wo, wi);
+                let cosine = wi.dot(bsdf.ns).abs();
+
+                // delta lights can not be hit by the bsdf samples, so they take the full weight
+                let weight = if light_sample.is_delta { 
+                    1.0
+                } else {
+                    power_heuristic(1, l_pdf, 1, b_pdf)
+                };
+
+                weight * li * throughput * rho * cosine / l_pdf
+            } else {
+                Spectrum::black()
+            };
         }
     }
 
@@ -64,26 +63,33 @@ fn multiple_importance_sampling(scene: &Scene, sampler: &Arc<dyn Sampler>, bsdf:
         let wo = -ray.d.normalize();
         let bsdf_sample = bsdf.sample_f(wo, sampler.get_2d());
 
+        // the light samples can not reach a delta direction, the path counts it at its next vertex
+        if bsdf_sample.is_delta {
+            return res;
+        }
+
         let wi = bsdf_sample.wi;
         let b_pdf = bsdf_sample.pdf;
         let rho = bsdf_sample.rho;
 
         // shoot a ray
-        let mut new_ray = Ray::new(isect.geo.p, wi, ray.time, INFINITY);
-        let (li, l_pdf) = if let Some(inter) = scene.intersect(&mut new_ray) {
+        let mut new_ray = Ray::new(isect.geo.p, wi, ray.time, f64::INFINITY);
+        let (li, l_pdf) = if let Some(inter) = scene.intersect_surface(&mut new_ray) {
             if inter.hit_light {
-                let l_pdf = inter.light.unwrap().pdf(inter.geo.p, inter.geo.n, isect.geo.p);
+                let light = inter.light.unwrap();
+                let l_pdf = light.pdf(inter.geo.p, inter.geo.n, isect.geo.p) * scene.lightlist.pmf(isect.geo.p, isect.geo.n, &light);
                 (inter.radiance.unwrap(), l_pdf)
             } else {
                 (Spectrum::black(), 0.0)
             }
         } else {
-            (Spectrum::black(), 0.0)
+            // the escaped ray is lit by the infinite lights
+            (scene.le_escaped(&new_ray), scene.pdf_escaped(isect.geo.p, isect.geo.n, wi))
         };
 
 
         res += if b_pdf > 0.0 && !li.is_black() {
-            let cosine = wi.dot(isect.geo.n).abs();
+            let cosine = wi.dot(bsdf.ns).abs();
             let weight = power_heuristic(1, b_pdf, 1, l_pdf);
             weight * li * throughput * rho * cosine / b_pdf
         } else {
@@ -94,21 +100,22 @@ fn multiple_importance_sampling(scene: &Scene, sampler: &Arc<dyn Sampler>, bsdf:
     res
 }
 
-fn sample_one_light(scene: &Scene, sampler: &Arc<dyn Sampler>, bsdf: &Bsdf, isect: &SurfaceInteraction, ray: &Ray, throughput: Spectrum) -> Spectrum {
-    // let (light, light_pdf) = scene.lightlist.importance_sample_light(sampler.get_2d());
-    
-    let (light, light_pdf) = scene.lightlist.uniform_pick_light(sampler.get_2d().x);
+fn sample_one_light(scene: &Scene, sampler: &mut dyn Sampler, bsdf: &Bsdf, isect: &SurfaceInteraction, ray: &Ray, throughput: Spectrum) -> Spectrum {
+    let (light, light_pdf) = match scene.lightlist.sample(isect.geo.p, isect.geo.n, sampler.get_1d()) {
+        Some(picked) => picked,
+        None => return Spectrum::black(),
+    };
 
-    let light_sample = light.sample_li(&isect, sampler.get_2d());
+    let light_sample = light.sample_li(isect.geo.p, sampler.get_2d());
 
-    let l_pdf = light_sample.pdf_area_to_solid(&isect) * light_pdf;
+    let l_pdf = light_sample.pdf_area_to_solid(isect.geo.p) * light_pdf;
 
     let li = light_sample.le;
 
     if l_pdf > 0.0 && !li.is_black() && visibility_test(&isect, light_sample.position, scene) {
         let wi = -light_sample.dir;
         let rho = bsdf.f(-ray.d.normalize(), wi);
-        let cosine = wi.dot(isect.geo.n).abs();
+        let cosine = wi.dot(bsdf.ns).abs();
 
         li * throughput * rho * cosine / l_pdf
     } else {
@@ -117,12 +124,22 @@ fn sample_one_light(scene: &Scene, sampler: &Arc<dyn Sampler>, bsdf: &Bsdf, isec
 }
 
 impl Integrator for PathIntegrator {
-    fn li(&self, ray: &mut Ray, scene: &Scene, sampler: &Arc<dyn Sampler>) -> Spectrum {
+    fn li(&self, ray: &mut Ray, scene: &Scene, sampler: &mut dyn Sampler) -> Spectrum {
+        let (direct, indirect) = self.li_split(ray, scene, sampler, &mut Vec::new());
+        direct + indirect
+    }
+
+    fn li_split(&self, ray: &mut Ray, scene: &Scene, sampler: &mut dyn Sampler, _splats: &mut Vec<Splat>) -> (Spectrum, Spectrum) {
         let mut throughput = Spectrum::new(1.0, 1.0, 1.0);
         let mut radiance = Spectrum::new(0.0, 0.0, 0.0);
         let mut specular = false;
+        // the scale of the radiance by the refractions, which russian roulette does not take as a loss
+        let mut eta_scale = 1.0;
+        // the radiance gathered before the first bounce, the emission seen and the lights sampled at the first hit
+        let mut direct = None;
 
-        for depth in 0..self.max_depth {
+        let mut depth = 0;
+        while depth < self.max_depth {
             if let Some(isect) = scene.intersect(ray) {
                 // hit the light after shot from the camera or leaving a specular vertex
                 if isect.hit_light {
@@ -133,8 +150,6 @@ impl Integrator for PathIntegrator {
                 }
 
                 if let Some(mat) = &isect.material {
-                    // check if it's specular vertex
-                    specular = mat.is_specular();
                     let bsdf = mat.compute_scattering(&isect);
                     // sample lights to estimate the radiance value
                     // if !specular && self.b_mis {
@@ -148,24 +163,197 @@ impl Integrator for PathIntegrator {
                     let sample = sampler.get_2d();
                     let bxdf_sample = bsdf.sample_f(-ray.d.normalize(), sample);
                     let (rho, wi, pdf) = (bxdf_sample.rho, bxdf_sample.wi, bxdf_sample.pdf);
+                    if pdf <= 0.0 || rho.is_black() {
+                        break;
+                    }
+
+                    // a delta direction is only lit through the next vertex, be the material specular or not
+                    specular = bxdf_sample.is_delta;
 
                     // update the throughput for next iteration, spawn the new ray
-                    let cosine = wi.dot(isect.geo.n).abs();
+                    let cosine = wi.dot(bsdf.ns).abs();
                     throughput *= rho * cosine / pdf;
-                    *ray = Ray::new(isect.geo.p, wi, ray.time, INFINITY);
+                    let wo = -ray.d.normalize();
+                    if wo.dot(isect.geo.n) * wi.dot(isect.geo.n) < 0.0 {
+                        eta_scale *= if wo.dot(isect.geo.n) > 0.0 { bsdf.eta * bsdf.eta } else { 1.0 / (bsdf.eta * bsdf.eta) };
+                    }
+
+                    // russian roulette, the paths carrying little radiance are more likely to end and the
+                    // surviving ones make up for them, every path may end so that the unbounded ones do
+                    if depth >= self.rr_depth {
+                        let q = (1.0 - (throughput * eta_scale).max_component()).max(0.05);
+                        if sampler.get_1d() < q {
+                            break;
+                        }
+                        throughput /= 1.0 - q;
+                    }
+
+                    // only specular bounces keep track of the pixel footprint
+                    let differential = if bxdf_sample.is_delta { isect.specular_differential(ray, wi, bsdf.eta) } else { None };
+                    *ray = Ray::new(isect.geo.p, wi, ray.time, f64::INFINITY);
+                    ray.differential = differential;
                 } else {
-                    // hit the medium, currently not implemented
-                    panic!();
+                    // the surface only bounds a medium, which this integrator ignores, so the ray goes on
+                    // without counting a bounce
+                    let medium = isect.get_medium(ray.d, &ray.medium);
+                    let differential = ray.differential;
+                    *ray = Ray::new_in_medium(isect.geo.p, ray.d, ray.time, f64::INFINITY, medium);
+                    ray.differential = differential;
+                    continue;
                 }
                 
             } else {
-                // does not hit the scene
-                // radiance += Spectrum::skyblue(ray.d.y) * throughput;
+                // does not hit the scene, the infinite lights are only added here when they were not sampled
+                if depth == 0 || specular {
+                    radiance += throughput * scene.le_escaped(ray);
+                }
                 break;
             }
+
+            if depth == 0 {
+                direct = Some(radiance);
+            }
+            depth += 1;
         }
 
-        radiance
+        let direct = direct.unwrap_or(radiance);
+        (direct, radiance - direct)
     }
 
-}
\ No newline at end of file
+    fn can_split(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{f64::consts::PI, sync::Arc};
+
+    use cgmath::{Point2, Point3, Vector3};
+
+    use super::*;
+    use crate::{
+        geometry::{shape::sphere::Sphere, transform::Transform},
+        geometry::shape::disk::Disk,
+        integrator::{direct_integrator::DirectIntegrator, volpath_integrator::VolPathIntegrator, test_scene::{TestScene, matte, camera, mean, render_luminance}},
+        light::{area::AreaLight, point::PointLight},
+        material::{matte::Matte, roughglass::RoughGlass},
+        texture::{constant::ConstantTexture, imagemap::ImageTexture, mapping::uv::UVMapping, mipmap::{MIPMap, WrapMode, FilterMode}},
+        medium::MediumInterface,
+        sampler::uniform_sampler::UniformSampler,
+    };
+
+    #[test]
+    fn test_point_light() {
+        // a matte floor at y = -1 under a point light 2 units above it
+        let light = PointLight::new(Point3::new(0.0, 1.0, 5.0), Spectrum::new(4.0, 4.0, 4.0));
+        let scene = TestScene::new().floor(matte(0.5)).light(Arc::new(light)).build();
+
+        // the floor right under the light receives I / d^2, nothing else lights it
+        let expected = 0.5 / PI * 4.0 / 4.0;
+        for b_mis in [true, false] {
+            let integrator = PathIntegrator::new(5, b_mis);
+            let mut sampler = UniformSampler::new(1, 3);
+            let mut ray = Ray::new(Point3::new(0.0, 0.0, 5.0), Vector3::new(0.0, -1.0, 0.0), 0.0, f64::INFINITY);
+            let radiance = integrator.li(&mut ray, &scene, &mut sampler);
+            assert!((radiance.r - expected).abs() < 1e-6, "mis {}: {} != {}", b_mis, radiance.r, expected);
+        }
+    }
+
+    #[test]
+    fn test_medium_boundaries_do_not_occlude() {
+        // a sphere bounding no medium hangs between the light and the floor, which it must not shadow
+        let lit_floor = |with_boundary: bool| {
+            let mut scene = TestScene::new().floor(matte(0.5));
+            if with_boundary {
+                let object_to_world = Transform::translate(Vector3::new(0.0, 1.0, 5.0));
+                let ball = Sphere::new(object_to_world.clone(), object_to_world.inverse(), 0.5);
+                scene = scene.shape(Box::new(ball), None, Some(MediumInterface::new(None, None)));
+            }
+            scene.disk_light(Vector3::new(0.0, 3.0, 5.0), 1.0, 4.0).build()
+        };
+
+        let integrators: [fn() -> Box<dyn Integrator>; 2] = [
+            || Box::new(PathIntegrator::new(5, true)),
+            || Box::new(DirectIntegrator::new(5)),
+        ];
+        for integrator in integrators {
+            let open = mean(&render_luminance(camera(), lit_floor(false), integrator(), 16, 2));
+            let bounded = mean(&render_luminance(camera(), lit_floor(true), integrator(), 16, 2));
+            // the paths are the same, they are only split where they cross the sphere
+            assert!(open > 0.0);
+            assert!((bounded - open).abs() < 1e-9 * open, "bounded {} open {}", bounded, open);
+        }
+    }
+
+    #[test]
+    fn test_medium_boundaries_keep_differentials() {
+        // a fine checker on the floor, seen through a ball around the camera that bounds no medium, is filtered
+        // over the footprint of the pixels as it is without the ball
+        let checkered_floor = |with_boundary: bool| {
+            let texels = (0..64 * 64).map(|i| if (i % 64 + i / 64) % 2 == 0 { Spectrum::new(1.0, 1.0, 1.0) } else { Spectrum::black() }).collect();
+            let mipmap = MIPMap::new(texels, Point2::new(64, 64), WrapMode::Repeat, FilterMode::Trilinear, 8.0);
+            let checker = ImageTexture::new(Box::new(UVMapping::new(8.0, 8.0, 0.0, 0.0)), Arc::new(mipmap));
+            let mut scene = TestScene::new().floor(Arc::new(Matte::new(Arc::new(checker))));
+            if with_boundary {
+                let object_to_world = Transform::translate(Vector3::new(0.0, 0.0, 0.0));
+                let ball = Sphere::new(object_to_world.clone(), object_to_world.inverse(), 0.5);
+                scene = scene.shape(Box::new(ball), None, Some(MediumInterface::new(None, None)));
+            }
+            scene.disk_light(Vector3::new(0.0, 3.0, 5.0), 1.0, 4.0).build()
+        };
+
+        let open = render_luminance(camera(), checkered_floor(false), Box::new(PathIntegrator::new(1, true)), 4, 2);
+        let bounded = render_luminance(camera(), checkered_floor(true), Box::new(PathIntegrator::new(1, true)), 4, 2);
+        assert!(open.iter().any(|&l| l > 0.0));
+        for (open, bounded) in open.iter().zip(bounded.iter()) {
+            assert!((bounded - open).abs() <= 1e-9 * open, "bounded {} open {}", bounded, open);
+        }
+    }
+
+    #[test]
+    fn test_light_samples_through_rough_glass() {
+        // a pane of rough glass between the camera and a light facing it, seen only through the pane, which
+        // the light samples reach from behind the surface
+        let facing_camera = |z: f64| Transform::translate(Vector3::new(0.0, 0.0, z)) * Transform::rotate(180.0, Vector3::new(1.0, 0.0, 0.0));
+        let scene = || {
+            let pane = Disk::new(facing_camera(5.0), facing_camera(5.0).inverse(), 4.0);
+            let white = Arc::new(ConstantTexture::new(Spectrum::new(1.0, 1.0, 1.0)));
+            let roughness = Arc::new(ConstantTexture::new(0.3));
+            let glass = RoughGlass::new(Arc::new(ConstantTexture::new(1.0)), Arc::new(ConstantTexture::new(1.5)), white.clone(), white, roughness.clone(), roughness);
+            let disk = Disk::new(facing_camera(6.0), facing_camera(6.0).inverse(), 1.0);
+            let light = AreaLight::new(Box::new(disk), Spectrum::new(4.0, 4.0, 4.0));
+            TestScene::new().shape(Box::new(pane), Some(Arc::new(glass)), None).light(Arc::new(light)).build()
+        };
+
+        // the volumetric path integrator traces its shadow rays with the transmittance, whichever side the light is on
+        let path = mean(&render_luminance(camera(), scene(), Box::new(PathIntegrator::new(5, true)), 64, 2));
+        let volpath = mean(&render_luminance(camera(), scene(), Box::new(VolPathIntegrator::new(5)), 64, 2));
+        assert!(volpath > 0.0);
+        assert!((path - volpath).abs() < 0.03 * volpath, "path {} volpath {}", path, volpath);
+    }
+
+    // the sphere lying on the floor, the light bounces between them
+    fn sphere_on_floor() -> Scene {
+        TestScene::new()
+            .floor(matte(0.8))
+            .sphere(matte(0.8))
+            .disk_light(Vector3::new(0.0, 3.0, 5.0), 1.0, 4.0)
+            .build()
+    }
+
+    #[test]
+    fn test_russian_roulette_unbiased() {
+        let mean_of = |integrator: PathIntegrator| mean(&render_luminance(camera(), sphere_on_floor(), Box::new(integrator), 64, 2));
+
+        // the unbounded paths ended by russian roulette converge to the long bounded ones
+        let bounded = mean_of(PathIntegrator::new(30, true).with_russian_roulette(usize::MAX));
+        let roulette = mean_of(PathIntegrator::new(usize::MAX, true).with_russian_roulette(0));
+        assert!(bounded > 0.0);
+        assert!((roulette - bounded).abs() < 0.02 * bounded, "roulette {} bounded {}", roulette, bounded);
+
+        // the short paths miss the light bouncing between the sphere and the floor
+        let short = mean_of(PathIntegrator::new(1, true));
+        assert!(short < 0.95 * bounded, "short {} bounded {}", short, bounded);
+    }
+}
@@ -0,0 +1,475 @@
+use std::{
+    collections::HashMap,
+    f64::consts::PI,
+    sync::{Arc, Mutex, atomic::{AtomicUsize, Ordering}},
+    thread,
+};
+
+use cgmath::{InnerSpace, Point2, Point3, Vector3};
+use indicatif::{ProgressBar, ProgressStyle};
+
+use crate::{
+    spectrum::Spectrum,
+    geometry::{ray::Ray, bound3::Bound3},
+    scene::Scene,
+    sampler::{Sampler, rng::Rng, lowdiscrepancy::hash},
+    bxdf::{bsdf::Bsdf, BxdfType},
+    camera::{Camera, CameraSample, film::FilmTile, perspective::PerspectiveCamera},
+    WorldSetting,
+};
+use super::{Integrator, importance_scale, path_integrator::multiple_importance_sampling};
+
+// the photons traced by a thread at once, their contributions are summed in the order of the chunks
+// so that the image does not depend on the number of threads
+const PHOTON_CHUNK: usize = 4096;
+
+/// stochastic progressive photon mapping, every iteration finds the first diffuse or glossy point seen
+/// through each pixel, then gathers the photons traced from the lights around them and shrinks the
+/// radius of the pixels that received photons
+///
+/// it renders the whole film, one iteration per sample of the pixels, and resolves the caustics of the
+/// specular surfaces lit by any light, including the point lights
+pub struct SppmIntegrator {
+    pub max_depth: usize,
+    pub photons_per_iteration: usize,
+    pub initial_radius: f64,
+    camera: Arc<PerspectiveCamera>,
+}
+
+impl SppmIntegrator {
+    pub fn new(max_depth: usize, photons_per_iteration: usize, initial_radius: f64, camera: Arc<PerspectiveCamera>) -> Self {
+        SppmIntegrator {
+            max_depth,
+            photons_per_iteration,
+            initial_radius,
+            camera,
+        }
+    }
+}
+
+/// the point a photon is gathered at, with the throughput of the camera path reaching it
+struct VisiblePoint {
+    p: Point3<f64>,
+    wo: Vector3<f64>,
+    bsdf: Bsdf,
+    beta: Spectrum,
+}
+
+/// the estimate of a pixel, refined over the iterations
+struct SppmPixel {
+    radius: f64,
+    ld: Spectrum, // the sum of the direct lighting and the emission seen through the pixel
+    n: f64, // the photons accumulated so far
+    tau: Spectrum, // the flux of the accumulated photons
+    vp: Option<VisiblePoint>,
+}
+
+/// the visible points are hashed by the cells of a uniform grid they overlap
+struct VisiblePointGrid {
+    bounds: Bound3,
+    resolution: [usize; 3],
+    cells: Vec<Vec<usize>>,
+}
+
+impl VisiblePointGrid {
+    fn new(pixels: &[SppmPixel]) -> Self {
+        let mut bounds: Option<Bound3> = None;
+        let mut max_radius: f64 = 0.0;
+        for pixel in pixels {
+            if let Some(vp) = &pixel.vp {
+                let b = Bound3::new(vp.p, vp.p).expand(pixel.radius);
+                bounds = Some(match bounds {
+                    Some(bounds) => bounds.union(&b),
+                    None => b,
+                });
+                max_radius = max_radius.max(pixel.radius);
+            }
+        }
+
+        let bounds = bounds.unwrap_or(Bound3::new(Point3::new(0.0, 0.0, 0.0), Point3::new(0.0, 0.0, 0.0)));
+        // the cells are about as large as the largest radius
+        let diagonal = bounds.diagonal();
+        let max_diagonal = diagonal.x.max(diagonal.y).max(diagonal.z);
+        let base_resolution = if max_radius > 0.0 { max_diagonal / max_radius } else { 1.0 };
+        let resolution = [0, 1, 2].map(|axis| ((base_resolution * diagonal[axis] / max_diagonal) as usize).clamp(1, 1 << 20));
+
+        let mut grid = VisiblePointGrid {
+            bounds,
+            resolution,
+            cells: vec![Vec::new(); pixels.len().max(1)],
+        };
+
+        for (index, pixel) in pixels.iter().enumerate() {
+            if let Some(vp) = &pixel.vp {
+                let radius = Vector3::new(pixel.radius, pixel.radius, pixel.radius);
+                let (p_min, p_max) = (grid.to_grid(vp.p - radius), grid.to_grid(vp.p + radius));
+                for z in p_min[2]..=p_max[2] {
+                    for y in p_min[1]..=p_max[1] {
+                        for x in p_min[0]..=p_max[0] {
+                            let h = grid.hash([x, y, z]);
+                            grid.cells[h].push(index);
+                        }
+                    }
+                }
+            }
+        }
+
+        grid
+    }
+
+    // the cell containing p, clamped to the grid
+    fn to_grid(&self, p: Point3<f64>) -> [usize; 3] {
+        let offset = self.bounds.offset(p);
+        [0, 1, 2].map(|axis| {
+            let o = if offset[axis].is_finite() { offset[axis] } else { 0.0 };
+            ((o * self.resolution[axis] as f64).max(0.0) as usize).min(self.resolution[axis] - 1)
+        })
+    }
+
+    fn hash(&self, p: [usize; 3]) -> usize {
+        let h = (p[0].wrapping_mul(73856093)) ^ (p[1].wrapping_mul(19349663)) ^ (p[2].wrapping_mul(83492791));
+        h % self.cells.len()
+    }
+
+    /// the pixels whose visible point may be within their radius of p
+    fn lookup(&self, p: Point3<f64>) -> Option<&[usize]> {
+        if !self.bounds.contains(p) {
+            return None;
+        }
+        Some(&self.cells[self.hash(self.to_grid(p))])
+    }
+}
+
+// whether the bsdf has a lobe of the type
+fn has_lobe(bsdf: &Bsdf, flag: BxdfType) -> bool {
+    let flag = flag as i32;
+    bsdf.bxdfs.iter().any(|bxdf| bxdf.types() & flag != 0)
+}
+
+impl SppmIntegrator {
+    /// trace the camera path of a pixel up to its first diffuse surface, or the last glossy one
+    fn camera_pass(&self, scene: &Scene, sampler: &mut dyn Sampler, pixel: Point2<usize>, iteration: usize, n_iterations: usize) -> (Spectrum, Option<VisiblePoint>) {
+        sampler.start_pixel_sample(pixel, iteration);
+        let offset = sampler.get_pixel_2d();
+        let sample = CameraSample::new(Point2::new(pixel.x as f64 + offset.x, pixel.y as f64 + offset.y), 0.0);
+        let mut ray = self.camera.generate_ray(sample);
+        ray.scale_differentials((1.0 / (n_iterations as f64).sqrt()).max(0.125));
+
+        let mut ld = Spectrum::black();
+        let mut beta = Spectrum::new(1.0, 1.0, 1.0);
+        let mut specular = false;
+
+        let mut depth = 0;
+        while depth < self.max_depth {
+            let isect = match scene.intersect(&mut ray) {
+                Some(isect) => isect,
+                None => {
+                    // the infinite lights were sampled by the previous vertex unless it is specular
+                    if depth == 0 || specular {
+                        ld += beta * scene.le_escaped(&ray);
+                    }
+                    break;
+                }
+            };
+
+            if isect.hit_light {
+                if depth == 0 || specular {
+                    ld += beta * isect.radiance.unwrap();
+                }
+                break;
+            }
+
+            let material = match &isect.material {
+                Some(material) => material,
+                None => {
+                    // the surface only bounds a medium, the ray goes on without counting a bounce
+                    let differential = ray.differential;
+                    ray = Ray::new(isect.geo.p, ray.d, ray.time, f64::INFINITY);
+                    ray.differential = differential;
+                    continue;
+                }
+            };
+
+            let bsdf = material.compute_scattering(&isect);
+            let wo = -ray.d.normalize();
+            let is_diffuse = has_lobe(&bsdf, BxdfType::Diffuse);
+            let is_glossy = has_lobe(&bsdf, BxdfType::Glossy);
+            // the lights seen through the specular surfaces are added when they are hit
+            if is_diffuse || is_glossy {
+                ld += multiple_importance_sampling(scene, sampler, &bsdf, &isect, &ray, beta);
+            }
+            if is_diffuse || (is_glossy && depth == self.max_depth - 1) {
+                let vp = VisiblePoint { p: isect.geo.p, wo, bsdf, beta };
+                return (ld, Some(vp));
+            }
+
+            let bsdf_sample = bsdf.sample_f(wo, sampler.get_2d());
+            if bsdf_sample.pdf <= 0.0 || bsdf_sample.rho.is_black() {
+                break;
+            }
+            specular = bsdf_sample.is_delta;
+            beta *= bsdf_sample.rho * bsdf_sample.wi.dot(bsdf.ns).abs() / bsdf_sample.pdf;
+
+            let differential = if specular { isect.specular_differential(&ray, bsdf_sample.wi, bsdf.eta) } else { None };
+            ray = Ray::new(isect.geo.p, bsdf_sample.wi, ray.time, f64::INFINITY);
+            ray.differential = differential;
+            depth += 1;
+        }
+
+        (ld, None)
+    }
+
+    /// trace a photon from the lights, the flux it brings to the visible points around its hits is
+    /// added to contributions by pixel, with the number of photons gathered
+    fn trace_photon(&self, scene: &Scene, pixels: &[SppmPixel], grid: &VisiblePointGrid, rng: &mut Rng, contributions: &mut HashMap<usize, (Spectrum, usize)>) {
+        let mut u2 = || Point2::new(rng.uniform_f64(), rng.uniform_f64());
+        let u_light = u2().x;
+        let (u_pos, u_dir) = (u2(), u2());
+
+        let (light, pmf) = match scene.lightlist.sample_emitter(u_light) {
+            Some(picked) => picked,
+            None => return,
+        };
+        let emission = light.sample_le(u_pos, u_dir, 0.0);
+        if emission.pdf_pos == 0.0 || emission.pdf_dir == 0.0 || emission.le.is_black() {
+            return;
+        }
+
+        let mut ray = emission.ray;
+        // the lights at a point emit the same in every direction
+        let cos_theta = if light.is_delta() { 1.0 } else { emission.normal.dot(ray.d).abs() };
+        let mut beta = emission.le * cos_theta / (pmf * emission.pdf_pos * emission.pdf_dir);
+
+        let mut depth = 0;
+        while depth < self.max_depth {
+            let isect = match scene.intersect(&mut ray) {
+                Some(isect) => isect,
+                None => break,
+            };
+            // the lights absorb the photons
+            if isect.hit_light {
+                break;
+            }
+
+            let material = match &isect.material {
+                Some(material) => material,
+                None => {
+                    ray = Ray::new(isect.geo.p, ray.d, ray.time, f64::INFINITY);
+                    continue;
+                }
+            };
+
+            // the photons arriving straight from the lights are the direct lighting, already estimated
+            let wi = -ray.d.normalize();
+            if depth > 0 {
+                if let Some(candidates) = grid.lookup(isect.geo.p) {
+                    for &index in candidates {
+                        let pixel = &pixels[index];
+                        let vp = pixel.vp.as_ref().unwrap();
+                        if (vp.p - isect.geo.p).magnitude2() > pixel.radius * pixel.radius {
+                            continue;
+                        }
+
+                        let phi = beta * vp.bsdf.f(vp.wo, wi);
+                        let entry = contributions.entry(index).or_insert((Spectrum::black(), 0));
+                        entry.0 += phi;
+                        entry.1 += 1;
+                    }
+                }
+            }
+
+            let bsdf = material.compute_scattering(&isect);
+            let sample = bsdf.sample_f(wi, u2());
+            if sample.pdf <= 0.0 || sample.rho.is_black() {
+                break;
+            }
+
+            let scattered = beta * sample.rho * (sample.wi.dot(bsdf.ns).abs() / sample.pdf * importance_scale(&bsdf, wi, sample.wi));
+            // russian roulette keeps the photons of about the same power
+            let q = (1.0 - scattered.luminance() / beta.luminance()).max(0.0);
+            if u2().x < q {
+                break;
+            }
+            beta = scattered / (1.0 - q);
+
+            ray = Ray::new(isect.geo.p, sample.wi, ray.time, f64::INFINITY);
+            depth += 1;
+        }
+    }
+}
+
+impl Integrator for SppmIntegrator {
+    fn li(&self, _ray: &mut Ray, _scene: &Scene, _sampler: &mut dyn Sampler) -> Spectrum {
+        unreachable!("the photon mapping renders the whole film at once")
+    }
+
+    fn render(&self, scene: &Scene, setting: &WorldSetting) -> bool {
+        let resolution = self.camera.film.resolution;
+        let n_iterations = setting.n_sample;
+        let n_thread = setting.n_thread;
+
+        let mut pixels: Vec<SppmPixel> = (0..resolution.x * resolution.y)
+            .map(|_| SppmPixel { radius: self.initial_radius, ld: Spectrum::black(), n: 0.0, tau: Spectrum::black(), vp: None })
+            .collect();
+
+        let bar = ProgressBar::new(n_iterations as u64);
+        bar.set_message(format!("{} threads running...", n_thread));
+        bar.set_style(ProgressStyle::with_template("{msg}  {bar:40.cyan/blue} {pos:>7}/{len:7} iterations [{elapsed_precise}]")
+            .unwrap()
+            .progress_chars("=>-"));
+
+        for iteration in 0..n_iterations {
+            // find the visible points, the rows are handed out to the threads
+            let next_row = AtomicUsize::new(0);
+            let rows = Mutex::new(Vec::new());
+            thread::scope(|s| {
+                for _ in 0..n_thread {
+                    let mut sampler = setting.sampler.clone_box();
+                    let (next_row, rows) = (&next_row, &rows);
+                    s.spawn(move || loop {
+                        let i = next_row.fetch_add(1, Ordering::Relaxed);
+                        if i >= resolution.y {
+                            break;
+                        }
+                        let row: Vec<_> = (0..resolution.x)
+                            .map(|j| self.camera_pass(scene, sampler.as_mut(), Point2::new(j, i), iteration, n_iterations))
+                            .collect();
+                        rows.lock().unwrap().push((i, row));
+                    });
+                }
+            });
+            for (i, row) in rows.into_inner().unwrap() {
+                for (j, (ld, vp)) in row.into_iter().enumerate() {
+                    let pixel = &mut pixels[i * resolution.x + j];
+                    pixel.ld += ld;
+                    pixel.vp = vp.filter(|vp| !vp.beta.is_black());
+                }
+            }
+
+            // gather the photons around the visible points
+            let grid = VisiblePointGrid::new(&pixels);
+            let n_chunks = self.photons_per_iteration.div_ceil(PHOTON_CHUNK);
+            let next_chunk = AtomicUsize::new(0);
+            let chunks = Mutex::new(Vec::new());
+            thread::scope(|s| {
+                for _ in 0..n_thread {
+                    let (pixels, grid, next_chunk, chunks) = (&pixels, &grid, &next_chunk, &chunks);
+                    s.spawn(move || loop {
+                        let chunk = next_chunk.fetch_add(1, Ordering::Relaxed);
+                        if chunk >= n_chunks {
+                            break;
+                        }
+
+                        let mut contributions = HashMap::new();
+                        let mut rng = Rng::new();
+                        for index in chunk * PHOTON_CHUNK..((chunk + 1) * PHOTON_CHUNK).min(self.photons_per_iteration) {
+                            rng.set_sequence(hash(&[setting.seed, iteration as u64, index as u64]), 0);
+                            self.trace_photon(scene, pixels, grid, &mut rng, &mut contributions);
+                        }
+                        chunks.lock().unwrap().push((chunk, contributions));
+                    });
+                }
+            });
+
+            let mut chunks = chunks.into_inner().unwrap();
+            chunks.sort_by_key(|(chunk, _)| *chunk);
+            let mut gathered = vec![(Spectrum::black(), 0); pixels.len()];
+            for (_, contributions) in chunks {
+                for (index, (phi, m)) in contributions {
+                    gathered[index].0 += phi;
+                    gathered[index].1 += m;
+                }
+            }
+
+            // shrink the radius of the pixels that gathered photons, keeping a fraction of the new ones
+            const GAMMA: f64 = 2.0 / 3.0;
+            for (pixel, (phi, m)) in pixels.iter_mut().zip(gathered) {
+                if let Some(vp) = pixel.vp.take() {
+                    if m > 0 {
+                        let n_new = pixel.n + GAMMA * m as f64;
+                        let radius_new = pixel.radius * (n_new / (pixel.n + m as f64)).sqrt();
+                        pixel.tau = (pixel.tau + vp.beta * phi) * (radius_new * radius_new / (pixel.radius * pixel.radius));
+                        pixel.n = n_new;
+                        pixel.radius = radius_new;
+                    }
+                }
+            }
+
+            bar.inc(1);
+        }
+        bar.finish();
+
+        // the photons are spread over the disk of each pixel
+        let n_photons = (n_iterations * self.photons_per_iteration) as f64;
+        let mut tile = FilmTile::new(0, Point2::new(0, 0), resolution);
+        for i in 0..resolution.y {
+            for j in 0..resolution.x {
+                let pixel = &pixels[i * resolution.x + j];
+                let radiance = pixel.ld / n_iterations as f64 + pixel.tau / (n_photons * PI * pixel.radius * pixel.radius);
+                tile.record(i, j, radiance);
+            }
+        }
+        self.camera.film.merge_tile(tile);
+
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        integrator::{bdpt_integrator::BdptIntegrator, test_scene::{TestScene, matte, lit_sphere, camera, render_pixels, WIDTH, HEIGHT}},
+        light::point::PointLight,
+        material::glass::Glass,
+        texture::constant::ConstantTexture,
+    };
+    use cgmath::Point3;
+
+    #[test]
+    fn test_sppm_deterministic_across_threads() {
+        let sppm = |n_thread| {
+            let camera = camera();
+            render_pixels(Arc::clone(&camera), lit_sphere(), Box::new(SppmIntegrator::new(5, 2000, 0.2, camera)), 4, n_thread)
+        };
+
+        // the photons are gathered in the same order whatever the number of threads
+        let single = sppm(1);
+        assert!(single.iter().any(|s| !s.is_black()));
+        assert_eq!(single, sppm(3));
+    }
+
+    #[test]
+    fn test_sppm_glass_caustic() {
+        // a glass ball focuses a point light behind it onto the floor in front of it, which the camera paths
+        // can not find as they never hit the light
+        let scene = || {
+            let glass = Glass::new(
+                Arc::new(ConstantTexture::new(1.0)),
+                Arc::new(ConstantTexture::new(1.5)),
+                Arc::new(ConstantTexture::new(Spectrum::new(1.0, 1.0, 1.0))),
+                Arc::new(ConstantTexture::new(Spectrum::new(1.0, 1.0, 1.0))),
+            );
+            TestScene::new()
+                .floor(matte(0.5))
+                .sphere(Arc::new(glass))
+                .light(Arc::new(PointLight::new(Point3::new(0.0, 3.0, 8.0), Spectrum::new(4.0, 4.0, 4.0))))
+                .build()
+        };
+
+        // the bidirectional path tracer reaches the caustic by tracing the light subpaths to the camera
+        let (bdpt_camera, sppm_camera) = (camera(), camera());
+        let bdpt = render_pixels(Arc::clone(&bdpt_camera), scene(), Box::new(BdptIntegrator::new(5, bdpt_camera)), 256, 2);
+        let sppm = render_pixels(Arc::clone(&sppm_camera), scene(), Box::new(SppmIntegrator::new(5, 20000, 0.1, sppm_camera)), 64, 2);
+
+        // the ball focuses the light to a spot in its shadow, much brighter than the floor lit directly, the
+        // photons blur it over their radius but keep its power
+        let floor = |pixels: &[Spectrum]| pixels[HEIGHT / 2 * WIDTH..].iter().map(|s| s.luminance()).collect::<Vec<_>>();
+        let (bdpt, sppm) = (floor(&bdpt), floor(&sppm));
+        let caustic = sppm.iter().cloned().fold(0.0, f64::max);
+        assert!(caustic > 0.1, "caustic {}", caustic);
+
+        let (bdpt, sppm) = (bdpt.iter().sum::<f64>(), sppm.iter().sum::<f64>());
+        assert!((sppm - bdpt).abs() < 0.05 * bdpt, "sppm {} bdpt {}", sppm, bdpt);
+    }
+}
@@ -0,0 +1,119 @@
+//! the scenes and renders the tests of the integrators share, built around a sphere of radius 1 five
+//! units in front of a 20 by 12 camera, over an optional floor and lit by disks facing down
+
+use std::sync::Arc;
+
+use cgmath::{Point2, Vector3};
+
+use crate::{
+    render, WorldSetting,
+    scene::Scene,
+    spectrum::Spectrum,
+    accelerator::bvh::BVH,
+    camera::{film::Film, perspective::PerspectiveCamera, tonemap::ToneMapper},
+    geometry::{shape::{disk::Disk, sphere::Sphere, Shape}, transform::Transform},
+    light::{Light, area::AreaLight, light_sampler::LightSamplerType},
+    material::{Material, matte::Matte},
+    medium::{Medium, MediumInterface},
+    primitive::{geometric_primitive::GeometricPrimitive, Primitive, ObjectIds},
+    sampler::uniform_sampler::UniformSampler,
+    texture::constant::ConstantTexture,
+};
+
+use super::Integrator;
+
+pub const WIDTH: usize = 20;
+pub const HEIGHT: usize = 12;
+
+#[derive(Default)]
+pub struct TestScene {
+    primitives: Vec<Box<dyn Primitive>>,
+    lights: Vec<Arc<dyn Light>>,
+}
+
+impl TestScene {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// the sphere of radius 1 at (0, 0, 5)
+    pub fn sphere(self, material: Arc<dyn Material>) -> Self {
+        let object_to_world = Transform::translate(Vector3::new(0.0, 0.0, 5.0));
+        let sphere = Sphere::new(object_to_world.clone(), object_to_world.inverse(), 1.0);
+        self.shape(Box::new(sphere), Some(material), None)
+    }
+
+    /// the floor the sphere lies on, a wide disk at y = -1
+    pub fn floor(self, material: Arc<dyn Material>) -> Self {
+        let object_to_world = Transform::translate(Vector3::new(0.0, -1.0, 5.0)) * Transform::rotate(-90.0, Vector3::new(1.0, 0.0, 0.0));
+        let floor = Disk::new(object_to_world.clone(), object_to_world.inverse(), 20.0);
+        self.shape(Box::new(floor), Some(material), None)
+    }
+
+    /// any other shape, a shape without material only bounds media, the shapes are numbered from 1 as they are added
+    pub fn shape(mut self, shape: Box<dyn Shape>, material: Option<Arc<dyn Material>>, medium_interface: Option<MediumInterface>) -> Self {
+        let id = self.primitives.len() as u32 + 1;
+        let primitive = GeometricPrimitive::new(shape, material, medium_interface).with_ids(ObjectIds { primitive: id, material: id });
+        self.primitives.push(Box::new(primitive));
+        self
+    }
+
+    /// a disk light centered at p facing down
+    pub fn disk_light(self, p: Vector3<f64>, radius: f64, le: f64) -> Self {
+        let object_to_world = Transform::translate(p) * Transform::rotate(90.0, Vector3::new(1.0, 0.0, 0.0));
+        let disk = Disk::new(object_to_world.clone(), object_to_world.inverse(), radius);
+        self.light(Arc::new(AreaLight::new(Box::new(disk), Spectrum::new(le, le, le))))
+    }
+
+    pub fn light(mut self, light: Arc<dyn Light>) -> Self {
+        self.lights.push(light);
+        self
+    }
+
+    pub fn build(self) -> Scene {
+        Scene::new(self.lights, LightSamplerType::Power, Box::new(BVH::new(self.primitives)))
+    }
+}
+
+pub fn matte(albedo: f64) -> Arc<dyn Material> {
+    Arc::new(Matte::new(Arc::new(ConstantTexture::new(Spectrum::new(albedo, albedo, albedo)))))
+}
+
+/// the sphere lit by a disk above it
+pub fn lit_sphere() -> Scene {
+    TestScene::new()
+        .sphere(matte(0.5))
+        .disk_light(Vector3::new(0.0, 3.0, 5.0), 1.0, 4.0)
+        .build()
+}
+
+/// the camera at the origin looking down the z axis
+pub fn camera() -> Arc<PerspectiveCamera> {
+    camera_with_film(Film::new("unused.png", WIDTH, HEIGHT, ToneMapper::default()), None)
+}
+
+/// the camera with a film of its own, in the medium around the origin
+pub fn camera_with_film(film: Film, medium: Option<Arc<dyn Medium>>) -> Arc<PerspectiveCamera> {
+    let camera_to_world = Transform::look_at(Vector3::new(0.0, 0.0, 0.0), Vector3::new(0.0, 0.0, 1.0), Vector3::new(0.0, 1.0, 0.0)).inverse();
+    let frame = WIDTH as f64 / HEIGHT as f64;
+    Arc::new(PerspectiveCamera::new(camera_to_world, (Point2::new(-frame, -1.0), Point2::new(frame, 1.0)), 0.0, 1.0, 45.0, film, medium))
+}
+
+/// the radiance of the pixels, with the splats
+pub fn render_pixels(camera: Arc<PerspectiveCamera>, scene: Scene, integrator: Box<dyn Integrator>, n_sample: usize, n_thread: usize) -> Vec<Spectrum> {
+    let sampler = Box::new(UniformSampler::new(n_sample, 3));
+    let setting = WorldSetting::new(n_sample, n_thread, Arc::new(integrator), sampler, 3);
+    if !setting.integrator.render(&scene, &setting) {
+        render(setting.integrator.clone(), Arc::clone(&camera), Arc::new(scene), &setting, false);
+    }
+
+    camera.film.pixels(1.0 / n_sample as f64)
+}
+
+pub fn render_luminance(camera: Arc<PerspectiveCamera>, scene: Scene, integrator: Box<dyn Integrator>, n_sample: usize, n_thread: usize) -> Vec<f64> {
+    render_pixels(camera, scene, integrator, n_sample, n_thread).iter().map(|s| s.luminance()).collect()
+}
+
+pub fn mean(pixels: &[f64]) -> f64 {
+    pixels.iter().sum::<f64>() / pixels.len() as f64
+}
@@ -0,0 +1,312 @@
+use std::sync::Arc;
+
+use cgmath::{InnerSpace, Point2, Point3, Vector3};
+
+use crate::{spectrum::Spectrum, geometry::{ray::Ray, interaction::SurfaceInteraction}, scene::Scene, sampler::Sampler, bxdf::bsdf::Bsdf, medium::{Medium, MediumInteraction}, camera::film::Splat};
+use super::{Integrator, power_heuristic};
+
+/// a path tracer that also scatters inside the participating media, the grid media are sampled
+/// with delta tracking and their transmittance estimated with ratio tracking
+pub struct VolPathIntegrator {
+    pub max_depth: usize,
+}
+
+impl VolPathIntegrator {
+    pub fn new(max_depth: usize) -> Self {
+        VolPathIntegrator { max_depth }
+    }
+}
+
+/// a point where the path scatters, on a surface or inside a medium
+enum ScatterVertex<'a> {
+    Surface(&'a SurfaceInteraction, &'a Bsdf),
+    Medium(&'a MediumInteraction),
+}
+
+impl ScatterVertex<'_> {
+    fn p(&self) -> Point3<f64> {
+        match self {
+            ScatterVertex::Surface(isect, _) => isect.geo.p,
+            ScatterVertex::Medium(mi) => mi.p,
+        }
+    }
+
+    // points in media have no normal
+    fn n(&self) -> Vector3<f64> {
+        match self {
+            ScatterVertex::Surface(isect, _) => isect.geo.n,
+            ScatterVertex::Medium(_) => Vector3::new(0.0, 0.0, 0.0),
+        }
+    }
+
+    fn time(&self) -> f64 {
+        match self {
+            ScatterVertex::Surface(isect, _) => isect.time,
+            ScatterVertex::Medium(mi) => mi.time,
+        }
+    }
+
+    /// the scattered fraction towards wi, including the cosine on surfaces
+    fn f(&self, wi: Vector3<f64>) -> Spectrum {
+        match self {
+            ScatterVertex::Surface(isect, bsdf) => bsdf.f(isect.geo.wo, wi) * wi.dot(bsdf.ns).abs(),
+            ScatterVertex::Medium(mi) => {
+                let p = mi.phase.p(mi.wo, wi);
+                Spectrum::new(p, p, p)
+            }
+        }
+    }
+
+    fn pdf(&self, wi: Vector3<f64>) -> f64 {
+        match self {
+            ScatterVertex::Surface(isect, bsdf) => bsdf.pdf(isect.geo.wo, wi),
+            ScatterVertex::Medium(mi) => mi.phase.p(mi.wo, wi),
+        }
+    }
+
+    /// returns (wi, f, pdf, is_delta), f includes the cosine on surfaces
+    fn sample(&self, u: Point2<f64>) -> (Vector3<f64>, Spectrum, f64, bool) {
+        match self {
+            ScatterVertex::Surface(isect, bsdf) => {
+                let sample = bsdf.sample_f(isect.geo.wo, u);
+                let f = sample.rho * sample.wi.dot(bsdf.ns).abs();
+                (sample.wi, f, sample.pdf, sample.is_delta)
+            }
+            ScatterVertex::Medium(mi) => {
+                let (wi, p) = mi.phase.sample_p(mi.wo, u);
+                (wi, Spectrum::new(p, p, p), p, false)
+            }
+        }
+    }
+
+    /// the medium a ray leaving the vertex towards w travels through
+    fn medium_towards(&self, w: Vector3<f64>, current: &Option<Arc<dyn Medium>>) -> Option<Arc<dyn Medium>> {
+        match self {
+            ScatterVertex::Surface(isect, _) => isect.get_medium(w, current),
+            ScatterVertex::Medium(_) => current.clone(),
+        }
+    }
+}
+
+/// the direct lighting at the vertex, combining a light sample and a scattering sample with mis
+///
+/// medium is the medium of the ray that reached the vertex
+fn estimate_direct(scene: &Scene, sampler: &mut dyn Sampler, vertex: &ScatterVertex, medium: &Option<Arc<dyn Medium>>) -> Spectrum {
+    let (p, n, time) = (vertex.p(), vertex.n(), vertex.time());
+    let picked = scene.lightlist.sample(p, n, sampler.get_1d());
+    let u_light = sampler.get_2d();
+    let u_scatter = sampler.get_2d();
+
+    let mut ld = Spectrum::black();
+
+    // sample the light, the shadow ray is attenuated by the media it crosses
+    if let Some((light, light_pmf)) = picked {
+        let light_sample = light.sample_li(p, u_light);
+        let l_pdf = light_sample.pdf_area_to_solid(p) * light_pmf;
+
+        if l_pdf > 0.0 && !light_sample.le.is_black() {
+            let wi = -light_sample.dir.normalize();
+            let f = vertex.f(wi);
+            if !f.is_black() {
+                let tr = scene.transmittance(p, light_sample.position, vertex.medium_towards(wi, medium), time, sampler);
+                // delta lights can not be hit by the scattering samples, so they take the full weight
+                let weight = if light_sample.is_delta { 1.0 } else { power_heuristic(1, l_pdf, 1, vertex.pdf(wi)) };
+                ld += weight * f * tr * light_sample.le / l_pdf;
+            }
+        }
+    }
+
+    // sample the scattering, specular directions are accounted for by the next vertex of the path
+    let (wi, f, s_pdf, is_delta) = vertex.sample(u_scatter);
+    if s_pdf > 0.0 && !f.is_black() && !is_delta {
+        let mut r = Ray::new_in_medium(p, wi, time, f64::INFINITY, vertex.medium_towards(wi, medium));
+        let (hit, tr) = scene.intersect_tr(&mut r, sampler);
+
+        let (li, l_pdf) = match hit {
+            Some(hit) if hit.hit_light => {
+                let light = hit.light.unwrap();
+                let l_pdf = light.pdf(hit.geo.p, hit.geo.n, p) * scene.lightlist.pmf(p, n, &light);
+                (hit.radiance.unwrap(), l_pdf)
+            }
+            Some(_) => (Spectrum::black(), 0.0),
+            // the escaped ray is lit by the infinite lights
+            None => (scene.le_escaped(&r), scene.pdf_escaped(p, n, wi)),
+        };
+
+        if !li.is_black() {
+            ld += power_heuristic(1, s_pdf, 1, l_pdf) * f * tr * li / s_pdf;
+        }
+    }
+
+    ld
+}
+
+impl Integrator for VolPathIntegrator {
+    fn li(&self, ray: &mut Ray, scene: &Scene, sampler: &mut dyn Sampler) -> Spectrum {
+        let (direct, indirect) = self.li_split(ray, scene, sampler, &mut Vec::new());
+        direct + indirect
+    }
+
+    fn li_split(&self, ray: &mut Ray, scene: &Scene, sampler: &mut dyn Sampler, _splats: &mut Vec<Splat>) -> (Spectrum, Spectrum) {
+        let mut throughput = Spectrum::new(1.0, 1.0, 1.0);
+        let mut radiance = Spectrum::black();
+        let mut specular = false;
+        // the radiance gathered before the first scattering, in a medium or on a surface
+        let mut direct = None;
+        let mut depth = 0;
+
+        loop {
+            let isect = scene.intersect(ray);
+
+            // the ray may scatter in its medium before reaching the surface
+            let mut medium_isect = None;
+            if let Some(medium) = &ray.medium {
+                let sample = medium.sample(ray, sampler);
+                throughput *= sample.weight;
+                medium_isect = sample.interaction;
+            }
+            if throughput.is_black() {
+                break;
+            }
+
+            if let Some(mi) = medium_isect {
+                if depth >= self.max_depth {
+                    break;
+                }
+
+                radiance += throughput * estimate_direct(scene, sampler, &ScatterVertex::Medium(&mi), &ray.medium);
+
+                // the phase function is sampled exactly, the throughput is unchanged
+                let (wi, _p) = mi.phase.sample_p(mi.wo, sampler.get_2d());
+                *ray = Ray::new_in_medium(mi.p, wi, ray.time, f64::INFINITY, ray.medium.clone());
+                specular = false;
+                if depth == 0 {
+                    direct = Some(radiance);
+                }
+                depth += 1;
+                continue;
+            }
+
+            let isect = match isect {
+                Some(isect) => isect,
+                None => {
+                    // the infinite lights are only added here when they were not sampled
+                    if depth == 0 || specular {
+                        radiance += throughput * scene.le_escaped(ray);
+                    }
+                    break;
+                }
+            };
+
+            // hit the light after shot from the camera or leaving a specular vertex
+            if isect.hit_light {
+                if depth == 0 || specular {
+                    radiance += throughput * isect.radiance.unwrap();
+                }
+                break;
+            }
+
+            let mat = match &isect.material {
+                Some(mat) => mat.clone(),
+                None => {
+                    // a medium boundary, the ray goes on without counting a bounce
+                    let medium = isect.get_medium(ray.d, &ray.medium);
+                    let differential = ray.differential;
+                    *ray = Ray::new_in_medium(isect.geo.p, ray.d, ray.time, f64::INFINITY, medium);
+                    ray.differential = differential;
+                    continue;
+                }
+            };
+
+            if depth >= self.max_depth {
+                break;
+            }
+
+            let bsdf = mat.compute_scattering(&isect);
+            let vertex = ScatterVertex::Surface(&isect, &bsdf);
+            if !mat.is_specular() {
+                radiance += throughput * estimate_direct(scene, sampler, &vertex, &ray.medium);
+            }
+
+            // sample the bsdf to get the scattered ray
+            let (wi, f, pdf, is_delta) = vertex.sample(sampler.get_2d());
+            if pdf <= 0.0 || f.is_black() {
+                break;
+            }
+
+            throughput *= f / pdf;
+            specular = is_delta;
+            // only specular bounces keep track of the pixel footprint
+            let differential = if is_delta { isect.specular_differential(ray, wi, bsdf.eta) } else { None };
+            let medium = vertex.medium_towards(wi, &ray.medium);
+            *ray = Ray::new_in_medium(isect.geo.p, wi, ray.time, f64::INFINITY, medium);
+            ray.differential = differential;
+            if depth == 0 {
+                direct = Some(radiance);
+            }
+            depth += 1;
+        }
+
+        let direct = direct.unwrap_or(radiance);
+        (direct, radiance - direct)
+    }
+
+    fn can_split(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        camera::{film::Film, perspective::PerspectiveCamera, tonemap::ToneMapper},
+        geometry::{shape::sphere::Sphere, transform::Transform},
+        integrator::test_scene::{TestScene, matte, lit_sphere, camera, camera_with_film, mean, render_luminance, WIDTH, HEIGHT},
+        light::environment::EnvironmentLight,
+        medium::{MediumInterface, homogeneous::HomogeneousMedium, phase::{PhaseFunction, Isotropic, HenyeyGreenstein}},
+    };
+
+    // a ball of radius 2 around the camera filled with the medium, which every camera ray crosses over the same length
+    fn camera_in_ball(medium: Arc<dyn Medium>, scene: TestScene) -> (Arc<PerspectiveCamera>, Scene) {
+        let object_to_world = Transform::translate(Vector3::new(0.0, 0.0, 0.0));
+        let ball = Sphere::new(object_to_world.clone(), object_to_world.inverse(), 2.0);
+        let scene = scene.shape(Box::new(ball), None, Some(MediumInterface::new(Some(medium.clone()), None))).build();
+        let camera = camera_with_film(Film::new("unused.png", WIDTH, HEIGHT, ToneMapper::default()), Some(medium));
+        (camera, scene)
+    }
+
+    #[test]
+    fn test_volpath_absorption() {
+        // the sphere and the light lie outside the ball, so the image is only dimmed by the absorption
+        let sigma_a = 0.5;
+        let medium: Arc<dyn Medium> = Arc::new(HomogeneousMedium::new(Spectrum::new(sigma_a, sigma_a, sigma_a), Spectrum::black(), Arc::new(Isotropic)));
+        let lit_sphere_in_ball = TestScene::new()
+            .sphere(matte(0.5))
+            .disk_light(Vector3::new(0.0, 3.0, 5.0), 1.0, 4.0);
+        let (camera_in_medium, absorbing) = camera_in_ball(medium, lit_sphere_in_ball);
+
+        let clear = mean(&render_luminance(camera(), lit_sphere(), Box::new(VolPathIntegrator::new(5)), 256, 2));
+        let absorbed = mean(&render_luminance(camera_in_medium, absorbing, Box::new(VolPathIntegrator::new(5)), 256, 2));
+
+        let expected = clear * (-sigma_a * 2.0).exp();
+        assert!((absorbed - expected).abs() < 0.03 * expected, "absorbed {} expected {}", absorbed, expected);
+    }
+
+    #[test]
+    fn test_volpath_scattering_furnace() {
+        // a medium that scatters without absorbing, under a uniform environment, neither creates nor loses light,
+        // whatever the phase function, so every pixel sees the radiance of the environment
+        let furnace = |phase: Arc<dyn PhaseFunction>| {
+            let medium: Arc<dyn Medium> = Arc::new(HomogeneousMedium::new(Spectrum::black(), Spectrum::new(0.5, 0.5, 0.5), phase));
+            let environment = EnvironmentLight::from_texels(Transform::translate(Vector3::new(0.0, 0.0, 0.0)), vec![Spectrum::new(1.0, 1.0, 1.0); 2], Point2::new(2, 1), Spectrum::new(1.0, 1.0, 1.0));
+            let (camera, scene) = camera_in_ball(medium, TestScene::new().light(Arc::new(environment)));
+            mean(&render_luminance(camera, scene, Box::new(VolPathIntegrator::new(50)), 64, 2))
+        };
+
+        for phase in [Arc::new(Isotropic) as Arc<dyn PhaseFunction>, Arc::new(HenyeyGreenstein::new(0.7))] {
+            let radiance = furnace(phase);
+            assert!((radiance - 1.0).abs() < 0.01, "radiance {}", radiance);
+        }
+    }
+}
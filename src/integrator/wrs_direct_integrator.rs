@@ -1,24 +1,448 @@
-use core::panic;
+use std::{
+    sync::{Arc, Mutex, atomic::{AtomicUsize, Ordering}},
+    thread,
+};
 
-use cgmath::{Point2, InnerSpace};
-use rand::random;
+use cgmath::{InnerSpace, Point2, Point3, Vector3};
+use indicatif::{ProgressBar, ProgressStyle};
 
-use crate::{spectrum::Spectrum, geometry::ray::Ray, scene::Scene};
+use crate::{
+    spectrum::Spectrum,
+    geometry::{ray::Ray, interaction::SurfaceInteraction},
+    scene::Scene,
+    bxdf::bsdf::Bsdf,
+    light::{Light, LightSample},
+    sampler::{Sampler, wrs::Reservoir, rng::Rng, lowdiscrepancy::hash},
+    camera::{Camera, CameraSample, film::FilmTile, perspective::PerspectiveCamera},
+    WorldSetting,
+};
 use super::{Integrator, visibility_test};
 
+// the previous samples of a pixel count at most as many candidates as this many new ones, so that the
+// reservoirs keep following the changes of the pixel
+const TEMPORAL_CAP: u32 = 20;
+
+/// direct lighting by resampled importance sampling, M candidates are drawn from the light sampler and
+/// one of them is kept, by weighted reservoir sampling, proportionally to its unshadowed contribution,
+/// only that one is tested for visibility
+///
+/// with reuse, the whole film is rendered at once and every pixel also resamples the reservoirs of its
+/// neighbours and of its previous samples
 pub struct WRSDirectIntegrator {
+    max_depth: usize,
     pub m: usize, // number of sample candiates
+    reuse: Option<Reuse>,
+    camera: Arc<PerspectiveCamera>,
 }
 
-impl WRSDirectIntegrator {
-    pub fn new(m: usize) -> Self {
-        WRSDirectIntegrator { m: 32 }
+/// how the reservoirs of the other pixels and samples are reused
+#[derive(Clone, Copy)]
+pub struct Reuse {
+    pub neighbors: usize, // the pixels resampled around every pixel
+    pub radius: f64, // in pixels
+    pub temporal: bool, // the reservoir of the previous sample of the pixel is resampled too
+}
+
+/// a candidate point on the lights
+#[derive(Clone)]
+struct LightCandidate {
+    le: Spectrum,
+    emitter: Emitter,
+}
+
+#[derive(Clone, Copy)]
+enum Emitter {
+    Area { p: Point3<f64>, n: Vector3<f64> },
+    Point { p: Point3<f64> },
+    // the infinite lights are sampled by direction, distance is long enough to leave the scene
+    Infinite { wi: Vector3<f64>, distance: f64 },
+}
+
+impl LightCandidate {
+    /// the candidate and the density it was sampled with by the light sample, which was drawn for p_ref,
+    /// in area for the lights at a finite distance and in solid angle for the infinite ones
+    fn new(light: &dyn Light, sample: &LightSample, p_ref: Point3<f64>) -> (Self, f64) {
+        let (emitter, pdf) = if light.is_infinite() {
+            let distance = (sample.position - p_ref).magnitude();
+            (Emitter::Infinite { wi: -sample.dir, distance }, sample.pdf * distance * distance)
+        } else if sample.is_delta {
+            (Emitter::Point { p: sample.position }, sample.pdf)
+        } else {
+            (Emitter::Area { p: sample.position, n: sample.normal }, sample.pdf)
+        };
+
+        (LightCandidate { le: sample.le, emitter }, pdf)
     }
+
+    /// the direction to the candidate from p, the point a shadow ray must reach and the geometric term
+    /// turning the density of the candidate into a solid angle one
+    fn incident(&self, p: Point3<f64>) -> Option<(Vector3<f64>, Point3<f64>, f64)> {
+        match self.emitter {
+            Emitter::Area { p: p_light, n } => {
+                let r2 = (p_light - p).magnitude2();
+                let wi = (p_light - p).normalize();
+                let cos_light = -wi.dot(n);
+                (r2 > 0.0 && cos_light > 0.0).then(|| (wi, p_light, cos_light / r2))
+            },
+            Emitter::Point { p: p_light } => {
+                let r2 = (p_light - p).magnitude2();
+                (r2 > 0.0).then(|| ((p_light - p).normalize(), p_light, 1.0 / r2))
+            },
+            Emitter::Infinite { wi, distance } => Some((wi, p + wi * distance, 1.0)),
+        }
+    }
+}
+
+/// the first non specular surface seen from the camera, the lights are sampled there
+struct VisiblePoint {
+    isect: SurfaceInteraction,
+    bsdf: Bsdf,
+    wo: Vector3<f64>,
+    beta: Spectrum, // the throughput of the specular bounces leading to it
 }
 
+impl VisiblePoint {
+    /// the unshadowed radiance reflected toward wo, in the measure of the candidate, and the point its
+    /// shadow ray goes to
+    fn contribution(&self, candidate: &LightCandidate) -> Option<(Spectrum, Point3<f64>)> {
+        let (wi, target, g) = candidate.incident(self.isect.geo.p)?;
+        let f = self.bsdf.f(self.wo, wi);
+        Some((f * candidate.le * wi.dot(self.bsdf.ns).abs() * g, target))
+    }
+
+    /// the target pdf of the resampling, up to a constant
+    fn p_hat(&self, candidate: &LightCandidate) -> f64 {
+        self.contribution(candidate).map_or(0.0, |(c, _)| c.luminance().max(0.0))
+    }
+
+    /// the radiance of the output of the reservoir, weighted by its contribution weight
+    fn shade(&self, reservoir: &Reservoir<LightCandidate>, scene: &Scene) -> Spectrum {
+        let contribution = reservoir.sample().and_then(|candidate| self.contribution(candidate));
+        match contribution {
+            Some((c, target)) if reservoir.w > 0.0 && !c.is_black() && visibility_test(&self.isect, target, scene) => {
+                self.beta * c * reservoir.w
+            },
+            _ => Spectrum::black(),
+        }
+    }
+}
+
+impl WRSDirectIntegrator {
+    pub fn new(max_depth: usize, m: usize, camera: Arc<PerspectiveCamera>) -> Self {
+        WRSDirectIntegrator {
+            max_depth,
+            m: m.max(1),
+            reuse: None,
+            camera,
+        }
+    }
+
+    /// resample the reservoirs of the neighbouring pixels and of the previous samples
+    pub fn with_reuse(mut self, neighbors: usize, radius: f64, temporal: bool) -> Self {
+        self.reuse = Some(Reuse { neighbors, radius, temporal });
+        self
+    }
+
+    /// follow the ray through the specular surfaces, returns the emission it meets and the surface the
+    /// lights are sampled at
+    fn visible_point(&self, ray: &mut Ray, scene: &Scene, sampler: &mut dyn Sampler) -> (Spectrum, Option<VisiblePoint>) {
+        let mut beta = Spectrum::new(1.0, 1.0, 1.0);
+
+        let mut depth = 0;
+        while depth < self.max_depth {
+            let isect = match scene.intersect(ray) {
+                Some(isect) => isect,
+                None => return (beta * scene.le_escaped(ray), None),
+            };
+            // the emission is only seen from the camera or through specular vertices
+            if isect.hit_light {
+                return (beta * isect.radiance.unwrap(), None);
+            }
+
+            let material = match &isect.material {
+                Some(material) => material,
+                None => {
+                    // a medium boundary, the ray goes on without counting a bounce
+                    let medium = isect.get_medium(ray.d, &ray.medium);
+                    let differential = ray.differential;
+                    *ray = Ray::new_in_medium(isect.geo.p, ray.d, ray.time, f64::INFINITY, medium);
+                    ray.differential = differential;
+                    continue;
+                }
+            };
+
+            let bsdf = material.compute_scattering(&isect);
+            let wo = -ray.d.normalize();
+            if !material.is_specular() {
+                return (Spectrum::black(), Some(VisiblePoint { isect, bsdf, wo, beta }));
+            }
+
+            // sample the specular bsdf
+            let bsdf_sample = bsdf.sample_f(wo, sampler.get_2d());
+            let (rho, wi, pdf) = (bsdf_sample.rho, bsdf_sample.wi, bsdf_sample.pdf);
+            if pdf <= 0.0 || rho.is_black() {
+                break;
+            }
+            beta *= rho * wi.dot(bsdf.ns).abs() / pdf;
+
+            // spawn the new ray, which keeps track of the pixel footprint
+            let differential = isect.specular_differential(ray, wi, bsdf.eta);
+            *ray = Ray::new(isect.geo.p, wi, ray.time, f64::INFINITY);
+            ray.differential = differential;
+
+            depth += 1;
+        }
+
+        (Spectrum::black(), None)
+    }
+
+    /// stream m candidates from the light sampler into a reservoir, with their weight p_hat / p
+    fn resample_lights(&self, scene: &Scene, sampler: &mut dyn Sampler, vp: &VisiblePoint) -> Reservoir<LightCandidate> {
+        let (p, n) = (vp.isect.geo.p, vp.isect.geo.n);
+        let mut reservoir = Reservoir::new();
+
+        for _ in 0..self.m {
+            // the same dimensions are consumed by every candidate
+            let picked = scene.lightlist.sample(p, n, sampler.get_1d());
+            let u_light = sampler.get_2d();
+            let u = sampler.get_1d();
+
+            let (light, light_pdf) = match picked {
+                Some(picked) => picked,
+                None => {
+                    reservoir.m += 1;
+                    continue;
+                }
+            };
+            let sample = light.sample_li(p, u_light);
+            let (candidate, pdf) = LightCandidate::new(light.as_ref(), &sample, p);
+            let pdf = pdf * light_pdf;
+
+            let weight = if pdf > 0.0 && !sample.le.is_black() { vp.p_hat(&candidate) / pdf } else { 0.0 };
+            reservoir.update(candidate, weight, u);
+        }
+
+        let p_hat = reservoir.sample().map_or(0.0, |candidate| vp.p_hat(candidate));
+        reservoir.w = if p_hat > 0.0 { reservoir.weight_sum / (reservoir.m as f64 * p_hat) } else { 0.0 };
+        reservoir
+    }
+
+    /// the emission met by the camera ray of the sample, the visible point and its reservoir
+    fn camera_pass(&self, scene: &Scene, sampler: &mut dyn Sampler, pixel: Point2<usize>, sample_index: usize, n_sample: usize) -> (Spectrum, Option<(VisiblePoint, Reservoir<LightCandidate>)>) {
+        sampler.start_pixel_sample(pixel, sample_index);
+        let offset = sampler.get_pixel_2d();
+        let sample = CameraSample::new(Point2::new(pixel.x as f64 + offset.x, pixel.y as f64 + offset.y), 0.0);
+        let mut ray = self.camera.generate_ray(sample);
+        ray.scale_differentials((1.0 / (n_sample as f64).sqrt()).max(0.125));
+
+        let (le, vp) = self.visible_point(&mut ray, scene, sampler);
+        let vp = vp.map(|vp| {
+            let reservoir = self.resample_lights(scene, sampler, &vp);
+            (vp, reservoir)
+        });
+        (le, vp)
+    }
+
+    /// combine the reservoir of the pixel with the ones of its previous sample and of its neighbours,
+    /// the estimate stays unbiased as the weights of the reservoirs able to produce a sample sum to one
+    fn reuse(&self, reuse: &Reuse, index: usize, rng: &mut Rng, current: &[Option<(VisiblePoint, Reservoir<LightCandidate>)>], previous: &[Option<(VisiblePoint, Reservoir<LightCandidate>)>]) -> Reservoir<LightCandidate> {
+        let resolution = self.camera.film.resolution;
+        let (vp, canonical) = current[index].as_ref().unwrap();
+
+        // the pixel, its previous sample and the neighbours
+        let mut sources = vec![(vp, canonical.clone())];
+        if let Some((previous_vp, reservoir)) = previous.get(index).and_then(|previous| previous.as_ref()).filter(|_| reuse.temporal) {
+            let mut reservoir = reservoir.clone();
+            reservoir.m = reservoir.m.min(TEMPORAL_CAP * canonical.m);
+            sources.push((previous_vp, reservoir));
+        }
+        let (x, y) = ((index % resolution.x) as f64, (index / resolution.x) as f64);
+        for _ in 0..reuse.neighbors {
+            // a pixel picked uniformly in the disk around this one
+            let r = reuse.radius * rng.uniform_f64().sqrt();
+            let phi = 2.0 * std::f64::consts::PI * rng.uniform_f64();
+            let nx = (x + r * phi.cos()).round().clamp(0.0, resolution.x as f64 - 1.0) as usize;
+            let ny = (y + r * phi.sin()).round().clamp(0.0, resolution.y as f64 - 1.0) as usize;
+            let neighbor = ny * resolution.x + nx;
+            // the surfaces facing other ways rarely share their lights
+            match &current[neighbor] {
+                Some((neighbor_vp, reservoir)) if neighbor != index && neighbor_vp.isect.geo.n.dot(vp.isect.geo.n) > 0.9 => {
+                    sources.push((neighbor_vp, reservoir.clone()));
+                },
+                _ => (),
+            }
+        }
+
+        // every output is weighted by the balance heuristic over the reservoirs that could have produced it
+        let owners: Vec<(&VisiblePoint, f64)> = sources.iter().map(|(owner, reservoir)| (*owner, reservoir.m as f64)).collect();
+        let mut combined = Reservoir::new();
+        for (i, (_, reservoir)) in sources.into_iter().enumerate() {
+            let (mis, p_hat) = match reservoir.sample() {
+                Some(candidate) => {
+                    let densities: Vec<f64> = owners.iter().map(|(owner, m)| m * owner.p_hat(candidate)).collect();
+                    let total: f64 = densities.iter().sum();
+                    (if total > 0.0 { densities[i] / total } else { 0.0 }, vp.p_hat(candidate))
+                },
+                None => (0.0, 0.0),
+            };
+            combined.merge(reservoir, mis, p_hat, rng.uniform_f64());
+        }
+
+        let p_hat = combined.sample().map_or(0.0, |candidate| vp.p_hat(candidate));
+        combined.w = if p_hat > 0.0 { combined.weight_sum / p_hat } else { 0.0 };
+        combined
+    }
+}
 
 impl Integrator for WRSDirectIntegrator {
-    fn li(&self, ray: &mut Ray, scene: &Scene) -> Spectrum {
-        
+    fn li(&self, ray: &mut Ray, scene: &Scene, sampler: &mut dyn Sampler) -> Spectrum {
+        let (le, vp) = self.visible_point(ray, scene, sampler);
+        match vp {
+            Some(vp) => {
+                let reservoir = self.resample_lights(scene, sampler, &vp);
+                le + vp.shade(&reservoir, scene)
+            },
+            None => le,
+        }
     }
-}
\ No newline at end of file
+
+    fn render(&self, scene: &Scene, setting: &WorldSetting) -> bool {
+        // without reuse the pixels are independent
+        let reuse = match &self.reuse {
+            Some(reuse) => reuse,
+            None => return false,
+        };
+        let resolution = self.camera.film.resolution;
+        let n_sample = setting.n_sample;
+        let n_thread = setting.n_thread;
+
+        let mut radiance = vec![Spectrum::black(); resolution.x * resolution.y];
+        let mut previous: Vec<Option<(VisiblePoint, Reservoir<LightCandidate>)>> = Vec::new();
+
+        let bar = ProgressBar::new(n_sample as u64);
+        bar.set_message(format!("{} threads running...", n_thread));
+        bar.set_style(ProgressStyle::with_template("{msg}  {bar:40.cyan/blue} {pos:>7}/{len:7} samples [{elapsed_precise}]")
+            .unwrap()
+            .progress_chars("=>-"));
+
+        for sample_index in 0..n_sample {
+            // the reservoirs of the visible points, the rows are handed out to the threads
+            let next_row = AtomicUsize::new(0);
+            let rows = Mutex::new(Vec::new());
+            thread::scope(|s| {
+                for _ in 0..n_thread {
+                    let mut sampler = setting.sampler.clone_box();
+                    let (next_row, rows) = (&next_row, &rows);
+                    s.spawn(move || loop {
+                        let i = next_row.fetch_add(1, Ordering::Relaxed);
+                        if i >= resolution.y {
+                            break;
+                        }
+                        let row: Vec<_> = (0..resolution.x)
+                            .map(|j| self.camera_pass(scene, sampler.as_mut(), Point2::new(j, i), sample_index, n_sample))
+                            .collect();
+                        rows.lock().unwrap().push((i, row));
+                    });
+                }
+            });
+            let mut rows = rows.into_inner().unwrap();
+            rows.sort_by_key(|(i, _)| *i);
+            let mut current = Vec::with_capacity(resolution.x * resolution.y);
+            for (_, row) in rows {
+                for (le, vp) in row {
+                    radiance[current.len()] += le;
+                    current.push(vp);
+                }
+            }
+
+            // resample the reservoirs of the other pixels and shade, the reservoirs are kept for the next sample
+            let next_row = AtomicUsize::new(0);
+            let rows = Mutex::new(Vec::new());
+            thread::scope(|s| {
+                for _ in 0..n_thread {
+                    let (next_row, rows, current, previous) = (&next_row, &rows, &current, &previous);
+                    s.spawn(move || loop {
+                        let i = next_row.fetch_add(1, Ordering::Relaxed);
+                        if i >= resolution.y {
+                            break;
+                        }
+                        let row: Vec<_> = (0..resolution.x)
+                            .map(|j| {
+                                let index = i * resolution.x + j;
+                                let (vp, _) = current[index].as_ref()?;
+                                let mut rng = Rng::new();
+                                rng.set_sequence(hash(&[setting.seed, sample_index as u64, index as u64]), 0);
+                                let reservoir = self.reuse(reuse, index, &mut rng, current, previous);
+                                Some((vp.shade(&reservoir, scene), reservoir))
+                            })
+                            .collect();
+                        rows.lock().unwrap().push((i, row));
+                    });
+                }
+            });
+            let mut rows = rows.into_inner().unwrap();
+            rows.sort_by_key(|(i, _)| *i);
+            let reservoirs = rows.into_iter().flat_map(|(_, row)| row);
+            previous = current.into_iter().zip(reservoirs).enumerate()
+                .map(|(index, (vp, shaded))| {
+                    let (vp, _) = vp?;
+                    let (l, reservoir) = shaded?;
+                    radiance[index] += l;
+                    Some((vp, reservoir))
+                })
+                .collect();
+
+            bar.inc(1);
+        }
+        bar.finish();
+
+        let mut tile = FilmTile::new(0, Point2::new(0, 0), resolution);
+        for i in 0..resolution.y {
+            for j in 0..resolution.x {
+                tile.record(i, j, radiance[i * resolution.x + j] / n_sample as f64);
+            }
+        }
+        self.camera.film.merge_tile(tile);
+
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::integrator::{direct_integrator::DirectIntegrator, test_scene::{TestScene, matte, camera, mean, render_luminance}};
+
+    // a floor lit by a row of small lights, the light sampler can not tell which of them matter at a point
+    fn floor_scene() -> Scene {
+        [-3.0, -1.0, 1.0, 3.0].iter()
+            .fold(TestScene::new().floor(matte(0.5)), |scene, &x| scene.disk_light(Vector3::new(x, 0.0, 6.0), 0.3, 4.0))
+            .build()
+    }
+
+    #[test]
+    fn test_wrs_direct_reduces_variance() {
+        let reference = render_luminance(camera(), floor_scene(), Box::new(DirectIntegrator::new(5)), 1024, 2);
+        let mse = |pixels: &[f64]| {
+            pixels.iter().zip(reference.iter()).map(|(p, r)| (p - r) * (p - r)).sum::<f64>() / pixels.len() as f64
+        };
+        let wrs = |integrator: fn(Arc<PerspectiveCamera>) -> WRSDirectIntegrator, n_sample, n_thread| {
+            let camera = camera();
+            render_luminance(Arc::clone(&camera), floor_scene(), Box::new(integrator(camera)), n_sample, n_thread)
+        };
+
+        // with the same samples per pixel, the resampled candidates are closer to the reference, and more
+        // so with the candidates of the close neighbours, which matter most when a pixel draws only a few
+        let direct = render_luminance(camera(), floor_scene(), Box::new(DirectIntegrator::new(5)), 4, 2);
+        let resampled = wrs(|camera| WRSDirectIntegrator::new(5, 2, camera), 4, 2);
+        let reused = wrs(|camera| WRSDirectIntegrator::new(5, 2, camera).with_reuse(8, 2.0, false), 4, 2);
+        assert!(mse(&resampled) < mse(&direct) && mse(&reused) < mse(&resampled),
+            "direct {} resampled {} reused {}", mse(&direct), mse(&resampled), mse(&reused));
+
+        // the reuse, of the previous samples too, does not depend on the threads and does not bias the image
+        let temporal = |camera| WRSDirectIntegrator::new(5, 8, camera).with_reuse(4, 5.0, true);
+        let single = wrs(temporal, 64, 1);
+        assert_eq!(single, wrs(temporal, 64, 3));
+        assert!((mean(&single) - mean(&reference)).abs() < 0.03 * mean(&reference), "reused {} reference {}", mean(&single), mean(&reference));
+    }
+}
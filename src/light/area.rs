@@ -1,7 +1,9 @@
+use std::f64::consts::PI;
+
 use cgmath::{Point3, Point2, InnerSpace, Vector3};
-use crate::{geometry::{interaction::SurfaceInteraction, ray::Ray, shape::Shape}, spectrum::Spectrum};
+use crate::{bxdf::cosine_sample_hemisphere, geometry::{interaction::SurfaceInteraction, ray::Ray, shape::Shape}, spectrum::Spectrum, utils::perpendicular};
 
-use super::{Light, LightSample};
+use super::{Light, LightEmission, LightSample, light_bvh::LightBounds};
 
 pub struct AreaLight {
     shape: Box<dyn Shape>,
@@ -15,14 +17,14 @@ impl AreaLight {
 }
 
 impl Light for AreaLight {
-    fn sample_li(&self, isect: &SurfaceInteraction, u: Point2::<f64>) -> LightSample {
+    fn sample_li(&self, p_ref: Point3<f64>, u: Point2::<f64>) -> LightSample {
         let (p, n, pdf_area) = self.shape.uniform_sample_point(u);
 
         LightSample {
             position: p,
             normal: n,
             le: self.le(),
-            dir: (isect.geo.p - p).normalize(),
+            dir: (p_ref - p).normalize(),
             pdf: pdf_area, 
             is_delta: false,
         }
@@ -32,6 +34,16 @@ impl Light for AreaLight {
         self.emit
     }
 
+    fn phi(&self) -> Spectrum {
+        // one sided emission
+        PI * self.shape.area() * self.emit
+    }
+
+    fn bounds(&self) -> Option<LightBounds> {
+        let normal_bounds = self.shape.normal_bounds();
+        Some(LightBounds::new(self.shape.world_bound(), normal_bounds.w, self.phi().luminance(), normal_bounds.cos_theta, 0.0, false))
+    }
+
     fn intersect_p(&self, r: &crate::geometry::ray::Ray) -> Option<f64> {
         self.shape.intersect_p(r)
     }
@@ -54,6 +66,9 @@ impl Light for AreaLight {
                 hit_light: true,
                 radiance: Some(radiance),
                 light: None,
+                medium_interface: None,
+                differentials: None,
+                ids: Default::default(),
             };
 
             Some(isect)
@@ -63,11 +78,37 @@ impl Light for AreaLight {
     }
 
     fn pdf(&self, isect_p: Point3<f64>, isect_n: Vector3<f64>, p: Point3<f64>) -> f64 {
-        let distance2 = (isect_p - p).magnitude2();
-        let we = (isect_p - p).normalize();
-        let cos_alpha = we.dot(isect_n).max(0.0);
+        // we points from the light to the lit point, like in pdf_area_to_solid
+        let distance2 = (p - isect_p).magnitude2();
+        let we = (p - isect_p).normalize();
+        let cos_alpha = we.dot(isect_n);
+        if cos_alpha <= 0.0 {
+            return 0.0;
+        }
 
         let pdf_area = 1.0 / self.shape.area();
         pdf_area * distance2 / cos_alpha
     }
+
+    fn sample_le(&self, u_pos: Point2<f64>, u_dir: Point2<f64>, time: f64) -> LightEmission {
+        let (p, n, pdf_pos) = self.shape.uniform_sample_point(u_pos);
+
+        // the light only leaves the side the normal points to, cosine weighted
+        let local = cosine_sample_hemisphere(u_dir);
+        let (s, t) = perpendicular(n);
+        let d = s * local.x + t * local.y + n * local.z;
+
+        LightEmission {
+            ray: Ray::new(p, d, time, f64::INFINITY),
+            normal: n,
+            le: self.emit,
+            pdf_pos,
+            pdf_dir: local.z / PI,
+        }
+    }
+
+    fn pdf_le(&self, _p: Point3<f64>, n: Vector3<f64>, w: Vector3<f64>) -> (f64, f64) {
+        let cos_theta = n.dot(w.normalize());
+        (1.0 / self.shape.area(), if cos_theta > 0.0 { cos_theta / PI } else { 0.0 })
+    }
 }
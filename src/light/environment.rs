@@ -0,0 +1,241 @@
+use std::{f64::consts::PI, fs::File, io::BufReader, sync::OnceLock};
+
+use cgmath::{Point2, Point3, Vector3, InnerSpace};
+use image::codecs::hdr::HdrDecoder;
+
+use crate::{bxdf::uniform_sample_disk, geometry::{bound3::Bound3, interaction::SurfaceInteraction, ray::Ray, transform::Transform}, sampler::distribution::Distribution2D, spectrum::Spectrum, utils::perpendicular};
+
+use super::{Light, LightEmission, LightSample};
+
+/// an infinitely far away light given by an equirectangular (latitude-longitude) radiance map
+///
+/// in light space +y points to the top row of the image and +z to its center column
+pub struct EnvironmentLight {
+    light_to_world: Transform,
+    world_to_light: Transform,
+    scale: Spectrum,
+    texels: Vec<Spectrum>,
+    resolution: Point2<usize>,
+    distribution: Distribution2D,
+    average: Spectrum,
+    scene_sphere: OnceLock<(Point3<f64>, f64)>, // bounding sphere of the scene, set by preprocess
+}
+
+impl EnvironmentLight {
+    pub fn new(light_to_world: Transform, filename: &str, scale: Spectrum) -> Self {
+        // image::open decodes radiance files to 8-bit, so they are read with the hdr decoder directly
+        let (resolution, pixels) = if filename.ends_with(".hdr") {
+            let file = BufReader::new(File::open(filename).expect("failed to open the environment map"));
+            let decoder = HdrDecoder::new(file).expect("failed to read the environment map");
+            let metadata = decoder.metadata();
+            let pixels = decoder.read_image_hdr().expect("failed to read the environment map");
+            (Point2::new(metadata.width as usize, metadata.height as usize), pixels)
+        } else {
+            let img = image::open(filename)
+                .expect("failed to open the environment map")
+                .to_rgb32f();
+            (Point2::new(img.width() as usize, img.height() as usize), img.pixels().cloned().collect())
+        };
+
+        let texels: Vec<Spectrum> = pixels.iter()
+            .map(|p| Spectrum::new(p[0] as f64, p[1] as f64, p[2] as f64))
+            .collect();
+
+        Self::from_texels(light_to_world, texels, resolution, scale)
+    }
+
+    pub fn from_texels(light_to_world: Transform, texels: Vec<Spectrum>, resolution: Point2<usize>, scale: Spectrum) -> Self {
+        assert_eq!(texels.len(), resolution.x * resolution.y);
+
+        // sample the texels proportionally to their luminance, the sine accounts for the
+        // rows near the poles covering less solid angle
+        let func: Vec<f64> = texels.iter().enumerate().map(|(i, texel)| {
+            let theta = PI * ((i / resolution.x) as f64 + 0.5) / resolution.y as f64;
+            texel.luminance() * theta.sin()
+        }).collect();
+        let distribution = Distribution2D::new(&func, resolution.x, resolution.y);
+
+        let mut average = Spectrum::black();
+        for texel in texels.iter() {
+            average += *texel;
+        }
+        average = average * scale / texels.len() as f64;
+
+        EnvironmentLight {
+            world_to_light: light_to_world.inverse(),
+            light_to_world,
+            scale,
+            texels,
+            resolution,
+            distribution,
+            average,
+            scene_sphere: OnceLock::new(),
+        }
+    }
+
+    fn lookup(&self, uv: Point2<f64>) -> Spectrum {
+        let x = ((uv.x * self.resolution.x as f64) as usize).min(self.resolution.x - 1);
+        let y = ((uv.y * self.resolution.y as f64) as usize).min(self.resolution.y - 1);
+        self.texels[y * self.resolution.x + x] * self.scale
+    }
+
+    /// solid angle pdf of a light space direction
+    fn pdf_light_space(&self, w: Vector3<f64>) -> f64 {
+        let uv = direction_to_uv(w);
+        let sin_theta = (uv.y * PI).sin();
+        if sin_theta <= 0.0 {
+            return 0.0;
+        }
+
+        self.distribution.pdf(uv) / (2.0 * PI * PI * sin_theta)
+    }
+}
+
+fn direction_to_uv(w: Vector3<f64>) -> Point2<f64> {
+    let theta = w.y.clamp(-1.0, 1.0).acos();
+    let phi = w.x.atan2(w.z);
+    Point2::new(0.5 + phi / (2.0 * PI), theta / PI)
+}
+
+fn uv_to_direction(uv: Point2<f64>) -> Vector3<f64> {
+    let theta = uv.y * PI;
+    let phi = (uv.x - 0.5) * 2.0 * PI;
+    Vector3::new(theta.sin() * phi.sin(), theta.cos(), theta.sin() * phi.cos())
+}
+
+impl Light for EnvironmentLight {
+    fn sample_li(&self, p_ref: Point3<f64>, u: Point2<f64>) -> LightSample {
+        let (uv, map_pdf) = self.distribution.sample_continuous(u);
+        let wi = self.light_to_world.transform_vector3(uv_to_direction(uv)).normalize();
+
+        // convert the pdf from the image to the solid angle
+        let sin_theta = (uv.y * PI).sin();
+        let pdf = if sin_theta > 0.0 { map_pdf / (2.0 * PI * PI * sin_theta) } else { 0.0 };
+
+        // place the sample outside of the scene so that the shadow ray covers all the geometry
+        let (_center, radius) = self.scene_sphere.get().expect("the environment light is not preprocessed");
+        let distance = 2.0 * radius;
+
+        LightSample {
+            position: p_ref + wi * distance,
+            normal: -wi,
+            le: if pdf > 0.0 { self.lookup(uv) } else { Spectrum::black() },
+            dir: -wi,
+            // the sample faces the lit point, the area pdf turns back into the solid angle one
+            pdf: pdf / (distance * distance),
+            is_delta: false,
+        }
+    }
+
+    fn le(&self) -> Spectrum {
+        self.average
+    }
+
+    fn phi(&self) -> Spectrum {
+        // the power arriving at a disk as large as the scene from every direction
+        let (_center, radius) = self.scene_sphere.get().expect("the environment light is not preprocessed");
+        4.0 * PI * PI * radius * radius * self.average
+    }
+
+    fn intersect_p(&self, _r: &Ray) -> Option<f64> {
+        None
+    }
+
+    fn intersect(&self, _r: &mut Ray) -> Option<SurfaceInteraction> {
+        None
+    }
+
+    fn pdf(&self, _isect_p: Point3<f64>, _isect_n: Vector3<f64>, _p: Point3<f64>) -> f64 {
+        0.0
+    }
+
+    fn is_infinite(&self) -> bool {
+        true
+    }
+
+    fn le_escaped(&self, r: &Ray) -> Spectrum {
+        let w = self.world_to_light.transform_vector3(r.d).normalize();
+        self.lookup(direction_to_uv(w))
+    }
+
+    fn pdf_escaped(&self, wi: Vector3<f64>) -> f64 {
+        self.pdf_light_space(self.world_to_light.transform_vector3(wi).normalize())
+    }
+
+    fn preprocess(&self, scene_bound: &Bound3) {
+        let mut center = Point3::new(0.0, 0.0, 0.0);
+        let mut radius = 0.0;
+        scene_bound.bounding_sphere(&mut center, &mut radius);
+        self.scene_sphere.get_or_init(|| (center, radius.max(1.0)));
+    }
+
+    fn sample_le(&self, u_pos: Point2<f64>, u_dir: Point2<f64>, time: f64) -> LightEmission {
+        let (uv, map_pdf) = self.distribution.sample_continuous(u_dir);
+        let wi = self.light_to_world.transform_vector3(uv_to_direction(uv)).normalize();
+        let sin_theta = (uv.y * PI).sin();
+        let pdf_dir = if sin_theta > 0.0 { map_pdf / (2.0 * PI * PI * sin_theta) } else { 0.0 };
+
+        // the parallel rays coming from wi cross a disk as large as the scene, in front of it
+        let (center, radius) = *self.scene_sphere.get().expect("the environment light is not preprocessed");
+        let (s, t) = perpendicular(wi);
+        let disk = uniform_sample_disk(u_pos);
+        let p = center + (s * disk.x + t * disk.y + wi) * radius;
+
+        LightEmission {
+            ray: Ray::new(p, -wi, time, f64::INFINITY),
+            normal: -wi,
+            le: if pdf_dir > 0.0 { self.lookup(uv) } else { Spectrum::black() },
+            pdf_pos: 1.0 / (PI * radius * radius),
+            pdf_dir,
+        }
+    }
+
+    fn pdf_le(&self, _p: Point3<f64>, _n: Vector3<f64>, w: Vector3<f64>) -> (f64, f64) {
+        let (_center, radius) = self.scene_sphere.get().expect("the environment light is not preprocessed");
+        (1.0 / (PI * radius * radius), self.pdf_escaped(-w.normalize()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_direction_mapping() {
+        for &(u, v) in &[(0.1, 0.2), (0.5, 0.5), (0.9, 0.7), (0.3, 0.95)] {
+            let uv = direction_to_uv(uv_to_direction(Point2::new(u, v)));
+            assert!((uv.x - u).abs() < 1e-9 && (uv.y - v).abs() < 1e-9);
+        }
+        // the center of the image is in front of the default camera
+        let uv = direction_to_uv(Vector3::new(0.0, 0.0, 1.0));
+        assert!((uv.x - 0.5).abs() < 1e-12 && (uv.y - 0.5).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_pdf_integrates_to_one() {
+        let resolution = Point2::new(16, 8);
+        let texels = (0..resolution.x * resolution.y)
+            .map(|i| Spectrum::new((i % 5) as f64, 1.0, (i % 3) as f64))
+            .collect();
+        let light = EnvironmentLight::from_texels(
+            Transform::rotate(30.0, Vector3::new(0.0, 1.0, 1.0).normalize()),
+            texels,
+            resolution,
+            Spectrum::new(1.0, 1.0, 1.0),
+        );
+
+        // integrate the solid angle pdf over the sphere in (cos theta, phi)
+        let n = 400;
+        let mut integral = 0.0;
+        for i in 0..n {
+            for j in 0..n {
+                let cos_theta = 1.0 - 2.0 * (i as f64 + 0.5) / n as f64;
+                let phi = 2.0 * PI * (j as f64 + 0.5) / n as f64;
+                let sin_theta = (1.0 - cos_theta * cos_theta).sqrt();
+                let w = Vector3::new(sin_theta * phi.cos(), cos_theta, sin_theta * phi.sin());
+                integral += light.pdf_escaped(w) * 4.0 * PI / (n * n) as f64;
+            }
+        }
+        assert!((integral - 1.0).abs() < 1e-2);
+    }
+}
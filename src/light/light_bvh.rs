@@ -0,0 +1,407 @@
+use std::{collections::HashMap, f64::consts::PI, sync::Arc};
+
+use cgmath::{InnerSpace, Point3, Vector3, EuclideanSpace};
+
+use crate::{geometry::{bound3::Bound3, cone::DirectionCone}, sampler::rng::ONE_MINUS_EPSILON};
+
+use super::{Light, light_sampler::{LightSampler, light_key}};
+
+/// spatial and directional bounds of the emission of one or more lights (pbrt-v4)
+#[derive(Clone)]
+pub struct LightBounds {
+    pub bounds: Bound3,
+    pub w: Vector3<f64>, // axis of the cone bounding the emitting surface normals
+    pub phi: f64, // emitted power
+    pub cos_theta_o: f64, // spread of the surface normals around w
+    pub cos_theta_e: f64, // spread of the emission around each normal
+    pub two_sided: bool,
+}
+
+// cos(max(0, a - b)) given the sines and cosines of a and b
+fn cos_sub_clamped(sin_a: f64, cos_a: f64, sin_b: f64, cos_b: f64) -> f64 {
+    if cos_a > cos_b {
+        1.0
+    } else {
+        cos_a * cos_b + sin_a * sin_b
+    }
+}
+
+// sin(max(0, a - b)) given the sines and cosines of a and b
+fn sin_sub_clamped(sin_a: f64, cos_a: f64, sin_b: f64, cos_b: f64) -> f64 {
+    if cos_a > cos_b {
+        0.0
+    } else {
+        sin_a * cos_b - cos_a * sin_b
+    }
+}
+
+fn safe_sqrt(x: f64) -> f64 {
+    x.max(0.0).sqrt()
+}
+
+impl LightBounds {
+    pub fn new(bounds: Bound3, w: Vector3<f64>, phi: f64, cos_theta_o: f64, cos_theta_e: f64, two_sided: bool) -> Self {
+        LightBounds { bounds, w: w.normalize(), phi, cos_theta_o, cos_theta_e, two_sided }
+    }
+
+    pub fn centroid(&self) -> Point3<f64> {
+        self.bounds.p_min.midpoint(self.bounds.p_max)
+    }
+
+    pub fn union(a: &LightBounds, b: &LightBounds) -> LightBounds {
+        if a.phi == 0.0 {
+            return b.clone();
+        }
+        if b.phi == 0.0 {
+            return a.clone();
+        }
+
+        let cone = DirectionCone::union(&DirectionCone::new(a.w, a.cos_theta_o), &DirectionCone::new(b.w, b.cos_theta_o));
+
+        LightBounds {
+            bounds: a.bounds.union(&b.bounds),
+            w: cone.w,
+            phi: a.phi + b.phi,
+            cos_theta_o: cone.cos_theta,
+            cos_theta_e: a.cos_theta_e.min(b.cos_theta_e),
+            two_sided: a.two_sided || b.two_sided,
+        }
+    }
+
+    /// a conservative estimate of the contribution of the lights to the point p with normal n,
+    /// n may be zero for points that are not on a surface
+    pub fn importance(&self, p: Point3<f64>, n: Vector3<f64>) -> f64 {
+        // clamp the distance to the centroid to half the bound's diagonal
+        let pc = self.centroid();
+        let distance2 = (p - pc).magnitude2().max(self.bounds.diagonal().magnitude() / 2.0);
+
+        let wi = (p - pc).normalize();
+        let mut cos_theta_w = self.w.dot(wi);
+        if self.two_sided {
+            cos_theta_w = cos_theta_w.abs();
+        }
+        let sin_theta_w = safe_sqrt(1.0 - cos_theta_w * cos_theta_w);
+
+        // the angle subtended by the bound as seen from p
+        let cos_theta_b = DirectionCone::bound_subtended_directions(&self.bounds, p).cos_theta;
+        let sin_theta_b = safe_sqrt(1.0 - cos_theta_b * cos_theta_b);
+
+        // the minimum angle between the emission axis and the direction to p
+        let sin_theta_o = safe_sqrt(1.0 - self.cos_theta_o * self.cos_theta_o);
+        let cos_theta_x = cos_sub_clamped(sin_theta_w, cos_theta_w, sin_theta_o, self.cos_theta_o);
+        let sin_theta_x = sin_sub_clamped(sin_theta_w, cos_theta_w, sin_theta_o, self.cos_theta_o);
+        let cos_theta_p = cos_sub_clamped(sin_theta_x, cos_theta_x, sin_theta_b, cos_theta_b);
+        if cos_theta_p <= self.cos_theta_e {
+            return 0.0;
+        }
+
+        let mut importance = self.phi * cos_theta_p / distance2;
+
+        // the minimum angle between the normal at p and the direction to the lights
+        if n.magnitude2() > 0.0 {
+            let cos_theta_i = wi.dot(n).abs();
+            let sin_theta_i = safe_sqrt(1.0 - cos_theta_i * cos_theta_i);
+            importance *= cos_sub_clamped(sin_theta_i, cos_theta_i, sin_theta_b, cos_theta_b);
+        }
+
+        importance.max(0.0)
+    }
+}
+
+struct LightBVHNode {
+    light_bounds: LightBounds,
+    // the index of the second child for interior nodes, the first one directly follows its parent
+    child_or_light_index: usize,
+    is_leaf: bool,
+}
+
+/// picks lights according to their estimated contribution at the shading point by walking
+/// down a bvh over the light bounds, infinite lights are picked with a fixed probability
+pub struct BVHLightSampler {
+    lights: Vec<Arc<dyn Light>>,
+    infinite_lights: Vec<Arc<dyn Light>>,
+    nodes: Vec<LightBVHNode>,
+    // the branches taken from the root to every light, one bit per level
+    light_to_bit_trail: HashMap<usize, u64>,
+}
+
+const N_BUCKETS: usize = 12;
+
+impl BVHLightSampler {
+    pub fn new(lights: &[Arc<dyn Light>]) -> Self {
+        let mut sampler = BVHLightSampler {
+            lights: lights.to_vec(),
+            infinite_lights: Vec::new(),
+            nodes: Vec::new(),
+            light_to_bit_trail: HashMap::new(),
+        };
+
+        let mut bvh_lights = Vec::new();
+        for (i, light) in lights.iter().enumerate() {
+            match light.bounds() {
+                Some(bounds) if bounds.phi > 0.0 => bvh_lights.push((i, bounds)),
+                Some(_) => {},
+                None => sampler.infinite_lights.push(light.clone()),
+            }
+        }
+
+        if !bvh_lights.is_empty() {
+            sampler.build(&mut bvh_lights, 0, 0);
+        }
+
+        sampler
+    }
+
+    fn build(&mut self, bvh_lights: &mut [(usize, LightBounds)], bit_trail: u64, depth: u32) -> (usize, LightBounds) {
+        if bvh_lights.len() == 1 {
+            let (light_index, light_bounds) = bvh_lights[0].clone();
+            let node_index = self.nodes.len();
+            self.nodes.push(LightBVHNode { light_bounds: light_bounds.clone(), child_or_light_index: light_index, is_leaf: true });
+            self.light_to_bit_trail.insert(light_key(&self.lights[light_index]), bit_trail);
+            return (node_index, light_bounds);
+        }
+        assert!(depth < 64, "the light bvh is too deep");
+
+        let mut bounds = bvh_lights[0].1.bounds.clone();
+        let mut centroid_bounds = Bound3::new(bvh_lights[0].1.centroid(), bvh_lights[0].1.centroid());
+        for (_, lb) in bvh_lights.iter() {
+            bounds = bounds.union(&lb.bounds);
+            centroid_bounds = centroid_bounds.union_point3(lb.centroid());
+        }
+
+        let bucket_of = |lb: &LightBounds, dim: usize| -> usize {
+            let offset = centroid_bounds.offset(lb.centroid())[dim];
+            ((N_BUCKETS as f64 * offset) as usize).min(N_BUCKETS - 1)
+        };
+
+        // find the cheapest split among the bucket boundaries of all axes
+        let mut min_cost = f64::INFINITY;
+        let mut split: Option<(usize, usize)> = None; // (dim, last bucket of the first child)
+        for dim in 0..3 {
+            if centroid_bounds.p_max[dim] == centroid_bounds.p_min[dim] {
+                continue;
+            }
+
+            let mut buckets: Vec<Option<LightBounds>> = vec![None; N_BUCKETS];
+            for (_, lb) in bvh_lights.iter() {
+                let b = bucket_of(lb, dim);
+                buckets[b] = Some(match &buckets[b] {
+                    Some(bucket) => LightBounds::union(bucket, lb),
+                    None => lb.clone(),
+                });
+            }
+
+            for i in 0..N_BUCKETS - 1 {
+                let below = union_all(&buckets[..=i]);
+                let above = union_all(&buckets[i + 1..]);
+                let cost = evaluate_cost(&below, &bounds, dim) + evaluate_cost(&above, &bounds, dim);
+                if cost > 0.0 && cost < min_cost {
+                    min_cost = cost;
+                    split = Some((dim, i));
+                }
+            }
+        }
+
+        let mut mid = match split {
+            Some((dim, bucket)) => partition(bvh_lights, |(_, lb)| bucket_of(lb, dim) <= bucket),
+            None => bvh_lights.len() / 2,
+        };
+        if mid == 0 || mid == bvh_lights.len() {
+            mid = bvh_lights.len() / 2;
+        }
+
+        // the placeholder is filled in once both children are built
+        let node_index = self.nodes.len();
+        self.nodes.push(LightBVHNode { light_bounds: bvh_lights[0].1.clone(), child_or_light_index: 0, is_leaf: false });
+
+        let (first, rest) = bvh_lights.split_at_mut(mid);
+        let (_, lb0) = self.build(first, bit_trail, depth + 1);
+        let (second_index, lb1) = self.build(rest, bit_trail | (1 << depth), depth + 1);
+
+        let light_bounds = LightBounds::union(&lb0, &lb1);
+        self.nodes[node_index] = LightBVHNode { light_bounds: light_bounds.clone(), child_or_light_index: second_index, is_leaf: false };
+
+        (node_index, light_bounds)
+    }
+
+    fn p_infinite(&self) -> f64 {
+        let n_infinite = self.infinite_lights.len() as f64;
+        let n_bvh = if self.nodes.is_empty() { 0.0 } else { 1.0 };
+        if n_infinite == 0.0 { 0.0 } else { n_infinite / (n_infinite + n_bvh) }
+    }
+}
+
+fn union_all(buckets: &[Option<LightBounds>]) -> Option<LightBounds> {
+    buckets.iter().flatten().fold(None, |acc, lb| match acc {
+        Some(acc) => Some(LightBounds::union(&acc, lb)),
+        None => Some(lb.clone()),
+    })
+}
+
+// the cost of a child is its power times the solid angle of its emission times its surface area,
+// long thin bounds along the split axis are penalized
+fn evaluate_cost(lb: &Option<LightBounds>, bounds: &Bound3, dim: usize) -> f64 {
+    let lb = match lb {
+        Some(lb) => lb,
+        None => return 0.0,
+    };
+
+    let theta_o = lb.cos_theta_o.clamp(-1.0, 1.0).acos();
+    let theta_e = lb.cos_theta_e.clamp(-1.0, 1.0).acos();
+    let theta_w = (theta_o + theta_e).min(PI);
+    let sin_theta_o = safe_sqrt(1.0 - lb.cos_theta_o * lb.cos_theta_o);
+    let m_omega = 2.0 * PI * (1.0 - lb.cos_theta_o)
+        + PI / 2.0 * (2.0 * theta_w * sin_theta_o - (theta_o - 2.0 * theta_w).cos() - 2.0 * theta_o * sin_theta_o + lb.cos_theta_o);
+
+    let diagonal = bounds.diagonal();
+    let kr = diagonal.x.max(diagonal.y).max(diagonal.z) / diagonal[dim];
+
+    lb.phi * m_omega * kr * lb.bounds.surface_area()
+}
+
+// move the elements satisfying pred to the front, returns their count
+fn partition<T>(v: &mut [T], pred: impl Fn(&T) -> bool) -> usize {
+    let mut mid = 0;
+    for i in 0..v.len() {
+        if pred(&v[i]) {
+            v.swap(i, mid);
+            mid += 1;
+        }
+    }
+    mid
+}
+
+impl LightSampler for BVHLightSampler {
+    fn sample(&self, p: Point3<f64>, n: Vector3<f64>, u: f64) -> Option<(Arc<dyn Light>, f64)> {
+        let p_infinite = self.p_infinite();
+
+        if u < p_infinite {
+            let n_infinite = self.infinite_lights.len();
+            let index = ((u / p_infinite * n_infinite as f64) as usize).min(n_infinite - 1);
+            return Some((self.infinite_lights[index].clone(), p_infinite / n_infinite as f64));
+        }
+
+        if self.nodes.is_empty() {
+            return None;
+        }
+
+        // descend the tree choosing the children by their importance, reusing u along the way
+        let mut u = ((u - p_infinite) / (1.0 - p_infinite)).min(ONE_MINUS_EPSILON);
+        let mut node_index = 0;
+        let mut pmf = 1.0 - p_infinite;
+
+        loop {
+            let node = &self.nodes[node_index];
+            if node.is_leaf {
+                if node_index > 0 || node.light_bounds.importance(p, n) > 0.0 {
+                    return Some((self.lights[node.child_or_light_index].clone(), pmf));
+                }
+                return None;
+            }
+
+            let children = [node_index + 1, node.child_or_light_index];
+            let ci = children.map(|c| self.nodes[c].light_bounds.importance(p, n));
+            if ci[0] == 0.0 && ci[1] == 0.0 {
+                return None;
+            }
+
+            let p0 = ci[0] / (ci[0] + ci[1]);
+            if u < p0 {
+                pmf *= p0;
+                u = (u / p0).min(ONE_MINUS_EPSILON);
+                node_index = children[0];
+            } else {
+                pmf *= 1.0 - p0;
+                u = ((u - p0) / (1.0 - p0)).min(ONE_MINUS_EPSILON);
+                node_index = children[1];
+            }
+        }
+    }
+
+    fn pmf(&self, p: Point3<f64>, n: Vector3<f64>, light: &Arc<dyn Light>) -> f64 {
+        let key = light_key(light);
+
+        if self.infinite_lights.iter().any(|l| light_key(l) == key) {
+            return self.p_infinite() / self.infinite_lights.len() as f64;
+        }
+
+        let mut bit_trail = match self.light_to_bit_trail.get(&key) {
+            Some(&bit_trail) => bit_trail,
+            None => return 0.0,
+        };
+
+        // follow the branches to the light, multiplying the probabilities of the choices
+        let mut pmf = 1.0 - self.p_infinite();
+        let mut node_index = 0;
+        loop {
+            let node = &self.nodes[node_index];
+            if node.is_leaf {
+                return pmf;
+            }
+
+            let children = [node_index + 1, node.child_or_light_index];
+            let ci = children.map(|c| self.nodes[c].light_bounds.importance(p, n));
+            let child = (bit_trail & 1) as usize;
+            if ci[child] == 0.0 {
+                return 0.0;
+            }
+
+            pmf *= ci[child] / (ci[0] + ci[1]);
+            node_index = children[child];
+            bit_trail >>= 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{geometry::{shape::disk::Disk, transform::Transform}, light::{area::AreaLight, point::PointLight}, spectrum::Spectrum};
+
+    fn lights() -> Vec<Arc<dyn Light>> {
+        let mut lights: Vec<Arc<dyn Light>> = Vec::new();
+        for i in 0..20 {
+            let x = (i % 5) as f64 * 3.0 - 6.0;
+            let z = (i / 5) as f64 * 4.0;
+            // facing down, some of them tilted
+            let object_to_world = Transform::translate(Vector3::new(x, 5.0, z)) * Transform::rotate(90.0 + 10.0 * (i % 3) as f64, Vector3::new(1.0, 0.0, 0.0));
+            let disk = Disk::new(object_to_world.clone(), object_to_world.inverse(), 0.5 + 0.1 * i as f64);
+            lights.push(Arc::new(AreaLight::new(Box::new(disk), Spectrum::new(1.0 + i as f64, 2.0, 3.0))));
+        }
+        lights.push(Arc::new(PointLight::new(Point3::new(0.0, 2.0, 3.0), Spectrum::new(5.0, 5.0, 5.0))));
+        lights
+    }
+
+    #[test]
+    fn test_bvh_light_sampler_pmf() {
+        let lights = lights();
+        let sampler = BVHLightSampler::new(&lights);
+        let (p, n) = (Point3::new(1.0, 0.0, 2.0), Vector3::new(0.0, 1.0, 0.0));
+
+        // the pmf of the sampled light matches the one evaluated afterwards
+        let n_samples = 1000;
+        let mut counts = HashMap::new();
+        for i in 0..n_samples {
+            let (light, pmf) = sampler.sample(p, n, (i as f64 + 0.5) / n_samples as f64).unwrap();
+            assert!((pmf - sampler.pmf(p, n, &light)).abs() < 1e-12);
+            *counts.entry(light_key(&light)).or_insert(0) += 1;
+        }
+
+        // the pmfs form a distribution that the samples follow
+        let total: f64 = lights.iter().map(|light| sampler.pmf(p, n, light)).sum();
+        assert!((total - 1.0).abs() < 1e-9);
+        for light in lights.iter() {
+            let frequency = *counts.get(&light_key(light)).unwrap_or(&0) as f64 / n_samples as f64;
+            assert!((frequency - sampler.pmf(p, n, light)).abs() < 0.01);
+        }
+    }
+
+    #[test]
+    fn test_importance_culls_back_facing_lights() {
+        // a light facing up does not lit the points below it
+        let bounds = Bound3::new(Point3::new(-1.0, 0.0, -1.0), Point3::new(1.0, 0.0, 1.0));
+        let light_bounds = LightBounds::new(bounds, Vector3::new(0.0, 1.0, 0.0), 1.0, 1.0, 0.0, false);
+        assert!(light_bounds.importance(Point3::new(0.0, 3.0, 0.0), Vector3::new(0.0, -1.0, 0.0)) > 0.0);
+        assert_eq!(light_bounds.importance(Point3::new(0.0, -3.0, 0.0), Vector3::new(0.0, 1.0, 0.0)), 0.0);
+    }
+}
@@ -0,0 +1,106 @@
+use std::{collections::HashMap, sync::Arc};
+
+use cgmath::{Point3, Vector3};
+
+use crate::sampler::distribution::AliasTable;
+
+use super::{Light, light_bvh::BVHLightSampler};
+
+/// strategies to choose the light sampled at a shading point
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LightSamplerType {
+    Uniform,
+    Power,
+    Bvh,
+}
+
+pub trait LightSampler: Sync + Send {
+    /// pick a light for the shading point p with normal n
+    ///
+    /// returns the light and the probability of choosing it, None if no light can lit p
+    fn sample(&self, p: Point3<f64>, n: Vector3<f64>, u: f64) -> Option<(Arc<dyn Light>, f64)>;
+    /// the probability of sample choosing the light at the shading point
+    fn pmf(&self, p: Point3<f64>, n: Vector3<f64>, light: &Arc<dyn Light>) -> f64;
+}
+
+pub fn create_light_sampler(tp: LightSamplerType, lights: &[Arc<dyn Light>]) -> Box<dyn LightSampler> {
+    match tp {
+        LightSamplerType::Uniform => Box::new(UniformLightSampler::new(lights)),
+        LightSamplerType::Power => Box::new(PowerLightSampler::new(lights)),
+        LightSamplerType::Bvh => Box::new(BVHLightSampler::new(lights)),
+    }
+}
+
+/// lights are identified by the address of their shared data
+pub fn light_key(light: &Arc<dyn Light>) -> usize {
+    Arc::as_ptr(light) as *const () as usize
+}
+
+pub struct UniformLightSampler {
+    lights: Vec<Arc<dyn Light>>,
+}
+
+impl UniformLightSampler {
+    pub fn new(lights: &[Arc<dyn Light>]) -> Self {
+        UniformLightSampler { lights: lights.to_vec() }
+    }
+}
+
+impl LightSampler for UniformLightSampler {
+    fn sample(&self, _p: Point3<f64>, _n: Vector3<f64>, u: f64) -> Option<(Arc<dyn Light>, f64)> {
+        if self.lights.is_empty() {
+            return None;
+        }
+
+        let i = ((u * self.lights.len() as f64) as usize).min(self.lights.len() - 1);
+        Some((self.lights[i].clone(), 1.0 / self.lights.len() as f64))
+    }
+
+    fn pmf(&self, _p: Point3<f64>, _n: Vector3<f64>, _light: &Arc<dyn Light>) -> f64 {
+        if self.lights.is_empty() {
+            0.0
+        } else {
+            1.0 / self.lights.len() as f64
+        }
+    }
+}
+
+/// picks the lights proportionally to their emitted power, regardless of the shading point
+pub struct PowerLightSampler {
+    lights: Vec<Arc<dyn Light>>,
+    alias_table: Option<AliasTable>,
+    light_to_index: HashMap<usize, usize>,
+}
+
+impl PowerLightSampler {
+    pub fn new(lights: &[Arc<dyn Light>]) -> Self {
+        let light_to_index = lights.iter().enumerate().map(|(i, light)| (light_key(light), i)).collect();
+
+        let alias_table = if lights.is_empty() {
+            None
+        } else {
+            let power: Vec<f64> = lights.iter().map(|light| light.phi().luminance().max(0.0)).collect();
+            Some(AliasTable::new(&power))
+        };
+
+        PowerLightSampler {
+            lights: lights.to_vec(),
+            alias_table,
+            light_to_index,
+        }
+    }
+}
+
+impl LightSampler for PowerLightSampler {
+    fn sample(&self, _p: Point3<f64>, _n: Vector3<f64>, u: f64) -> Option<(Arc<dyn Light>, f64)> {
+        let (index, pmf, _) = self.alias_table.as_ref()?.sample(u);
+        Some((self.lights[index].clone(), pmf))
+    }
+
+    fn pmf(&self, _p: Point3<f64>, _n: Vector3<f64>, light: &Arc<dyn Light>) -> f64 {
+        match (&self.alias_table, self.light_to_index.get(&light_key(light))) {
+            (Some(table), Some(&index)) => table.pmf(index),
+            _ => 0.0,
+        }
+    }
+}
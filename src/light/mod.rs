@@ -1,59 +1,113 @@
 pub mod point;
 pub mod area;
+pub mod environment;
+pub mod light_sampler;
+pub mod light_bvh;
 
 use std::sync::Arc;
 
-use crate::{spectrum::Spectrum, geometry::{interaction::SurfaceInteraction, ray::Ray}, sampler::wrs::Reservoir};
+use crate::{spectrum::Spectrum, geometry::{bound3::Bound3, interaction::SurfaceInteraction, ray::Ray}, };
 use cgmath::{Point2, Point3, Vector3, InnerSpace};
 
+use self::{light_bvh::LightBounds, light_sampler::{LightSampler, LightSamplerType, PowerLightSampler, create_light_sampler}};
+
 pub trait Light: Sync + Send {
-    fn sample_li(&self, isect: &SurfaceInteraction, u: Point2<f64>) -> LightSample;
+    // sample a point on the light to illuminate the point p_ref
+    fn sample_li(&self, p_ref: Point3<f64>, u: Point2<f64>) -> LightSample;
 
     fn le(&self) -> Spectrum;
+    // the total power emitted by the light
+    fn phi(&self) -> Spectrum;
+    // the spatial and directional bounds of the emission, None for infinite lights
+    fn bounds(&self) -> Option<LightBounds> {
+        None
+    }
     fn intersect_p(&self, r: &Ray) -> Option<f64>;
     fn intersect(&self, r: &mut Ray) -> Option<SurfaceInteraction>;
     // return the pdf with respect to the solid angle, p is the lit point
     fn pdf(&self, isect_p: Point3<f64>, isect_n: Vector3<f64>, p: Point3<f64>) -> f64;
+
+    // infinite lights have no geometry to hit, they are seen by the rays escaping the scene
+    fn is_infinite(&self) -> bool {
+        false
+    }
+    fn le_escaped(&self, _r: &Ray) -> Spectrum {
+        Spectrum::black()
+    }
+    // the solid angle pdf of sample_li choosing the direction wi
+    fn pdf_escaped(&self, _wi: Vector3<f64>) -> f64 {
+        0.0
+    }
+    // called once the bound of the scene is known
+    fn preprocess(&self, _scene_bound: &Bound3) {}
+
+    // lights at a single point can only be reached by sampling them
+    fn is_delta(&self) -> bool {
+        false
+    }
+    /// sample a ray leaving the light, for the paths that start from the lights
+    fn sample_le(&self, u_pos: Point2<f64>, u_dir: Point2<f64>, time: f64) -> LightEmission;
+    /// the densities (pdf_pos, pdf_dir) of sample_le choosing the ray leaving p, with the normal n of
+    /// the light there, in the direction w
+    fn pdf_le(&self, p: Point3<f64>, n: Vector3<f64>, w: Vector3<f64>) -> (f64, f64);
 }
 
 pub struct LightList {
     pub lights: Vec<Arc<dyn Light>>,
+    pub infinite_lights: Vec<Arc<dyn Light>>,
+    light_sampler: Box<dyn LightSampler>,
+    emitter_sampler: PowerLightSampler, // where the paths traced from the lights start
 }
 
 
 impl LightList {
-    pub fn new(lights: Vec<Arc<dyn Light>>) -> Self {
+    /// the lights must have been preprocessed, their power may depend on the scene bound
+    pub fn new(lights: Vec<Arc<dyn Light>>, light_sampler: LightSamplerType) -> Self {
+        let infinite_lights = lights.iter().filter(|light| light.is_infinite()).cloned().collect();
+        let light_sampler = create_light_sampler(light_sampler, &lights);
+        let emitter_sampler = PowerLightSampler::new(&lights);
+
         LightList {
             lights,
+            infinite_lights,
+            light_sampler,
+            emitter_sampler,
         }
     }
 
-    pub fn uniform_pick_light(&self, u: f64) -> (Arc<dyn Light>, f64) {
-        let i = (u * self.lights.len() as f64) as usize;
-        let light = self.lights[i].clone();
-        let light_pdf = 1.0 / self.lights.len() as f64;
+    /// pick a light to be sampled at the point p with normal n
+    ///
+    /// returns the light and the probability of picking it
+    pub fn sample(&self, p: Point3<f64>, n: Vector3<f64>, u: f64) -> Option<(Arc<dyn Light>, f64)> {
+        self.light_sampler.sample(p, n, u)
+    }
 
-        (light, light_pdf)
+    /// the probability of sample picking the light at the point p with normal n
+    pub fn pmf(&self, p: Point3<f64>, n: Vector3<f64>, light: &Arc<dyn Light>) -> f64 {
+        self.light_sampler.pmf(p, n, light)
     }
 
-    /// sampling the lights with respect to their power
-    /// 
-    /// returns the ptr to the light and its sampling probability
-    pub fn importance_sample_light(&self, _u: Point2<f64>) -> (Arc<dyn Light>, f64) {
-        // sample the light 
-        assert!(!self.lights.is_empty());
-        let mut r = Reservoir::new();
-
-        for light in &self.lights {
-            let weight = light.le().sum();
-            r.update(light.clone(), weight);
-        }
+    /// pick the light a path leaving the lights starts from, proportionally to its power
+    pub fn sample_emitter(&self, u: f64) -> Option<(Arc<dyn Light>, f64)> {
+        let origin = Point3::new(0.0, 0.0, 0.0);
+        self.emitter_sampler.sample(origin, Vector3::new(0.0, 0.0, 1.0), u)
+    }
 
-        let y = r.output_sample();
-        (y.clone(), y.le().sum() / r.weight_sum)
+    /// the probability of sample_emitter picking the light
+    pub fn pmf_emitter(&self, light: &Arc<dyn Light>) -> f64 {
+        self.emitter_sampler.pmf(Point3::new(0.0, 0.0, 0.0), Vector3::new(0.0, 0.0, 1.0), light)
     }
 }
 
+/// a ray leaving a light, with the densities of the point and direction it was sampled with
+pub struct LightEmission {
+    pub ray: Ray,
+    pub normal: Vector3<f64>, // the normal of the light at the origin, zero for the lights at a point
+    pub le: Spectrum,
+    pub pdf_pos: f64, // pdf in area
+    pub pdf_dir: f64, // pdf in solid angle
+}
+
 pub struct LightSample {
     pub position: Point3<f64>,
     pub normal: Vector3<f64>,
@@ -64,9 +118,9 @@ pub struct LightSample {
 }
 
 impl LightSample {
-    pub fn pdf_area_to_solid(&self, isect: &SurfaceInteraction) -> f64 {
-        let distance2 = (isect.geo.p - self.position).magnitude2();
-        let we = (isect.geo.p - self.position).normalize();
+    pub fn pdf_area_to_solid(&self, p_ref: Point3<f64>) -> f64 {
+        let distance2 = (p_ref - self.position).magnitude2();
+        let we = (p_ref - self.position).normalize();
         let cos_alpha = we.dot(self.normal);
 
         if cos_alpha > 0.0 && !self.is_delta {
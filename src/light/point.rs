@@ -1,4 +1,7 @@
-use super::{Light, LightSample};
+use std::f64::consts::PI;
+
+use super::{Light, LightEmission, LightSample, light_bvh::LightBounds};
+use crate::geometry::bound3::Bound3;
 use crate::geometry::{interaction::SurfaceInteraction, ray::Ray};
 use crate::spectrum::Spectrum;
 use cgmath::{Point2, Point3, InnerSpace, Vector3};
@@ -17,9 +20,9 @@ impl PointLight {
 }
 
 impl Light for PointLight {
-    fn sample_li(&self, isect: &SurfaceInteraction, _u: Point2<f64>) -> LightSample {
-        let distance2 = (self.p - isect.geo.p).magnitude2();
-        let dir = (isect.geo.p - self.p).normalize();
+    fn sample_li(&self, p_ref: Point3<f64>, _u: Point2<f64>) -> LightSample {
+        let distance2 = (self.p - p_ref).magnitude2();
+        let dir = (p_ref - self.p).normalize();
 
         if distance2 > 0.0 {
             LightSample {
@@ -46,6 +49,16 @@ impl Light for PointLight {
         self.le
     }
 
+    fn phi(&self) -> Spectrum {
+        4.0 * PI * self.le
+    }
+
+    fn bounds(&self) -> Option<LightBounds> {
+        // emits in all directions
+        let bounds = Bound3::new(self.p, self.p);
+        Some(LightBounds::new(bounds, Vector3::new(0.0, 0.0, 1.0), self.phi().luminance(), -1.0, 0.0, false))
+    }
+
     fn intersect_p(&self, _r: &crate::geometry::ray::Ray) -> Option<f64> {
         None
     }
@@ -57,4 +70,28 @@ impl Light for PointLight {
     fn pdf(&self, _isect_p: Point3<f64>, _isect_n: Vector3<f64>, _p: Point3<f64>) -> f64 {
         0.0
     }
+
+    fn is_delta(&self) -> bool {
+        true
+    }
+
+    fn sample_le(&self, _u_pos: Point2<f64>, u_dir: Point2<f64>, time: f64) -> LightEmission {
+        // uniformly in every direction
+        let z = 1.0 - 2.0 * u_dir.x;
+        let r = (1.0 - z * z).max(0.0).sqrt();
+        let phi = 2.0 * PI * u_dir.y;
+        let d = Vector3::new(r * phi.cos(), r * phi.sin(), z);
+
+        LightEmission {
+            ray: Ray::new(self.p, d, time, f64::INFINITY),
+            normal: Vector3::new(0.0, 0.0, 0.0),
+            le: self.le,
+            pdf_pos: 1.0,
+            pdf_dir: 1.0 / (4.0 * PI),
+        }
+    }
+
+    fn pdf_le(&self, _p: Point3<f64>, _n: Vector3<f64>, _w: Vector3<f64>) -> (f64, f64) {
+        (0.0, 1.0 / (4.0 * PI))
+    }
 }
@@ -13,10 +13,13 @@ mod texture;
 mod accelerator;
 mod scene;
 mod parser;
+mod medium;
 
+use camera::{film::FilmTile, aov::Aov};
 use camera::perspective::PerspectiveCamera;
 use cgmath::Point2;
-use indicatif::{ProgressBar, MultiProgress, ProgressStyle};
+use crossbeam_deque::{Injector, Stealer, Worker};
+use indicatif::{ProgressBar, ProgressStyle};
 use sampler::Sampler;
 
 use crate::parser::parse_scene;
@@ -25,26 +28,29 @@ use crate::spectrum::Spectrum;
 use crate::camera::{Camera, CameraSample};
 use crate::integrator::Integrator;
 
-use rand::random;
-
 use std::sync::Arc;
 use std::thread;
 use std::env;
 
+// width and height of the image blocks handed out to the render threads
+const TILE_SIZE: usize = 16;
+
 pub struct WorldSetting {
     pub n_sample: usize,
     pub n_thread: usize, 
     pub integrator: Arc<Box<dyn Integrator>>,
-    pub sampler: Arc<dyn Sampler>,
+    pub sampler: Box<dyn Sampler>, // prototype of the samplers owned by the render threads
+    pub seed: u64, // the integrators drawing their own random numbers mix it in too
 }
 
 impl WorldSetting {
-    pub fn new(n_sample: usize, n_thread: usize, integrator: Arc<Box<dyn Integrator>>, sampler: Arc<dyn Sampler>) -> WorldSetting {
+    pub fn new(n_sample: usize, n_thread: usize, integrator: Arc<Box<dyn Integrator>>, sampler: Box<dyn Sampler>, seed: u64) -> WorldSetting {
         Self {
             n_sample,
             n_thread,
             integrator,
-            sampler
+            sampler,
+            seed,
         }
     }
 }
@@ -69,7 +75,14 @@ fn main() {
 
     // render
     let s_render = std::time::Instant::now();
-    render(setting.integrator.clone(), camera, scene, &setting);
+    if !setting.integrator.render(&scene, &setting) {
+        render(setting.integrator.clone(), Arc::clone(&camera), Arc::new(scene), &setting, false);
+    } else if !camera.film.aovs.is_empty() {
+        // the integrators rendering the whole film leave the auxiliary outputs to another pass
+        render(setting.integrator.clone(), Arc::clone(&camera), Arc::new(scene), &setting, true);
+    }
+    // the splats are summed over all the samples of the pixels
+    camera.film.write_to_image(1.0 / setting.n_sample as f64);
 
     let render_cost = s_render.elapsed().as_millis();
     println!("RENDER COST: {} secs", (render_cost as f64) / 1000.0);
@@ -77,56 +90,93 @@ fn main() {
 
 
 
-fn render(integrator: Arc<Box<dyn Integrator>>, camera: PerspectiveCamera, scene: Scene, setting: &WorldSetting) {
+/// accumulate the radiance of every pixel into the camera's film, along with the auxiliary outputs it asks for,
+/// aovs_only records the auxiliary outputs alone, whose direct and indirect light are then left black
+fn render(integrator: Arc<Box<dyn Integrator>>, camera: Arc<PerspectiveCamera>, scene: Arc<Scene>, setting: &WorldSetting, aovs_only: bool) {
     let n_thread = setting.n_thread;
     let n_sample = setting.n_sample;
-    let camera = Arc::new(camera);
+    let with_aovs = !camera.film.aovs.is_empty();
+
+    // every tile is pushed into the global queue, workers take tiles in batches and steal from each other when idle
+    let tiles = camera.film.tiles(TILE_SIZE);
+    let n_tiles = tiles.len();
+    let injector = Arc::new(Injector::new());
+    for tile in tiles {
+        injector.push(tile);
+    }
 
-    let res = camera.film.resolution;
-    let (width, height) = (res.x, res.y);
+    let workers: Vec<Worker<FilmTile>> = (0..n_thread).map(|_| Worker::new_fifo()).collect();
+    let stealers: Arc<Vec<Stealer<FilmTile>>> = Arc::new(workers.iter().map(|w| w.stealer()).collect());
 
-    let scene = Arc::new(scene);
+    // set the progress bar, one step per finished tile
+    let bar = ProgressBar::new(n_tiles as u64);
+    bar.set_message(format!("{} threads running...", n_thread));
+    bar.set_style(ProgressStyle::with_template("{msg}  {bar:40.cyan/blue} {pos:>7}/{len:7} tiles [{elapsed_precise}]")
+        .unwrap()
+        .progress_chars("=>-"));
 
     let mut handlers = Vec::new();
 
-    let multi_bar = MultiProgress::new();
-
-    multi_bar.println(format!("{} threads running...", n_thread)).unwrap();
-
-    for tid in 0..n_thread {
+    for local in workers {
         let int = Arc::clone(&integrator);
         let scene = Arc::clone(&scene);
         let camera = Arc::clone(&camera);
-        let sampler = Arc::clone(&setting.sampler);
-        // set the progress bar
-        let bar = multi_bar.add(ProgressBar::new(height as u64));
-        bar.set_message(format!("t{}", tid));
-        bar.set_style(ProgressStyle::with_template("{msg}  {bar:40.cyan/blue} {pos:>7}/{len:7} [{elapsed_precise}]")
-            .unwrap()
-            .progress_chars("=>-"));
+        let mut sampler = setting.sampler.clone_box();
+        let injector = Arc::clone(&injector);
+        let stealers = Arc::clone(&stealers);
+        let bar = bar.clone();
 
         // create thread
         let handler = thread::spawn(move || {
-            for i in 0..height {
-                for j in 0..width {
-                    // first render the upper left pixel, then go rightwards and downwards
-                    let mut radiance = Spectrum::black();
-
-                    for _count in 0..n_sample {
-                        let sample = CameraSample::new(Point2::new(j as f64 + random::<f64>(), i as f64 + random::<f64>()), 0.0);
-                        
-                        let mut r = camera.generate_ray(sample);
-
-                        radiance += int.li(&mut r, &scene, &sampler);
+            while let Some(mut tile) = find_tile(&local, &injector, &stealers) {
+                for i in tile.p_min.y..tile.p_max.y {
+                    for j in tile.p_min.x..tile.p_max.x {
+                        let mut radiance = Spectrum::black();
+                        let mut aov = Aov::default();
+
+                        for count in 0..n_sample {
+                            sampler.start_pixel_sample(Point2::new(j, i), count);
+                            let offset = sampler.get_pixel_2d();
+                            let sample = CameraSample::new(Point2::new(j as f64 + offset.x, i as f64 + offset.y), 0.0);
+
+                            let mut r = camera.generate_ray(sample);
+                            // the samples of a pixel split its footprint
+                            r.scale_differentials((1.0 / (n_sample as f64).sqrt()).max(0.125));
+
+                            if !with_aovs {
+                                radiance += int.li_splat(&mut r, &scene, sampler.as_mut(), &mut tile.splats);
+                                continue;
+                            }
+
+                            let mut sample_aov = Aov::first_hit(&r, &scene);
+                            if !aovs_only {
+                                let (direct, indirect) = int.li_split(&mut r, &scene, sampler.as_mut(), &mut tile.splats);
+                                radiance += direct + indirect;
+                                sample_aov.direct = direct;
+                                sample_aov.indirect = indirect;
+                            }
+                            // the first sample gives the outputs that are not averaged
+                            if count == 0 {
+                                aov = sample_aov;
+                            } else {
+                                aov.accumulate(&sample_aov);
+                            }
+                        }
+
+                        if !aovs_only {
+                            radiance /= n_sample as f64;
+                            tile.record(i, j, radiance);
+                        }
+                        if with_aovs {
+                            aov.average(n_sample);
+                            tile.record_aov(i, j, aov);
+                        }
                     }
-
-                    radiance /= n_sample as f64 * n_thread as f64;
-                    camera.film.record(i, j, radiance.tone_mapping());
                 }
+
+                camera.film.merge_tile(tile);
                 bar.inc(1);
             }
-
-            bar.finish();
         });
 
         handlers.push(handler);
@@ -136,5 +186,54 @@ fn render(integrator: Arc<Box<dyn Integrator>>, camera: PerspectiveCamera, scene
         handler.join().unwrap();
     }
 
-    camera.film.write_to_image();
+    bar.finish();
+}
+
+/// pop a tile from the local queue, otherwise refill it from the global queue or steal from the other workers
+fn find_tile(local: &Worker<FilmTile>, global: &Injector<FilmTile>, stealers: &[Stealer<FilmTile>]) -> Option<FilmTile> {
+    local.pop().or_else(|| {
+        std::iter::repeat_with(|| {
+            global.steal_batch_and_pop(local)
+                .or_else(|| stealers.iter().map(|s| s.steal()).collect())
+        })
+        .find(|s| !s.is_retry())
+        .and_then(|s| s.success())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::integrator::{path_integrator::PathIntegrator, test_scene::{camera, lit_sphere}};
+    use crate::sampler::{halton::HaltonSampler, uniform_sampler::UniformSampler};
+
+    fn render_with(n_thread: usize, sampler: Box<dyn Sampler>) -> Vec<Spectrum> {
+        let camera = camera();
+        let integrator: Arc<Box<dyn Integrator>> = Arc::new(Box::new(PathIntegrator::new(5, true)));
+        let setting = WorldSetting::new(sampler.samples_per_pixel(), n_thread, integrator, sampler, 3);
+
+        render(setting.integrator.clone(), Arc::clone(&camera), Arc::new(lit_sphere()), &setting, false);
+
+        let radiance_map = camera.film.radiance_map.lock().unwrap();
+        radiance_map.clone()
+    }
+
+    #[test]
+    fn test_deterministic_across_threads() {
+        let samplers: [fn(u64) -> Box<dyn Sampler>; 2] = [
+            |seed| Box::new(UniformSampler::new(4, seed)),
+            |seed| Box::new(HaltonSampler::new(4, Point2::new(20, 12), seed)),
+        ];
+
+        for sampler in samplers {
+            let single = render_with(1, sampler(7));
+            let multiple = render_with(3, sampler(7));
+            assert!(single.iter().any(|s| !s.is_black()));
+            assert_eq!(single, multiple);
+
+            // a different seed gives a different image
+            let reseeded = render_with(1, sampler(8));
+            assert_ne!(single, reseeded);
+        }
+    }
 }
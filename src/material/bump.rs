@@ -0,0 +1,142 @@
+use std::sync::Arc;
+
+use cgmath::InnerSpace;
+
+use crate::{geometry::interaction::{GeometryInfo, ShadingGeometry, SurfaceInteraction}, spectrum::Spectrum, texture::Texture};
+
+/// a perturbation of the shading normal, applied before the bxdfs of a material are built
+pub enum Bump {
+    /// a height field displacing the surface along its shading normal, in world units
+    Height(Arc<dyn Texture<f64>>),
+    /// normals in the tangent space of the shading frame, encoded as (n + 1) / 2 like the common
+    /// normal map images, whose texels have to be read without the sRGB decoding
+    NormalMap(Arc<dyn Texture<Spectrum>>),
+}
+
+/// the geometry the bsdf is built on, the one of the hit without a bump
+pub fn apply(bump: &Option<Bump>, isect: &SurfaceInteraction) -> GeometryInfo {
+    let mut geo = isect.geo.clone();
+    match bump {
+        None => (),
+        Some(Bump::Height(height)) => geo.set_shading_geometry(displace(height.as_ref(), isect), false),
+        Some(Bump::NormalMap(normal_map)) => geo.set_shading_geometry(normal_mapped(normal_map.as_ref(), isect), false),
+    }
+    geo
+}
+
+// the shading frame of the displaced surface p + h(u, v) n, from forward differences of the height
+fn displace(height: &dyn Texture<f64>, isect: &SurfaceInteraction) -> ShadingGeometry {
+    let shading = isect.geo.shading;
+
+    // shift by about half the pixel footprint, a small fixed amount when it is unknown
+    let (du, dv) = match &isect.differentials {
+        Some(d) => (0.5 * (d.dudx.abs() + d.dudy.abs()), 0.5 * (d.dvdx.abs() + d.dvdy.abs())),
+        None => (0.0, 0.0),
+    };
+    let du = if du == 0.0 { 0.0005 } else { du };
+    let dv = if dv == 0.0 { 0.0005 } else { dv };
+
+    let mut shifted = isect.clone();
+    shifted.geo.p = isect.geo.p + shading.dpdu * du;
+    shifted.geo.uv.x = isect.geo.uv.x + du;
+    shifted.geo.shading.n = (shading.n + shading.dndu * du).normalize();
+    let u_displace = height.evaluate(&shifted);
+
+    shifted.geo.p = isect.geo.p + shading.dpdv * dv;
+    shifted.geo.uv = isect.geo.uv;
+    shifted.geo.uv.y = isect.geo.uv.y + dv;
+    shifted.geo.shading.n = (shading.n + shading.dndv * dv).normalize();
+    let v_displace = height.evaluate(&shifted);
+
+    let displace = height.evaluate(isect);
+
+    let dpdu = shading.dpdu + shading.n * ((u_displace - displace) / du) + shading.dndu * displace;
+    let dpdv = shading.dpdv + shading.n * ((v_displace - displace) / dv) + shading.dndv * displace;
+    let mut n = dpdu.cross(dpdv);
+    // the parametrization may be left handed, keep the normal on the side of the unperturbed one
+    if n.dot(shading.n) < 0.0 {
+        n = -n;
+    }
+    ShadingGeometry { n, dpdu, dpdv, ..shading }
+}
+
+// the shading frame rotated to the normal read from the map
+fn normal_mapped(normal_map: &dyn Texture<Spectrum>, isect: &SurfaceInteraction) -> ShadingGeometry {
+    let shading = isect.geo.shading;
+    let c = normal_map.evaluate(isect);
+    let (s, t) = isect.geo.shading_frame();
+    let n = s * (2.0 * c.r - 1.0) + t * (2.0 * c.g - 1.0) + shading.n * (2.0 * c.b - 1.0);
+    if n.magnitude2() == 0.0 {
+        return shading;
+    }
+    let n = n.normalize();
+
+    // keep the lengths of the partial derivatives and make them tangent to the new normal
+    let dpdu = shading.dpdu - n * n.dot(shading.dpdu);
+    let dpdu = if dpdu.magnitude2() > 0.0 { dpdu.normalize() * shading.dpdu.magnitude() } else { t.cross(n).normalize() * shading.dpdu.magnitude() };
+    let dpdv = n.cross(dpdu).normalize() * shading.dpdv.magnitude();
+    ShadingGeometry { n, dpdu, dpdv, ..shading }
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::{Point2, Point3, Vector3};
+
+    use super::*;
+
+    // a flat patch in the plane z = 0 with (u, v) = (x, y)
+    fn patch() -> SurfaceInteraction {
+        SurfaceInteraction {
+            geo: GeometryInfo::new(
+                Point3::new(0.3, 0.4, 0.0), Vector3::new(0.0, 0.0, 1.0), 1.0, Vector3::new(0.0, 0.0, 1.0),
+                Point2::new(0.3, 0.4), Vector3::new(1.0, 0.0, 0.0), Vector3::new(0.0, 1.0, 0.0),
+            ),
+            time: 0.0,
+            material: None,
+            hit_light: false,
+            radiance: None,
+            light: None,
+            medium_interface: None,
+            differentials: None,
+            ids: Default::default(),
+        }
+    }
+
+    struct Ramp;
+
+    impl Texture<f64> for Ramp {
+        fn evaluate(&self, isect: &SurfaceInteraction) -> f64 {
+            0.5 * isect.geo.uv.x
+        }
+    }
+
+    struct Constant(Spectrum);
+
+    impl Texture<Spectrum> for Constant {
+        fn evaluate(&self, _isect: &SurfaceInteraction) -> Spectrum {
+            self.0
+        }
+    }
+
+    #[test]
+    fn test_bump_and_normal_map() {
+        let isect = patch();
+        assert!((apply(&None, &isect).shading.n - isect.geo.n).magnitude() < 1e-12);
+
+        // the height rises along u with a slope of 0.5
+        let geo = apply(&Some(Bump::Height(Arc::new(Ramp))), &isect);
+        let expected = Vector3::new(-0.5, 0.0, 1.0).normalize();
+        assert!((geo.shading.n - expected).magnitude() < 1e-9);
+        assert_eq!(geo.n, isect.geo.n);
+
+        // the flat normal (0.5, 0.5, 1) leaves the frame unchanged, and the normals are read in
+        // the tangent frame
+        let flat = apply(&Some(Bump::NormalMap(Arc::new(Constant(Spectrum::new(0.5, 0.5, 1.0))))), &isect);
+        assert!((flat.shading.n - isect.geo.n).magnitude() < 1e-12);
+        assert!((flat.shading.dpdu - isect.geo.dpdu).magnitude() < 1e-12);
+        let tilted = Spectrum::new(0.75, 0.5, 0.5 + 0.5 * 0.75f64.sqrt());
+        let tilted = apply(&Some(Bump::NormalMap(Arc::new(Constant(tilted)))), &isect);
+        assert!((tilted.shading.n - Vector3::new(0.5, 0.0, 0.75f64.sqrt())).magnitude() < 1e-12);
+        assert!(tilted.shading.dpdu.dot(tilted.shading.n).abs() < 1e-12);
+    }
+}
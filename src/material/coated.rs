@@ -0,0 +1,180 @@
+use std::sync::Arc;
+
+use crate::{
+    bxdf::{bsdf::Bsdf, layered::LayeredBxdf, microfacet::{MicrofacetDistribution, MicrofacetModel}},
+    geometry::interaction::SurfaceInteraction,
+    spectrum::Spectrum,
+    texture::Texture,
+};
+
+use super::{Material, bump::{self, Bump}};
+
+/// any opaque material under a dielectric coat, such as a varnish or the clear coat of a car paint,
+/// the layer between them absorbs and scatters the light with its thickness and albedo
+///
+/// the lobes of the base are seen in the shading frame of the coat, so its own bump is ignored
+pub struct Coated {
+    base: Arc<dyn Material>,
+    roughness: Arc<dyn Texture<f64>>, // the coat is smooth at 0
+    eta: f64,
+    thickness: Arc<dyn Texture<f64>>,
+    albedo: Arc<dyn Texture<Spectrum>>,
+    g: f64,
+    max_depth: usize,
+    n_samples: usize,
+    model: MicrofacetModel,
+    sample_visible: bool,
+    bump: Option<Bump>,
+}
+
+impl Coated {
+    pub fn new(
+        base: Arc<dyn Material>,
+        roughness: Arc<dyn Texture<f64>>,
+        eta: f64,
+        thickness: Arc<dyn Texture<f64>>,
+        albedo: Arc<dyn Texture<Spectrum>>,
+    ) -> Self {
+        Self {
+            base,
+            roughness,
+            eta,
+            thickness,
+            albedo,
+            g: 0.0,
+            max_depth: 10,
+            n_samples: 1,
+            model: MicrofacetModel::TrowbridgeReitz,
+            sample_visible: true,
+            bump: None,
+        }
+    }
+
+    pub fn with_bump(mut self, bump: Option<Bump>) -> Self {
+        self.bump = bump;
+        self
+    }
+
+    /// the shape of the microfacet distribution and whether its visible normals are sampled
+    pub fn with_distribution(mut self, model: MicrofacetModel, sample_visible: bool) -> Self {
+        self.model = model;
+        self.sample_visible = sample_visible;
+        self
+    }
+
+    /// the asymmetry of the scattering in the layer
+    pub fn with_g(mut self, g: f64) -> Self {
+        self.g = g;
+        self
+    }
+
+    /// the bounces of a random walk between the layers and the walks that estimate each value of f
+    pub fn with_random_walk(mut self, max_depth: usize, n_samples: usize) -> Self {
+        self.max_depth = max_depth;
+        self.n_samples = n_samples;
+        self
+    }
+}
+
+impl Material for Coated {
+    fn compute_scattering(&self, isect: &SurfaceInteraction) -> Bsdf {
+        let base = self.base.compute_scattering(isect).bxdfs;
+
+        let roughness = self.roughness.evaluate(isect);
+        let distribution = if roughness > 0.0 {
+            let alpha = MicrofacetDistribution::roughness_to_alpha(roughness);
+            Some(MicrofacetDistribution::new(self.model, alpha, alpha, self.sample_visible))
+        } else {
+            None
+        };
+
+        let layered = LayeredBxdf::new(self.eta, distribution, base, self.thickness.evaluate(isect), self.albedo.evaluate(isect))
+            .with_g(self.g)
+            .with_random_walk(self.max_depth, self.n_samples);
+
+        Bsdf::new(&bump::apply(&self.bump, isect), vec![Box::new(layered)])
+    }
+
+    fn is_specular(&self) -> bool {
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        integrator::{path_integrator::PathIntegrator, test_scene::{TestScene, matte, camera, render_pixels, WIDTH, HEIGHT}},
+        material::{glass::Glass, matte::Matte},
+        texture::constant::ConstantTexture,
+    };
+    use cgmath::Vector3;
+
+    #[test]
+    fn test_coated_diffuse() {
+        // the floor of the sphere, a blue diffuse base under a smooth clear coat
+        let floor = |coated: bool| {
+            let base: Arc<dyn Material> = Arc::new(Matte::new(Arc::new(ConstantTexture::new(Spectrum::new(0.2, 0.3, 0.6)))));
+            let floor: Arc<dyn Material> = if coated {
+                Arc::new(Coated::new(
+                    base,
+                    Arc::new(ConstantTexture::new(0.0)),
+                    1.5,
+                    Arc::new(ConstantTexture::new(0.01)),
+                    Arc::new(ConstantTexture::new(Spectrum::black())),
+                ))
+            } else {
+                base
+            };
+            let scene = TestScene::new()
+                .floor(floor)
+                .sphere(matte(0.5))
+                .disk_light(Vector3::new(0.0, 3.0, 5.0), 1.0, 4.0)
+                .build();
+
+            // the bottom row sees the floor in front of the sphere
+            let pixels = render_pixels(camera(), scene, Box::new(PathIntegrator::new(5, true)), 64, 2);
+            pixels[(HEIGHT - 1) * WIDTH..].iter().fold(Spectrum::black(), |sum, &p| sum + p) / WIDTH as f64
+        };
+
+        let bare = floor(false);
+        let coated = floor(true);
+        assert!(coated.r.is_finite() && coated.b > coated.g && coated.g > coated.r, "coated {:?}", coated);
+
+        // the light reflected back into the layer by the coat bounces on the base again, which absorbs more
+        // of the red than of the blue, so the coated floor is darker and deeper blue, the mirror reflection
+        // of the coat leaving toward the back of the scene
+        assert!(coated.r < bare.r && coated.b < bare.b, "coated {:?} bare {:?}", coated, bare);
+        assert!(coated.b / coated.r > bare.b / bare.r, "coated {:?} bare {:?}", coated, bare);
+    }
+
+    #[test]
+    fn test_coat_highlight() {
+        // a light hanging 2 units above the floor, whose mirror image in the floor the bottom rows see
+        let highlight = |floor: Arc<dyn Material>| {
+            let scene = TestScene::new()
+                .floor(floor)
+                .disk_light(Vector3::new(0.0, 1.0, 8.0), 1.0, 4.0)
+                .build();
+
+            let pixels = render_pixels(camera(), scene, Box::new(PathIntegrator::new(5, true)), 256, 2);
+            pixels[HEIGHT / 2 * WIDTH..].iter().fold(Spectrum::black(), |sum, &p| sum + p)
+        };
+
+        // over a black base, the smooth coat reflects the light as much as a bare glass interface does
+        let coated = highlight(Arc::new(Coated::new(
+            matte(0.0),
+            Arc::new(ConstantTexture::new(0.0)),
+            1.5,
+            Arc::new(ConstantTexture::new(0.01)),
+            Arc::new(ConstantTexture::new(Spectrum::black())),
+        )));
+        let glass = highlight(Arc::new(Glass::new(
+            Arc::new(ConstantTexture::new(1.0)),
+            Arc::new(ConstantTexture::new(1.5)),
+            Arc::new(ConstantTexture::new(Spectrum::new(1.0, 1.0, 1.0))),
+            Arc::new(ConstantTexture::new(Spectrum::new(1.0, 1.0, 1.0))),
+        )));
+        assert!(glass.g > 0.0 && (coated.g - glass.g).abs() < 0.05 * glass.g, "coated {:?} glass {:?}", coated, glass);
+    }
+}
@@ -1,31 +1,33 @@
-use crate::{bxdf::{bsdf::Bsdf, fresnel::FresnelSpecular}, utils::perpendicular, spectrum::Spectrum};
-use super::Material;
+use std::sync::Arc;
+
+use crate::{bxdf::{bsdf::Bsdf, fresnel::FresnelSpecular}, spectrum::Spectrum, texture::Texture};
+use super::{Material, bump::{self, Bump}};
 
 
 pub struct Glass {
-    eta_a: f64,
-    eta_b: f64,
-    r: Spectrum,
-    t: Spectrum,
+    eta_a: Arc<dyn Texture<f64>>,
+    eta_b: Arc<dyn Texture<f64>>,
+    r: Arc<dyn Texture<Spectrum>>,
+    t: Arc<dyn Texture<Spectrum>>,
+    bump: Option<Bump>,
 }
 
 impl Glass {
-    pub fn new(eta_a: f64, eta_b: f64, r: Spectrum, t: Spectrum) -> Glass {
-        Glass { eta_a, eta_b, r, t}
+    pub fn new(eta_a: Arc<dyn Texture<f64>>, eta_b: Arc<dyn Texture<f64>>, r: Arc<dyn Texture<Spectrum>>, t: Arc<dyn Texture<Spectrum>>) -> Glass {
+        Glass { eta_a, eta_b, r, t, bump: None }
+    }
+
+    pub fn with_bump(mut self, bump: Option<Bump>) -> Self {
+        self.bump = bump;
+        self
     }
 }
 
 impl Material for Glass {
     fn compute_scattering(&self, isect: &crate::geometry::interaction::SurfaceInteraction) -> crate::bxdf::bsdf::Bsdf {
-        let (ss, ts) = perpendicular(isect.geo.n);
-        Bsdf {
-            ns: isect.geo.n,
-            ng: isect.geo.n,
-            ss,
-            ts,
-            bxdfs: vec![Box::new(FresnelSpecular::new(self.eta_a, self.eta_b, self.r, self.t)) ],
-            n_bxdfs: 1,
-        }
+        let (eta_a, eta_b) = (self.eta_a.evaluate(isect), self.eta_b.evaluate(isect));
+        let fresnel = FresnelSpecular::new(eta_a, eta_b, self.r.evaluate(isect), self.t.evaluate(isect));
+        Bsdf::new(&bump::apply(&self.bump, isect), vec![Box::new(fresnel)]).with_eta(eta_b / eta_a)
     }
 
     fn is_specular(&self) -> bool {
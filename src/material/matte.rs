@@ -1,35 +1,31 @@
+use std::sync::Arc;
+
 use crate::bxdf::lambertian::LambertianReflection;
 use crate::spectrum::Spectrum;
 use crate::texture::Texture;
-use crate::utils::perpendicular;
 use crate::bxdf::bsdf::Bsdf;
 
-use super::Material;
+use super::{Material, bump::{self, Bump}};
 
 pub struct Matte {
-    kd: Box<dyn Texture<Spectrum>>,
+    kd: Arc<dyn Texture<Spectrum>>,
+    bump: Option<Bump>,
 }
 
 impl Matte {
-    pub fn new(kd: Box<dyn Texture<Spectrum>>) -> Matte {
-        Matte { kd }
+    pub fn new(kd: Arc<dyn Texture<Spectrum>>) -> Matte {
+        Matte { kd, bump: None }
+    }
+
+    pub fn with_bump(mut self, bump: Option<Bump>) -> Self {
+        self.bump = bump;
+        self
     }
 }
 
 impl Material for Matte {
     fn compute_scattering(&self, isect: &crate::geometry::interaction::SurfaceInteraction) -> crate::bxdf::bsdf::Bsdf {
-        let (ss, ts) =  perpendicular(isect.geo.n); 
-
-        let ret = Bsdf {
-            ns: isect.geo.n,
-            ng: isect.geo.n,
-            ss,
-            ts,
-            bxdfs: vec![Box::new(LambertianReflection::new(self.kd.evaluate(isect))) ],
-            n_bxdfs: 1,
-        };
-
-        ret
+        Bsdf::new(&bump::apply(&self.bump, isect), vec![Box::new(LambertianReflection::new(self.kd.evaluate(isect)))])
     }
 
     fn is_specular(&self) -> bool {
@@ -0,0 +1,73 @@
+use std::sync::Arc;
+
+use crate::{bxdf::{bsdf::Bsdf, fresnel::FresnelConductor, microfacet::{MicrofacetDistribution, MicrofacetModel, MicrofacetReflection}}, spectrum::Spectrum, texture::Texture};
+
+use super::{Material, bump::{self, Bump}};
+
+/// a rough conductor, the index of refraction eta + i k is given per channel
+pub struct Metal {
+    eta: Arc<dyn Texture<Spectrum>>,
+    k: Arc<dyn Texture<Spectrum>>,
+    u_roughness: Arc<dyn Texture<f64>>, // along dpdu
+    v_roughness: Arc<dyn Texture<f64>>,
+    model: MicrofacetModel,
+    sample_visible: bool,
+    bump: Option<Bump>,
+}
+
+impl Metal {
+    pub fn new(eta: Arc<dyn Texture<Spectrum>>, k: Arc<dyn Texture<Spectrum>>, u_roughness: Arc<dyn Texture<f64>>, v_roughness: Arc<dyn Texture<f64>>) -> Self {
+        Self {
+            eta,
+            k,
+            u_roughness,
+            v_roughness,
+            model: MicrofacetModel::TrowbridgeReitz,
+            sample_visible: true,
+            bump: None,
+        }
+    }
+
+    pub fn with_bump(mut self, bump: Option<Bump>) -> Self {
+        self.bump = bump;
+        self
+    }
+
+    /// the shape of the microfacet distribution and whether its visible normals are sampled
+    pub fn with_distribution(mut self, model: MicrofacetModel, sample_visible: bool) -> Self {
+        self.model = model;
+        self.sample_visible = sample_visible;
+        self
+    }
+
+    /// the (eta, k) of the metals named in the scene files, fitted to the rgb primaries
+    pub fn preset(name: &str) -> Option<(Spectrum, Spectrum)> {
+        let (eta, k) = match name {
+            "gold" => ([0.143, 0.374, 1.442], [3.983, 2.385, 1.603]),
+            "silver" => ([0.155, 0.117, 0.138], [4.828, 3.122, 2.147]),
+            "copper" => ([0.200, 0.924, 1.102], [3.912, 2.452, 2.142]),
+            "aluminum" => ([1.657, 0.880, 0.521], [9.224, 6.270, 4.837]),
+            _ => return None,
+        };
+        Some((Spectrum::new(eta[0], eta[1], eta[2]), Spectrum::new(k[0], k[1], k[2])))
+    }
+}
+
+impl Material for Metal {
+    fn compute_scattering(&self, isect: &crate::geometry::interaction::SurfaceInteraction) -> Bsdf {
+        let distribution = MicrofacetDistribution::new(
+            self.model,
+            MicrofacetDistribution::roughness_to_alpha(self.u_roughness.evaluate(isect)),
+            MicrofacetDistribution::roughness_to_alpha(self.v_roughness.evaluate(isect)),
+            self.sample_visible,
+        );
+        let fresnel = Box::new(FresnelConductor::new(self.eta.evaluate(isect), self.k.evaluate(isect)));
+        let micro = MicrofacetReflection::new(distribution, fresnel, Spectrum::new(1.0, 1.0, 1.0));
+
+        Bsdf::new(&bump::apply(&self.bump, isect), vec![Box::new(micro)])
+    }
+
+    fn is_specular(&self) -> bool {
+        false
+    }
+}
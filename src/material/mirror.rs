@@ -1,33 +1,31 @@
-use crate::{spectrum::Spectrum, utils::perpendicular, bxdf::{perfect_specular::PerfectSpecular, bsdf::Bsdf}};
+use std::sync::Arc;
 
-use super::Material;
+use crate::{spectrum::Spectrum, bxdf::{perfect_specular::PerfectSpecular, bsdf::Bsdf}, texture::Texture};
+
+use super::{Material, bump::{self, Bump}};
 
 pub struct Mirror {
-    reflectance: Spectrum
+    reflectance: Arc<dyn Texture<Spectrum>>,
+    bump: Option<Bump>,
 }
 
 impl Mirror {
-    pub fn new(reflectance: Spectrum) -> Self {
+    pub fn new(reflectance: Arc<dyn Texture<Spectrum>>) -> Self {
         Self {
-            reflectance
+            reflectance,
+            bump: None,
         }
     }
+
+    pub fn with_bump(mut self, bump: Option<Bump>) -> Self {
+        self.bump = bump;
+        self
+    }
 }
 
 impl Material for Mirror {
     fn compute_scattering(&self, isect: &crate::geometry::interaction::SurfaceInteraction) -> crate::bxdf::bsdf::Bsdf {
-        let (ss, ts) = perpendicular(isect.geo.n);
-
-        let bsdf = Bsdf {
-            ns: isect.geo.n,
-            ng: isect.geo.n,
-            ss,
-            ts,
-            bxdfs: vec![Box::new(PerfectSpecular::new(self.reflectance))],
-            n_bxdfs: 1
-        };
-
-        bsdf
+        Bsdf::new(&bump::apply(&self.bump, isect), vec![Box::new(PerfectSpecular::new(self.reflectance.evaluate(isect)))])
     }
 
     fn is_specular(&self) -> bool {
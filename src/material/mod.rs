@@ -4,7 +4,12 @@ pub mod matte;
 pub mod glass;
 pub mod plastic;
 pub mod mirror;
-// pub mod layered_diffuse;
+pub mod metal;
+pub mod roughglass;
+pub mod principled;
+pub mod bump;
+pub mod coated;
+pub mod substrate;
 
 pub trait Material: Sync + Send {
     fn compute_scattering(&self, isect: &SurfaceInteraction) -> Bsdf;
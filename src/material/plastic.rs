@@ -1,30 +1,47 @@
-use crate::{bxdf::{bsdf::Bsdf, microfacet::{MicrofacetDistribution, MicrofacetReflection}, fresnel::FresnelNoOp, lambertian::LambertianReflection}, utils::sphere_tangent, spectrum::Spectrum};
+use std::sync::Arc;
 
-use super::Material;
+use crate::{bxdf::{bsdf::Bsdf, microfacet::{MicrofacetDistribution, MicrofacetModel, MicrofacetReflection}, fresnel::FresnelNoOp, lambertian::LambertianReflection}, spectrum::Spectrum, texture::Texture};
+
+use super::{Material, bump::{self, Bump}};
 
 pub struct Plastic {
-    roughness: f64,
-    ks: Spectrum, // reflectance for specular reflection
-    kd: Spectrum, // reflectance for diffuse reflection
+    roughness: Arc<dyn Texture<f64>>,
+    ks: Arc<dyn Texture<Spectrum>>, // reflectance for specular reflection
+    kd: Arc<dyn Texture<Spectrum>>, // reflectance for diffuse reflection
+    model: MicrofacetModel,
+    sample_visible: bool,
+    bump: Option<Bump>,
 }
 
 impl Plastic {
-    pub fn new(roughness: f64, ks: Spectrum, kd: Spectrum) -> Self {
+    pub fn new(roughness: Arc<dyn Texture<f64>>, ks: Arc<dyn Texture<Spectrum>>, kd: Arc<dyn Texture<Spectrum>>) -> Self {
         Self {
             roughness,
             ks,
             kd,
+            model: MicrofacetModel::TrowbridgeReitz,
+            sample_visible: true,
+            bump: None,
         }
     }
+
+    pub fn with_bump(mut self, bump: Option<Bump>) -> Self {
+        self.bump = bump;
+        self
+    }
+
+    /// the shape of the microfacet distribution and whether its visible normals are sampled
+    pub fn with_distribution(mut self, model: MicrofacetModel, sample_visible: bool) -> Self {
+        self.model = model;
+        self.sample_visible = sample_visible;
+        self
+    }
 }
 
 impl Material for Plastic {
     fn compute_scattering(&self, isect: &crate::geometry::interaction::SurfaceInteraction) -> Bsdf {
-        let (ss, ts) = sphere_tangent(isect.geo.n);
-        // let (ss, ts) = perpendicular(isect.geo.n);
-
-        let alpha = MicrofacetDistribution::roughness_to_alpha(self.roughness);
-        let distribution = MicrofacetDistribution::TrowbridgeReitz { alpha_x: alpha , alpha_y: alpha };
+        let alpha = MicrofacetDistribution::roughness_to_alpha(self.roughness.evaluate(isect));
+        let distribution = MicrofacetDistribution::new(self.model, alpha, alpha, self.sample_visible);
         // let fresnel = Box::new(FresnelSpecular::new(1.0, 1.5, Spectrum::new(1.0, 1.0, 1.0), Spectrum::new(1.0, 1.0, 1.0)));
         let fresnel = Box::new(FresnelNoOp::new());
         // let fresnel = Box::new(FresnelSchlick::new(1.0, 1.5));
@@ -32,21 +49,14 @@ impl Material for Plastic {
         let micro = MicrofacetReflection::new(
             distribution,
             fresnel,
-            self.ks
+            self.ks.evaluate(isect)
         );
 
         let lambert = LambertianReflection::new(
-            self.kd,
+            self.kd.evaluate(isect),
         );
 
-        Bsdf {
-            ng: isect.geo.n,
-            ns: isect.geo.n,
-            ss,
-            ts,
-            bxdfs: vec![Box::new(micro), Box::new(lambert)],
-            n_bxdfs: 2,
-        }
+        Bsdf::new(&bump::apply(&self.bump, isect), vec![Box::new(micro), Box::new(lambert)])
     }
 
     fn is_specular(&self) -> bool {
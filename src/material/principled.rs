@@ -0,0 +1,266 @@
+use std::sync::Arc;
+
+use crate::{
+    bxdf::{
+        Bxdf,
+        bsdf::Bsdf,
+        microfacet::{MicrofacetDistribution, MicrofacetModel, MicrofacetReflection, MicrofacetTransmission},
+        principled::{schlick_r0_from_eta, PrincipledClearcoat, PrincipledDiffuse, PrincipledFresnel, PrincipledRetro, PrincipledSheen},
+    },
+    geometry::interaction::SurfaceInteraction,
+    spectrum::Spectrum,
+    texture::{Texture, constant::ConstantTexture},
+};
+
+use super::{Material, bump::{self, Bump}};
+
+/// the parameters of the principled material, each in [0, 1] except the color
+pub struct PrincipledParameters {
+    pub color: Arc<dyn Texture<Spectrum>>,
+    pub metallic: Arc<dyn Texture<f64>>,
+    pub roughness: Arc<dyn Texture<f64>>,
+    pub specular: Arc<dyn Texture<f64>>, // the reflectance at normal incidence of the dielectric, 0.5 is an index of 1.5
+    pub specular_tint: Arc<dyn Texture<f64>>, // how much the dielectric reflection takes the hue of the color
+    pub anisotropic: Arc<dyn Texture<f64>>, // stretches the highlight along dpdu
+    pub sheen: Arc<dyn Texture<f64>>,
+    pub sheen_tint: Arc<dyn Texture<f64>>,
+    pub clearcoat: Arc<dyn Texture<f64>>,
+    pub clearcoat_gloss: Arc<dyn Texture<f64>>,
+    pub transmission: Arc<dyn Texture<f64>>, // the fraction of the dielectric that refracts the light instead of diffusing it
+}
+
+impl Default for PrincipledParameters {
+    fn default() -> Self {
+        let constant = |value: f64| -> Arc<dyn Texture<f64>> { Arc::new(ConstantTexture::new(value)) };
+        Self {
+            color: Arc::new(ConstantTexture::new(Spectrum::new(0.5, 0.5, 0.5))),
+            metallic: constant(0.0),
+            roughness: constant(0.5),
+            specular: constant(0.5),
+            specular_tint: constant(0.0),
+            anisotropic: constant(0.0),
+            sheen: constant(0.0),
+            sheen_tint: constant(0.5),
+            clearcoat: constant(0.0),
+            clearcoat_gloss: constant(1.0),
+            transmission: constant(0.0),
+        }
+    }
+}
+
+/// the Disney principled material, which blends a diffuse dielectric, a metal and a rough glass
+/// under an optional sheen and clear coat, from the parameters artists paint
+pub struct Principled {
+    parameters: PrincipledParameters,
+    bump: Option<Bump>,
+}
+
+impl Principled {
+    pub fn new(parameters: PrincipledParameters) -> Self {
+        Self { parameters, bump: None }
+    }
+
+    pub fn with_bump(mut self, bump: Option<Bump>) -> Self {
+        self.bump = bump;
+        self
+    }
+}
+
+fn lerp(t: f64, a: Spectrum, b: Spectrum) -> Spectrum {
+    a * (1.0 - t) + b * t
+}
+
+impl Material for Principled {
+    fn compute_scattering(&self, isect: &SurfaceInteraction) -> Bsdf {
+        let p = &self.parameters;
+        let color = p.color.evaluate(isect);
+        let metallic = p.metallic.evaluate(isect).clamp(0.0, 1.0);
+        let roughness = p.roughness.evaluate(isect).clamp(0.0, 1.0);
+        let transmission = p.transmission.evaluate(isect).clamp(0.0, 1.0);
+        let white = Spectrum::new(1.0, 1.0, 1.0);
+
+        // the index of the dielectric giving its reflectance at normal incidence of 0.08 specular
+        let r0 = (0.08 * p.specular.evaluate(isect).clamp(0.0, 1.0)).sqrt().min(0.99);
+        let eta = (1.0 + r0) / (1.0 - r0);
+
+        // the hue of the color, for the tints
+        let luminance = color.luminance();
+        let tint = if luminance > 0.0 { color / luminance } else { white };
+
+        let mut bxdfs: Vec<Box<dyn Bxdf>> = Vec::new();
+
+        let diffuse_weight = (1.0 - metallic) * (1.0 - transmission);
+        if diffuse_weight > 0.0 {
+            bxdfs.push(Box::new(PrincipledDiffuse::new(color * diffuse_weight)));
+            bxdfs.push(Box::new(PrincipledRetro::new(color * diffuse_weight, roughness)));
+
+            let sheen = p.sheen.evaluate(isect);
+            if sheen > 0.0 {
+                let sheen_color = lerp(p.sheen_tint.evaluate(isect), white, tint);
+                bxdfs.push(Box::new(PrincipledSheen::new(sheen_color * (diffuse_weight * sheen))));
+            }
+        }
+
+        // the anisotropy narrows the highlight across dpdu
+        let aspect = (1.0 - 0.9 * p.anisotropic.evaluate(isect).clamp(0.0, 1.0)).sqrt();
+        let alpha_x = (roughness * roughness / aspect).max(0.001);
+        let alpha_y = (roughness * roughness * aspect).max(0.001);
+        let distribution = MicrofacetDistribution::new(MicrofacetModel::TrowbridgeReitz, alpha_x, alpha_y, true);
+
+        let dielectric_r0 = lerp(p.specular_tint.evaluate(isect), white, tint) * schlick_r0_from_eta(eta);
+        let specular_r0 = lerp(metallic, dielectric_r0, color);
+        let fresnel = Box::new(PrincipledFresnel::new(specular_r0, metallic, eta));
+        bxdfs.push(Box::new(MicrofacetReflection::new(distribution, fresnel, white)));
+
+        let clearcoat = p.clearcoat.evaluate(isect);
+        if clearcoat > 0.0 {
+            let gloss = p.clearcoat_gloss.evaluate(isect).clamp(0.0, 1.0);
+            bxdfs.push(Box::new(PrincipledClearcoat::new(clearcoat, 0.1 * (1.0 - gloss) + 0.001 * gloss)));
+        }
+
+        let geo = bump::apply(&self.bump, isect);
+        let transmission_weight = (1.0 - metallic) * transmission;
+        if transmission_weight > 0.0 {
+            // the square root of the color gives it back after the light enters and leaves
+            let t = Spectrum::new(color.r.sqrt(), color.g.sqrt(), color.b.sqrt()) * transmission_weight;
+            bxdfs.push(Box::new(MicrofacetTransmission::new(distribution, t, 1.0, eta)));
+            return Bsdf::new(&geo, bxdfs).with_eta(eta);
+        }
+
+        Bsdf::new(&geo, bxdfs)
+    }
+
+    fn is_specular(&self) -> bool {
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::{InnerSpace, Point2, Point3, Vector3};
+
+    use super::*;
+    use crate::{
+        bxdf::{BxdfSample, BxdfType, validation::{albedo, assert_sample_f_matches_pdf}},
+        geometry::interaction::GeometryInfo,
+    };
+
+    // a flat patch in the plane z = 0 whose shading frame is the world one, dpdu along x
+    fn patch() -> SurfaceInteraction {
+        SurfaceInteraction {
+            geo: GeometryInfo::new(
+                Point3::new(0.3, 0.4, 0.0), Vector3::new(0.0, 0.0, 1.0), 1.0, Vector3::new(0.0, 0.0, 1.0),
+                Point2::new(0.3, 0.4), Vector3::new(1.0, 0.0, 0.0), Vector3::new(0.0, 1.0, 0.0),
+            ),
+            time: 0.0,
+            material: None,
+            hit_light: false,
+            radiance: None,
+            light: None,
+            medium_interface: None,
+            differentials: None,
+            ids: Default::default(),
+        }
+    }
+
+    // the whole bsdf of the patch seen as a single bxdf, its local directions are the world ones
+    struct Lobes(Bsdf);
+
+    impl Bxdf for Lobes {
+        fn f(&self, wo: Vector3<f64>, wi: Vector3<f64>) -> Spectrum {
+            self.0.f(wo, wi)
+        }
+
+        fn sample_f(&self, wo: Vector3<f64>, sample: Point2<f64>) -> BxdfSample {
+            self.0.sample_f(wo, sample)
+        }
+
+        fn pdf(&self, wo: Vector3<f64>, wi: Vector3<f64>) -> f64 {
+            self.0.pdf(wo, wi)
+        }
+
+        fn types(&self) -> i32 {
+            BxdfType::Reflection | BxdfType::Transmission | BxdfType::Glossy
+        }
+
+        fn is_delta(&self) -> bool {
+            false
+        }
+    }
+
+    // changes the default parameters of the material
+    type Configure = dyn Fn(&mut PrincipledParameters);
+
+    fn lobes(configure: impl FnOnce(&mut PrincipledParameters)) -> Lobes {
+        let mut parameters = PrincipledParameters::default();
+        configure(&mut parameters);
+        Lobes(Principled::new(parameters).compute_scattering(&patch()))
+    }
+
+    fn constant(value: f64) -> Arc<dyn Texture<f64>> {
+        Arc::new(ConstantTexture::new(value))
+    }
+
+    // the albedo of the bsdf with the eta^2 the refracted radiance gains, the light is not created
+    fn furnace(lobes: &Lobes, wo: Vector3<f64>) -> f64 {
+        let eta = lobes.0.eta;
+        let scale = |wi: Vector3<f64>| if wi.z * wo.z >= 0.0 { 1.0 } else if wo.z > 0.0 { eta * eta } else { 1.0 / (eta * eta) };
+        albedo(lobes, wo, 100000, &scale).g
+    }
+
+    #[test]
+    fn test_principled_white_furnace() {
+        let white = |p: &mut PrincipledParameters| p.color = Arc::new(ConstantTexture::new(Spectrum::new(1.0, 1.0, 1.0)));
+        let wos = [Vector3::new(0.0, 0.0, 1.0), Vector3::new(0.6, 0.2, 0.4).normalize()];
+
+        for wo in wos {
+            // the diffuse of a smooth dielectric darkens at grazing angles, its specular reflection adds a few
+            // percent on top, as burley's model does not take it from the diffuse
+            let diffuse = furnace(&lobes(|p| { white(p); p.roughness = constant(0.2); p.specular = constant(0.0); }), wo);
+            assert!(diffuse > 0.9 && diffuse < 1.0, "diffuse wo {:?}: albedo {}", wo, diffuse);
+            let dielectric = furnace(&lobes(|p| { white(p); p.roughness = constant(0.2); }), wo);
+            assert!(dielectric - diffuse > 0.02 && dielectric - diffuse < 0.1, "dielectric wo {:?}: albedo {}", wo, dielectric);
+
+            // a metal only reflects, a full transmission leaves no diffuse, and neither creates light
+            let metal = furnace(&lobes(|p| { white(p); p.metallic = constant(1.0); }), wo);
+            assert!(metal > 0.8 && metal < 1.01, "metal wo {:?}: albedo {}", wo, metal);
+            for wo in [wo, -wo] {
+                let glass = furnace(&lobes(|p| { white(p); p.transmission = constant(1.0); }), wo);
+                assert!(glass > 0.8 && glass < 1.01, "glass wo {:?}: albedo {}", wo, glass);
+            }
+
+            // the metal takes over the transmission too
+            let metal_glass = furnace(&lobes(|p| { white(p); p.metallic = constant(1.0); p.transmission = constant(1.0); }), wo);
+            assert_eq!(metal_glass, metal);
+        }
+    }
+
+    #[test]
+    fn test_principled_sample_f() {
+        let wos = [Vector3::new(0.0, 0.0, 1.0), Vector3::new(0.6, 0.2, 0.4).normalize(), Vector3::new(-0.3, 0.3, -0.8).normalize()];
+        // every lobe at once, half metal and half transmissive, anisotropic, with sheen and a coat
+        let blended = |p: &mut PrincipledParameters| {
+            p.metallic = constant(0.5);
+            p.transmission = constant(0.5);
+            p.roughness = constant(0.4);
+            p.anisotropic = constant(0.8);
+            p.sheen = constant(1.0);
+            p.clearcoat = constant(0.5);
+        };
+        let configurations: [(&str, &Configure); 3] = [
+            ("default", &|_| {}),
+            ("blended", &blended),
+            ("glass", &|p| { p.transmission = constant(1.0); p.roughness = constant(0.3); }),
+        ];
+        let test_count = wos.len() * configurations.len();
+
+        for (name, configure) in configurations {
+            let lobes = lobes(configure);
+            // the refracting configurations carry the index of the dielectric
+            assert_eq!(lobes.0.eta != 1.0, name != "default", "{}: eta {}", name, lobes.0.eta);
+            for wo in wos {
+                assert_sample_f_matches_pdf(&format!("{} wo {:?}", name, wo), &lobes, wo, 100000, test_count);
+            }
+        }
+    }
+}
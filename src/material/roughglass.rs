@@ -0,0 +1,64 @@
+use std::sync::Arc;
+
+use crate::{bxdf::{bsdf::Bsdf, fresnel::FresnelDielectric, microfacet::{MicrofacetDistribution, MicrofacetModel, MicrofacetReflection, MicrofacetTransmission}}, spectrum::Spectrum, texture::Texture};
+
+use super::{Material, bump::{self, Bump}};
+
+/// a dielectric whose microfacets reflect and refract the light, the rough counterpart of Glass
+pub struct RoughGlass {
+    eta_a: Arc<dyn Texture<f64>>,
+    eta_b: Arc<dyn Texture<f64>>,
+    r: Arc<dyn Texture<Spectrum>>,
+    t: Arc<dyn Texture<Spectrum>>,
+    u_roughness: Arc<dyn Texture<f64>>, // along dpdu
+    v_roughness: Arc<dyn Texture<f64>>,
+    model: MicrofacetModel,
+    sample_visible: bool,
+    bump: Option<Bump>,
+}
+
+impl RoughGlass {
+    pub fn new(
+        eta_a: Arc<dyn Texture<f64>>,
+        eta_b: Arc<dyn Texture<f64>>,
+        r: Arc<dyn Texture<Spectrum>>,
+        t: Arc<dyn Texture<Spectrum>>,
+        u_roughness: Arc<dyn Texture<f64>>,
+        v_roughness: Arc<dyn Texture<f64>>,
+    ) -> Self {
+        Self { eta_a, eta_b, r, t, u_roughness, v_roughness, model: MicrofacetModel::TrowbridgeReitz, sample_visible: true, bump: None }
+    }
+
+    pub fn with_bump(mut self, bump: Option<Bump>) -> Self {
+        self.bump = bump;
+        self
+    }
+
+    /// the shape of the microfacet distribution and whether its visible normals are sampled
+    pub fn with_distribution(mut self, model: MicrofacetModel, sample_visible: bool) -> Self {
+        self.model = model;
+        self.sample_visible = sample_visible;
+        self
+    }
+}
+
+impl Material for RoughGlass {
+    fn compute_scattering(&self, isect: &crate::geometry::interaction::SurfaceInteraction) -> Bsdf {
+        let (eta_a, eta_b) = (self.eta_a.evaluate(isect), self.eta_b.evaluate(isect));
+        let distribution = MicrofacetDistribution::new(
+            self.model,
+            MicrofacetDistribution::roughness_to_alpha(self.u_roughness.evaluate(isect)),
+            MicrofacetDistribution::roughness_to_alpha(self.v_roughness.evaluate(isect)),
+            self.sample_visible,
+        );
+
+        let reflection = MicrofacetReflection::new(distribution, Box::new(FresnelDielectric::new(eta_a, eta_b)), self.r.evaluate(isect));
+        let transmission = MicrofacetTransmission::new(distribution, self.t.evaluate(isect), eta_a, eta_b);
+
+        Bsdf::new(&bump::apply(&self.bump, isect), vec![Box::new(reflection), Box::new(transmission)]).with_eta(eta_b / eta_a)
+    }
+
+    fn is_specular(&self) -> bool {
+        false
+    }
+}
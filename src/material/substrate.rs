@@ -0,0 +1,60 @@
+use std::sync::Arc;
+
+use crate::{bxdf::{bsdf::Bsdf, fresnel_blend::FresnelBlend, microfacet::{MicrofacetDistribution, MicrofacetModel}}, spectrum::Spectrum, texture::Texture};
+
+use super::{Material, bump::{self, Bump}};
+
+/// a glossy coating over a diffuse base, the base is lit by what the coating lets through
+pub struct Substrate {
+    kd: Arc<dyn Texture<Spectrum>>, // reflectance of the diffuse base
+    ks: Arc<dyn Texture<Spectrum>>, // reflectance of the coating at normal incidence
+    u_roughness: Arc<dyn Texture<f64>>, // along dpdu
+    v_roughness: Arc<dyn Texture<f64>>,
+    model: MicrofacetModel,
+    sample_visible: bool,
+    bump: Option<Bump>,
+}
+
+impl Substrate {
+    pub fn new(kd: Arc<dyn Texture<Spectrum>>, ks: Arc<dyn Texture<Spectrum>>, u_roughness: Arc<dyn Texture<f64>>, v_roughness: Arc<dyn Texture<f64>>) -> Self {
+        Self {
+            kd,
+            ks,
+            u_roughness,
+            v_roughness,
+            model: MicrofacetModel::TrowbridgeReitz,
+            sample_visible: true,
+            bump: None,
+        }
+    }
+
+    pub fn with_bump(mut self, bump: Option<Bump>) -> Self {
+        self.bump = bump;
+        self
+    }
+
+    /// the shape of the microfacet distribution and whether its visible normals are sampled
+    pub fn with_distribution(mut self, model: MicrofacetModel, sample_visible: bool) -> Self {
+        self.model = model;
+        self.sample_visible = sample_visible;
+        self
+    }
+}
+
+impl Material for Substrate {
+    fn compute_scattering(&self, isect: &crate::geometry::interaction::SurfaceInteraction) -> Bsdf {
+        let distribution = MicrofacetDistribution::new(
+            self.model,
+            MicrofacetDistribution::roughness_to_alpha(self.u_roughness.evaluate(isect)),
+            MicrofacetDistribution::roughness_to_alpha(self.v_roughness.evaluate(isect)),
+            self.sample_visible,
+        );
+        let blend = FresnelBlend::new(distribution, self.ks.evaluate(isect), self.kd.evaluate(isect));
+
+        Bsdf::new(&bump::apply(&self.bump, isect), vec![Box::new(blend)])
+    }
+
+    fn is_specular(&self) -> bool {
+        false
+    }
+}
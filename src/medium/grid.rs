@@ -0,0 +1,171 @@
+use std::sync::Arc;
+
+use cgmath::{InnerSpace, Point3, Vector3};
+
+use crate::{geometry::{bound3::Bound3, ray::Ray, transform::Transform}, sampler::Sampler, spectrum::Spectrum};
+
+use super::{Medium, MediumInteraction, MediumSample, phase::PhaseFunction};
+
+/// a medium whose density is trilinearly interpolated from a voxel grid filling the box [p_min, p_max]
+///
+/// the extinction has to be the same in every channel, the grid is tracked with a single majorant
+pub struct GridDensityMedium {
+    sigma_s: Spectrum,
+    sigma_t: f64,
+    phase: Arc<dyn PhaseFunction>,
+    resolution: [usize; 3],
+    density: Vec<f64>, // x varies fastest, then y, then z
+    inv_max_density: f64,
+    world_to_medium: Transform, // maps the box to [0, 1]^3
+}
+
+impl GridDensityMedium {
+    pub fn new(
+        sigma_a: Spectrum,
+        sigma_s: Spectrum,
+        phase: Arc<dyn PhaseFunction>,
+        resolution: [usize; 3],
+        density: Vec<f64>,
+        p_min: Point3<f64>,
+        p_max: Point3<f64>,
+    ) -> Self {
+        assert_eq!(density.len(), resolution[0] * resolution[1] * resolution[2]);
+
+        let max_density = density.iter().cloned().fold(0.0, f64::max);
+        let medium_to_world = Transform::translate(Vector3::new(p_min.x, p_min.y, p_min.z)) * Transform::scale(p_max - p_min);
+
+        GridDensityMedium {
+            sigma_s,
+            sigma_t: (sigma_a + sigma_s).average(),
+            phase,
+            resolution,
+            density,
+            inv_max_density: if max_density > 0.0 { 1.0 / max_density } else { 0.0 },
+            world_to_medium: medium_to_world.inverse(),
+        }
+    }
+
+    // the density of voxel (x, y, z), zero outside of the grid
+    fn d(&self, x: i64, y: i64, z: i64) -> f64 {
+        let [nx, ny, nz] = self.resolution;
+        if x < 0 || y < 0 || z < 0 || x >= nx as i64 || y >= ny as i64 || z >= nz as i64 {
+            return 0.0;
+        }
+        self.density[(z as usize * ny + y as usize) * nx + x as usize]
+    }
+
+    /// the density at a point of [0, 1]^3, voxel values sit at the voxel centers
+    fn density(&self, p: Point3<f64>) -> f64 {
+        let ps = [
+            p.x * self.resolution[0] as f64 - 0.5,
+            p.y * self.resolution[1] as f64 - 0.5,
+            p.z * self.resolution[2] as f64 - 0.5,
+        ];
+        let pi = ps.map(|c| c.floor() as i64);
+        let [dx, dy, dz] = [ps[0] - pi[0] as f64, ps[1] - pi[1] as f64, ps[2] - pi[2] as f64];
+
+        let lerp = |t: f64, a: f64, b: f64| (1.0 - t) * a + t * b;
+        let d00 = lerp(dx, self.d(pi[0], pi[1], pi[2]), self.d(pi[0] + 1, pi[1], pi[2]));
+        let d10 = lerp(dx, self.d(pi[0], pi[1] + 1, pi[2]), self.d(pi[0] + 1, pi[1] + 1, pi[2]));
+        let d01 = lerp(dx, self.d(pi[0], pi[1], pi[2] + 1), self.d(pi[0] + 1, pi[1], pi[2] + 1));
+        let d11 = lerp(dx, self.d(pi[0], pi[1] + 1, pi[2] + 1), self.d(pi[0] + 1, pi[1] + 1, pi[2] + 1));
+        lerp(dz, lerp(dy, d00, d10), lerp(dy, d01, d11))
+    }
+
+    /// the ray in medium space with t measured in world space distance, and the overlap with the grid
+    fn ray_in_grid(&self, r: &Ray) -> Option<(Ray, f64, f64)> {
+        let len = r.d.magnitude();
+        let world_ray = Ray::new(r.o, r.d / len, r.time, r.t_max * len);
+        let medium_ray = self.world_to_medium.transform_ray(&world_ray);
+
+        let bound = Bound3::new(Point3::new(0.0, 0.0, 0.0), Point3::new(1.0, 1.0, 1.0));
+        let (t_min, t_max) = bound.intersect_p(&medium_ray)?;
+        Some((medium_ray, t_min, t_max))
+    }
+}
+
+impl Medium for GridDensityMedium {
+    fn sample(&self, r: &Ray, sampler: &mut dyn Sampler) -> MediumSample {
+        let passed = MediumSample { weight: Spectrum::new(1.0, 1.0, 1.0), interaction: None };
+        let (medium_ray, t_min, t_max) = match self.ray_in_grid(r) {
+            Some(overlap) if self.sigma_t > 0.0 => overlap,
+            _ => return passed,
+        };
+
+        // delta tracking, the grid is filled with fictitious particles up to the maximum density
+        let mut t = t_min;
+        loop {
+            t -= (1.0 - sampler.get_1d()).ln() * self.inv_max_density / self.sigma_t;
+            if t >= t_max {
+                return passed;
+            }
+
+            if self.density(medium_ray.o + medium_ray.d * t) * self.inv_max_density > sampler.get_1d() {
+                let len = r.d.magnitude();
+                return MediumSample {
+                    weight: self.sigma_s / self.sigma_t,
+                    interaction: Some(MediumInteraction {
+                        p: r.o + r.d * (t / len),
+                        wo: -r.d / len,
+                        time: r.time,
+                        phase: self.phase.clone(),
+                    }),
+                };
+            }
+        }
+    }
+
+    fn tr(&self, r: &Ray, sampler: &mut dyn Sampler) -> Spectrum {
+        let (medium_ray, t_min, t_max) = match self.ray_in_grid(r) {
+            Some(overlap) if self.sigma_t > 0.0 => overlap,
+            _ => return Spectrum::new(1.0, 1.0, 1.0),
+        };
+
+        // ratio tracking, every fictitious collision scales the transmittance by the null probability
+        let mut tr = 1.0;
+        let mut t = t_min;
+        loop {
+            t -= (1.0 - sampler.get_1d()).ln() * self.inv_max_density / self.sigma_t;
+            if t >= t_max {
+                break;
+            }
+            tr *= 1.0 - (self.density(medium_ray.o + medium_ray.d * t) * self.inv_max_density).max(0.0);
+        }
+
+        Spectrum::new(tr, tr, tr)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{medium::{homogeneous::HomogeneousMedium, phase::Isotropic}, sampler::uniform_sampler::UniformSampler};
+
+    use super::*;
+
+    #[test]
+    fn test_grid_matches_homogeneous() {
+        let (sigma_a, sigma_s) = (Spectrum::new(0.3, 0.3, 0.3), Spectrum::new(0.9, 0.9, 0.9));
+        // the ray stays in the inner voxels, where the interpolated density is constant
+        let grid = GridDensityMedium::new(
+            sigma_a, sigma_s, Arc::new(Isotropic), [4, 4, 4], vec![1.0; 64],
+            Point3::new(-2.0, -2.0, -2.0), Point3::new(2.0, 2.0, 2.0),
+        );
+        let homogeneous = HomogeneousMedium::new(sigma_a, sigma_s, Arc::new(Isotropic));
+        let mut sampler = UniformSampler::new(1, 7);
+
+        let r = Ray::new(Point3::new(-0.5, 0.2, -0.5), Vector3::new(0.5, 0.0, 0.5), 0.0, 2.0);
+        let expected = homogeneous.tr(&r, &mut sampler).r;
+
+        let n = 100000;
+        let mut tr = 0.0;
+        let mut passed = 0.0;
+        for _ in 0..n {
+            tr += grid.tr(&r, &mut sampler).r / n as f64;
+            if grid.sample(&r, &mut sampler).interaction.is_none() {
+                passed += 1.0 / n as f64;
+            }
+        }
+        assert!((tr - expected).abs() < 1e-2);
+        assert!((passed - expected).abs() < 1e-2);
+    }
+}
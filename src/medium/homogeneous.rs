@@ -0,0 +1,102 @@
+use std::sync::Arc;
+
+use cgmath::InnerSpace;
+
+use crate::{geometry::ray::Ray, sampler::Sampler, spectrum::Spectrum};
+
+use super::{Medium, MediumInteraction, MediumSample, phase::PhaseFunction};
+
+/// a medium with the same coefficients everywhere
+pub struct HomogeneousMedium {
+    sigma_s: Spectrum,
+    sigma_t: Spectrum,
+    phase: Arc<dyn PhaseFunction>,
+}
+
+impl HomogeneousMedium {
+    pub fn new(sigma_a: Spectrum, sigma_s: Spectrum, phase: Arc<dyn PhaseFunction>) -> Self {
+        HomogeneousMedium {
+            sigma_s,
+            sigma_t: sigma_a + sigma_s,
+            phase,
+        }
+    }
+
+    // transmittance over the world space distance, an infinite distance in a clear channel stays clear
+    fn transmittance(&self, distance: f64) -> Spectrum {
+        (self.sigma_t * -distance.min(f64::MAX)).exp()
+    }
+}
+
+impl Medium for HomogeneousMedium {
+    fn sample(&self, r: &Ray, sampler: &mut dyn Sampler) -> MediumSample {
+        let len = r.d.magnitude();
+
+        // sample the distance with the extinction of one of the channels, and weight by the
+        // average pdf over the channels
+        let channel = ((sampler.get_1d() * 3.0) as usize).min(2);
+        let distance = -(1.0 - sampler.get_1d()).ln() / self.sigma_t[channel];
+        let t = (distance / len).min(r.t_max);
+        let sampled_medium = t < r.t_max;
+
+        let tr = self.transmittance(t * len);
+        let density = if sampled_medium { self.sigma_t * tr } else { tr };
+        let pdf = density.average();
+        if pdf <= 0.0 {
+            return MediumSample { weight: Spectrum::black(), interaction: None };
+        }
+
+        if sampled_medium {
+            MediumSample {
+                weight: tr * self.sigma_s / pdf,
+                interaction: Some(MediumInteraction {
+                    p: r.o + r.d * t,
+                    wo: -r.d / len,
+                    time: r.time,
+                    phase: self.phase.clone(),
+                }),
+            }
+        } else {
+            MediumSample { weight: tr / pdf, interaction: None }
+        }
+    }
+
+    fn tr(&self, r: &Ray, _sampler: &mut dyn Sampler) -> Spectrum {
+        self.transmittance(r.t_max * r.d.magnitude())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::{Point3, Vector3};
+
+    use crate::{medium::phase::Isotropic, sampler::uniform_sampler::UniformSampler};
+
+    use super::*;
+
+    #[test]
+    fn test_homogeneous_transmittance() {
+        let medium = HomogeneousMedium::new(Spectrum::new(0.5, 1.0, 0.0), Spectrum::new(0.5, 0.0, 0.0), Arc::new(Isotropic));
+        let mut sampler = UniformSampler::new(1, 0);
+
+        // the distance is measured in world space, whatever the length of the direction
+        let r = Ray::new(Point3::new(0.0, 0.0, 0.0), Vector3::new(0.0, 2.0, 0.0), 0.0, 1.5);
+        let tr = medium.tr(&r, &mut sampler);
+        assert!((tr.r - (-3.0f64).exp()).abs() < 1e-12);
+        assert!((tr.g - (-3.0f64).exp()).abs() < 1e-12);
+        assert_eq!(tr.b, 1.0);
+
+        // the sampling weights average to the transmittance of the whole segment
+        let n = 200000;
+        let mut passed = Spectrum::black();
+        for _ in 0..n {
+            let sample = medium.sample(&r, &mut sampler);
+            if sample.interaction.is_none() {
+                passed += sample.weight / n as f64;
+            }
+        }
+        assert!((passed.r - tr.r).abs() < 2e-2);
+        assert!((passed.g - tr.g).abs() < 2e-2);
+        assert!((passed.b - tr.b).abs() < 2e-2);
+    }
+}
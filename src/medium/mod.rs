@@ -0,0 +1,50 @@
+pub mod phase;
+pub mod homogeneous;
+pub mod grid;
+
+use std::sync::Arc;
+
+use cgmath::{Point3, Vector3};
+
+use crate::{geometry::ray::Ray, sampler::Sampler, spectrum::Spectrum};
+
+use self::phase::PhaseFunction;
+
+pub trait Medium: Sync + Send {
+    /// sample a scattering point along the ray segment [0, r.t_max]
+    ///
+    /// the weight is the transmittance to the sampled point times sigma_s divided by the pdf of
+    /// sampling it, or the transmittance to the end of the segment divided by the probability of
+    /// passing through it when no interaction is returned
+    fn sample(&self, r: &Ray, sampler: &mut dyn Sampler) -> MediumSample;
+    /// the transmittance along the ray segment [0, r.t_max]
+    fn tr(&self, r: &Ray, sampler: &mut dyn Sampler) -> Spectrum;
+}
+
+pub struct MediumSample {
+    pub weight: Spectrum,
+    pub interaction: Option<MediumInteraction>,
+}
+
+/// a scattering event inside a participating medium, the scattered ray stays in the ray's medium
+pub struct MediumInteraction {
+    pub p: Point3<f64>,
+    pub wo: Vector3<f64>, // normalized reverse direction of incoming ray
+    pub time: f64,
+    pub phase: Arc<dyn PhaseFunction>,
+}
+
+/// the media on both sides of a surface, None stands for vacuum
+///
+/// outside is the side the surface normal points to
+#[derive(Clone)]
+pub struct MediumInterface {
+    pub inside: Option<Arc<dyn Medium>>,
+    pub outside: Option<Arc<dyn Medium>>,
+}
+
+impl MediumInterface {
+    pub fn new(inside: Option<Arc<dyn Medium>>, outside: Option<Arc<dyn Medium>>) -> Self {
+        MediumInterface { inside, outside }
+    }
+}
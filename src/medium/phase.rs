@@ -0,0 +1,122 @@
+use std::f64::consts::PI;
+
+use cgmath::{InnerSpace, Point2, Vector3};
+
+use crate::utils::perpendicular;
+
+/// the angular distribution of light scattered in a medium
+///
+/// wo and wi both point away from the scattering point, like in the bxdfs
+pub trait PhaseFunction: Sync + Send {
+    fn p(&self, wo: Vector3<f64>, wi: Vector3<f64>) -> f64;
+    /// returns (wi, p), the phase function is sampled exactly so p is also the pdf
+    fn sample_p(&self, wo: Vector3<f64>, u: Point2<f64>) -> (Vector3<f64>, f64);
+}
+
+pub struct Isotropic;
+
+impl PhaseFunction for Isotropic {
+    fn p(&self, _wo: Vector3<f64>, _wi: Vector3<f64>) -> f64 {
+        1.0 / (4.0 * PI)
+    }
+
+    fn sample_p(&self, wo: Vector3<f64>, u: Point2<f64>) -> (Vector3<f64>, f64) {
+        let cos_theta = 1.0 - 2.0 * u.x;
+        (direction_around(wo, cos_theta, 2.0 * PI * u.y), 1.0 / (4.0 * PI))
+    }
+}
+
+/// g in (-1, 1) is the average cosine of the scattering angle, positive values scatter forward
+pub struct HenyeyGreenstein {
+    g: f64,
+}
+
+impl HenyeyGreenstein {
+    pub fn new(g: f64) -> Self {
+        HenyeyGreenstein { g: g.clamp(-0.99, 0.99) }
+    }
+}
+
+// cos_theta is the cosine between wo and wi, -1 for light going straight through
+fn henyey_greenstein(cos_theta: f64, g: f64) -> f64 {
+    let denom = 1.0 + g * g + 2.0 * g * cos_theta;
+    (1.0 - g * g) / (4.0 * PI * denom * denom.max(0.0).sqrt())
+}
+
+// the direction making the angle acos(cos_theta) with w
+fn direction_around(w: Vector3<f64>, cos_theta: f64, phi: f64) -> Vector3<f64> {
+    let (u, v) = perpendicular(w);
+    let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+    (sin_theta * phi.cos() * u + sin_theta * phi.sin() * v + cos_theta * w.normalize()).normalize()
+}
+
+impl PhaseFunction for HenyeyGreenstein {
+    fn p(&self, wo: Vector3<f64>, wi: Vector3<f64>) -> f64 {
+        henyey_greenstein(wo.dot(wi), self.g)
+    }
+
+    fn sample_p(&self, wo: Vector3<f64>, u: Point2<f64>) -> (Vector3<f64>, f64) {
+        let g = self.g;
+        // the cosine with respect to the propagation direction -wo
+        let mu = if g.abs() < 1e-3 {
+            1.0 - 2.0 * u.x
+        } else {
+            let sqr_term = (1.0 - g * g) / (1.0 - g + 2.0 * g * u.x);
+            ((1.0 + g * g - sqr_term * sqr_term) / (2.0 * g)).clamp(-1.0, 1.0)
+        };
+
+        let cos_theta = -mu;
+        (direction_around(wo, cos_theta, 2.0 * PI * u.y), henyey_greenstein(cos_theta, g))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_phase_normalized() {
+        let wo = Vector3::new(0.3, -0.4, 0.5).normalize();
+        let phases: [Box<dyn PhaseFunction>; 4] = [
+            Box::new(Isotropic),
+            Box::new(HenyeyGreenstein::new(0.0)),
+            Box::new(HenyeyGreenstein::new(0.6)),
+            Box::new(HenyeyGreenstein::new(-0.4)),
+        ];
+
+        for phase in phases.iter() {
+            // integrate over the sphere in (cos theta, phi) around wo
+            let n = 400;
+            let mut integral = 0.0;
+            for i in 0..n {
+                for j in 0..n {
+                    let cos_theta = 1.0 - 2.0 * (i as f64 + 0.5) / n as f64;
+                    let wi = direction_around(wo, cos_theta, 2.0 * PI * (j as f64 + 0.5) / n as f64);
+                    integral += phase.p(wo, wi) * 4.0 * PI / (n * n) as f64;
+                }
+            }
+            assert!((integral - 1.0).abs() < 1e-3);
+        }
+    }
+
+    #[test]
+    fn test_henyey_greenstein_sampling() {
+        let wo = Vector3::new(-0.2, 0.9, 0.1).normalize();
+        for g in [-0.7, 0.0, 0.3, 0.85] {
+            let phase = HenyeyGreenstein::new(g);
+
+            let n = 200;
+            let mut mean_cos = 0.0;
+            for i in 0..n {
+                for j in 0..n {
+                    let u = Point2::new((i as f64 + 0.5) / n as f64, (j as f64 + 0.5) / n as f64);
+                    let (wi, p) = phase.sample_p(wo, u);
+                    assert!((p - phase.p(wo, wi)).abs() < 1e-9 * p.max(1.0));
+                    mean_cos += (-wo).dot(wi) / (n * n) as f64;
+                }
+            }
+            // the average cosine of the scattering angle is g
+            assert!((mean_cos - g).abs() < 1e-3);
+        }
+    }
+}
@@ -1,5 +1,5 @@
-use crate::geometry::{bound3::Bound3, interaction::GeometryInfo, ray::{Beam, Ray}, transform::Transform}; 
-use cgmath::{Point3, Point2, Vector3, EuclideanSpace, InnerSpace};
+use crate::{geometry::{bound3::Bound3, interaction::{GeometryInfo, ShadingGeometry}, ray::{Beam, Ray}, transform::Transform}, utils::perpendicular}; 
+use cgmath::{Point3, Point2, Vector2, Vector3, EuclideanSpace, InnerSpace};
 use std::{sync::Arc, collections::HashMap};
 
 use tobj;
@@ -101,7 +101,24 @@ impl Triangle {
         Point3::new(p[kx], p[ky], p[kz])
     }
 
+    /// the derivatives (d/du, d/dv) of a quantity that differs by d02 and d12 between the vertices,
+    /// None if the texture coordinates are degenerate
+    fn solve_uv(duv02: Vector2<f64>, duv12: Vector2<f64>, d02: Vector3<f64>, d12: Vector3<f64>) -> Option<(Vector3<f64>, Vector3<f64>)> {
+        let determinant = duv02.x * duv12.y - duv02.y * duv12.x;
+        if determinant.abs() < 1e-12 {
+            return None;
+        }
+
+        let inv_det = 1.0 / determinant;
+        Some((
+            (d02 * duv12.y - d12 * duv02.y) * inv_det,
+            (d12 * duv02.x - d02 * duv12.x) * inv_det,
+        ))
+    }
+
+    // the axis along which p is the longest
     fn max_dimension(p: Vector3<f64>) -> usize {
+        let p = Vector3::new(p.x.abs(), p.y.abs(), p.z.abs());
         if p[0] >= p[1] && p[0] >= p[2] { 0 }
         else if p[1] >= p[0] && p[1] >= p[2] { 1 }
         else { 2}
@@ -175,8 +192,39 @@ impl Shape for Triangle {
         // the orientation here is very important. if the model rendered is dark, the normal may need flipping.
         let n = (a-b).cross(c-a).normalize();
 
+        // the texture coordinates default to a parameterization over the triangle
+        let [uv0, uv1, uv2] = if self.mesh.texcoords.is_empty() {
+            [Point2::new(0.0, 0.0), Point2::new(1.0, 0.0), Point2::new(1.0, 1.0)]
+        } else {
+            [self.mesh.texcoords[self.a], self.mesh.texcoords[self.b], self.mesh.texcoords[self.c]]
+        };
+        let uv = Point2::from_vec(uv0.to_vec() * b0 + uv1.to_vec() * b1 + uv2.to_vec() * b2);
+
+        // solve p - p2 = (u - u2) dpdu + (v - v2) dpdv at both other vertices
+        let (duv02, duv12) = (uv0 - uv2, uv1 - uv2);
+        let (dpdu, dpdv) = match Self::solve_uv(duv02, duv12, a - c, b - c) {
+            Some((dpdu, dpdv)) if dpdu.cross(dpdv).magnitude2() > 0.0 => (dpdu, dpdv),
+            // degenerate texture coordinates, any frame around the normal will do
+            _ => perpendicular(n),
+        };
+
         let wo = -r.d.normalize();
-        let geo = GeometryInfo {p, n, t, wo};
+        let mut geo = GeometryInfo::new(p, n, t, wo, uv, dpdu, dpdv);
+
+        // smooth shading with the interpolated vertex normals, which also decide which side is outside
+        if !self.mesh.normals.is_empty() {
+            let n0 = self.object_to_world.transform_normal(self.mesh.normals[self.a]);
+            let n1 = self.object_to_world.transform_normal(self.mesh.normals[self.b]);
+            let n2 = self.object_to_world.transform_normal(self.mesh.normals[self.c]);
+            let ns = n0 * b0 + n1 * b1 + n2 * b2;
+
+            if ns.magnitude2() > 0.0 {
+                let zero = Vector3::new(0.0, 0.0, 0.0);
+                let (dndu, dndv) = Self::solve_uv(duv02, duv12, n0 - n2, n1 - n2).unwrap_or((zero, zero));
+                let shading = ShadingGeometry { n: ns, dpdu, dpdv, dndu, dndv };
+                geo.set_shading_geometry(shading, true);
+            }
+        }
 
         Some(geo)
     }
@@ -273,3 +321,33 @@ impl Shape for Triangle {
         unimplemented!()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_triangle_interpolation() {
+        let positions = vec![Point3::new(0.0, 0.0, 0.0), Point3::new(2.0, 0.0, 0.0), Point3::new(0.0, 2.0, 0.0)];
+        let texcoords = vec![Point2::new(0.0, 0.0), Point2::new(1.0, 0.0), Point2::new(0.0, 1.0)];
+        let normals = vec![
+            Vector3::new(0.0, 0.0, 1.0),
+            Vector3::new(1.0, 0.0, 1.0).normalize(),
+            Vector3::new(0.0, 1.0, 1.0).normalize(),
+        ];
+        let mesh = Arc::new(TriangleMesh::new(positions, texcoords, normals.clone(), vec![0, 1, 2]));
+        let triangle = Triangle::new(0, 1, 2, mesh, Transform::translate(Vector3::new(0.0, 0.0, 1.0)));
+
+        let r = Ray::new(Point3::new(0.5, 1.0, 3.0), Vector3::new(0.0, 0.0, -1.0), 0.0, f64::INFINITY);
+        let geo = triangle.intersect(&r).unwrap();
+        assert!((geo.t - 2.0).abs() < 1e-9);
+        assert!((geo.uv - Point2::new(0.25, 0.5)).magnitude() < 1e-9);
+        assert!((geo.dpdu - Vector3::new(2.0, 0.0, 0.0)).magnitude() < 1e-9);
+        assert!((geo.dpdv - Vector3::new(0.0, 2.0, 0.0)).magnitude() < 1e-9);
+
+        // barycentric (0.25, 0.25, 0.5), the geometric normal is flipped to the side of the shading one
+        let ns = (normals[0] * 0.25 + normals[1] * 0.25 + normals[2] * 0.5).normalize();
+        assert!((geo.shading.n - ns).magnitude() < 1e-9);
+        assert!((geo.n - Vector3::new(0.0, 0.0, 1.0)).magnitude() < 1e-9);
+    }
+}
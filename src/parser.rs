@@ -1,9 +1,19 @@
 use core::panic;
-use std::{fs, sync::Arc};
+use std::{collections::HashMap, fs, sync::Arc};
 use cgmath::{Vector3, Vector4, Point2, InnerSpace, Point3};
 use json::JsonValue;
 
-use crate::{scene::Scene, camera::{perspective::PerspectiveCamera, film::Film}, geometry::{transform::Transform, shape::{cuboid::Cuboid, disk::Disk, sphere::Sphere, Shape}}, WorldSetting, integrator::{path_integrator::PathIntegrator, direct_integrator::DirectIntegrator, Integrator}, light::{LightList, Light, area::AreaLight, point::PointLight}, accelerator::bvh::BVH, primitive::{geometric_primitive::GeometricPrimitive, Primitive, mesh_primitive::MeshPrimitive}, material::{Material, matte::Matte, plastic::Plastic, glass::Glass, mirror::Mirror}, spectrum::Spectrum, texture::constant::ConstantTexture, mesh::TriangleMesh, sampler::uniform_sampler::UniformSampler};
+use crate::{scene::Scene, camera::{perspective::PerspectiveCamera, film::Film, tonemap::{ToneMapper, ToneMapping}, aov::AovKind}, geometry::{transform::Transform, shape::{cuboid::Cuboid, disk::Disk, sphere::Sphere, Shape}}, WorldSetting, integrator::{path_integrator::PathIntegrator, direct_integrator::DirectIntegrator, volpath_integrator::VolPathIntegrator, bdpt_integrator::BdptIntegrator, sppm_integrator::SppmIntegrator, mlt_integrator::MltIntegrator, wrs_direct_integrator::WRSDirectIntegrator, Integrator}, light::{Light, light_sampler::LightSamplerType, area::AreaLight, point::PointLight, environment::EnvironmentLight}, accelerator::bvh::BVH, bxdf::microfacet::MicrofacetModel, primitive::{geometric_primitive::GeometricPrimitive, Primitive, ObjectIds, mesh_primitive::MeshPrimitive}, material::{Material, bump::Bump, matte::Matte, plastic::Plastic, glass::Glass, mirror::Mirror, metal::Metal, roughglass::RoughGlass, principled::{Principled, PrincipledParameters}, coated::Coated, substrate::Substrate}, spectrum::Spectrum, texture::{Texture, constant::ConstantTexture, scale::ScaleTexture, mix::MixTexture, imagemap::ImageTexture, mipmap::{MIPMap, WrapMode, FilterMode, ColorEncoding}, mapping::{TextureMapping2D, TextureMapping3D, uv::UVMapping, spherical::SphericalMapping, point::PointMapping}, Blend, noise::NoiseTexture, fbm::FbmTexture, turbulence::TurbulenceTexture, checkerboard::{Checkerboard2DTexture, Checkerboard3DTexture}, grid::GridTexture, wood::WoodTexture, marble::MarbleTexture}, mesh::TriangleMesh, sampler::{Sampler, uniform_sampler::UniformSampler, stratified::StratifiedSampler, halton::HaltonSampler, sobol::SobolSampler}, medium::{Medium, MediumInterface, homogeneous::HomogeneousMedium, grid::GridDensityMedium, phase::{PhaseFunction, Isotropic, HenyeyGreenstein}}};
+
+// the media of the scene by name
+type MediaMap = HashMap<String, Arc<dyn Medium>>;
+
+// the textures of the scene by name, a texture is declared either as a spectrum or a float one
+#[derive(Default)]
+struct TextureMap {
+    spectrum: HashMap<String, Arc<dyn Texture<Spectrum>>>,
+    float: HashMap<String, Arc<dyn Texture<f64>>>,
+}
 
 macro_rules! report_parsing_error {
     ($s:expr) => {
@@ -26,6 +36,14 @@ fn get_object_property(value: JsonValue, property: &str) -> JsonValue {
     }
 }
 
+fn get_optional_property(value: JsonValue, property: &str) -> Option<JsonValue> {
+    if let JsonValue::Object(obj) = value {
+        obj.get(property).cloned()
+    } else {
+        panic!("value is not an object")
+    }
+}
+
 fn parse_number(number: JsonValue, debug_info: &str) -> f64 {
     match number {
         JsonValue::Number(num) => num.into(),
@@ -33,6 +51,17 @@ fn parse_number(number: JsonValue, debug_info: &str) -> f64 {
     }
 }
 
+// an optional number of an object, default when it is missing
+fn parse_optional_number(value: JsonValue, property: &str, default: f64) -> f64 {
+    match get_optional_property(value, property) {
+        Some(number) => {
+            let msg = format!("'{}' should be a number", property);
+            parse_number(number, msg.as_str())
+        },
+        None => default,
+    }
+}
+
 fn parse_vec3(scale: &Vec<JsonValue>, name: &str) -> Vector3<f64> {
     if scale.len() != 3 {
         let msg = format!("the size vector {} should be 3", name);
@@ -220,15 +249,20 @@ fn parse_cuboid(shape: JsonValue) -> Box<dyn Shape> {
     }
 }
 
-fn parse_material(mat: JsonValue) -> Arc<dyn Material> {
+fn parse_material(mat: JsonValue, textures: &TextureMap) -> Arc<dyn Material> {
     let tp = get_object_property(mat.clone(), "type");
     match tp {
         JsonValue::Short(tp) => {
             match tp.as_str() {
-                "matte" => parse_matte(mat.clone()),
-                "plastic" => parse_plastic(mat.clone()),
-                "glass" => parse_glass(mat.clone()),
-                "mirror" => parse_mirror(mat),
+                "matte" => parse_matte(mat.clone(), textures),
+                "plastic" => parse_plastic(mat.clone(), textures),
+                "glass" => parse_glass(mat.clone(), textures),
+                "mirror" => parse_mirror(mat, textures),
+                "metal" => parse_metal(mat, textures),
+                "roughglass" => parse_roughglass(mat, textures),
+                "principled" => parse_principled(mat, textures),
+                "coated" => parse_coated(mat, textures),
+                "substrate" => parse_substrate(mat, textures),
                 _ => {
                     let msg = format!("no material type named {}", tp);
                     report_parsing_error!(msg.as_str());
@@ -239,118 +273,477 @@ fn parse_material(mat: JsonValue) -> Arc<dyn Material> {
     }
 }
 
-fn parse_matte(mat: JsonValue) -> Arc<dyn Material> {
-    if let JsonValue::Object(mat) = mat {
-        let kd = match mat.get("kd") {
-            Some(JsonValue::Array(kd)) => {
-                parse_vec3(kd, "kd")
-            },
+fn parse_matte(mat: JsonValue, textures: &TextureMap) -> Arc<dyn Material> {
+    let kd = parse_texture_param(get_object_property(mat.clone(), "kd"), textures, "kd");
 
-            // Some(JsonValue::Object(kd)) => {
-            //     match kd.get("type") {
-            //     }
-            // }
-            _ => panic!(),
-        };
+    Arc::new(Matte::new(kd).with_bump(parse_bump(mat, textures)))
+}
 
-        let kd = Spectrum::new(kd.x, kd.y, kd.z);
+fn parse_plastic(mat: JsonValue, textures: &TextureMap) -> Arc<dyn Material> {
+    let roughness = parse_texture_param(get_object_property(mat.clone(), "roughness"), textures, "roughness");
+    let ks = parse_texture_param(get_object_property(mat.clone(), "ks"), textures, "ks");
+    let kd = parse_texture_param(get_object_property(mat.clone(), "kd"), textures, "kd");
 
-        Arc::new(Matte::new(Box::new(ConstantTexture::new(kd))))
-    } else {
-        panic!()
-    }
+    let (model, sample_visible) = parse_distribution(mat.clone());
+    Arc::new(Plastic::new(roughness, ks, kd).with_distribution(model, sample_visible).with_bump(parse_bump(mat, textures)))
 }
 
-fn parse_plastic(mat: JsonValue) -> Arc<dyn Material> {
-    // roughness
-    if let JsonValue::Object(mat) = mat {
-        let roughness = match mat.get("roughness") {
-            Some(JsonValue::Number(roughness)) => roughness.clone().into(),
-            _ => report_parsing_error!("material's roughness should be a number"),
-        };
+fn parse_glass(mat: JsonValue, textures: &TextureMap) -> Arc<dyn Material> {
+    let eta_a = parse_texture_param(get_object_property(mat.clone(), "eta_a"), textures, "eta_a");
+    let eta_b = parse_texture_param(get_object_property(mat.clone(), "eta_b"), textures, "eta_b");
+    let kr = parse_texture_param(get_object_property(mat.clone(), "kr"), textures, "kr");
+    let kt = parse_texture_param(get_object_property(mat.clone(), "kt"), textures, "kt");
 
-        let ks = match mat.get("ks") {
-            Some(JsonValue::Array(ks)) => {
-                parse_vec3(ks, "ks")
-            },
-            _ => report_parsing_error!("plastic's ks property should be of array type"),
-        };
+    Arc::new(Glass::new(eta_a, eta_b, kr, kt).with_bump(parse_bump(mat, textures)))
+}
 
-        let kd = match mat.get("kd") {
-            Some(JsonValue::Array(kd)) => {
-                parse_vec3(kd, "kd")
-            },
-            _ => report_parsing_error!("plastic's ks property should be of array type"),
-        };
+fn parse_mirror(mat: JsonValue, textures: &TextureMap) -> Arc<dyn Material> {
+    let r = parse_texture_param(get_object_property(mat.clone(), "reflectance"), textures, "reflectance");
 
-        let ks = Spectrum::new(ks.x, ks.y, ks.z);
-        let kd = Spectrum::new(kd.x, kd.y, kd.z);
+    Arc::new(Mirror::new(r).with_bump(parse_bump(mat, textures)))
+}
 
-        Arc::new(Plastic::new(roughness, ks, kd))
-    } else {
-        panic!()
+fn parse_metal(mat: JsonValue, textures: &TextureMap) -> Arc<dyn Material> {
+    // a preset gives the eta and k that are not set
+    let preset = get_optional_property(mat.clone(), "preset").map(|name| {
+        let name = parse_string(name);
+        match Metal::preset(&name) {
+            Some(preset) => preset,
+            None => {
+                let msg = format!("no metal preset named {}", name);
+                report_parsing_error!(msg.as_str());
+            }
+        }
+    });
+    let parse_ior = |name: &str, default: Option<Spectrum>| -> Arc<dyn Texture<Spectrum>> {
+        match (get_optional_property(mat.clone(), name), default) {
+            (Some(value), _) => parse_texture_param(value, textures, name),
+            (None, Some(default)) => Arc::new(ConstantTexture::new(default)),
+            (None, None) => {
+                let msg = format!("metal should have a '{}' or a 'preset'", name);
+                report_parsing_error!(msg.as_str());
+            }
+        }
+    };
+    let eta = parse_ior("eta", preset.map(|(eta, _)| eta));
+    let k = parse_ior("k", preset.map(|(_, k)| k));
+    let (u_roughness, v_roughness) = parse_roughness(mat.clone(), textures);
+
+    let (model, sample_visible) = parse_distribution(mat.clone());
+    Arc::new(Metal::new(eta, k, u_roughness, v_roughness).with_distribution(model, sample_visible).with_bump(parse_bump(mat, textures)))
+}
+
+fn parse_substrate(mat: JsonValue, textures: &TextureMap) -> Arc<dyn Material> {
+    let kd = parse_texture_param(get_object_property(mat.clone(), "kd"), textures, "kd");
+    let ks = parse_texture_param(get_object_property(mat.clone(), "ks"), textures, "ks");
+    let (u_roughness, v_roughness) = parse_roughness(mat.clone(), textures);
+
+    let (model, sample_visible) = parse_distribution(mat.clone());
+    Arc::new(Substrate::new(kd, ks, u_roughness, v_roughness).with_distribution(model, sample_visible).with_bump(parse_bump(mat, textures)))
+}
+
+fn parse_roughglass(mat: JsonValue, textures: &TextureMap) -> Arc<dyn Material> {
+    let eta_a = parse_texture_param(get_object_property(mat.clone(), "eta_a"), textures, "eta_a");
+    let eta_b = parse_texture_param(get_object_property(mat.clone(), "eta_b"), textures, "eta_b");
+    let kr = parse_texture_param(get_object_property(mat.clone(), "kr"), textures, "kr");
+    let kt = parse_texture_param(get_object_property(mat.clone(), "kt"), textures, "kt");
+    let (u_roughness, v_roughness) = parse_roughness(mat.clone(), textures);
+
+    let (model, sample_visible) = parse_distribution(mat.clone());
+    Arc::new(
+        RoughGlass::new(eta_a, eta_b, kr, kt, u_roughness, v_roughness)
+            .with_distribution(model, sample_visible)
+            .with_bump(parse_bump(mat, textures)),
+    )
+}
+
+fn parse_principled(mat: JsonValue, textures: &TextureMap) -> Arc<dyn Material> {
+    // the omitted parameters keep their defaults
+    let mut parameters = PrincipledParameters::default();
+    if let Some(color) = get_optional_property(mat.clone(), "color") {
+        parameters.color = parse_texture_param(color, textures, "color");
+    }
+    let scalars = [
+        ("metallic", &mut parameters.metallic),
+        ("roughness", &mut parameters.roughness),
+        ("specular", &mut parameters.specular),
+        ("specular_tint", &mut parameters.specular_tint),
+        ("anisotropic", &mut parameters.anisotropic),
+        ("sheen", &mut parameters.sheen),
+        ("sheen_tint", &mut parameters.sheen_tint),
+        ("clearcoat", &mut parameters.clearcoat),
+        ("clearcoat_gloss", &mut parameters.clearcoat_gloss),
+        ("transmission", &mut parameters.transmission),
+    ];
+    for (name, parameter) in scalars {
+        if let Some(value) = get_optional_property(mat.clone(), name) {
+            *parameter = parse_texture_param(value, textures, name);
+        }
     }
+
+    Arc::new(Principled::new(parameters).with_bump(parse_bump(mat, textures)))
 }
 
-fn parse_glass(mat: JsonValue) -> Arc<dyn Material> {
-    if let JsonValue::Object(mat) = mat {
-        let eta_a = match mat.get("eta_a") {
-            Some(JsonValue::Number(eta_a)) => eta_a.clone().into(),
-            _ => report_parsing_error!("glass's eta_a should be a number"),
-        };
+fn parse_coated(mat: JsonValue, textures: &TextureMap) -> Arc<dyn Material> {
+    let base = parse_material(get_object_property(mat.clone(), "base"), textures);
+    let parse_optional = |name: &str, default: f64| -> Arc<dyn Texture<f64>> {
+        match get_optional_property(mat.clone(), name) {
+            Some(value) => parse_texture_param(value, textures, name),
+            None => Arc::new(ConstantTexture::new(default)),
+        }
+    };
+    let roughness = parse_optional("roughness", 0.0);
+    let thickness = parse_optional("thickness", 0.01);
+    let albedo = match get_optional_property(mat.clone(), "albedo") {
+        Some(albedo) => parse_texture_param(albedo, textures, "albedo"),
+        None => Arc::new(ConstantTexture::new(Spectrum::black())),
+    };
+    let eta = parse_optional_number(mat.clone(), "eta", 1.5);
+    let g = parse_optional_number(mat.clone(), "g", 0.0);
+    let max_depth = parse_optional_number(mat.clone(), "max_depth", 10.0).max(0.0) as usize;
+    let n_samples = parse_optional_number(mat.clone(), "samples", 1.0).max(1.0) as usize;
+
+    let (model, sample_visible) = parse_distribution(mat.clone());
+    Arc::new(
+        Coated::new(base, roughness, eta, thickness, albedo)
+            .with_g(g)
+            .with_random_walk(max_depth, n_samples)
+            .with_distribution(model, sample_visible)
+            .with_bump(parse_bump(mat, textures)),
+    )
+}
 
-        let eta_b = match mat.get("eta_b") {
-            Some(JsonValue::Number(eta_b)) => eta_b.clone().into(),
-            _ => report_parsing_error!("glass's eta_b should be a number"),
-        };
+/// the "roughness" of a microfacet material, or its anisotropic "uroughness" and "vroughness"
+fn parse_roughness(mat: JsonValue, textures: &TextureMap) -> (Arc<dyn Texture<f64>>, Arc<dyn Texture<f64>>) {
+    match get_optional_property(mat.clone(), "roughness") {
+        Some(roughness) => {
+            let roughness = parse_texture_param(roughness, textures, "roughness");
+            (roughness.clone(), roughness)
+        },
+        None => (
+            parse_texture_param(get_object_property(mat.clone(), "uroughness"), textures, "uroughness"),
+            parse_texture_param(get_object_property(mat, "vroughness"), textures, "vroughness"),
+        ),
+    }
+}
 
-        let kr = match mat.get("kr") {
-            Some(JsonValue::Array(kr)) => {
-                parse_vec3(kr, "kr")
+/// the optional "distribution" of the microfacets and whether their visible normals are sampled
+fn parse_distribution(mat: JsonValue) -> (MicrofacetModel, bool) {
+    let model = match get_optional_property(mat.clone(), "distribution") {
+        Some(distribution) => match distribution.as_str() {
+            Some("trowbridge_reitz") => MicrofacetModel::TrowbridgeReitz,
+            Some("beckmann") => MicrofacetModel::Beckmann,
+            Some(other) => {
+                let msg = format!("no microfacet distribution named {}", other);
+                report_parsing_error!(msg.as_str());
             },
-            _ => report_parsing_error!("glass's ks property should be of array type"),
-        };
+            None => report_parsing_error!("distribution should be a string"),
+        },
+        None => MicrofacetModel::TrowbridgeReitz,
+    };
+    let sample_visible = match get_optional_property(mat, "sample_visible") {
+        Some(sample_visible) => parse_bool(sample_visible, "sample_visible"),
+        None => true,
+    };
+    (model, sample_visible)
+}
+
+/// the optional "bump" height or "normal_map" of any material
+fn parse_bump(mat: JsonValue, textures: &TextureMap) -> Option<Bump> {
+    let height = get_optional_property(mat.clone(), "bump");
+    let normal_map = get_optional_property(mat, "normal_map");
+    match (height, normal_map) {
+        (None, None) => None,
+        (Some(height), None) => Some(Bump::Height(parse_texture_param(height, textures, "bump"))),
+        (None, Some(normal_map)) => Some(Bump::NormalMap(parse_texture_param(normal_map, textures, "normal_map"))),
+        (Some(_), Some(_)) => report_parsing_error!("a material can not have both a bump and a normal_map"),
+    }
+}
+
+/// the values a texture can take in the scene file
+trait TextureValue: Blend + Send + Sync + 'static {
+    /// a literal such as [r, g, b] or 0.5, None if the value is not one
+    fn parse_literal(value: &JsonValue) -> Option<Self>;
+    /// the declared textures of this type
+    fn named(textures: &TextureMap) -> &HashMap<String, Arc<dyn Texture<Self>>>;
+}
 
-        let kt = match mat.get("kt") {
-            Some(JsonValue::Array(kt)) => {
-                parse_vec3(kt, "kt")
+impl TextureValue for Spectrum {
+    fn parse_literal(value: &JsonValue) -> Option<Self> {
+        match value {
+            JsonValue::Array(_) => Some(parse_spectrum(value.clone(), "spectrum")),
+            // a single number is a grey spectrum
+            JsonValue::Number(value) => {
+                let value: f64 = (*value).into();
+                Some(Spectrum::new(value, value, value))
             },
-            _ => report_parsing_error!("glass's ks property should be of array type"),
-        };
+            _ => None,
+        }
+    }
 
-        let kr = Spectrum::new(kr.x, kr.y, kr.z);
-        let kt = Spectrum::new(kt.x, kt.y, kt.z);
+    fn named(textures: &TextureMap) -> &HashMap<String, Arc<dyn Texture<Self>>> {
+        &textures.spectrum
+    }
+}
 
-        Arc::new(Glass::new(eta_a, eta_b, kr, kt))
-    } else {
-        panic!()
+impl TextureValue for f64 {
+    fn parse_literal(value: &JsonValue) -> Option<Self> {
+        match value {
+            JsonValue::Number(value) => Some((*value).into()),
+            _ => None,
+        }
+    }
+
+    fn named(textures: &TextureMap) -> &HashMap<String, Arc<dyn Texture<Self>>> {
+        &textures.float
     }
 }
 
-fn parse_mirror(mat: JsonValue) -> Arc<dyn Material> {
-    if let JsonValue::Object(mat) = mat {
-        let r = match mat.get("reflectance") {
-            Some(JsonValue::Array(reflectance)) => {
-                parse_vec3(reflectance, "reflectance")
-            },
-            _ => report_parsing_error!("mirror's reflectance property should be of array type"),
-        };
+/// a material parameter, either a literal, the name of a declared texture or an inline texture
+fn parse_texture_param<T: TextureValue>(value: JsonValue, textures: &TextureMap, name: &str) -> Arc<dyn Texture<T>>
+where
+    ConstantTexture<T>: Texture<T>,
+    ScaleTexture<f64, T>: Texture<T>,
+    MixTexture<T>: Texture<T>,
+    ImageTexture: Texture<T>,
+    MarbleTexture: Texture<T>,
+{
+    if let Some(literal) = T::parse_literal(&value) {
+        return Arc::new(ConstantTexture::new(literal));
+    }
 
-        Arc::new(Mirror::new(Spectrum::new(r.x, r.y, r.z)))
-    } else {
-        panic!()
+    match value {
+        JsonValue::Short(_) | JsonValue::String(_) => {
+            let texture_name = parse_string(value);
+            match T::named(textures).get(&texture_name) {
+                Some(texture) => texture.clone(),
+                None => {
+                    let msg = format!("no {} texture named {} for '{}'", std::any::type_name::<T>(), texture_name, name);
+                    report_parsing_error!(msg.as_str());
+                }
+            }
+        },
+        JsonValue::Object(_) => parse_texture(value, textures),
+        _ => {
+            let msg = format!("'{}' should be a literal, a texture name or a texture", name);
+            report_parsing_error!(msg.as_str());
+        }
+    }
+}
+
+fn parse_texture<T: TextureValue>(tex: JsonValue, textures: &TextureMap) -> Arc<dyn Texture<T>>
+where
+    ConstantTexture<T>: Texture<T>,
+    ScaleTexture<f64, T>: Texture<T>,
+    MixTexture<T>: Texture<T>,
+    ImageTexture: Texture<T>,
+    MarbleTexture: Texture<T>,
+{
+    let tp = parse_string(get_object_property(tex.clone(), "type"));
+    match tp.as_str() {
+        "constant" => {
+            match T::parse_literal(&get_object_property(tex, "value")) {
+                Some(value) => Arc::new(ConstantTexture::new(value)),
+                None => report_parsing_error!("constant texture's value should be a literal"),
+            }
+        },
+        "imagemap" => {
+            let filename = parse_string(get_object_property(tex.clone(), "filename"));
+            let mapping = parse_mapping(get_optional_property(tex.clone(), "mapping"));
+
+            let wrap = match get_optional_property(tex.clone(), "wrap").map(parse_string).as_deref() {
+                None | Some("repeat") => WrapMode::Repeat,
+                Some("clamp") => WrapMode::Clamp,
+                Some("mirror") => WrapMode::Mirror,
+                Some("black") => WrapMode::Black,
+                Some(wrap) => {
+                    let msg = format!("no wrap mode named {}", wrap);
+                    report_parsing_error!(msg.as_str());
+                }
+            };
+            let filter = match get_optional_property(tex.clone(), "filter").map(parse_string).as_deref() {
+                None | Some("bilinear") => FilterMode::Bilinear,
+                Some("trilinear") => FilterMode::Trilinear,
+                Some("ewa") => FilterMode::Ewa,
+                Some(filter) => {
+                    let msg = format!("no texture filter named {}", filter);
+                    report_parsing_error!(msg.as_str());
+                }
+            };
+            let max_anisotropy = match get_optional_property(tex.clone(), "max_anisotropy") {
+                Some(value) => parse_number(value, "imagemap's max_anisotropy should be a number"),
+                None => 8.0,
+            };
+
+            let encoding = match get_optional_property(tex, "encoding").map(parse_string).as_deref() {
+                None => ColorEncoding::of_file(&filename),
+                Some("linear") => ColorEncoding::Linear,
+                Some("srgb") => ColorEncoding::Srgb,
+                Some(encoding) => {
+                    let msg = format!("no color encoding named {}", encoding);
+                    report_parsing_error!(msg.as_str());
+                }
+            };
+
+            let mipmap = MIPMap::from_file(&filename, encoding, wrap, filter, max_anisotropy);
+            Arc::new(ImageTexture::new(mapping, Arc::new(mipmap)))
+        },
+        "scale" => {
+            let scale = parse_texture_param::<f64>(get_object_property(tex.clone(), "scale"), textures, "scale");
+            let texture = parse_texture_param(get_object_property(tex, "tex"), textures, "tex");
+            Arc::new(ScaleTexture::<f64, T>::new(scale, texture))
+        },
+        "mix" => {
+            let tex1 = parse_texture_param(get_object_property(tex.clone(), "tex1"), textures, "tex1");
+            let tex2 = parse_texture_param(get_object_property(tex.clone(), "tex2"), textures, "tex2");
+            let amount = parse_texture_param::<f64>(get_object_property(tex, "amount"), textures, "amount");
+            Arc::new(MixTexture::new(tex1, tex2, amount))
+        },
+        "checkerboard" => {
+            let tex1 = parse_texture_param(get_object_property(tex.clone(), "tex1"), textures, "tex1");
+            let tex2 = parse_texture_param(get_object_property(tex.clone(), "tex2"), textures, "tex2");
+            match parse_optional_number(tex.clone(), "dimension", 2.0) as usize {
+                2 => Arc::new(Checkerboard2DTexture::new(parse_mapping(get_optional_property(tex, "mapping")), tex1, tex2)),
+                3 => Arc::new(Checkerboard3DTexture::new(parse_point_mapping(tex), tex1, tex2)),
+                _ => report_parsing_error!("checkerboard's dimension should be 2 or 3"),
+            }
+        },
+        "grid" => {
+            let tex1 = parse_texture_param(get_object_property(tex.clone(), "tex1"), textures, "tex1");
+            let tex2 = parse_texture_param(get_object_property(tex.clone(), "tex2"), textures, "tex2");
+            let line_width = parse_optional_number(tex.clone(), "line_width", 0.05);
+            Arc::new(GridTexture::new(parse_mapping(get_optional_property(tex, "mapping")), tex1, tex2, line_width))
+        },
+        "noise" => Arc::new(NoiseTexture::new(parse_point_mapping(tex))),
+        "fbm" => {
+            let omega = parse_optional_number(tex.clone(), "omega", 0.5);
+            let octaves = parse_optional_number(tex.clone(), "octaves", 8.0).max(0.0) as usize;
+            Arc::new(FbmTexture::new(parse_point_mapping(tex), omega, octaves))
+        },
+        "turbulence" => {
+            let omega = parse_optional_number(tex.clone(), "omega", 0.5);
+            let octaves = parse_optional_number(tex.clone(), "octaves", 8.0).max(0.0) as usize;
+            Arc::new(TurbulenceTexture::new(parse_point_mapping(tex), omega, octaves))
+        },
+        "wood" => {
+            let tex1 = parse_texture_param(get_object_property(tex.clone(), "tex1"), textures, "tex1");
+            let tex2 = parse_texture_param(get_object_property(tex.clone(), "tex2"), textures, "tex2");
+            let rings = parse_optional_number(tex.clone(), "rings", 10.0);
+            let distortion = parse_optional_number(tex.clone(), "distortion", 0.05);
+            let omega = parse_optional_number(tex.clone(), "omega", 0.5);
+            let octaves = parse_optional_number(tex.clone(), "octaves", 4.0).max(0.0) as usize;
+            Arc::new(WoodTexture::new(parse_point_mapping(tex), tex1, tex2, rings, distortion, omega, octaves))
+        },
+        "marble" => {
+            let omega = parse_optional_number(tex.clone(), "omega", 0.5);
+            let octaves = parse_optional_number(tex.clone(), "octaves", 8.0).max(0.0) as usize;
+            let scale = parse_optional_number(tex.clone(), "scale", 1.0);
+            let variation = parse_optional_number(tex.clone(), "variation", 0.2);
+            Arc::new(MarbleTexture::new(parse_point_mapping(tex), omega, octaves, scale, variation))
+        },
+        _ => {
+            let msg = format!("no texture type named {}", tp);
+            report_parsing_error!(msg.as_str());
+        }
     }
 }
 
+/// the placement of a texture space in the world, returned as the world to texture transform
+fn parse_texture_transform(value: JsonValue) -> Transform {
+    let scale = match get_optional_property(value.clone(), "scale") {
+        Some(JsonValue::Array(scale)) => parse_vec3(&scale, "scale"),
+        Some(scale) => {
+            let scale = parse_number(scale, "texture's 'scale' should be a number or a vec3");
+            Vector3::new(scale, scale, scale)
+        },
+        None => Vector3::new(1.0, 1.0, 1.0),
+    };
+    let rotate = match get_optional_property(value.clone(), "rotate") {
+        Some(JsonValue::Array(rotate)) => parse_rotate(&rotate, "rotate"),
+        Some(_) => report_parsing_error!("texture's 'rotate' should be a vec4"),
+        None => Vector4::new(0.0, 1.0, 0.0, 0.0),
+    };
+    let translate = match get_optional_property(value, "translate") {
+        Some(JsonValue::Array(translate)) => parse_vec3(&translate, "translate"),
+        Some(_) => report_parsing_error!("texture's 'translate' should be a vec3"),
+        None => Vector3::new(0.0, 0.0, 0.0),
+    };
+
+    let texture_to_world =
+        Transform::translate(translate) *
+        Transform::rotate(rotate.w, rotate.truncate().normalize()) *
+        Transform::scale(scale);
+    texture_to_world.inverse()
+}
+
+fn parse_mapping(mapping: Option<JsonValue>) -> Box<dyn TextureMapping2D> {
+    // the surface uv by default
+    let mapping = match mapping {
+        Some(mapping) => mapping,
+        None => return Box::new(UVMapping::new(1.0, 1.0, 0.0, 0.0)),
+    };
+
+    let tp = parse_string(get_object_property(mapping.clone(), "type"));
+    match tp.as_str() {
+        "uv" => {
+            let number = |name: &str, default: f64| match get_optional_property(mapping.clone(), name) {
+                Some(value) => parse_number(value, "uv mapping's parameters should be numbers"),
+                None => default,
+            };
+            Box::new(UVMapping::new(number("su", 1.0), number("sv", 1.0), number("du", 0.0), number("dv", 0.0)))
+        },
+        "spherical" => Box::new(SphericalMapping::new(parse_texture_transform(mapping))),
+        _ => {
+            let msg = format!("no texture mapping named {}", tp);
+            report_parsing_error!(msg.as_str());
+        }
+    }
+}
+
+/// the texture space of the solid textures, placed by their optional "transform"
+fn parse_point_mapping(tex: JsonValue) -> Box<dyn TextureMapping3D> {
+    // the texture space is the world space by default
+    let transform = get_optional_property(tex, "transform").unwrap_or_else(JsonValue::new_object);
+    Box::new(PointMapping::new(parse_texture_transform(transform)))
+}
+
+fn parse_textures(world: JsonValue) -> TextureMap {
+    let mut textures = TextureMap::default();
+    match get_optional_property(world, "textures") {
+        Some(JsonValue::Object(json_textures)) => {
+            // a texture may refer to the ones declared before it
+            for (name, texture) in json_textures.iter() {
+                let format = match get_optional_property(texture.clone(), "format") {
+                    Some(format) => parse_string(format),
+                    None => String::from("spectrum"),
+                };
+                match format.as_str() {
+                    "spectrum" => {
+                        let texture = parse_texture(texture.clone(), &textures);
+                        textures.spectrum.insert(name.to_string(), texture);
+                    },
+                    "float" => {
+                        let texture = parse_texture(texture.clone(), &textures);
+                        textures.float.insert(name.to_string(), texture);
+                    },
+                    _ => report_parsing_error!("texture's format should be 'spectrum' or 'float'"),
+                }
+            }
+        },
+        Some(_) => report_parsing_error!("'textures' should be an object"),
+        None => {},
+    }
+    textures
+}
 
-fn parse_primitive(primi: JsonValue) -> Box<dyn Primitive> {
+fn parse_primitive(primi: JsonValue, ids: ObjectIds, media: &MediaMap, textures: &TextureMap) -> Box<dyn Primitive> {
     let tp = get_object_property(primi.clone(), "type");
     match tp {
         JsonValue::Short(tp) => {
             match tp.as_str() {
-                "geometric" => parse_geometric(primi),
+                "geometric" => parse_geometric(primi, ids, media, textures),
                 // "mesh" => parse_mesh(primi),
-                "mesh" => parse_mesh(primi),
+                "mesh" => parse_mesh(primi, ids, media, textures),
                 _ => {
                     let msg = format!("no primitive type named {}", tp);
                     report_parsing_error!(msg.as_str());
@@ -361,17 +754,19 @@ fn parse_primitive(primi: JsonValue) -> Box<dyn Primitive> {
     }
 }
 
-fn parse_geometric(primi: JsonValue) -> Box<dyn Primitive> {
+fn parse_geometric(primi: JsonValue, ids: ObjectIds, media: &MediaMap, textures: &TextureMap) -> Box<dyn Primitive> {
     let shape = get_object_property(primi.clone(), "shape");
     let shape = parse_shape(shape);
     
-    let mat = get_object_property(primi, "material");
-    let mat = parse_material(mat);
+    // a primitive without material only bounds a medium
+    let mat = get_optional_property(primi.clone(), "material").map(|mat| parse_material(mat, textures));
+    let medium_interface = get_optional_property(primi, "medium_interface")
+        .map(|mi| parse_medium_interface(mi, media));
 
-    Box::new(GeometricPrimitive::new(shape, mat))
+    Box::new(GeometricPrimitive::new(shape, mat, medium_interface).with_ids(ids))
 }
 
-fn parse_mesh(primi: JsonValue) -> Box<dyn Primitive> {
+fn parse_mesh(primi: JsonValue, ids: ObjectIds, media: &MediaMap, textures: &TextureMap) -> Box<dyn Primitive> {
     // path and mesh
     let json_path = get_object_property(primi.clone(), "path");
     let path = parse_string(json_path);
@@ -411,11 +806,31 @@ fn parse_mesh(primi: JsonValue) -> Box<dyn Primitive> {
         Transform::scale(scale);
 
     // material 
-    let json_material = get_object_property(primi.clone(), "material");
-    let material = parse_material(json_material);
+    let material = get_optional_property(primi.clone(), "material").map(|mat| parse_material(mat, textures));
+    let medium_interface = get_optional_property(primi, "medium_interface")
+        .map(|mi| parse_medium_interface(mi, media));
 
 
-    Box::new(MeshPrimitive::new(mesh.clone(), material, object_to_world))
+    Box::new(MeshPrimitive::new(mesh.clone(), material, medium_interface, object_to_world).with_ids(ids))
+}
+
+fn parse_bool(value: JsonValue, name: &str) -> bool {
+    match value {
+        JsonValue::Short(value) => {
+            match value.as_str() {
+                "true" => true,
+                "false" => false,
+                _ => {
+                    let msg = format!("value of '{}' should be 'true' or 'false'", name);
+                    report_parsing_error!(msg.as_str());
+                }
+            }
+        }
+        _ => {
+            let msg = format!("'{}' should be a string", name);
+            report_parsing_error!(msg.as_str());
+        }
+    }
 }
 
 fn parse_string(s: JsonValue) -> String {
@@ -439,6 +854,7 @@ fn parse_light(light: JsonValue) -> Arc<dyn Light> {
             match tp.as_str() {
                 "area" => parse_area(light.clone()),
                 "point" => parse_point(light),
+                "environment" => parse_environment(light),
                 _ => {
                     let msg = format!("no light type named {}", tp);
                     report_parsing_error!(msg.as_str());
@@ -477,8 +893,173 @@ fn parse_point(light: JsonValue) -> Arc<dyn Light> {
     }
 }
 
-fn parse_world(world: JsonValue) -> Scene {
+fn parse_environment(light: JsonValue) -> Arc<dyn Light> {
+    let path = parse_string(get_object_property(light.clone(), "path"));
+
+    // rotate the map around the scene, defaults to no rotation
+    let light_to_world = match get_optional_property(light.clone(), "rotate") {
+        Some(JsonValue::Array(rotate)) => {
+            let rotate = parse_rotate(&rotate, "rotate");
+            Transform::rotate(rotate.w, rotate.truncate().normalize())
+        },
+        Some(_) => report_parsing_error!("environment light's 'rotate' should be a vec4"),
+        None => Transform::rotate(0.0, Vector3::new(0.0, 1.0, 0.0)),
+    };
+
+    let scale = match get_optional_property(light, "scale") {
+        Some(JsonValue::Array(scale)) => parse_vec3(&scale, "scale"),
+        Some(scale) => {
+            let scale = parse_number(scale, "environment light's 'scale' should be a number or a vec3");
+            Vector3::new(scale, scale, scale)
+        },
+        None => Vector3::new(1.0, 1.0, 1.0),
+    };
+
+    Arc::new(EnvironmentLight::new(light_to_world, &path, Spectrum::new(scale.x, scale.y, scale.z)))
+}
+
+fn parse_light_sampler(light_sampler: Option<JsonValue>) -> LightSamplerType {
+    match light_sampler {
+        None => LightSamplerType::Uniform,
+        Some(light_sampler) => match parse_string(light_sampler).as_str() {
+            "uniform" => LightSamplerType::Uniform,
+            "power" => LightSamplerType::Power,
+            "bvh" => LightSamplerType::Bvh,
+            tp => {
+                let msg = format!("no light sampler named {}", tp);
+                report_parsing_error!(msg.as_str());
+            }
+        },
+    }
+}
+
+fn parse_medium_ref(name: JsonValue, media: &MediaMap) -> Arc<dyn Medium> {
+    let name = parse_string(name);
+    match media.get(&name) {
+        Some(medium) => medium.clone(),
+        None => {
+            let msg = format!("no medium named {}", name);
+            report_parsing_error!(msg.as_str());
+        }
+    }
+}
+
+fn parse_medium_interface(mi: JsonValue, media: &MediaMap) -> MediumInterface {
+    // a missing side is vacuum
+    let inside = get_optional_property(mi.clone(), "inside").map(|name| parse_medium_ref(name, media));
+    let outside = get_optional_property(mi, "outside").map(|name| parse_medium_ref(name, media));
+    MediumInterface::new(inside, outside)
+}
+
+fn parse_phase(phase: Option<JsonValue>) -> Arc<dyn PhaseFunction> {
+    let phase = match phase {
+        Some(phase) => phase,
+        None => return Arc::new(Isotropic),
+    };
+
+    match parse_string(get_object_property(phase.clone(), "type")).as_str() {
+        "isotropic" => Arc::new(Isotropic),
+        "henyey_greenstein" => {
+            let g = parse_number(get_object_property(phase, "g"), "henyey_greenstein's g should be a number");
+            Arc::new(HenyeyGreenstein::new(g))
+        },
+        tp => {
+            let msg = format!("no phase function named {}", tp);
+            report_parsing_error!(msg.as_str());
+        }
+    }
+}
+
+fn parse_spectrum(value: JsonValue, name: &str) -> Spectrum {
+    match value {
+        JsonValue::Array(value) => {
+            let v = parse_vec3(&value, name);
+            Spectrum::new(v.x, v.y, v.z)
+        },
+        _ => {
+            let msg = format!("'{}' should be a vec3", name);
+            report_parsing_error!(msg.as_str());
+        }
+    }
+}
+
+fn parse_medium(medium: JsonValue) -> Arc<dyn Medium> {
+    let tp = parse_string(get_object_property(medium.clone(), "type"));
+    let scale = match get_optional_property(medium.clone(), "scale") {
+        Some(scale) => parse_number(scale, "medium's scale should be a number"),
+        None => 1.0,
+    };
+    let sigma_a = parse_spectrum(get_object_property(medium.clone(), "sigma_a"), "sigma_a") * scale;
+    let sigma_s = parse_spectrum(get_object_property(medium.clone(), "sigma_s"), "sigma_s") * scale;
+    let phase = parse_phase(get_optional_property(medium.clone(), "phase"));
+
+    match tp.as_str() {
+        "homogeneous" => Arc::new(HomogeneousMedium::new(sigma_a, sigma_s, phase)),
+        "grid" => {
+            let resolution = match get_object_property(medium.clone(), "resolution") {
+                JsonValue::Array(resolution) => parse_vec3(&resolution, "resolution"),
+                _ => report_parsing_error!("grid's resolution should be an array of 3 numbers"),
+            };
+            let resolution = [resolution.x as usize, resolution.y as usize, resolution.z as usize];
+
+            let density: Vec<f64> = match get_object_property(medium.clone(), "density") {
+                JsonValue::Array(density) => density.into_iter()
+                    .map(|d| parse_number(d, "grid's density should only contain numbers"))
+                    .collect(),
+                _ => report_parsing_error!("grid's density should be an array"),
+            };
+            if density.len() != resolution[0] * resolution[1] * resolution[2] {
+                report_parsing_error!("grid's density should have resolution[0] * resolution[1] * resolution[2] values");
+            }
+
+            let p_min = match get_object_property(medium.clone(), "p_min") {
+                JsonValue::Array(p) => parse_vec3(&p, "p_min"),
+                _ => report_parsing_error!("grid's p_min should be a vec3"),
+            };
+            let p_max = match get_object_property(medium, "p_max") {
+                JsonValue::Array(p) => parse_vec3(&p, "p_max"),
+                _ => report_parsing_error!("grid's p_max should be a vec3"),
+            };
+
+            let sigma_t = sigma_a + sigma_s;
+            if sigma_t.r != sigma_t.g || sigma_t.g != sigma_t.b {
+                eprintln!("warning: the grid medium only supports a grey extinction, using the average of sigma_a + sigma_s");
+            }
+
+            Arc::new(GridDensityMedium::new(
+                sigma_a,
+                sigma_s,
+                phase,
+                resolution,
+                density,
+                Point3::new(p_min.x, p_min.y, p_min.z),
+                Point3::new(p_max.x, p_max.y, p_max.z),
+            ))
+        },
+        _ => {
+            let msg = format!("no medium type named {}", tp);
+            report_parsing_error!(msg.as_str());
+        }
+    }
+}
+
+fn parse_media(world: JsonValue) -> MediaMap {
+    let mut media = MediaMap::new();
+    match get_optional_property(world, "media") {
+        Some(JsonValue::Object(json_media)) => {
+            for (name, medium) in json_media.iter() {
+                media.insert(name.to_string(), parse_medium(medium.clone()));
+            }
+        },
+        Some(_) => report_parsing_error!("'media' should be an object"),
+        None => {},
+    }
+    media
+}
+
+fn parse_world(world: JsonValue, media: &MediaMap, textures: &TextureMap) -> Scene {
     let json_lights = get_object_property(world.clone(), "lights");
+    let light_sampler = parse_light_sampler(get_optional_property(world.clone(), "light_sampler"));
     let json_primitives = get_object_property(world, "primitives");
 
     let mut lights = Vec::new();
@@ -495,17 +1076,24 @@ fn parse_world(world: JsonValue) -> Scene {
 
     match json_primitives {
         JsonValue::Array(json_primitives) => {
-            for json_primi in json_primitives {
-                primitives.push(parse_primitive(json_primi));
+            // the primitives are numbered from 1 in the order of the file, the materials by their first
+            // appearance, the same description sharing the same id
+            let mut material_ids: HashMap<String, u32> = HashMap::new();
+            for (i, json_primi) in json_primitives.into_iter().enumerate() {
+                let material = get_optional_property(json_primi.clone(), "material").map_or(0, |mat| {
+                    let next = material_ids.len() as u32 + 1;
+                    *material_ids.entry(mat.dump()).or_insert(next)
+                });
+                let ids = ObjectIds { primitive: i as u32 + 1, material };
+                primitives.push(parse_primitive(json_primi, ids, media, textures));
             }
         },
         _ => report_parsing_error!("'primitives' should be of array type"),
     }
 
-    let lightlist = LightList::new(lights);
     let bvh = BVH::new(primitives);
 
-    Scene::new(lightlist, Box::new(bvh))
+    Scene::new(lights, light_sampler, Box::new(bvh))
 }
 
 // fn parse_cylinder(shape: JsonValue) -> Box<dyn Shape> {
@@ -514,11 +1102,11 @@ fn parse_world(world: JsonValue) -> Scene {
 //     }
 // }
 
-fn parse_camera(camera: JsonValue) -> PerspectiveCamera {
+fn parse_camera(camera: JsonValue, media: &MediaMap) -> PerspectiveCamera {
     let tp = get_object_property(camera.clone(), "type");
     if let JsonValue::Short(tp) = tp {
         match tp.as_str() {
-            "perspective" => parse_perspective(camera),
+            "perspective" => parse_perspective(camera, media),
             _ => {
                 let msg = format!("no type {} for camera", tp);
                 report_parsing_error!(msg.as_str());
@@ -529,10 +1117,12 @@ fn parse_camera(camera: JsonValue) -> PerspectiveCamera {
     }
 }
 
-fn parse_perspective(camera: JsonValue) -> PerspectiveCamera {
+fn parse_perspective(camera: JsonValue, media: &MediaMap) -> PerspectiveCamera {
     let lookat = get_object_property(camera.clone(), "lookat");
     let film = get_object_property(camera.clone(), "film");
-    let fov = get_object_property(camera, "fov");
+    let fov = get_object_property(camera.clone(), "fov");
+    // the camera is in vacuum unless told otherwise
+    let medium = get_optional_property(camera, "medium").map(|name| parse_medium_ref(name, media));
 
     // lookat 
     let camera_to_world = match lookat {
@@ -561,7 +1151,25 @@ fn parse_perspective(camera: JsonValue) -> PerspectiveCamera {
 
     // film 
     let resolution = get_object_property(film.clone(), "resolution");
-    let filename = get_object_property(film, "filename");
+    let filename = get_object_property(film.clone(), "filename");
+    let tone_mapper = match get_optional_property(film.clone(), "tonemap") {
+        Some(tonemap) => parse_tonemap(tonemap),
+        None => ToneMapper::default(),
+    };
+    let aovs = match get_optional_property(film, "aovs") {
+        Some(JsonValue::Array(names)) => names.into_iter().map(|name| {
+            let name = parse_string(name);
+            match AovKind::from_name(&name) {
+                Some(kind) => kind,
+                None => {
+                    let msg = format!("no aov named {}", name);
+                    report_parsing_error!(msg.as_str());
+                },
+            }
+        }).collect(),
+        Some(_) => report_parsing_error!("aovs should be an array of names"),
+        None => Vec::new(),
+    };
 
     let (width, height): (f64, f64) = match resolution {
         JsonValue::Array(res) => {
@@ -575,7 +1183,7 @@ fn parse_perspective(camera: JsonValue) -> PerspectiveCamera {
     };
 
     let filename = parse_string(filename);
-    let film = Film::new(&filename, width as usize, height as usize);
+    let film = Film::new(&filename, width as usize, height as usize, tone_mapper).with_aovs(aovs);
 
     // fov
     let fov = parse_number(fov, "fov should be a number");
@@ -588,28 +1196,59 @@ fn parse_perspective(camera: JsonValue) -> PerspectiveCamera {
         0.0,
         1.0,
         fov,
-        film
+        film,
+        medium,
     )
 }
 
-fn parse_setting(setting: JsonValue) -> WorldSetting {
-    let n_sample = get_object_property(setting.clone(), "n_sample");
-    let n_thread = get_object_property(setting.clone(), "n_thread");
-    let sampler = get_object_property(setting.clone(), "sampler");
-    let integrator = get_object_property(setting, "integrator");
+fn parse_tonemap(tonemap: JsonValue) -> ToneMapper {
+    let tp = get_object_property(tonemap.clone(), "type");
+    let exposure = match get_optional_property(tonemap.clone(), "exposure") {
+        Some(exposure) => parse_number(exposure, "tonemap's exposure should be a number"),
+        None => 0.0,
+    };
+    let white = get_optional_property(tonemap, "white")
+        .map(|white| parse_number(white, "tonemap's white should be a number"));
 
+    let operator = match tp {
+        JsonValue::Short(tp) => {
+            match tp.as_str() {
+                "linear" => ToneMapping::Linear,
+                "reinhard" => ToneMapping::Reinhard,
+                "reinhard_extended" => ToneMapping::ExtendedReinhard { white: white.unwrap_or(4.0) },
+                "aces" => ToneMapping::Aces,
+                // Hable's white point of 11.2 is given after his exposure bias of 2
+                "uncharted2" => ToneMapping::Uncharted2 { white: white.unwrap_or(5.6) },
+                _ => {
+                    let msg = format!("no type {} for tonemap", tp);
+                    report_parsing_error!(msg.as_str());
+                },
+            }
+        },
+        _ => report_parsing_error!("tonemap type should be a string"),
+    };
 
-    let n_sample = parse_number(n_sample, "spp should be a number").max(1.0) as usize;
-    let n_thread = parse_number(n_thread, "n_thread should be a number").max(1.0) as usize;
+    ToneMapper::new(operator, exposure)
+}
 
-    // sampler
-    let sampler_tp = get_object_property(sampler, "type");
-    let sampler = match sampler_tp {
+fn parse_sampler(sampler: JsonValue, n_sample: usize, resolution: Point2<usize>, seed: u64) -> Box<dyn Sampler> {
+    let tp = get_object_property(sampler.clone(), "type");
+    match tp {
         JsonValue::Short(tp) => {
             match tp.as_str() {
-                "uniform" => {
-                    UniformSampler::new()
+                "uniform" => Box::new(UniformSampler::new(n_sample, seed)),
+                "stratified" => {
+                    let jitter = match get_optional_property(sampler, "jitter") {
+                        Some(jitter) => parse_bool(jitter, "jitter"),
+                        None => true,
+                    };
+                    // the smallest grid holding at least n_sample strata
+                    let x_samples = (n_sample as f64).sqrt().ceil() as usize;
+                    let y_samples = n_sample.div_ceil(x_samples);
+                    Box::new(StratifiedSampler::new(x_samples, y_samples, jitter, seed))
                 },
+                "halton" => Box::new(HaltonSampler::new(n_sample, resolution, seed)),
+                "sobol" => Box::new(SobolSampler::new(n_sample, seed)),
                 _ => {
                     let msg = format!("no type {} for sampler", tp);
                     report_parsing_error!(msg.as_str());
@@ -617,35 +1256,97 @@ fn parse_setting(setting: JsonValue) -> WorldSetting {
             }
         },
         _ => report_parsing_error!("sampler type should be a string"),
+    }
+}
+
+fn parse_setting(setting: JsonValue, camera: &Arc<PerspectiveCamera>) -> WorldSetting {
+    let n_sample = get_object_property(setting.clone(), "n_sample");
+    let n_thread = get_object_property(setting.clone(), "n_thread");
+    let sampler = get_object_property(setting.clone(), "sampler");
+    let integrator = get_object_property(setting.clone(), "integrator");
+
+
+    let n_sample = parse_number(n_sample, "spp should be a number").max(1.0) as usize;
+    let n_thread = parse_number(n_thread, "n_thread should be a number").max(1.0) as usize;
+    // the image only depends on the seed, not on the number of threads
+    let seed = match get_optional_property(setting, "seed") {
+        Some(seed) => parse_number(seed, "seed should be a number").max(0.0) as u64,
+        None => 0,
     };
 
+    // sampler
+    let sampler = parse_sampler(sampler, n_sample, camera.film.resolution, seed);
+    if sampler.samples_per_pixel() != n_sample {
+        eprintln!("warning: the sampler rounds n_sample {} to {}", n_sample, sampler.samples_per_pixel());
+    }
+    let n_sample = sampler.samples_per_pixel();
+
     // integrator
     let integrator_tp = get_object_property(integrator.clone(), "type");
     let b_mis = get_object_property(integrator.clone(), "b_mis");
-    let b_mis = match b_mis {
-        JsonValue::Short(b_mis) => {
-            match b_mis.as_str() {
-                "true" => true,
-                "false" => false,
-                _ => {
-                    let msg = format!("value of 'b_mis' should be 'true' or 'false'");
-                    report_parsing_error!(msg.as_str());
-                }
-            }
-        }
-        _ => report_parsing_error!("'b_mis' should be a string")
-    };
+    let b_mis = parse_bool(b_mis, "b_mis");
 
-    let json_depth = get_object_property(integrator, "max_depth");
-    let max_depth = parse_number(json_depth, "value of 'max_depth' should be an integer") as usize;
+    // a negative max_depth leaves the length of the paths to russian roulette
+    let json_depth = get_object_property(integrator.clone(), "max_depth");
+    let max_depth = parse_number(json_depth, "value of 'max_depth' should be an integer");
+    let unbounded = max_depth < 0.0;
+    let max_depth = if unbounded { usize::MAX } else { max_depth as usize };
+    let rr_depth = parse_optional_number(integrator.clone(), "rr_depth", 3.0).max(0.0) as usize;
 
 
     let integrator: Arc<Box<dyn Integrator>>= match integrator_tp {
         JsonValue::Short(tp) => {
+            // the other integrators do not end their paths randomly
+            if unbounded && !matches!(tp.as_str(), "path" | "mlt") {
+                let msg = format!("integrator {} needs a nonnegative max_depth", tp);
+                report_parsing_error!(msg.as_str());
+            }
             match tp.as_str() {
-                "path" => Arc::new(Box::new(PathIntegrator::new(max_depth, b_mis))),
+                "path" => Arc::new(Box::new(PathIntegrator::new(max_depth, b_mis).with_russian_roulette(rr_depth))),
                 "direct" => Arc::new(Box::new(DirectIntegrator::new(max_depth))),
-                // "wrsdirect" => setting.integrator = Box::new,
+                "volpath" => Arc::new(Box::new(VolPathIntegrator::new(max_depth))),
+                // the paths from the lights reaching the camera are splatted on its film
+                "bdpt" => Arc::new(Box::new(BdptIntegrator::new(max_depth, Arc::clone(camera)))),
+                "sppm" => {
+                    // one photon per pixel and iteration by default, the radius depends on the scale of the scene
+                    let resolution = camera.film.resolution;
+                    let photons = match get_optional_property(integrator.clone(), "photons") {
+                        Some(photons) => parse_number(photons, "photons should be a number").max(1.0) as usize,
+                        None => resolution.x * resolution.y,
+                    };
+                    let radius = match get_optional_property(integrator, "radius") {
+                        Some(radius) => parse_number(radius, "radius should be a number"),
+                        None => 1.0,
+                    };
+                    Arc::new(Box::new(SppmIntegrator::new(max_depth, photons, radius, Arc::clone(camera))))
+                },
+                "mlt" => {
+                    let optional = |property: &str, default: f64| match get_optional_property(integrator.clone(), property) {
+                        Some(value) => parse_number(value, &format!("{} should be a number", property)),
+                        None => default,
+                    };
+                    let mlt = MltIntegrator::new(max_depth, b_mis, Arc::clone(camera))
+                        .with_russian_roulette(rr_depth)
+                        .with_chains(optional("bootstrap", 100000.0) as usize, optional("chains", 1000.0) as usize)
+                        .with_mutations(optional("sigma", 0.01), optional("large_step", 0.3));
+                    Arc::new(Box::new(mlt))
+                },
+                "wrsdirect" => {
+                    // the reservoirs are only shared between the pixels when some reuse is asked for
+                    let candidates = parse_optional_number(integrator.clone(), "candidates", 32.0).max(1.0) as usize;
+                    let neighbors = parse_optional_number(integrator.clone(), "neighbors", 0.0).max(0.0) as usize;
+                    let radius = parse_optional_number(integrator.clone(), "radius", 30.0);
+                    let temporal = match get_optional_property(integrator, "temporal") {
+                        Some(temporal) => parse_bool(temporal, "temporal"),
+                        None => false,
+                    };
+                    let wrs = WRSDirectIntegrator::new(max_depth, candidates, Arc::clone(camera));
+                    if neighbors > 0 || temporal {
+                        Arc::new(Box::new(wrs.with_reuse(neighbors, radius, temporal)))
+                    } else {
+                        Arc::new(Box::new(wrs))
+                    }
+                },
                 _ => {
                     let msg = format!("no type {} for integrator", tp);
                     report_parsing_error!(msg.as_str());
@@ -655,17 +1356,24 @@ fn parse_setting(setting: JsonValue) -> WorldSetting {
         _ => report_parsing_error!("sampler type should be a string"),
     };
 
+    // the direct and indirect outputs are only kept apart by some integrators
+    let split = camera.film.aovs.iter().any(|&kind| matches!(kind, AovKind::Direct | AovKind::Indirect));
+    if split && !integrator.can_split() {
+        report_parsing_error!("the integrator can not split the direct and indirect aovs");
+    }
+
     WorldSetting::new(
         n_sample,
         n_thread,
         integrator,
-        Arc::new(sampler),
+        sampler,
+        seed,
     )
 }
 
 
 
-pub fn parse_scene(path: &str) -> (WorldSetting, PerspectiveCamera, Scene) {
+pub fn parse_scene(path: &str) -> (WorldSetting, Arc<PerspectiveCamera>, Scene) {
     let file_soure = fs::read_to_string(path).unwrap();
 
     match json::parse(&file_soure) {
@@ -674,10 +1382,14 @@ pub fn parse_scene(path: &str) -> (WorldSetting, PerspectiveCamera, Scene) {
             let setting = get_object_property(config.clone(), "setting");
             let world = get_object_property(config, "world");
 
+            // the media are shared by the camera and the world
+            let media = parse_media(world.clone());
+            let textures = parse_textures(world.clone());
+
             // world
-            let camera = parse_camera(camera);
-            let setting = parse_setting(setting);
-            let scene = parse_world(world);
+            let camera = Arc::new(parse_camera(camera, &media));
+            let setting = parse_setting(setting, &camera);
+            let scene = parse_world(world, &media, &textures);
 
             (setting, camera, scene)
         },
@@ -685,3 +1397,101 @@ pub fn parse_scene(path: &str) -> (WorldSetting, PerspectiveCamera, Scene) {
         Err(e) => panic!("{:?}", e),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geometry::interaction::{GeometryInfo, SurfaceInteraction};
+    use crate::integrator::test_scene::{camera_with_film, WIDTH, HEIGHT};
+
+    fn isect() -> SurfaceInteraction {
+        SurfaceInteraction {
+            geo: GeometryInfo::new(
+                Point3::new(0.3, 0.4, 0.0), Vector3::new(0.0, 0.0, 1.0), 1.0, Vector3::new(0.0, 0.0, 1.0),
+                Point2::new(0.3, 0.4), Vector3::new(1.0, 0.0, 0.0), Vector3::new(0.0, 1.0, 0.0),
+            ),
+            time: 0.0,
+            material: None,
+            hit_light: false,
+            radiance: None,
+            light: None,
+            medium_interface: None,
+            differentials: None,
+            ids: Default::default(),
+        }
+    }
+
+    fn world_textures() -> TextureMap {
+        let world = json::parse(r#"{
+            "textures": {
+                "half": { "type": "constant", "format": "float", "value": 0.5 },
+                "red": { "type": "constant", "value": [1.0, 0.0, 0.0] },
+                "dim_red": { "type": "scale", "scale": "half", "tex": "red" },
+                "purple": { "type": "mix", "tex1": "dim_red", "tex2": [0.0, 0.0, 1.0], "amount": 0.25 }
+            }
+        }"#).unwrap();
+        parse_textures(world)
+    }
+
+    #[test]
+    fn test_parse_textures() {
+        let textures = world_textures();
+        let isect = isect();
+        let evaluate = |value: &str| parse_texture_param::<Spectrum>(json::parse(value).unwrap(), &textures, "kd").evaluate(&isect);
+
+        // the named textures refer to the ones declared before them
+        assert_eq!(textures.float["half"].evaluate(&isect), 0.5);
+        assert_eq!(evaluate(r#""dim_red""#), Spectrum::new(0.5, 0.0, 0.0));
+        assert_eq!(evaluate(r#""purple""#), Spectrum::new(0.375, 0.0, 0.25));
+
+        // a parameter is also a literal or a texture of its own
+        assert_eq!(evaluate("0.2"), Spectrum::new(0.2, 0.2, 0.2));
+        assert_eq!(evaluate(r#"{ "type": "scale", "scale": 2.0, "tex": "dim_red" }"#), Spectrum::new(1.0, 0.0, 0.0));
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_parse_unknown_texture() {
+        let textures = world_textures();
+        parse_texture_param::<Spectrum>(JsonValue::from("green"), &textures, "kd");
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_parse_texture_of_another_format() {
+        // a float texture is not a spectrum one
+        let textures = world_textures();
+        parse_texture_param::<Spectrum>(JsonValue::from("half"), &textures, "kd");
+    }
+
+    // the setting of a single sample per pixel with the integrator, over a film asking for the direct light
+    fn parse_split_setting(integrator: &str) -> WorldSetting {
+        let setting = json::parse(&format!(r#"{{
+            "n_sample": 1,
+            "n_thread": 1,
+            "sampler": {{ "type": "uniform" }},
+            "integrator": {{ "type": "{}", "b_mis": "true", "max_depth": 5 }}
+        }}"#, integrator)).unwrap();
+        let film = Film::new("unused.png", WIDTH, HEIGHT, ToneMapper::default()).with_aovs(vec![AovKind::Direct]);
+        parse_setting(setting, &camera_with_film(film, None))
+    }
+
+    #[test]
+    fn test_parse_split_aovs() {
+        for integrator in ["path", "volpath", "bdpt"] {
+            assert!(parse_split_setting(integrator).integrator.can_split());
+        }
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_parse_split_aovs_of_sppm() {
+        parse_split_setting("sppm");
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_parse_split_aovs_of_mlt() {
+        parse_split_setting("mlt");
+    }
+}
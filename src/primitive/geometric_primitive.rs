@@ -1,16 +1,23 @@
-use super::Primitive;
+use super::{Primitive, ObjectIds};
 use std::sync::Arc;
-use crate::{geometry::{ray::Ray, interaction::SurfaceInteraction, bound3::Bound3, shape::Shape}, material::Material};
+use crate::{geometry::{ray::Ray, interaction::SurfaceInteraction, bound3::Bound3, shape::Shape}, material::Material, medium::MediumInterface};
 
 
 pub struct GeometricPrimitive {
     shape: Box<dyn Shape>,
-    material: Arc<dyn Material>,
+    material: Option<Arc<dyn Material>>, // None for a surface that only bounds a medium
+    medium_interface: Option<MediumInterface>,
+    ids: ObjectIds,
 }
 
 impl GeometricPrimitive {
-    pub fn new(shape: Box<dyn Shape>, material: Arc<dyn Material>) -> Self {
-        GeometricPrimitive { shape, material}
+    pub fn new(shape: Box<dyn Shape>, material: Option<Arc<dyn Material>>, medium_interface: Option<MediumInterface>) -> Self {
+        GeometricPrimitive { shape, material, medium_interface, ids: ObjectIds::default() }
+    }
+
+    pub fn with_ids(mut self, ids: ObjectIds) -> Self {
+        self.ids = ids;
+        self
     }
 }
 
@@ -23,10 +30,13 @@ impl Primitive for GeometricPrimitive {
             let isect = SurfaceInteraction {
                 geo,
                 time: r.time,
-                material: Some(Arc::clone(&self.material)),
+                material: self.material.clone(),
                 hit_light: false,
                 radiance: None,
                 light: None,
+                medium_interface: self.medium_interface.clone(),
+                differentials: None,
+                ids: self.ids,
             };
 
             // let isect point to the primitive
@@ -37,8 +47,9 @@ impl Primitive for GeometricPrimitive {
     }
 
     fn intersect_p(&self, r: &Ray) -> Option<f64> {
+        // the boundaries of media do not block the light, the media themselves are accounted for by the transmittance
+        self.material.as_ref()?;
         self.shape.intersect_p(r)
-
     }
 
     fn world_bound(&self) -> Bound3 {
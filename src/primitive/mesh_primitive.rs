@@ -1,35 +1,42 @@
 
 use std::sync::Arc;
 
-use crate::{mesh::{TriangleMesh, Triangle}, accelerator::bvh::BVH, material::Material, geometry::transform::Transform};
+use crate::{mesh::{TriangleMesh, Triangle}, accelerator::bvh::BVH, material::Material, geometry::{transform::Transform, interaction::SurfaceInteraction}, medium::MediumInterface};
 
-use super::{geometric_primitive::GeometricPrimitive, Primitive};
+use super::{geometric_primitive::GeometricPrimitive, Primitive, ObjectIds};
 
 pub struct MeshPrimitive {
     bvh: BVH,
+    ids: ObjectIds, // shared by the triangles
 }
 
 impl MeshPrimitive {
-    pub fn new(mesh: Arc<TriangleMesh>, material: Arc<dyn Material>, object_to_world: Transform) -> Self {
+    pub fn new(mesh: Arc<TriangleMesh>, material: Option<Arc<dyn Material>>, medium_interface: Option<MediumInterface>, object_to_world: Transform) -> Self {
         let mut primitives: Vec<Box<dyn Primitive>> = Vec::new();
 
         for ind in mesh.indices.chunks(3) {
             let triangle = Triangle::new(ind[0], ind[1], ind[2], mesh.clone(), object_to_world.clone());
-            let triangle_obj = GeometricPrimitive::new(Box::new(triangle), material.clone());
+            let triangle_obj = GeometricPrimitive::new(Box::new(triangle), material.clone(), medium_interface.clone());
             primitives.push(Box::new(triangle_obj));
         }
 
         let bvh = BVH::new(primitives);
 
         Self {
-            bvh
+            bvh,
+            ids: ObjectIds::default(),
         }
     }
+
+    pub fn with_ids(mut self, ids: ObjectIds) -> Self {
+        self.ids = ids;
+        self
+    }
 }
 
 impl Primitive for MeshPrimitive {
-    fn intersect(&self, r: &mut crate::geometry::ray::Ray) -> Option<crate::geometry::interaction::SurfaceInteraction> {
-        self.bvh.intersect(r)
+    fn intersect(&self, r: &mut crate::geometry::ray::Ray) -> Option<SurfaceInteraction> {
+        self.bvh.intersect(r).map(|isect| SurfaceInteraction { ids: self.ids, ..isect })
     }
 
     fn intersect_p(&self, r: &crate::geometry::ray::Ray) -> Option<f64> {
@@ -3,6 +3,14 @@ pub mod mesh_primitive;
 
 use crate::geometry::{ray::Ray, interaction::SurfaceInteraction, bound3::Bound3};
 
+/// the ids of the primitive and of the material a ray hits, written to the id outputs of the film,
+/// 0 for the surfaces that were not given any
+#[derive(Clone, Copy, Default, Debug, PartialEq)]
+pub struct ObjectIds {
+    pub primitive: u32,
+    pub material: u32,
+}
+
 pub trait Primitive: Sync + Send {
     fn intersect(&self, r: &mut Ray) -> Option<SurfaceInteraction>;
     fn intersect_p(&self, r: &Ray) -> Option<f64>;
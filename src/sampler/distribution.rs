@@ -0,0 +1,265 @@
+use cgmath::Point2;
+
+use super::rng::ONE_MINUS_EPSILON;
+
+/// a piecewise constant function over [0, 1) that can be sampled proportionally to its value
+pub struct Distribution1D {
+    func: Vec<f64>,
+    cdf: Vec<f64>,
+    pub func_int: f64, // integral of func over [0, 1)
+}
+
+#[allow(dead_code)]
+impl Distribution1D {
+    pub fn new(func: Vec<f64>) -> Self {
+        let n = func.len();
+        assert!(n > 0);
+
+        let mut cdf = vec![0.0; n + 1];
+        for i in 1..=n {
+            cdf[i] = cdf[i - 1] + func[i - 1].abs() / n as f64;
+        }
+
+        let func_int = cdf[n];
+        for (i, c) in cdf.iter_mut().enumerate() {
+            // fall back to a uniform distribution for functions that are zero everywhere
+            *c = if func_int == 0.0 { i as f64 / n as f64 } else { *c / func_int };
+        }
+
+        Distribution1D { func, cdf, func_int }
+    }
+
+    pub fn count(&self) -> usize {
+        self.func.len()
+    }
+
+    // the largest index whose cdf is not greater than u
+    fn find_interval(&self, u: f64) -> usize {
+        let i = self.cdf.partition_point(|&c| c <= u);
+        i.saturating_sub(1).min(self.count() - 1)
+    }
+
+    /// returns (x, pdf, offset), x in [0, 1) and offset is the index of the piece x lies in
+    pub fn sample_continuous(&self, u: f64) -> (f64, f64, usize) {
+        let offset = self.find_interval(u);
+
+        let mut du = u - self.cdf[offset];
+        let width = self.cdf[offset + 1] - self.cdf[offset];
+        if width > 0.0 {
+            du /= width;
+        }
+
+        let pdf = if self.func_int > 0.0 { self.func[offset].abs() / self.func_int } else { 0.0 };
+        let x = (offset as f64 + du) / self.count() as f64;
+
+        (x, pdf, offset)
+    }
+
+    /// returns (index, pmf)
+    pub fn sample_discrete(&self, u: f64) -> (usize, f64) {
+        let offset = self.find_interval(u);
+        (offset, self.discrete_pmf(offset))
+    }
+
+    pub fn discrete_pmf(&self, index: usize) -> f64 {
+        self.cdf[index + 1] - self.cdf[index]
+    }
+
+    pub fn pdf(&self, x: f64) -> f64 {
+        if self.func_int == 0.0 {
+            return 0.0;
+        }
+        let offset = ((x * self.count() as f64) as usize).min(self.count() - 1);
+        self.func[offset].abs() / self.func_int
+    }
+}
+
+/// a piecewise constant function over [0, 1)^2, func is stored row by row (v major)
+pub struct Distribution2D {
+    conditional: Vec<Distribution1D>,
+    marginal: Distribution1D,
+}
+
+impl Distribution2D {
+    pub fn new(func: &[f64], nu: usize, nv: usize) -> Self {
+        assert_eq!(func.len(), nu * nv);
+
+        let conditional: Vec<Distribution1D> = func
+            .chunks(nu)
+            .map(|row| Distribution1D::new(row.to_vec()))
+            .collect();
+        let marginal = Distribution1D::new(conditional.iter().map(|d| d.func_int).collect());
+
+        Distribution2D { conditional, marginal }
+    }
+
+    /// returns the sampled (u, v) and its pdf with respect to the area of [0, 1)^2
+    pub fn sample_continuous(&self, u: Point2<f64>) -> (Point2<f64>, f64) {
+        let (d1, pdf1, v) = self.marginal.sample_continuous(u.y);
+        let (d0, pdf0, _) = self.conditional[v].sample_continuous(u.x);
+
+        (Point2::new(d0, d1), pdf0 * pdf1)
+    }
+
+    pub fn pdf(&self, p: Point2<f64>) -> f64 {
+        if self.marginal.func_int == 0.0 {
+            return 0.0;
+        }
+        let nu = self.conditional[0].count();
+        let nv = self.marginal.count();
+        let iu = ((p.x * nu as f64) as usize).min(nu - 1);
+        let iv = ((p.y * nv as f64) as usize).min(nv - 1);
+
+        self.conditional[iv].func[iu].abs() / self.marginal.func_int
+    }
+}
+
+struct AliasBin {
+    q: f64, // probability of keeping the bin's own index
+    alias: usize,
+}
+
+/// samples an index proportionally to its weight in constant time (Vose's alias method)
+pub struct AliasTable {
+    pmf: Vec<f64>,
+    bins: Vec<AliasBin>,
+}
+
+impl AliasTable {
+    pub fn new(weights: &[f64]) -> Self {
+        let n = weights.len();
+        assert!(n > 0);
+
+        let sum: f64 = weights.iter().sum();
+        let pmf: Vec<f64> = if sum > 0.0 {
+            weights.iter().map(|w| w / sum).collect()
+        } else {
+            vec![1.0 / n as f64; n]
+        };
+
+        // split the bins into those under and over the average probability
+        let mut under = Vec::new();
+        let mut over = Vec::new();
+        for (i, p) in pmf.iter().enumerate() {
+            let p_hat = p * n as f64;
+            if p_hat < 1.0 {
+                under.push((i, p_hat));
+            } else {
+                over.push((i, p_hat));
+            }
+        }
+
+        let mut bins: Vec<AliasBin> = (0..n).map(|i| AliasBin { q: 1.0, alias: i }).collect();
+
+        // fill every under-full bin with the excess of an over-full one
+        while let (Some(&(un, p_un)), Some(&(ov, p_ov))) = (under.last(), over.last()) {
+            under.pop();
+            over.pop();
+
+            bins[un] = AliasBin { q: p_un, alias: ov };
+
+            let p_excess = p_un + p_ov - 1.0;
+            if p_excess < 1.0 {
+                under.push((ov, p_excess));
+            } else {
+                over.push((ov, p_excess));
+            }
+        }
+
+        // the remaining bins are full up to round off error
+        for (i, _) in under.into_iter().chain(over) {
+            bins[i] = AliasBin { q: 1.0, alias: i };
+        }
+
+        AliasTable { pmf, bins }
+    }
+
+    pub fn size(&self) -> usize {
+        self.pmf.len()
+    }
+
+    /// returns (index, pmf, u remapped to [0, 1))
+    pub fn sample(&self, u: f64) -> (usize, f64, f64) {
+        let n = self.size();
+        let offset = ((u * n as f64) as usize).min(n - 1);
+        let up = (u * n as f64 - offset as f64).min(ONE_MINUS_EPSILON);
+
+        let bin = &self.bins[offset];
+        if up < bin.q {
+            (offset, self.pmf[offset], (up / bin.q).min(ONE_MINUS_EPSILON))
+        } else {
+            let alias = bin.alias;
+            (alias, self.pmf[alias], ((up - bin.q) / (1.0 - bin.q)).min(ONE_MINUS_EPSILON))
+        }
+    }
+
+    pub fn pmf(&self, index: usize) -> f64 {
+        self.pmf[index]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_distribution_1d() {
+        let d = Distribution1D::new(vec![1.0, 3.0, 0.0, 4.0]);
+        assert!((d.func_int - 2.0).abs() < 1e-12);
+
+        // the pieces are chosen proportionally to their values
+        assert!((d.discrete_pmf(1) - 3.0 / 8.0).abs() < 1e-12);
+        assert_eq!(d.discrete_pmf(2), 0.0);
+
+        for i in 0..100 {
+            let u = i as f64 / 100.0;
+            let (x, pdf, offset) = d.sample_continuous(u);
+            assert!((0.0..1.0).contains(&x));
+            assert_ne!(offset, 2);
+            assert!((pdf - d.pdf(x)).abs() < 1e-12);
+        }
+    }
+
+    #[test]
+    fn test_alias_table() {
+        let weights = [1.0, 0.0, 5.0, 2.0, 0.5, 1.5];
+        let table = AliasTable::new(&weights);
+
+        // a uniform sweep over u hits every index exactly in proportion to its weight
+        let n = 100000;
+        let mut counts = [0usize; 6];
+        for i in 0..n {
+            let (index, pmf, u_remapped) = table.sample((i as f64 + 0.5) / n as f64);
+            assert!((0.0..1.0).contains(&u_remapped));
+            assert_eq!(pmf, table.pmf(index));
+            counts[index] += 1;
+        }
+        for (i, count) in counts.iter().enumerate() {
+            assert!((*count as f64 / n as f64 - weights[i] / 10.0).abs() < 1e-3);
+        }
+    }
+
+    #[test]
+    fn test_distribution_2d_normalized() {
+        let (nu, nv) = (8, 5);
+        let func: Vec<f64> = (0..nu * nv).map(|i| ((i * 7) % 11) as f64).collect();
+        let d = Distribution2D::new(&func, nu, nv);
+
+        // the pdf integrates to one over the unit square
+        let n = 200;
+        let mut integral = 0.0;
+        for i in 0..n {
+            for j in 0..n {
+                let p = Point2::new((i as f64 + 0.5) / n as f64, (j as f64 + 0.5) / n as f64);
+                integral += d.pdf(p) / (n * n) as f64;
+            }
+        }
+        assert!((integral - 1.0).abs() < 1e-6);
+
+        for i in 0..50 {
+            let u = Point2::new((i as f64 * 0.618).fract(), i as f64 / 50.0);
+            let (p, pdf) = d.sample_continuous(u);
+            assert!((pdf - d.pdf(p)).abs() < 1e-9);
+        }
+    }
+}
@@ -0,0 +1,156 @@
+use std::sync::Arc;
+
+use cgmath::Point2;
+
+use super::{Sampler, lowdiscrepancy::{DigitPermutation, PRIME_TABLE_SIZE, primes, radical_inverse, inverse_radical_inverse, scrambled_radical_inverse}};
+
+// the halton points are tiled over the image in blocks of at most this many pixels
+const MAX_HALTON_RESOLUTION: usize = 128;
+
+/// the halton sequence randomized by a random permutation of the digits of every base.
+///
+/// the first two dimensions are spread over the whole image, the samples of a pixel are the
+/// points of the sequence falling inside it
+#[derive(Clone)]
+pub struct HaltonSampler {
+    samples_per_pixel: usize,
+    base_scales: [u64; 2],
+    base_exponents: [u32; 2],
+    mult_inverse: [u64; 2],
+    permutations: Arc<Vec<DigitPermutation>>,
+    halton_index: u64,
+    dimension: usize,
+}
+
+impl HaltonSampler {
+    pub fn new(samples_per_pixel: usize, resolution: Point2<usize>, seed: u64) -> Self {
+        // find the scales 2^j and 3^k covering the image
+        let mut base_scales = [1u64; 2];
+        let mut base_exponents = [0u32; 2];
+        for i in 0..2 {
+            let base = primes()[i];
+            while base_scales[i] < resolution[i].min(MAX_HALTON_RESOLUTION) as u64 {
+                base_scales[i] *= base;
+                base_exponents[i] += 1;
+            }
+        }
+
+        let mult_inverse = [
+            multiplicative_inverse(base_scales[1] as i64, base_scales[0] as i64),
+            multiplicative_inverse(base_scales[0] as i64, base_scales[1] as i64),
+        ];
+
+        let permutations = primes().iter().map(|&base| DigitPermutation::new(base, seed)).collect();
+
+        HaltonSampler {
+            samples_per_pixel,
+            base_scales,
+            base_exponents,
+            mult_inverse,
+            permutations: Arc::new(permutations),
+            halton_index: 0,
+            dimension: 0,
+        }
+    }
+
+    fn sample_dimension(&self, dimension: usize) -> f64 {
+        scrambled_radical_inverse(self.halton_index, &self.permutations[dimension])
+    }
+}
+
+impl Sampler for HaltonSampler {
+    fn samples_per_pixel(&self) -> usize {
+        self.samples_per_pixel
+    }
+
+    fn start_pixel_sample(&mut self, p: Point2<usize>, sample_index: usize) {
+        // compute the index of the first halton point inside the pixel with the chinese remainder theorem
+        let sample_stride = self.base_scales[0] * self.base_scales[1];
+        self.halton_index = 0;
+
+        if sample_stride > 1 {
+            let pm = [(p.x % MAX_HALTON_RESOLUTION) as u64, (p.y % MAX_HALTON_RESOLUTION) as u64];
+            for (i, &pm) in pm.iter().enumerate() {
+                let dim_offset = inverse_radical_inverse(pm, primes()[i], self.base_exponents[i]);
+                self.halton_index += dim_offset * (sample_stride / self.base_scales[i]) * self.mult_inverse[i];
+            }
+            self.halton_index %= sample_stride;
+        }
+
+        self.halton_index += sample_index as u64 * sample_stride;
+        self.dimension = 2;
+    }
+
+    fn get_1d(&mut self) -> f64 {
+        // restart from the third base when running out of primes
+        if self.dimension >= PRIME_TABLE_SIZE {
+            self.dimension = 2;
+        }
+        let dim = self.dimension;
+        self.dimension += 1;
+
+        self.sample_dimension(dim)
+    }
+
+    fn get_2d(&mut self) -> Point2<f64> {
+        if self.dimension + 1 >= PRIME_TABLE_SIZE {
+            self.dimension = 2;
+        }
+        let dim = self.dimension;
+        self.dimension += 2;
+
+        Point2::new(self.sample_dimension(dim), self.sample_dimension(dim + 1))
+    }
+
+    fn get_pixel_2d(&mut self) -> Point2<f64> {
+        // the digits below the scale of the pixel grid give the offset inside the pixel
+        Point2::new(
+            radical_inverse(0, self.halton_index >> self.base_exponents[0]),
+            radical_inverse(1, self.halton_index / self.base_scales[1]),
+        )
+    }
+
+    fn clone_box(&self) -> Box<dyn Sampler> {
+        Box::new(self.clone())
+    }
+}
+
+fn extended_gcd(a: i64, b: i64) -> (i64, i64) {
+    if b == 0 {
+        return (1, 0);
+    }
+    let d = a / b;
+    let (xp, yp) = extended_gcd(b, a % b);
+    (yp, xp - d * yp)
+}
+
+fn multiplicative_inverse(a: i64, n: i64) -> u64 {
+    let (x, _) = extended_gcd(a, n);
+    x.rem_euclid(n) as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pixel_samples_inside_pixel() {
+        // the unscrambled first two dimensions of a pixel's samples land inside that pixel
+        let sampler = HaltonSampler::new(4, Point2::new(20, 10), 0);
+        let mut sampler = sampler;
+        let scales = sampler.base_scales;
+        for (x, y) in [(0, 0), (3, 7), (19, 9)] {
+            for s in 0..4 {
+                sampler.start_pixel_sample(Point2::new(x, y), s);
+                let px = radical_inverse(0, sampler.halton_index) * scales[0] as f64;
+                let py = radical_inverse(1, sampler.halton_index) * scales[1] as f64;
+                assert_eq!(px as usize, x);
+                assert_eq!(py as usize, y);
+
+                let offset = sampler.get_pixel_2d();
+                assert!((px.fract() - offset.x).abs() < 1e-9);
+                assert!((py.fract() - offset.y).abs() < 1e-9);
+            }
+        }
+    }
+}
@@ -0,0 +1,294 @@
+use std::sync::OnceLock;
+
+use super::rng::ONE_MINUS_EPSILON;
+
+/// the number of prime bases available for the radical inverse
+pub const PRIME_TABLE_SIZE: usize = 256;
+
+pub fn primes() -> &'static [u64] {
+    static PRIMES: OnceLock<Vec<u64>> = OnceLock::new();
+    PRIMES.get_or_init(|| {
+        let mut primes: Vec<u64> = Vec::with_capacity(PRIME_TABLE_SIZE);
+        let mut n = 2;
+        while primes.len() < PRIME_TABLE_SIZE {
+            if primes.iter().take_while(|&&p| p * p <= n).all(|&p| n % p != 0) {
+                primes.push(n);
+            }
+            n += 1;
+        }
+        primes
+    })
+}
+
+pub fn mix_bits(v: u64) -> u64 {
+    let mut v = v;
+    v ^= v >> 31;
+    v = v.wrapping_mul(0x7fb5d329728ea185);
+    v ^= v >> 27;
+    v = v.wrapping_mul(0x81dadef4bc2dd44d);
+    v ^= v >> 33;
+    v
+}
+
+/// MurmurHash64A over a sequence of 64-bit words
+pub fn hash(values: &[u64]) -> u64 {
+    const M: u64 = 0xc6a4a7935bd1e995;
+    const R: u32 = 47;
+
+    let mut h = (values.len() as u64 * 8).wrapping_mul(M);
+    for &v in values {
+        let mut k = v.wrapping_mul(M);
+        k ^= k >> R;
+        k = k.wrapping_mul(M);
+        h ^= k;
+        h = h.wrapping_mul(M);
+    }
+
+    h ^= h >> R;
+    h = h.wrapping_mul(M);
+    h ^= h >> R;
+    h
+}
+
+/// the i-th element of a pseudo random permutation of [0, l) selected by p (Kensler 2013)
+pub fn permutation_element(i: u32, l: u32, p: u32) -> u32 {
+    let mut w = l - 1;
+    w |= w >> 1;
+    w |= w >> 2;
+    w |= w >> 4;
+    w |= w >> 8;
+    w |= w >> 16;
+
+    let mut i = i;
+    loop {
+        i ^= p;
+        i = i.wrapping_mul(0xe170893d);
+        i ^= p >> 16;
+        i ^= (i & w) >> 4;
+        i ^= p >> 8;
+        i = i.wrapping_mul(0x0929eb3f);
+        i ^= p >> 23;
+        i ^= (i & w) >> 1;
+        i = i.wrapping_mul(1 | p >> 27);
+        i = i.wrapping_mul(0x6935fa69);
+        i ^= (i & w) >> 11;
+        i = i.wrapping_mul(0x74dcb303);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0x9e501cc3);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0xc860a3df);
+        i &= w;
+        i ^= i >> 5;
+
+        if i < l {
+            break;
+        }
+    }
+
+    (i.wrapping_add(p)) % l
+}
+
+/// mirror the base-b digits of a around the radix point
+pub fn radical_inverse(base_index: usize, a: u64) -> f64 {
+    let base = primes()[base_index];
+    let inv_base = 1.0 / base as f64;
+    let mut inv_base_m = 1.0;
+    let mut reversed_digits = 0u64;
+    let mut a = a;
+
+    while a != 0 {
+        let next = a / base;
+        let digit = a - next * base;
+        reversed_digits = reversed_digits * base + digit;
+        inv_base_m *= inv_base;
+        a = next;
+    }
+
+    (reversed_digits as f64 * inv_base_m).min(ONE_MINUS_EPSILON)
+}
+
+/// the index whose first n_digits base-b digits are reversed to give `inverse`
+pub fn inverse_radical_inverse(inverse: u64, base: u64, n_digits: u32) -> u64 {
+    let mut inverse = inverse;
+    let mut index = 0;
+    for _ in 0..n_digits {
+        let digit = inverse % base;
+        inverse /= base;
+        index = index * base + digit;
+    }
+    index
+}
+
+/// a random permutation of the digits for every digit position of a base
+pub struct DigitPermutation {
+    base: u64,
+    n_digits: usize,
+    permutations: Vec<u16>,
+}
+
+impl DigitPermutation {
+    pub fn new(base: u64, seed: u64) -> Self {
+        // the number of digits that still change a double precision value
+        let inv_base = 1.0 / base as f64;
+        let mut inv_base_m = 1.0;
+        let mut n_digits = 0;
+        while 1.0 - (base - 1) as f64 * inv_base_m < 1.0 {
+            n_digits += 1;
+            inv_base_m *= inv_base;
+        }
+
+        let mut permutations = Vec::with_capacity(n_digits * base as usize);
+        for digit_index in 0..n_digits {
+            let digit_seed = hash(&[base, digit_index as u64, seed]) as u32;
+            for digit in 0..base {
+                permutations.push(permutation_element(digit as u32, base as u32, digit_seed) as u16);
+            }
+        }
+
+        DigitPermutation {
+            base,
+            n_digits,
+            permutations,
+        }
+    }
+
+    fn permute(&self, digit_index: usize, digit: u64) -> u64 {
+        self.permutations[digit_index * self.base as usize + digit as usize] as u64
+    }
+}
+
+/// the radical inverse with every digit (including the trailing zeros) permuted
+pub fn scrambled_radical_inverse(a: u64, perm: &DigitPermutation) -> f64 {
+    let base = perm.base;
+    let inv_base = 1.0 / base as f64;
+    let mut inv_base_m = 1.0;
+    let mut reversed_digits = 0u64;
+    let mut a = a;
+
+    for digit_index in 0..perm.n_digits {
+        let next = a / base;
+        let digit = a - next * base;
+        reversed_digits = reversed_digits * base + perm.permute(digit_index, digit);
+        inv_base_m *= inv_base;
+        a = next;
+    }
+
+    (reversed_digits as f64 * inv_base_m).min(ONE_MINUS_EPSILON)
+}
+
+/// generator matrices of the first two sobol dimensions, column i is applied for bit i of the index
+fn sobol_matrices() -> &'static [[u32; 32]; 2] {
+    static MATRICES: OnceLock<[[u32; 32]; 2]> = OnceLock::new();
+    MATRICES.get_or_init(|| {
+        let mut matrices = [[0u32; 32]; 2];
+        // the first dimension is the van der corput sequence
+        for (i, column) in matrices[0].iter_mut().enumerate() {
+            *column = 1 << (31 - i);
+        }
+        // the second dimension uses the primitive polynomial x + 1
+        matrices[1][0] = 1 << 31;
+        for i in 1..32 {
+            let prev = matrices[1][i - 1];
+            matrices[1][i] = prev ^ (prev >> 1);
+        }
+        matrices
+    })
+}
+
+/// the a-th point of the (0, 2)-sequence formed by the first two sobol dimensions, as 32-bit fixed point
+pub fn sobol_sample(a: u32, dimension: usize) -> u32 {
+    let matrix = &sobol_matrices()[dimension];
+    let mut v = 0;
+    let mut a = a;
+    let mut i = 0;
+    while a != 0 {
+        if a & 1 == 1 {
+            v ^= matrix[i];
+        }
+        a >>= 1;
+        i += 1;
+    }
+    v
+}
+
+/// nested uniform (Owen) scrambling of a 32-bit fixed point value, every bit is flipped
+/// depending on a hash of the bits above it
+pub fn owen_scramble(v: u32, seed: u32) -> u32 {
+    let mut v = v;
+    if seed & 1 == 1 {
+        v ^= 1 << 31;
+    }
+
+    for b in 1..32 {
+        let mask = !0u32 << (32 - b);
+        if (mix_bits(((v & mask) ^ seed) as u64) as u32) & (1 << b) != 0 {
+            v ^= 1 << (31 - b);
+        }
+    }
+
+    v
+}
+
+pub fn fixed_point_to_f64(v: u32) -> f64 {
+    (v as f64 * f64::powi(2.0, -32)).min(ONE_MINUS_EPSILON)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_permutation_element() {
+        for l in [1, 2, 3, 7, 16, 100] {
+            let mut seen = vec![false; l as usize];
+            for i in 0..l {
+                let e = permutation_element(i, l, 0xdeadbeef);
+                assert!(!seen[e as usize]);
+                seen[e as usize] = true;
+            }
+        }
+    }
+
+    #[test]
+    fn test_radical_inverse() {
+        // base 2 and base 3
+        assert_eq!(radical_inverse(0, 1), 0.5);
+        assert_eq!(radical_inverse(0, 6), 0.375);
+        assert!((radical_inverse(1, 5) - 7.0 / 9.0).abs() < 1e-12);
+
+        for a in 0..81 {
+            let digits = inverse_radical_inverse((radical_inverse(1, a) * 81.0).round() as u64, 3, 4);
+            assert_eq!(digits, a);
+        }
+    }
+
+    #[test]
+    fn test_scrambled_radical_inverse_stratified() {
+        // the first base^k points still fall into distinct intervals of size base^-k
+        let perm = DigitPermutation::new(3, 17);
+        let mut seen = [false; 27];
+        for a in 0..27 {
+            let v = scrambled_radical_inverse(a, &perm);
+            let cell = (v * 27.0) as usize;
+            assert!(!seen[cell]);
+            seen[cell] = true;
+        }
+    }
+
+    #[test]
+    fn test_sobol_elementary_intervals() {
+        // every 2^4 x 2^4 / 256 elementary interval holds exactly one of the first 256 scrambled points
+        let seeds = (0x1234567, 0x89abcdef);
+        for log_x in 0..=8 {
+            let log_y = 8 - log_x;
+            let mut seen = vec![false; 256];
+            for a in 0..256 {
+                let x = fixed_point_to_f64(owen_scramble(sobol_sample(a, 0), seeds.0));
+                let y = fixed_point_to_f64(owen_scramble(sobol_sample(a, 1), seeds.1));
+                let cell = ((x * (1 << log_x) as f64) as usize) << log_y | (y * (1 << log_y) as f64) as usize;
+                assert!(!seen[cell]);
+                seen[cell] = true;
+            }
+        }
+    }
+}
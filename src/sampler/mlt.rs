@@ -0,0 +1,181 @@
+use std::f64::consts::SQRT_2;
+
+use cgmath::Point2;
+
+use crate::utils::erf_inv;
+
+use super::{Sampler, rng::Rng};
+
+/// a coordinate of the primary sample space, with the value it had before the current mutation
+#[derive(Clone, Default)]
+struct PrimarySample {
+    value: f64,
+    last_modification: usize, // the iteration it was last mutated at
+    value_backup: f64,
+    modification_backup: usize,
+}
+
+impl PrimarySample {
+    fn backup(&mut self) {
+        self.value_backup = self.value;
+        self.modification_backup = self.last_modification;
+    }
+
+    fn restore(&mut self) {
+        self.value = self.value_backup;
+        self.last_modification = self.modification_backup;
+    }
+}
+
+/// the sampler of a markov chain in the primary sample space, every iteration mutates the samples of the
+/// previous one, either by a small gaussian step or by drawing them anew (a large step)
+///
+/// the samples are created and mutated lazily, when they are first requested in an iteration
+#[derive(Clone)]
+pub struct MltSampler {
+    rng: Rng,
+    sigma: f64, // the standard deviation of the small steps
+    large_step_probability: f64,
+    x: Vec<PrimarySample>,
+    current_iteration: usize,
+    large_step: bool,
+    last_large_step_iteration: usize,
+    sample_index: usize, // the next dimension
+}
+
+impl MltSampler {
+    /// the first iteration is a large step, it is the same for the same sequence_index
+    pub fn new(sequence_index: u64, sigma: f64, large_step_probability: f64) -> Self {
+        let mut rng = Rng::new();
+        rng.set_sequence(sequence_index, 0);
+
+        MltSampler {
+            rng,
+            sigma,
+            large_step_probability,
+            x: Vec::new(),
+            current_iteration: 0,
+            large_step: true,
+            last_large_step_iteration: 0,
+            sample_index: 0,
+        }
+    }
+
+    /// start proposing a mutation of the current samples
+    pub fn start_iteration(&mut self) {
+        self.current_iteration += 1;
+        self.large_step = self.rng.uniform_f64() < self.large_step_probability;
+        self.sample_index = 0;
+    }
+
+    /// the proposed samples become the current ones
+    pub fn accept(&mut self) {
+        if self.large_step {
+            self.last_large_step_iteration = self.current_iteration;
+        }
+    }
+
+    /// go back to the samples before the proposal
+    pub fn reject(&mut self) {
+        for xi in self.x.iter_mut() {
+            if xi.last_modification == self.current_iteration {
+                xi.restore();
+            }
+        }
+        self.current_iteration -= 1;
+    }
+
+    // bring the sample up to date with the current iteration
+    fn ensure_ready(&mut self, index: usize) {
+        if index >= self.x.len() {
+            self.x.resize(index + 1, PrimarySample::default());
+        }
+        let xi = &mut self.x[index];
+
+        // a sample not used since the last accepted large step has been drawn anew by it
+        if xi.last_modification < self.last_large_step_iteration {
+            xi.value = self.rng.uniform_f64();
+            xi.last_modification = self.last_large_step_iteration;
+        }
+
+        xi.backup();
+        if self.large_step {
+            xi.value = self.rng.uniform_f64();
+        } else {
+            // the small steps missed since its last use add up to a wider gaussian
+            let n_small = (self.current_iteration - xi.last_modification) as f64;
+            let normal = SQRT_2 * erf_inv(2.0 * self.rng.uniform_f64() - 1.0);
+            xi.value += normal * self.sigma * n_small.sqrt();
+            xi.value -= xi.value.floor();
+        }
+        xi.last_modification = self.current_iteration;
+    }
+}
+
+impl Sampler for MltSampler {
+    // the chain takes as many samples as its iterations
+    fn samples_per_pixel(&self) -> usize {
+        1
+    }
+
+    fn start_pixel_sample(&mut self, _p: Point2<usize>, _sample_index: usize) {
+        self.sample_index = 0;
+    }
+
+    fn get_1d(&mut self) -> f64 {
+        let index = self.sample_index;
+        self.sample_index += 1;
+        self.ensure_ready(index);
+        self.x[index].value
+    }
+
+    fn get_2d(&mut self) -> Point2<f64> {
+        Point2::new(self.get_1d(), self.get_1d())
+    }
+
+    fn clone_box(&self) -> Box<dyn Sampler> {
+        Box::new(self.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn draw(sampler: &mut MltSampler, n: usize) -> Vec<f64> {
+        (0..n).map(|_| sampler.get_1d()).collect()
+    }
+
+    #[test]
+    fn test_mlt_sampler() {
+        // the first iteration only depends on the sequence
+        let mut sampler = MltSampler::new(5, 0.01, 0.0);
+        let initial = draw(&mut sampler, 4);
+        assert_eq!(initial, draw(&mut MltSampler::new(5, 0.01, 0.0), 4));
+
+        // the small steps stay close, a rejected one goes back to the previous samples
+        sampler.start_iteration();
+        let mutated = draw(&mut sampler, 4);
+        for (a, b) in initial.iter().zip(mutated.iter()) {
+            let d = (a - b).abs();
+            assert!(a != b && d.min(1.0 - d) < 0.1);
+        }
+        sampler.reject();
+        assert!(sampler.x.iter().zip(initial.iter()).all(|(xi, &v)| xi.value == v));
+
+        // the samples first requested after an accepted large step are drawn anew
+        let mut sampler = MltSampler::new(5, 0.01, 1.0);
+        let initial = draw(&mut sampler, 2);
+        sampler.start_iteration();
+        let mutated = draw(&mut sampler, 1);
+        sampler.accept();
+        assert!(mutated[0] != initial[0]);
+        for _ in 0..3 {
+            sampler.start_iteration();
+            sampler.accept();
+        }
+        sampler.start_iteration();
+        let late = draw(&mut sampler, 2);
+        assert!(late.iter().all(|v| (0.0..1.0).contains(v)) && late[1] != initial[1]);
+    }
+}
@@ -1,8 +1,25 @@
 use cgmath::Point2;
 
 pub mod wrs;
+pub mod rng;
+pub mod lowdiscrepancy;
 pub mod uniform_sampler;
+pub mod stratified;
+pub mod halton;
+pub mod sobol;
+pub mod mlt;
+pub mod distribution;
 
+/// samplers are stateful, every render thread owns its own copy created by clone_box
 pub trait Sampler : Sync + Send {
-    fn get_2d(&self) -> Point2<f64>;
-}
\ No newline at end of file
+    fn samples_per_pixel(&self) -> usize;
+    /// restart at the first dimension of the sample_index-th sample of pixel p
+    fn start_pixel_sample(&mut self, p: Point2<usize>, sample_index: usize);
+    fn get_1d(&mut self) -> f64;
+    fn get_2d(&mut self) -> Point2<f64>;
+    /// the position of the film sample inside the pixel, requested before any other dimension
+    fn get_pixel_2d(&mut self) -> Point2<f64> {
+        self.get_2d()
+    }
+    fn clone_box(&self) -> Box<dyn Sampler>;
+}
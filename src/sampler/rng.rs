@@ -0,0 +1,94 @@
+const PCG32_DEFAULT_STATE: u64 = 0x853c49e6748fea9b;
+const PCG32_DEFAULT_STREAM: u64 = 0xda3e39cb94b95bdb;
+const PCG32_MULT: u64 = 0x5851f42d4c957f2d;
+
+pub const ONE_MINUS_EPSILON: f64 = 1.0 - f64::EPSILON / 2.0;
+
+/// the pcg32 generator used by pbrt, supports independent streams and skipping ahead
+#[derive(Clone)]
+pub struct Rng {
+    state: u64,
+    inc: u64,
+}
+
+impl Rng {
+    pub fn new() -> Self {
+        Rng {
+            state: PCG32_DEFAULT_STATE,
+            inc: PCG32_DEFAULT_STREAM,
+        }
+    }
+
+    pub fn set_sequence(&mut self, sequence_index: u64, seed: u64) {
+        self.state = 0;
+        self.inc = (sequence_index << 1) | 1;
+        self.uniform_u32();
+        self.state = self.state.wrapping_add(seed);
+        self.uniform_u32();
+    }
+
+    pub fn uniform_u32(&mut self) -> u32 {
+        let old_state = self.state;
+        self.state = old_state.wrapping_mul(PCG32_MULT).wrapping_add(self.inc);
+        let xor_shifted = (((old_state >> 18) ^ old_state) >> 27) as u32;
+        let rot = (old_state >> 59) as u32;
+        xor_shifted.rotate_right(rot)
+    }
+
+    /// a uniformly distributed value in [0, 1)
+    pub fn uniform_f64(&mut self) -> f64 {
+        let hi = self.uniform_u32() as u64;
+        let lo = self.uniform_u32() as u64;
+        (((hi << 32) | lo) as f64 * f64::powi(2.0, -64)).min(ONE_MINUS_EPSILON)
+    }
+
+    /// skip the next delta values of the stream in O(log(delta)) time
+    pub fn advance(&mut self, delta: u64) {
+        let (mut cur_mult, mut cur_plus) = (PCG32_MULT, self.inc);
+        let (mut acc_mult, mut acc_plus) = (1u64, 0u64);
+        let mut delta = delta;
+
+        while delta > 0 {
+            if delta & 1 == 1 {
+                acc_mult = acc_mult.wrapping_mul(cur_mult);
+                acc_plus = acc_plus.wrapping_mul(cur_mult).wrapping_add(cur_plus);
+            }
+            cur_plus = cur_mult.wrapping_add(1).wrapping_mul(cur_plus);
+            cur_mult = cur_mult.wrapping_mul(cur_mult);
+            delta /= 2;
+        }
+
+        self.state = acc_mult.wrapping_mul(self.state).wrapping_add(acc_plus);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_advance() {
+        let mut a = Rng::new();
+        a.set_sequence(7, 42);
+        let mut b = a.clone();
+        for _ in 0..1000 {
+            a.uniform_u32();
+        }
+        b.advance(1000);
+        assert_eq!(a.uniform_u32(), b.uniform_u32());
+    }
+
+    #[test]
+    fn test_uniform_f64() {
+        let mut rng = Rng::new();
+        rng.set_sequence(0, 0);
+        let n = 100000;
+        let mut sum = 0.0;
+        for _ in 0..n {
+            let u = rng.uniform_f64();
+            assert!((0.0..1.0).contains(&u));
+            sum += u;
+        }
+        assert!((sum / n as f64 - 0.5).abs() < 0.01);
+    }
+}
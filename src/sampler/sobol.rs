@@ -0,0 +1,71 @@
+use cgmath::Point2;
+
+use super::{Sampler, lowdiscrepancy::{hash, permutation_element, sobol_sample, owen_scramble, fixed_point_to_f64}};
+
+/// Owen scrambled sobol points, padded over the dimensions.
+///
+/// every pair of dimensions takes the first two dimensions of the sobol sequence with its own
+/// scrambling and its own shuffled order of the pixel's samples, so only the number of
+/// samples per pixel (a power of two) limits the stratification
+#[derive(Clone)]
+pub struct SobolSampler {
+    samples_per_pixel: usize,
+    seed: u64,
+    pixel: Point2<usize>,
+    sample_index: usize,
+    dimension: usize,
+}
+
+impl SobolSampler {
+    pub fn new(samples_per_pixel: usize, seed: u64) -> Self {
+        SobolSampler {
+            samples_per_pixel: samples_per_pixel.max(1).next_power_of_two(),
+            seed,
+            pixel: Point2::new(0, 0),
+            sample_index: 0,
+            dimension: 0,
+        }
+    }
+
+    fn dimension_hash(&self) -> u64 {
+        hash(&[self.pixel.x as u64, self.pixel.y as u64, self.dimension as u64, self.seed])
+    }
+}
+
+impl Sampler for SobolSampler {
+    fn samples_per_pixel(&self) -> usize {
+        self.samples_per_pixel
+    }
+
+    fn start_pixel_sample(&mut self, p: Point2<usize>, sample_index: usize) {
+        self.pixel = p;
+        self.sample_index = sample_index;
+        self.dimension = 0;
+    }
+
+    fn get_1d(&mut self) -> f64 {
+        let dim_hash = self.dimension_hash();
+        let index = permutation_element(self.sample_index as u32, self.samples_per_pixel as u32, dim_hash as u32);
+        self.dimension += 1;
+
+        fixed_point_to_f64(owen_scramble(sobol_sample(index, 0), (dim_hash >> 32) as u32))
+    }
+
+    fn get_2d(&mut self) -> Point2<f64> {
+        let dim_hash = self.dimension_hash();
+        let index = permutation_element(self.sample_index as u32, self.samples_per_pixel as u32, dim_hash as u32);
+        self.dimension += 2;
+
+        // derive a second seed for y, the lower half of the hash already shuffles the index
+        let seed_y = hash(&[dim_hash]) as u32;
+
+        Point2::new(
+            fixed_point_to_f64(owen_scramble(sobol_sample(index, 0), (dim_hash >> 32) as u32)),
+            fixed_point_to_f64(owen_scramble(sobol_sample(index, 1), seed_y)),
+        )
+    }
+
+    fn clone_box(&self) -> Box<dyn Sampler> {
+        Box::new(self.clone())
+    }
+}
@@ -0,0 +1,87 @@
+use cgmath::Point2;
+
+use super::{Sampler, rng::Rng, lowdiscrepancy::{hash, permutation_element}};
+
+/// jittered stratified sampling, each dimension of a pixel's samples covers an x_samples * y_samples grid once.
+///
+/// the strata are visited in a different random order for every pixel and dimension, so that
+/// the dimensions are not correlated with each other
+#[derive(Clone)]
+pub struct StratifiedSampler {
+    x_samples: usize,
+    y_samples: usize,
+    jitter: bool,
+    seed: u64,
+    rng: Rng,
+    pixel: Point2<usize>,
+    sample_index: usize,
+    dimension: usize,
+}
+
+impl StratifiedSampler {
+    pub fn new(x_samples: usize, y_samples: usize, jitter: bool, seed: u64) -> Self {
+        StratifiedSampler {
+            x_samples: x_samples.max(1),
+            y_samples: y_samples.max(1),
+            jitter,
+            seed,
+            rng: Rng::new(),
+            pixel: Point2::new(0, 0),
+            sample_index: 0,
+            dimension: 0,
+        }
+    }
+
+    /// the index of the stratum the current sample falls in for the current dimension
+    fn stratum(&self) -> u32 {
+        let hash = hash(&[self.pixel.x as u64, self.pixel.y as u64, self.dimension as u64, self.seed]);
+        permutation_element(self.sample_index as u32, self.samples_per_pixel() as u32, hash as u32)
+    }
+
+    fn jitter(&mut self) -> f64 {
+        if self.jitter {
+            self.rng.uniform_f64()
+        } else {
+            0.5
+        }
+    }
+}
+
+impl Sampler for StratifiedSampler {
+    fn samples_per_pixel(&self) -> usize {
+        self.x_samples * self.y_samples
+    }
+
+    fn start_pixel_sample(&mut self, p: Point2<usize>, sample_index: usize) {
+        self.pixel = p;
+        self.sample_index = sample_index;
+        self.dimension = 0;
+        // every sample gets its own part of the pixel's random stream
+        self.rng.set_sequence(hash(&[p.x as u64, p.y as u64, self.seed]), 0);
+        self.rng.advance(sample_index as u64 * 65536);
+    }
+
+    fn get_1d(&mut self) -> f64 {
+        let stratum = self.stratum();
+        self.dimension += 1;
+
+        (stratum as f64 + self.jitter()) / self.samples_per_pixel() as f64
+    }
+
+    fn get_2d(&mut self) -> Point2<f64> {
+        let stratum = self.stratum() as usize;
+        self.dimension += 2;
+
+        let (x, y) = (stratum % self.x_samples, stratum / self.x_samples);
+        let (dx, dy) = (self.jitter(), self.jitter());
+
+        Point2::new(
+            (x as f64 + dx) / self.x_samples as f64,
+            (y as f64 + dy) / self.y_samples as f64,
+        )
+    }
+
+    fn clone_box(&self) -> Box<dyn Sampler> {
+        Box::new(self.clone())
+    }
+}
@@ -1,21 +1,44 @@
 use cgmath::Point2;
-use rand::random;
 
-use super::Sampler;
+use super::{Sampler, rng::Rng, lowdiscrepancy::hash};
 
+/// independent uniform random samples, drawn from a stream determined by the pixel, the sample index and the seed
+#[derive(Clone)]
 pub struct UniformSampler {
+    samples_per_pixel: usize,
+    seed: u64,
+    rng: Rng,
 }
 
 impl UniformSampler {
-    pub fn new() -> Self {
-        Self {}
+    pub fn new(samples_per_pixel: usize, seed: u64) -> Self {
+        Self {
+            samples_per_pixel,
+            seed,
+            rng: Rng::new(),
+        }
     }
-
-
 }
 
 impl Sampler for UniformSampler {
-    fn get_2d(&self) -> Point2<f64> {
-        Point2::new(random::<f64>(), random::<f64>())
+    fn samples_per_pixel(&self) -> usize {
+        self.samples_per_pixel
+    }
+
+    fn start_pixel_sample(&mut self, p: Point2<usize>, sample_index: usize) {
+        self.rng.set_sequence(hash(&[p.x as u64, p.y as u64, self.seed]), 0);
+        self.rng.advance(sample_index as u64 * 65536);
+    }
+
+    fn get_1d(&mut self) -> f64 {
+        self.rng.uniform_f64()
     }
-}
\ No newline at end of file
+
+    fn get_2d(&mut self) -> Point2<f64> {
+        Point2::new(self.rng.uniform_f64(), self.rng.uniform_f64())
+    }
+
+    fn clone_box(&self) -> Box<dyn Sampler> {
+        Box::new(self.clone())
+    }
+}
@@ -1,10 +1,11 @@
-use rand::random;
-
-/// a reservoir with only one output
+/// a reservoir with only one output, picked among a stream of candidates with the probability of its
+/// weight over the sum of the weights
+#[derive(Clone)]
 pub struct Reservoir<T: Clone> {
     y: Option<T>, // the output sample
     pub weight_sum: f64, // sum of the weights
     pub m: u32,  // candidates count
+    pub w: f64, // the unbiased contribution weight of the output, set by the owner once the stream ends
 }
 
 impl<T: Clone> Reservoir<T> {
@@ -13,21 +14,78 @@ impl<T: Clone> Reservoir<T> {
             y: None,
             weight_sum: 0.0,
             m: 0,
+            w: 0.0,
         }
     }
 
-    pub fn update(&mut self, x: T, w: f64) {
-        self.weight_sum += w; 
+    /// u is a uniform random number deciding whether x replaces the current output
+    pub fn update(&mut self, x: T, w: f64, u: f64) {
+        self.weight_sum += w;
         self.m += 1;
-        if self.y.is_none() || random::<f64>() < w / self.weight_sum {
+        if self.y.is_none() || u < w / self.weight_sum {
             // update
             self.y = Some(x);
         }
-   }
+    }
+
+    /// stream the output of another reservoir, whose candidates are counted with the ones of this one,
+    /// mis is the weight of the other reservoir among the ones merged and p_hat the target pdf of its
+    /// output for this reservoir
+    pub fn merge(&mut self, other: Reservoir<T>, mis: f64, p_hat: f64, u: f64) {
+        let m = self.m + other.m;
+        if let Some(y) = other.y {
+            self.update(y, mis * p_hat * other.w, u);
+        }
+        self.m = m;
+    }
+
+    /// None if the reservoir is empty
+    pub fn sample(&self) -> Option<&T> {
+        self.y.as_ref()
+    }
+}
+
+impl<T: Clone> Default for Reservoir<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sampler::rng::Rng;
+
+    #[test]
+    fn test_reservoir_sampling() {
+        let weights = [1.0, 2.0, 3.0, 4.0];
+        let mut rng = Rng::new();
+        let mut counts = [0; 4];
+
+        let n = 40000;
+        for _ in 0..n {
+            let mut reservoir = Reservoir::new();
+            for (i, &w) in weights.iter().enumerate() {
+                reservoir.update(i, w, rng.uniform_f64());
+            }
+            assert_eq!(reservoir.m, 4);
+            counts[*reservoir.sample().unwrap()] += 1;
+        }
+
+        // every candidate is kept with the probability of its weight over the total
+        for (count, w) in counts.iter().zip(weights.iter()) {
+            let frequency = *count as f64 / n as f64;
+            assert!((frequency - w / 10.0).abs() < 0.01, "frequency {} weight {}", frequency, w);
+        }
 
-    /// if the reservoir is empty, panic
-    pub fn output_sample(&self) -> T {
-        assert!(self.y.is_some());
-        self.y.clone().unwrap()
+        // a merged reservoir counts all the candidates of the other one
+        let mut other = Reservoir::new();
+        other.update(7, 1.0, 0.5);
+        other.update(8, 1.0, 0.5);
+        other.w = 0.5;
+        let mut reservoir = Reservoir::new();
+        reservoir.merge(other, 0.5, 2.0, 0.5);
+        assert_eq!((reservoir.m, reservoir.weight_sum), (2, 0.5));
+        assert!(reservoir.sample().is_some());
     }
-}
\ No newline at end of file
+}
@@ -8,7 +8,7 @@ use std::f64::INFINITY;
 // use crate::geometry::shape::disk::Disk;
 // use crate::mesh::TriangleMesh;
 // // use crate::geometry::shape::sphere::Sphere;
-use crate::light::LightList;
+use crate::light::{Light, LightList, light_sampler::LightSamplerType};
 // use crate::light::area::AreaLight;
 // use crate::material::glass::Glass;
 // use crate::material::matte::Matte;
@@ -19,8 +19,14 @@ use crate::light::LightList;
 // use crate::texture::mapping::spherical::SphericalMapping;
 // use crate::{light::Light, geometry::ray::Ray, spectrum::Spectrum};
 use crate::geometry::ray::Ray;
+use crate::spectrum::Spectrum;
+use cgmath::{Point3, Vector3};
+use std::sync::Arc;
 // use crate::Transform;
 use crate::primitive::Primitive;
+use crate::geometry::interaction::SurfaceInteraction;
+use crate::medium::Medium;
+use crate::sampler::Sampler;
 // use crate::primitive::geometric_primitive::GeometricPrimitive;
 // use cgmath::{Vector3, Point2};
 
@@ -33,7 +39,14 @@ pub struct Scene {
 
 #[allow(dead_code)]
 impl Scene {
-    pub fn new(lightlist: LightList, aggregate: Box<dyn Primitive>) -> Self {
+    pub fn new(lights: Vec<Arc<dyn Light>>, light_sampler: LightSamplerType, aggregate: Box<dyn Primitive>) -> Self {
+        // the lights are preprocessed before the light sampler evaluates their power
+        let bound = aggregate.world_bound();
+        for light in lights.iter() {
+            light.preprocess(&bound);
+        }
+        let lightlist = LightList::new(lights, light_sampler);
+
         Scene {
             lightlist,
             aggregate,
@@ -51,9 +64,94 @@ impl Scene {
             }
         }
 
+        // the footprint is only needed at the closest hit
+        if let Some(isect) = &mut ret {
+            isect.compute_differentials(r);
+        }
+
         ret
     }
 
+    /// intersect the ray with the scene, passing through the surfaces that only bound media
+    ///
+    /// returns the first surface that scatters or emits light along with the transmittance of the
+    /// media crossed to reach it, the ray is left in the medium the surface is hit from
+    pub fn intersect_tr(&self, r: &mut Ray, sampler: &mut dyn Sampler) -> (Option<SurfaceInteraction>, Spectrum) {
+        let mut tr = Spectrum::new(1.0, 1.0, 1.0);
+        loop {
+            let isect = self.intersect(r);
+            if let Some(medium) = &r.medium {
+                tr *= medium.tr(r, sampler);
+            }
+
+            match isect {
+                Some(isect) if isect.material.is_none() && !isect.hit_light => {
+                    let medium = isect.get_medium(r.d, &r.medium);
+                    *r = Ray::new_in_medium(isect.geo.p, r.d, r.time, f64::INFINITY, medium);
+                }
+                isect => return (isect, tr),
+            }
+        }
+    }
+
+    /// intersect the ray with the scene, passing through the surfaces that only bound media, for the
+    /// integrators that ignore the media
+    pub fn intersect_surface(&self, r: &mut Ray) -> Option<SurfaceInteraction> {
+        loop {
+            match self.intersect(r) {
+                Some(isect) if isect.material.is_none() && !isect.hit_light => {
+                    *r = Ray::new(isect.geo.p, r.d, r.time, f64::INFINITY);
+                }
+                isect => return isect,
+            }
+        }
+    }
+
+    /// the transmittance between p0 and p1, black if a surface that is not a medium boundary lies in between
+    ///
+    /// medium is the medium around p0
+    pub fn transmittance(&self, p0: Point3<f64>, p1: Point3<f64>, medium: Option<Arc<dyn Medium>>, time: f64, sampler: &mut dyn Sampler) -> Spectrum {
+        let mut r = Ray::new_in_medium(p0, p1 - p0, time, 1.0 - 0.0001, medium);
+        let mut tr = Spectrum::new(1.0, 1.0, 1.0);
+        loop {
+            let isect = self.intersect(&mut r);
+            if let Some(isect) = &isect {
+                if isect.material.is_some() || isect.hit_light {
+                    return Spectrum::black();
+                }
+            }
+
+            if let Some(medium) = &r.medium {
+                tr *= medium.tr(&r, sampler);
+            }
+
+            match isect {
+                Some(isect) => {
+                    let medium = isect.get_medium(r.d, &r.medium);
+                    r = Ray::new_in_medium(isect.geo.p, p1 - isect.geo.p, time, 1.0 - 0.0001, medium);
+                }
+                None => return tr,
+            }
+        }
+    }
+
+    /// the radiance carried by a ray that leaves the scene
+    pub fn le_escaped(&self, r: &Ray) -> Spectrum {
+        let mut le = Spectrum::black();
+        for light in self.lightlist.infinite_lights.iter() {
+            le += light.le_escaped(r);
+        }
+        le
+    }
+
+    /// the solid angle pdf of the direction wi being sampled from the point p with normal n
+    /// through the infinite lights, including the probability of picking them
+    pub fn pdf_escaped(&self, p: Point3<f64>, n: Vector3<f64>, wi: Vector3<f64>) -> f64 {
+        self.lightlist.infinite_lights.iter()
+            .map(|light| self.lightlist.pmf(p, n, light) * light.pdf_escaped(wi))
+            .sum()
+    }
+
     pub fn intersect_p(&self, r: &Ray) -> Option<f64> {
         let mut t = INFINITY;
 
@@ -625,83 +723,4 @@ impl Scene {
 
     //     (camera, scene)
     // }
-
-//     pub fn test_layerd_diffuse() -> (PerspectiveCamera, Scene) {
-//         let mut primitives: Vec<Box<dyn Primitive>> = Vec::new();
-//         let mut lights: Vec<Arc<dyn Light>> = Vec::new();
-
-//         let object_to_world = Transform::translate(Vector3::new(10.0, 0.0, 0.0)) * Transform::rotate_y(90.0);
-//         let world_to_object = object_to_world.inverse();
-//         let right_wall = Disk::new(object_to_world, world_to_object, 150.0);
-//         let matte_material = Matte::new(Box::new(ConstantTexture::new(Spectrum::new(0.65, 0.05, 0.05))));
-//         let right_wall = GeometricPrimitive::new(Box::new(right_wall), Arc::new(matte_material));
-//         primitives.push(Box::new(right_wall));
-
-//         let object_to_world = Transform::translate(Vector3::new(-10.0, 0.0, 0.0)) * Transform::rotate_y(90.0);
-//         let world_to_object = object_to_world.inverse();
-//         let left_wall = Disk::new(object_to_world, world_to_object, 150.0);
-//         let matte_material = Matte::new(Box::new(ConstantTexture::new(Spectrum::new(0.12, 0.45, 0.15))));
-//         let left_wall = GeometricPrimitive::new(Box::new(left_wall), Arc::new(matte_material));
-//         primitives.push(Box::new(left_wall));
-
-//         let object_to_world = Transform::translate(Vector3::new(0.0, 0.0, 30.0));
-//         let world_to_object = object_to_world.inverse();
-//         let back_wall = Disk::new(object_to_world, world_to_object, 150.0);
-//         let matte_material = Matte::new(Box::new(ConstantTexture::new(Spectrum::new(0.73, 0.73, 0.73))));
-//         let back_wall = GeometricPrimitive::new(Box::new(back_wall), Arc::new(matte_material));
-//         primitives.push(Box::new(back_wall));
-
-//         let object_to_world = Transform::translate(Vector3::new(0.0, 10.0, 0.0)) * Transform::rotate_x(90.0);
-//         let world_to_object = object_to_world.inverse();
-//         let upper_wall = Disk::new(object_to_world, world_to_object, 150.0);
-//         let matte_material = Matte::new(Box::new(ConstantTexture::new(Spectrum::new(0.73, 0.73, 0.73))));
-//         let upper_wall = GeometricPrimitive::new(Box::new(upper_wall), Arc::new(matte_material));
-//         primitives.push(Box::new(upper_wall));
-
-//         let object_to_world = Transform::translate(Vector3::new(0.0, -10.0, 0.0)) * Transform::rotate_x(90.0);
-//         let world_to_object = object_to_world.inverse();
-//         let bot_wall = Disk::new(object_to_world, world_to_object, 150.0);
-//         let matte_material = LayeredDiffuse::new(0.0, Spectrum::new(1.0, 1.0, 1.0), Spectrum::new(0.2, 0.3, 0.6));
-//         let bot_wall = GeometricPrimitive::new(Box::new(bot_wall), Arc::new(matte_material));
-//         primitives.push(Box::new(bot_wall));
-
-
-//         let object_to_world = Transform::translate(Vector3::new(0.0, -6.0, 20.0));
-//         let world_to_object = object_to_world.inverse();
-//         let sphere = Sphere::new(object_to_world, world_to_object, 3.0);
-//         let metal_material = Matte::new(Box::new(ConstantTexture::new(Spectrum::new(0.6, 0.4, 0.5))));
-//         let ball = GeometricPrimitive::new(Box::new(sphere), Arc::new(metal_material));
-//         primitives.push(Box::new(ball));
-
-//         let object_to_world4 = Transform::translate(Vector3::new(0.0, 9.9999, 20.0)) * Transform::rotate_x(90.0);
-//         let world_to_object4 = object_to_world4.inverse();
-//         let disk_light = Disk::new(object_to_world4, world_to_object4, 3.0);
-//         let disk_light = AreaLight::new(Box::new(disk_light), Spectrum::new(10.0, 10.0, 10.0));
-//         lights.push(Arc::new(disk_light));
-
-//         let bvh = BVH::new(primitives);
-//         let scene = Scene::new(LightList::new(lights), Box::new(bvh));
-
-//         // camera
-//         const WIDTH: usize = 500;
-//         const HEIGHT: usize = 500;
-//         const FRAME: f64 = WIDTH as f64 / HEIGHT as f64;
-
-//         let pos = Vector3::new(0.0, 0.0, 0.0);
-//         let look = Vector3::new(0.0, 0.0, 1.0);
-//         let up = Vector3::new(0.0, 1.0, 0.0);
-//         let camera_to_world = Transform::look_at(pos, look, up).inverse();
-
-//         let camera = PerspectiveCamera::new(
-//             camera_to_world,
-//             (Point2::new(-FRAME, -1.0), Point2::new(FRAME, 1.0)),
-//             0.0,
-//             1.0,
-//             60.0,
-//             Film::new(WIDTH, HEIGHT),
-//         );
-
-//         (camera, scene)
-//     }
-
-}
\ No newline at end of file
+}
@@ -1,6 +1,6 @@
-use std::{ops::{Add, Sub, Mul, Div, AddAssign, MulAssign, DivAssign}, process::exit};
+use std::{ops::{Add, Sub, Mul, Div, AddAssign, MulAssign, DivAssign, Index}, process::exit};
 
-use crate::camera::pixel::Pixel;
+use crate::camera::{pixel::Pixel, tonemap::srgb_oetf};
 
 pub type Spectrum = RGBSpectrum;
 
@@ -24,13 +24,13 @@ impl RGBSpectrum {
         self.r == 0.0 && self.g == 0.0 && self.b == 0.0
     }
 
+    /// encode a tone mapped value in [0, 1] with the sRGB transfer function
     pub fn to_pixel(&self) -> Pixel {
         check_invalid(self);
-        // gamma correction 2.0
-        let r = self.r.sqrt();
-        let g = self.g.sqrt();
-        let b = self.b.sqrt();
-        
+        let r = srgb_oetf(self.r);
+        let g = srgb_oetf(self.g);
+        let b = srgb_oetf(self.b);
+
         Pixel::new(r, g, b)
     }
 
@@ -38,12 +38,21 @@ impl RGBSpectrum {
         self.r + self.g + self.b
     }
 
-    pub fn tone_mapping(&self) -> RGBSpectrum {
-        RGBSpectrum::new(
-            self.r,
-            self.g,
-            self.b,
-        )
+    pub fn average(&self) -> f64 {
+        self.sum() / 3.0
+    }
+
+    pub fn max_component(&self) -> f64 {
+        self.r.max(self.g).max(self.b)
+    }
+
+    pub fn exp(&self) -> RGBSpectrum {
+        RGBSpectrum::new(self.r.exp(), self.g.exp(), self.b.exp())
+    }
+
+    /// the Y component of the linear sRGB value
+    pub fn luminance(&self) -> f64 {
+        0.2126 * self.r + 0.7152 * self.g + 0.0722 * self.b
     }
 
     pub fn skyblue(t: f64) -> RGBSpectrum {
@@ -70,6 +79,26 @@ fn check_invalid(radiance: &Spectrum) {
     }
 }
 
+// a single value is a grey spectrum
+impl From<f64> for RGBSpectrum {
+    fn from(value: f64) -> Self {
+        RGBSpectrum::new(value, value, value)
+    }
+}
+
+impl Index<usize> for RGBSpectrum {
+    type Output = f64;
+
+    fn index(&self, i: usize) -> &f64 {
+        match i {
+            0 => &self.r,
+            1 => &self.g,
+            2 => &self.b,
+            _ => panic!("spectrum index {} out of range", i),
+        }
+    }
+}
+
 impl Add<RGBSpectrum> for RGBSpectrum {
     type Output = RGBSpectrum;
 
@@ -0,0 +1,71 @@
+use std::sync::Arc;
+
+use super::{Blend, Texture, mapping::{TextureMapping2D, TextureMapping3D}};
+
+/// alternates between tex1 and tex2 on the unit squares of st, tex1 covers the square at the origin
+pub struct Checkerboard2DTexture<T> {
+    mapping: Box<dyn TextureMapping2D>,
+    tex1: Arc<dyn Texture<T>>,
+    tex2: Arc<dyn Texture<T>>,
+}
+
+impl<T> Checkerboard2DTexture<T> {
+    pub fn new(mapping: Box<dyn TextureMapping2D>, tex1: Arc<dyn Texture<T>>, tex2: Arc<dyn Texture<T>>) -> Self {
+        Checkerboard2DTexture { mapping, tex1, tex2 }
+    }
+}
+
+// the integral of the 0/1 square wave that is 1 on the odd unit intervals
+fn bump_int(x: f64) -> f64 {
+    (x / 2.0).floor() + 2.0 * (x / 2.0 - (x / 2.0).floor() - 0.5).max(0.0)
+}
+
+impl<T: Blend> Texture<T> for Checkerboard2DTexture<T> {
+    fn evaluate(&self, isect: &crate::geometry::interaction::SurfaceInteraction) -> T {
+        let (st, dstdx, dstdy) = self.mapping.map(isect);
+        let ds = dstdx.x.abs().max(dstdy.x.abs());
+        let dt = dstdx.y.abs().max(dstdy.y.abs());
+        let (s0, s1) = (st.x - ds, st.x + ds);
+        let (t0, t1) = (st.y - dt, st.y + dt);
+
+        // the footprint is inside a single square
+        if s0.floor() == s1.floor() && t0.floor() == t1.floor() {
+            return if (st.x.floor() + st.y.floor()) as i64 % 2 == 0 {
+                self.tex1.evaluate(isect)
+            } else {
+                self.tex2.evaluate(isect)
+            };
+        }
+
+        // box filter the footprint, the fraction covered by tex2 comes from the integrals of the
+        // square waves along s and t
+        let s_int = if ds > 0.0 { (bump_int(s1) - bump_int(s0)) / (2.0 * ds) } else { ((st.x.floor() as i64) & 1) as f64 };
+        let t_int = if dt > 0.0 { (bump_int(t1) - bump_int(t0)) / (2.0 * dt) } else { ((st.y.floor() as i64) & 1) as f64 };
+        let area2 = s_int + t_int - 2.0 * s_int * t_int;
+        T::lerp(area2, self.tex1.evaluate(isect), self.tex2.evaluate(isect))
+    }
+}
+
+/// alternates between tex1 and tex2 on the unit cubes of the texture space
+pub struct Checkerboard3DTexture<T> {
+    mapping: Box<dyn TextureMapping3D>,
+    tex1: Arc<dyn Texture<T>>,
+    tex2: Arc<dyn Texture<T>>,
+}
+
+impl<T> Checkerboard3DTexture<T> {
+    pub fn new(mapping: Box<dyn TextureMapping3D>, tex1: Arc<dyn Texture<T>>, tex2: Arc<dyn Texture<T>>) -> Self {
+        Checkerboard3DTexture { mapping, tex1, tex2 }
+    }
+}
+
+impl<T: Blend> Texture<T> for Checkerboard3DTexture<T> {
+    fn evaluate(&self, isect: &crate::geometry::interaction::SurfaceInteraction) -> T {
+        let (p, _, _) = self.mapping.map(isect);
+        if (p.x.floor() + p.y.floor() + p.z.floor()) as i64 % 2 == 0 {
+            self.tex1.evaluate(isect)
+        } else {
+            self.tex2.evaluate(isect)
+        }
+    }
+}
@@ -0,0 +1,21 @@
+use super::{Blend, Texture, mapping::TextureMapping3D, noise::fbm};
+
+/// fractional Brownian motion, centered on 0
+pub struct FbmTexture {
+    mapping: Box<dyn TextureMapping3D>,
+    omega: f64,
+    octaves: usize,
+}
+
+impl FbmTexture {
+    pub fn new(mapping: Box<dyn TextureMapping3D>, omega: f64, octaves: usize) -> Self {
+        FbmTexture { mapping, omega, octaves }
+    }
+}
+
+impl<T: Blend> Texture<T> for FbmTexture {
+    fn evaluate(&self, isect: &crate::geometry::interaction::SurfaceInteraction) -> T {
+        let (p, dpdx, dpdy) = self.mapping.map(isect);
+        T::from(fbm(p, dpdx, dpdy, self.omega, self.octaves))
+    }
+}
@@ -0,0 +1,53 @@
+use std::sync::Arc;
+
+use super::{Blend, Texture, mapping::TextureMapping2D};
+
+/// lines of tex2 along the integer values of s and t over a background of tex1
+pub struct GridTexture<T> {
+    mapping: Box<dyn TextureMapping2D>,
+    tex1: Arc<dyn Texture<T>>,
+    tex2: Arc<dyn Texture<T>>,
+    line_width: f64,
+}
+
+impl<T> GridTexture<T> {
+    pub fn new(mapping: Box<dyn TextureMapping2D>, tex1: Arc<dyn Texture<T>>, tex2: Arc<dyn Texture<T>>, line_width: f64) -> Self {
+        GridTexture { mapping, tex1, tex2, line_width: line_width.clamp(0.0, 1.0) }
+    }
+
+    // the integral of the 0/1 wave that is 1 within line_width / 2 of the integers
+    fn line_int(&self, x: f64) -> f64 {
+        let half = 0.5 * self.line_width;
+        let f = x - x.floor();
+        x.floor() * self.line_width + f.min(half) + (f - (1.0 - half)).max(0.0)
+    }
+
+    // the fraction of [x - dx, x + dx] covered by the lines
+    fn coverage(&self, x: f64, dx: f64) -> f64 {
+        if dx > 0.0 {
+            (self.line_int(x + dx) - self.line_int(x - dx)) / (2.0 * dx)
+        } else {
+            let f = x - x.floor();
+            if f < 0.5 * self.line_width || f > 1.0 - 0.5 * self.line_width { 1.0 } else { 0.0 }
+        }
+    }
+}
+
+impl<T: Blend> Texture<T> for GridTexture<T> {
+    fn evaluate(&self, isect: &crate::geometry::interaction::SurfaceInteraction) -> T {
+        let (st, dstdx, dstdy) = self.mapping.map(isect);
+        let ds = dstdx.x.abs().max(dstdy.x.abs());
+        let dt = dstdx.y.abs().max(dstdy.y.abs());
+
+        // box filter the footprint, a point is on the grid when it is on a line of s or of t
+        let (s_line, t_line) = (self.coverage(st.x, ds), self.coverage(st.y, dt));
+        let line = s_line + t_line - s_line * t_line;
+        if line <= 0.0 {
+            self.tex1.evaluate(isect)
+        } else if line >= 1.0 {
+            self.tex2.evaluate(isect)
+        } else {
+            T::lerp(line, self.tex1.evaluate(isect), self.tex2.evaluate(isect))
+        }
+    }
+}
@@ -1,10 +1,17 @@
-use cgmath::Point2;
+use cgmath::{Point2, Point3, Vector2, Vector3};
 
 use crate::geometry::interaction::SurfaceInteraction;
 
 pub mod uv;
 pub mod spherical;
+pub mod point;
 
 pub trait TextureMapping2D : Send + Sync{
-    fn map(&self, isect: &SurfaceInteraction) -> Point2<f64>;
-}
\ No newline at end of file
+    /// returns st and its derivatives along the x and y axes of the film
+    fn map(&self, isect: &SurfaceInteraction) -> (Point2<f64>, Vector2<f64>, Vector2<f64>);
+}
+
+pub trait TextureMapping3D : Send + Sync{
+    /// returns the point in texture space and its derivatives along the x and y axes of the film
+    fn map(&self, isect: &SurfaceInteraction) -> (Point3<f64>, Vector3<f64>, Vector3<f64>);
+}
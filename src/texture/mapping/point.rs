@@ -0,0 +1,26 @@
+use cgmath::{Point3, Vector3};
+
+use crate::geometry::{interaction::SurfaceInteraction, transform::Transform};
+
+use super::TextureMapping3D;
+
+/// places the solid textures in the world, the hit point is looked up in texture space
+pub struct PointMapping {
+    world_to_texture: Transform,
+}
+
+impl PointMapping {
+    pub fn new(world_to_texture: Transform) -> Self {
+        PointMapping { world_to_texture }
+    }
+}
+
+impl TextureMapping3D for PointMapping {
+    fn map(&self, isect: &SurfaceInteraction) -> (Point3<f64>, Vector3<f64>, Vector3<f64>) {
+        let p = self.world_to_texture.transform_point3(isect.geo.p);
+        match &isect.differentials {
+            Some(d) => (p, self.world_to_texture.transform_vector3(d.dpdx), self.world_to_texture.transform_vector3(d.dpdy)),
+            None => (p, Vector3::new(0.0, 0.0, 0.0), Vector3::new(0.0, 0.0, 0.0)),
+        }
+    }
+}
@@ -1,6 +1,6 @@
 use std::f64::consts::PI;
 
-use cgmath::{Point2, Point3, EuclideanSpace, InnerSpace};
+use cgmath::{Point2, Point3, Vector2, Vector3, EuclideanSpace, InnerSpace};
 
 use crate::geometry::transform::Transform;
 
@@ -21,13 +21,29 @@ impl SphericalMapping {
         let vec = self.world_to_texture.transform_point3(p).to_vec().normalize();
         let phi = vec.y.atan2(vec.x);
         let theta = vec.z.acos();
-        Point2::new(phi / (2.0 * PI), theta / PI)
+        // t goes up from the south pole like the uv of spheres
+        Point2::new(phi / (2.0 * PI), 1.0 - theta / PI)
     }
 }
 
 impl TextureMapping2D for SphericalMapping {
-    fn map(&self, isect: &crate::geometry::interaction::SurfaceInteraction) -> cgmath::Point2<f64> {
+    fn map(&self, isect: &crate::geometry::interaction::SurfaceInteraction) -> (Point2<f64>, Vector2<f64>, Vector2<f64>) {
         let st = self.sphere(isect.geo.p);
-        st
+
+        // forward differences over a fraction of the footprint, s wraps around at the seam
+        let delta = 0.1;
+        let difference = |dp: Vector3<f64>| {
+            let mut dst = (self.sphere(isect.geo.p + dp * delta) - st) / delta;
+            if dst.x > 0.5 / delta {
+                dst.x -= 1.0 / delta;
+            } else if dst.x < -0.5 / delta {
+                dst.x += 1.0 / delta;
+            }
+            dst
+        };
+        match &isect.differentials {
+            Some(d) => (st, difference(d.dpdx), difference(d.dpdy)),
+            None => (st, Vector2::new(0.0, 0.0), Vector2::new(0.0, 0.0)),
+        }
     }
 }
\ No newline at end of file
@@ -0,0 +1,35 @@
+use cgmath::{Point2, Vector2};
+
+use crate::geometry::interaction::SurfaceInteraction;
+
+use super::TextureMapping2D;
+
+/// maps the surface (u, v) to (su * u + du, sv * v + dv)
+pub struct UVMapping {
+    su: f64,
+    sv: f64,
+    du: f64,
+    dv: f64,
+}
+
+impl UVMapping {
+    pub fn new(su: f64, sv: f64, du: f64, dv: f64) -> Self {
+        UVMapping { su, sv, du, dv }
+    }
+}
+
+impl TextureMapping2D for UVMapping {
+    fn map(&self, isect: &SurfaceInteraction) -> (Point2<f64>, Vector2<f64>, Vector2<f64>) {
+        let uv = isect.geo.uv;
+        let st = Point2::new(self.su * uv.x + self.du, self.sv * uv.y + self.dv);
+
+        let (dstdx, dstdy) = match &isect.differentials {
+            Some(d) => (
+                Vector2::new(self.su * d.dudx, self.sv * d.dvdx),
+                Vector2::new(self.su * d.dudy, self.sv * d.dvdy),
+            ),
+            None => (Vector2::new(0.0, 0.0), Vector2::new(0.0, 0.0)),
+        };
+        (st, dstdx, dstdy)
+    }
+}
@@ -0,0 +1,54 @@
+use crate::spectrum::Spectrum;
+
+use super::{Texture, mapping::TextureMapping3D, noise::fbm};
+
+/// the marble of pbrt, veins along y perturbed by fbm and colored with a spline
+pub struct MarbleTexture {
+    mapping: Box<dyn TextureMapping3D>,
+    omega: f64,
+    octaves: usize,
+    scale: f64,
+    variation: f64,
+}
+
+impl MarbleTexture {
+    pub fn new(mapping: Box<dyn TextureMapping3D>, omega: f64, octaves: usize, scale: f64, variation: f64) -> Self {
+        MarbleTexture { mapping, omega, octaves, scale, variation }
+    }
+
+    fn marble(&self, isect: &crate::geometry::interaction::SurfaceInteraction) -> Spectrum {
+        let (p, dpdx, dpdy) = self.mapping.map(isect);
+        let p = p * self.scale;
+        let marble = p.y + self.variation * fbm(p, dpdx * self.scale, dpdy * self.scale, self.omega, self.octaves);
+        let t = 0.5 + 0.5 * marble.sin();
+
+        // evaluate the cubic bezier segment t falls into
+        let colors = [
+            [0.58, 0.58, 0.6], [0.58, 0.58, 0.6], [0.58, 0.58, 0.6],
+            [0.5, 0.5, 0.5], [0.6, 0.59, 0.58], [0.58, 0.58, 0.6],
+            [0.58, 0.58, 0.6], [0.2, 0.2, 0.33], [0.58, 0.58, 0.6],
+        ].map(|[r, g, b]| Spectrum::new(r, g, b));
+        let n_seg = colors.len() - 3;
+        let first = ((t * n_seg as f64).floor() as usize).min(n_seg - 1);
+        let t = t * n_seg as f64 - first as f64;
+
+        let lerp = |t: f64, a: Spectrum, b: Spectrum| (1.0 - t) * a + t * b;
+        let c = &colors[first..first + 4];
+        let (s0, s1, s2) = (lerp(t, c[0], c[1]), lerp(t, c[1], c[2]), lerp(t, c[2], c[3]));
+        let (s0, s1) = (lerp(t, s0, s1), lerp(t, s1, s2));
+        1.5 * lerp(t, s0, s1)
+    }
+}
+
+impl Texture<Spectrum> for MarbleTexture {
+    fn evaluate(&self, isect: &crate::geometry::interaction::SurfaceInteraction) -> Spectrum {
+        self.marble(isect)
+    }
+}
+
+// a float texture reads the average of the channels
+impl Texture<f64> for MarbleTexture {
+    fn evaluate(&self, isect: &crate::geometry::interaction::SurfaceInteraction) -> f64 {
+        self.marble(isect).average()
+    }
+}
@@ -0,0 +1,363 @@
+use std::{fs::File, io::BufReader};
+
+use cgmath::{InnerSpace, Point2, Vector2};
+use image::codecs::hdr::HdrDecoder;
+
+use crate::{camera::tonemap::srgb_eotf, spectrum::Spectrum};
+
+/// how the texels outside of the image are looked up
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum WrapMode {
+    Repeat,
+    Clamp,
+    Mirror,
+    Black,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum FilterMode {
+    /// bilinear interpolation in the level closest to the footprint
+    Bilinear,
+    /// bilinear interpolation in the two levels around the footprint
+    Trilinear,
+    /// an elliptically weighted average over the anisotropic footprint
+    Ewa,
+}
+
+/// how the 8-bit values of an image file map to linear values
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ColorEncoding {
+    Linear,
+    Srgb,
+}
+
+impl ColorEncoding {
+    /// the usual encoding of a file, radiance (.hdr) and openexr files are linear, the other formats are sRGB
+    pub fn of_file(filename: &str) -> Self {
+        if filename.ends_with(".hdr") || filename.ends_with(".exr") {
+            ColorEncoding::Linear
+        } else {
+            ColorEncoding::Srgb
+        }
+    }
+}
+
+// the gaussian weights of the ewa filter, indexed by the squared distance to the center
+const WEIGHT_LUT_SIZE: usize = 128;
+const EWA_ALPHA: f64 = 2.0;
+
+struct Level {
+    resolution: Point2<usize>,
+    texels: Vec<Spectrum>, // row 0 is the top of the image
+}
+
+/// an image with its pyramid of box filtered downsampled versions, level 0 is the full image
+///
+/// lookups take st in [0, 1]^2 where t = 0 is the bottom row of the image, outside of it the
+/// wrap mode applies
+pub struct MIPMap {
+    pyramid: Vec<Level>,
+    wrap: WrapMode,
+    filter: FilterMode,
+    max_anisotropy: f64,
+    weight_lut: Vec<f64>,
+}
+
+impl MIPMap {
+    pub fn new(texels: Vec<Spectrum>, resolution: Point2<usize>, wrap: WrapMode, filter: FilterMode, max_anisotropy: f64) -> Self {
+        assert_eq!(texels.len(), resolution.x * resolution.y);
+        assert!(resolution.x > 0 && resolution.y > 0);
+
+        let mut pyramid = vec![Level { resolution, texels }];
+        loop {
+            let last = pyramid.last().unwrap();
+            if last.resolution.x == 1 && last.resolution.y == 1 {
+                break;
+            }
+            let next = Self::downsample(last);
+            pyramid.push(next);
+        }
+
+        let weight_lut = (0..WEIGHT_LUT_SIZE).map(|i| {
+            let r2 = i as f64 / (WEIGHT_LUT_SIZE - 1) as f64;
+            (-EWA_ALPHA * r2).exp() - (-EWA_ALPHA).exp()
+        }).collect();
+
+        MIPMap { pyramid, wrap, filter, max_anisotropy, weight_lut }
+    }
+
+    /// read an image file, the values are decoded to linear with the encoding
+    pub fn from_file(filename: &str, encoding: ColorEncoding, wrap: WrapMode, filter: FilterMode, max_anisotropy: f64) -> Self {
+        let c = |v: f32| match encoding {
+            ColorEncoding::Linear => v as f64,
+            ColorEncoding::Srgb => srgb_eotf(v as f64),
+        };
+        let (resolution, texels) = if filename.ends_with(".hdr") {
+            // image::open decodes radiance files to 8-bit, so they are read with the hdr decoder directly
+            let file = BufReader::new(File::open(filename).expect("failed to open image file"));
+            let decoder = HdrDecoder::new(file).expect("failed to read the image");
+            let metadata = decoder.metadata();
+            let pixels = decoder.read_image_hdr().expect("failed to read the image");
+            let texels = pixels.iter().map(|p| Spectrum::new(c(p[0]), c(p[1]), c(p[2]))).collect();
+            (Point2::new(metadata.width as usize, metadata.height as usize), texels)
+        } else {
+            let img = image::open(filename).expect("failed to open image file").to_rgb32f();
+            let texels = img.pixels().map(|p| Spectrum::new(c(p[0]), c(p[1]), c(p[2]))).collect();
+            (Point2::new(img.width() as usize, img.height() as usize), texels)
+        };
+
+        Self::new(texels, resolution, wrap, filter, max_anisotropy)
+    }
+
+    pub fn levels(&self) -> usize {
+        self.pyramid.len()
+    }
+
+    // halves the resolution, every texel averages the area of the level it covers
+    fn downsample(level: &Level) -> Level {
+        let (w, h) = (level.resolution.x, level.resolution.y);
+        let (nw, nh) = ((w / 2).max(1), (h / 2).max(1));
+
+        // the weights of the source texels overlapping output texel i, along one axis
+        let footprint = |i: usize, n: usize, nn: usize| -> Vec<(usize, f64)> {
+            let (x0, x1) = (i as f64 * n as f64 / nn as f64, (i + 1) as f64 * n as f64 / nn as f64);
+            let scale = nn as f64 / n as f64;
+            (x0.floor() as usize..(x1.ceil() as usize).min(n))
+                .map(|x| (x, ((x as f64 + 1.0).min(x1) - (x as f64).max(x0)) * scale))
+                .collect()
+        };
+
+        let mut texels = vec![Spectrum::black(); nw * nh];
+        for y in 0..nh {
+            let ys = footprint(y, h, nh);
+            for x in 0..nw {
+                let xs = footprint(x, w, nw);
+                let mut sum = Spectrum::black();
+                for &(sy, wy) in ys.iter() {
+                    for &(sx, wx) in xs.iter() {
+                        sum += level.texels[sy * w + sx] * (wx * wy);
+                    }
+                }
+                texels[y * nw + x] = sum;
+            }
+        }
+
+        Level { resolution: Point2::new(nw, nh), texels }
+    }
+
+    /// the texel (x, y) of a level, y counts rows from the top
+    pub fn texel(&self, level: usize, x: i64, y: i64) -> Spectrum {
+        let l = &self.pyramid[level];
+        let wrap = |c: i64, n: usize| -> Option<usize> {
+            let n = n as i64;
+            match self.wrap {
+                WrapMode::Repeat => Some(c.rem_euclid(n) as usize),
+                WrapMode::Clamp => Some(c.clamp(0, n - 1) as usize),
+                WrapMode::Mirror => {
+                    let c = c.rem_euclid(2 * n);
+                    Some(if c >= n { 2 * n - 1 - c } else { c } as usize)
+                },
+                WrapMode::Black => if c < 0 || c >= n { None } else { Some(c as usize) },
+            }
+        };
+
+        match (wrap(x, l.resolution.x), wrap(y, l.resolution.y)) {
+            (Some(x), Some(y)) => l.texels[y * l.resolution.x + x],
+            _ => Spectrum::black(),
+        }
+    }
+
+    /// bilinear interpolation between the four texels around st
+    pub fn bilerp(&self, level: usize, st: Point2<f64>) -> Spectrum {
+        let resolution = self.pyramid[level].resolution;
+        // texel centers sit at half integers
+        let x = st.x * resolution.x as f64 - 0.5;
+        let y = (1.0 - st.y) * resolution.y as f64 - 0.5;
+        let (x0, y0) = (x.floor(), y.floor());
+        let (dx, dy) = (x - x0, y - y0);
+        let (x0, y0) = (x0 as i64, y0 as i64);
+
+        self.texel(level, x0, y0) * ((1.0 - dx) * (1.0 - dy))
+            + self.texel(level, x0 + 1, y0) * (dx * (1.0 - dy))
+            + self.texel(level, x0, y0 + 1) * ((1.0 - dx) * dy)
+            + self.texel(level, x0 + 1, y0 + 1) * (dx * dy)
+    }
+
+    // the continuous level whose texels are as wide as the footprint
+    fn level_of(&self, width: f64) -> f64 {
+        (self.levels() - 1) as f64 + width.max(1e-8).log2()
+    }
+
+    /// the filtered value over the footprint spanned by the derivatives of st along the screen axes
+    pub fn filter(&self, st: Point2<f64>, dst0: Vector2<f64>, dst1: Vector2<f64>) -> Spectrum {
+        let width = 2.0 * dst0.x.abs().max(dst0.y.abs()).max(dst1.x.abs()).max(dst1.y.abs());
+
+        match self.filter {
+            FilterMode::Bilinear => {
+                let level = self.level_of(width).round().clamp(0.0, (self.levels() - 1) as f64);
+                self.bilerp(level as usize, st)
+            },
+            FilterMode::Trilinear => self.trilinear(st, width),
+            FilterMode::Ewa => self.ewa(st, dst0, dst1),
+        }
+    }
+
+    /// interpolates the bilinear lookups of the two levels around a square footprint of the width
+    pub fn trilinear(&self, st: Point2<f64>, width: f64) -> Spectrum {
+        let level = self.level_of(width);
+        if level <= 0.0 {
+            return self.bilerp(0, st);
+        }
+        if level >= (self.levels() - 1) as f64 {
+            return self.texel(self.levels() - 1, 0, 0);
+        }
+
+        let i = level.floor() as usize;
+        let delta = level - i as f64;
+        self.bilerp(i, st) * (1.0 - delta) + self.bilerp(i + 1, st) * delta
+    }
+
+    fn ewa(&self, st: Point2<f64>, mut dst0: Vector2<f64>, mut dst1: Vector2<f64>) -> Spectrum {
+        // dst0 is the major axis of the ellipse
+        if dst0.magnitude2() < dst1.magnitude2() {
+            std::mem::swap(&mut dst0, &mut dst1);
+        }
+        let major = dst0.magnitude();
+        let mut minor = dst1.magnitude();
+
+        // too eccentric ellipses cover too many texels of the level picked by the minor axis
+        if minor * self.max_anisotropy < major && minor > 0.0 {
+            let scale = major / (minor * self.max_anisotropy);
+            dst1 *= scale;
+            minor *= scale;
+        }
+        if minor == 0.0 {
+            return self.bilerp(0, st);
+        }
+
+        let level = self.level_of(minor).max(0.0);
+        let i = level.floor() as usize;
+        let delta = level - i as f64;
+        self.ewa_level(i, st, dst0, dst1) * (1.0 - delta) + self.ewa_level(i + 1, st, dst0, dst1) * delta
+    }
+
+    fn ewa_level(&self, level: usize, st: Point2<f64>, dst0: Vector2<f64>, dst1: Vector2<f64>) -> Spectrum {
+        if level >= self.levels() {
+            return self.texel(self.levels() - 1, 0, 0);
+        }
+
+        // the ellipse in texel space, t is flipped to count rows from the top
+        let resolution = self.pyramid[level].resolution;
+        let (w, h) = (resolution.x as f64, resolution.y as f64);
+        let s = st.x * w - 0.5;
+        let t = (1.0 - st.y) * h - 0.5;
+        let (ds0, dt0) = (dst0.x * w, -dst0.y * h);
+        let (ds1, dt1) = (dst1.x * w, -dst1.y * h);
+
+        // the implicit ellipse A s^2 + B s t + C t^2 = 1
+        let mut a = dt0 * dt0 + dt1 * dt1 + 1.0;
+        let mut b = -2.0 * (ds0 * dt0 + ds1 * dt1);
+        let mut c = ds0 * ds0 + ds1 * ds1 + 1.0;
+        let inv_f = 1.0 / (a * c - b * b * 0.25);
+        a *= inv_f;
+        b *= inv_f;
+        c *= inv_f;
+
+        // the bounding box of the ellipse
+        let det = -b * b + 4.0 * a * c;
+        let inv_det = 1.0 / det;
+        let u_sqrt = (det * c).sqrt();
+        let v_sqrt = (a * det).sqrt();
+        let s0 = (s - 2.0 * inv_det * u_sqrt).ceil() as i64;
+        let s1 = (s + 2.0 * inv_det * u_sqrt).floor() as i64;
+        let t0 = (t - 2.0 * inv_det * v_sqrt).ceil() as i64;
+        let t1 = (t + 2.0 * inv_det * v_sqrt).floor() as i64;
+
+        let mut sum = Spectrum::black();
+        let mut sum_weights = 0.0;
+        for it in t0..=t1 {
+            let tt = it as f64 - t;
+            for is in s0..=s1 {
+                let ss = is as f64 - s;
+                let r2 = a * ss * ss + b * ss * tt + c * tt * tt;
+                if r2 < 1.0 {
+                    let index = ((r2 * WEIGHT_LUT_SIZE as f64) as usize).min(WEIGHT_LUT_SIZE - 1);
+                    let weight = self.weight_lut[index];
+                    sum += self.texel(level, is, it) * weight;
+                    sum_weights += weight;
+                }
+            }
+        }
+
+        if sum_weights > 0.0 { sum / sum_weights } else { self.bilerp(level, st) }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn checker(n: usize) -> Vec<Spectrum> {
+        (0..n * n).map(|i| {
+            let v = ((i % n + i / n) % 2) as f64;
+            Spectrum::new(v, 0.5, 1.0 - v)
+        }).collect()
+    }
+
+    #[test]
+    fn test_pyramid_preserves_average() {
+        // odd resolutions do not line up with the levels above
+        let texels: Vec<Spectrum> = (0..7 * 5).map(|i| Spectrum::new(i as f64, 1.0, (i * i % 11) as f64)).collect();
+        let mut average = Spectrum::black();
+        for t in texels.iter() {
+            average += *t / texels.len() as f64;
+        }
+
+        let mipmap = MIPMap::new(texels, Point2::new(7, 5), WrapMode::Repeat, FilterMode::Trilinear, 8.0);
+        assert_eq!(mipmap.pyramid[1].resolution, Point2::new(3, 2));
+        let top = mipmap.texel(mipmap.levels() - 1, 0, 0);
+        for c in 0..3 {
+            assert!((top[c] - average[c]).abs() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn test_wrap_modes() {
+        let texels = vec![Spectrum::new(1.0, 1.0, 1.0), Spectrum::new(2.0, 2.0, 2.0), Spectrum::new(3.0, 3.0, 3.0)];
+        let lookup = |wrap, x| MIPMap::new(texels.clone(), Point2::new(3, 1), wrap, FilterMode::Bilinear, 8.0).texel(0, x, 0).r;
+
+        assert_eq!(lookup(WrapMode::Repeat, -1), 3.0);
+        assert_eq!(lookup(WrapMode::Repeat, 4), 2.0);
+        assert_eq!(lookup(WrapMode::Clamp, -5), 1.0);
+        assert_eq!(lookup(WrapMode::Clamp, 7), 3.0);
+        assert_eq!(lookup(WrapMode::Mirror, -1), 1.0);
+        assert_eq!(lookup(WrapMode::Mirror, 3), 3.0);
+        assert_eq!(lookup(WrapMode::Mirror, 5), 1.0);
+        assert_eq!(lookup(WrapMode::Black, 3), 0.0);
+        assert_eq!(lookup(WrapMode::Black, -1), 0.0);
+    }
+
+    #[test]
+    fn test_lookups() {
+        let texels = checker(8);
+        let dst = Vector2::new(0.0, 0.0);
+        for filter in [FilterMode::Bilinear, FilterMode::Trilinear, FilterMode::Ewa] {
+            let mipmap = MIPMap::new(texels.clone(), Point2::new(8, 8), WrapMode::Repeat, filter, 8.0);
+
+            // the center of the bottom left texel, which is texel (0, 7)
+            let v = mipmap.filter(Point2::new(1.0 / 16.0, 1.0 / 16.0), dst, dst);
+            for c in 0..3 {
+                assert!((v[c] - texels[7 * 8][c]).abs() < 1e-9);
+            }
+
+            // the borders and outside of [0, 1] are wrapped
+            for st in [Point2::new(1.0, 1.0), Point2::new(-0.3, 2.5), Point2::new(0.0, 0.0)] {
+                assert!(!mipmap.filter(st, dst, dst).contain_nan());
+            }
+
+            // a footprint covering the whole image averages the checkerboard
+            let v = mipmap.filter(Point2::new(0.3, 0.6), Vector2::new(2.0, 0.0), Vector2::new(0.0, 2.0));
+            assert!((v.r - 0.5).abs() < 1e-2 && (v.b - 0.5).abs() < 1e-2);
+        }
+    }
+}
@@ -2,10 +2,29 @@ pub mod constant;
 pub mod scale;
 pub mod mix;
 pub mod imagemap;
+pub mod mipmap;
 pub mod mapping;
+pub mod noise;
+pub mod fbm;
+pub mod turbulence;
+pub mod checkerboard;
+pub mod grid;
+pub mod wood;
+pub mod marble;
+
+use std::ops::{Add, Mul};
 
 use crate::geometry::interaction::SurfaceInteraction;
 
 pub trait Texture<T>: Send + Sync{
     fn evaluate(&self, isect: &SurfaceInteraction) -> T;
 }
+
+/// the values the procedural textures produce, built from a float or blended between two textures
+pub trait Blend: Copy + From<f64> + Add<Output = Self> + Mul<f64, Output = Self> {
+    fn lerp(t: f64, a: Self, b: Self) -> Self {
+        a * (1.0 - t) + b * t
+    }
+}
+
+impl<T: Copy + From<f64> + Add<Output = T> + Mul<f64, Output = T>> Blend for T {}
@@ -0,0 +1,175 @@
+use cgmath::{InnerSpace, Point3, Vector3};
+
+use super::{Blend, Texture, mapping::TextureMapping3D};
+
+/// Ken Perlin's permutation of the lattice
+const PERMUTATION: [usize; 256] = [
+    151, 160, 137, 91, 90, 15, 131, 13, 201, 95, 96, 53, 194, 233, 7, 225, 140, 36, 103, 30, 69, 142,
+    8, 99, 37, 240, 21, 10, 23, 190, 6, 148, 247, 120, 234, 75, 0, 26, 197, 62, 94, 252, 219, 203, 117,
+    35, 11, 32, 57, 177, 33, 88, 237, 149, 56, 87, 174, 20, 125, 136, 171, 168, 68, 175, 74, 165, 71,
+    134, 139, 48, 27, 166, 77, 146, 158, 231, 83, 111, 229, 122, 60, 211, 133, 230, 220, 105, 92, 41,
+    55, 46, 245, 40, 244, 102, 143, 54, 65, 25, 63, 161, 1, 216, 80, 73, 209, 76, 132, 187, 208, 89,
+    18, 169, 200, 196, 135, 130, 116, 188, 159, 86, 164, 100, 109, 198, 173, 186, 3, 64, 52, 217, 226,
+    250, 124, 123, 5, 202, 38, 147, 118, 126, 255, 82, 85, 212, 207, 206, 59, 227, 47, 16, 58, 17, 182,
+    189, 28, 42, 223, 183, 170, 213, 119, 248, 152, 2, 44, 154, 163, 70, 221, 153, 101, 155, 167, 43,
+    172, 9, 129, 22, 39, 253, 19, 98, 108, 110, 79, 113, 224, 232, 178, 185, 112, 104, 218, 246, 97,
+    228, 251, 34, 242, 193, 238, 210, 144, 12, 191, 179, 162, 241, 81, 51, 145, 235, 249, 14, 239,
+    107, 49, 192, 214, 31, 181, 199, 106, 157, 184, 84, 204, 176, 115, 121, 50, 45, 127, 4, 150, 254,
+    138, 236, 205, 93, 222, 114, 67, 29, 24, 72, 243, 141, 128, 195, 78, 66, 215, 61, 156, 180,
+];
+
+fn perm(i: usize) -> usize {
+    PERMUTATION[i & 255]
+}
+
+// the dot product of the offset with one of the 12 edge directions of the cube picked by the lattice point
+fn grad(x: usize, y: usize, z: usize, d: Vector3<f64>) -> f64 {
+    let h = perm(perm(perm(x) + y) + z) & 15;
+    let u = if h < 8 || h == 12 || h == 13 { d.x } else { d.y };
+    let v = if h < 4 || h == 12 || h == 13 { d.y } else { d.z };
+    (if h & 1 == 0 { u } else { -u }) + (if h & 2 == 0 { v } else { -v })
+}
+
+// the quintic fade, its first and second derivatives vanish at the lattice
+fn weight(t: f64) -> f64 {
+    t * t * t * (t * (t * 6.0 - 15.0) + 10.0)
+}
+
+fn lerp(t: f64, a: f64, b: f64) -> f64 {
+    (1.0 - t) * a + t * b
+}
+
+pub fn smooth_step(a: f64, b: f64, x: f64) -> f64 {
+    let t = ((x - a) / (b - a)).clamp(0.0, 1.0);
+    t * t * (3.0 - 2.0 * t)
+}
+
+/// Perlin's gradient noise, zero on the integer lattice and roughly within [-1, 1]
+pub fn noise(p: Point3<f64>) -> f64 {
+    let (fx, fy, fz) = (p.x.floor(), p.y.floor(), p.z.floor());
+    let d = Vector3::new(p.x - fx, p.y - fy, p.z - fz);
+    // the lattice repeats every 256 cells
+    let (x, y, z) = ((fx as i64 & 255) as usize, (fy as i64 & 255) as usize, (fz as i64 & 255) as usize);
+
+    let w000 = grad(x, y, z, d);
+    let w100 = grad(x + 1, y, z, d - Vector3::new(1.0, 0.0, 0.0));
+    let w010 = grad(x, y + 1, z, d - Vector3::new(0.0, 1.0, 0.0));
+    let w110 = grad(x + 1, y + 1, z, d - Vector3::new(1.0, 1.0, 0.0));
+    let w001 = grad(x, y, z + 1, d - Vector3::new(0.0, 0.0, 1.0));
+    let w101 = grad(x + 1, y, z + 1, d - Vector3::new(1.0, 0.0, 1.0));
+    let w011 = grad(x, y + 1, z + 1, d - Vector3::new(0.0, 1.0, 1.0));
+    let w111 = grad(x + 1, y + 1, z + 1, d - Vector3::new(1.0, 1.0, 1.0));
+
+    let (wx, wy, wz) = (weight(d.x), weight(d.y), weight(d.z));
+    let x00 = lerp(wx, w000, w100);
+    let x10 = lerp(wx, w010, w110);
+    let x01 = lerp(wx, w001, w101);
+    let x11 = lerp(wx, w011, w111);
+    lerp(wz, lerp(wy, x00, x10), lerp(wy, x01, x11))
+}
+
+// the number of octaves whose frequency stays below the Nyquist limit of the footprint, the
+// fractional part fades the last octave in
+fn octaves(dpdx: Vector3<f64>, dpdy: Vector3<f64>, max_octaves: usize) -> f64 {
+    let len2 = dpdx.magnitude2().max(dpdy.magnitude2());
+    (-1.0 - 0.5 * len2.log2()).clamp(0.0, max_octaves as f64)
+}
+
+/// fractional Brownian motion, the sum of octaves of noise scaled by omega at each doubling of
+/// the frequency
+pub fn fbm(p: Point3<f64>, dpdx: Vector3<f64>, dpdy: Vector3<f64>, omega: f64, max_octaves: usize) -> f64 {
+    let n = octaves(dpdx, dpdy, max_octaves);
+    let n_int = n.floor() as usize;
+
+    let (mut sum, mut lambda, mut o) = (0.0, 1.0, 1.0);
+    for _ in 0..n_int {
+        sum += o * noise(p * lambda);
+        lambda *= 1.99;
+        o *= omega;
+    }
+    let partial = n - n_int as f64;
+    sum += o * smooth_step(0.3, 0.7, partial) * noise(p * lambda);
+    sum
+}
+
+/// like fbm with the absolute value of every octave
+pub fn turbulence(p: Point3<f64>, dpdx: Vector3<f64>, dpdy: Vector3<f64>, omega: f64, max_octaves: usize) -> f64 {
+    let n = octaves(dpdx, dpdy, max_octaves);
+    let n_int = n.floor() as usize;
+
+    let (mut sum, mut lambda, mut o) = (0.0, 1.0, 1.0);
+    for _ in 0..n_int {
+        sum += o * noise(p * lambda).abs();
+        lambda *= 1.99;
+        o *= omega;
+    }
+    // the octaves past the footprint are replaced by the average of |noise|, the first of them fades in
+    if n_int < max_octaves {
+        let partial = n - n_int as f64;
+        sum += o * lerp(smooth_step(0.3, 0.7, partial), 0.2, noise(p * lambda).abs());
+        o *= omega;
+        for _ in n_int + 1..max_octaves {
+            sum += o * 0.2;
+            o *= omega;
+        }
+    }
+    sum
+}
+
+/// a single octave of noise remapped to [0, 1]
+pub struct NoiseTexture {
+    mapping: Box<dyn TextureMapping3D>,
+}
+
+impl NoiseTexture {
+    pub fn new(mapping: Box<dyn TextureMapping3D>) -> Self {
+        NoiseTexture { mapping }
+    }
+}
+
+impl<T: Blend> Texture<T> for NoiseTexture {
+    fn evaluate(&self, isect: &crate::geometry::interaction::SurfaceInteraction) -> T {
+        let (p, _, _) = self.mapping.map(isect);
+        T::from((0.5 + 0.5 * noise(p)).clamp(0.0, 1.0))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_noise() {
+        let mut seen = [false; 256];
+        for &i in PERMUTATION.iter() {
+            assert!(!seen[i]);
+            seen[i] = true;
+        }
+
+        // zero on the lattice, bounded and continuous in between
+        assert_eq!(noise(Point3::new(3.0, -7.0, 12.0)), 0.0);
+        let mut nonzero = false;
+        for i in 0..1000 {
+            let p = Point3::new(i as f64 * 0.137, i as f64 * -0.291, i as f64 * 0.0713);
+            let v = noise(p);
+            assert!(v.abs() <= 1.5);
+            assert!((noise(p + Vector3::new(1e-6, 1e-6, 1e-6)) - v).abs() < 1e-4);
+            nonzero |= v.abs() > 0.1;
+        }
+        assert!(nonzero);
+    }
+
+    #[test]
+    fn test_fbm_octaves() {
+        let p = Point3::new(0.3, 1.7, -2.2);
+        let zero = Vector3::new(0.0, 0.0, 0.0);
+        // without a footprint all the octaves are summed
+        let expected: f64 = (0..4).map(|i| 0.5f64.powi(i) * noise(p * 1.99f64.powi(i))).sum();
+        assert!((fbm(p, zero, zero, 0.5, 4) - expected).abs() < 1e-12);
+
+        // a footprint larger than the lattice filters every octave out
+        let wide = Vector3::new(4.0, 0.0, 0.0);
+        assert_eq!(fbm(p, wide, wide, 0.5, 4), 0.0);
+        assert!((turbulence(p, wide, wide, 0.5, 4) - 0.2 * (1.0 + 0.5 + 0.25 + 0.125)).abs() < 1e-12);
+    }
+}
@@ -1,10 +1,18 @@
+use std::sync::Arc;
+
 use crate::spectrum::Spectrum;
 
 use super::Texture;
 
 pub struct ScaleTexture<T, U> {
-    tex1: Box<dyn Texture<T>>,
-    tex2: Box<dyn Texture<U>>,
+    tex1: Arc<dyn Texture<T>>,
+    tex2: Arc<dyn Texture<U>>,
+}
+
+impl<T, U> ScaleTexture<T, U> {
+    pub fn new(tex1: Arc<dyn Texture<T>>, tex2: Arc<dyn Texture<U>>) -> Self {
+        ScaleTexture { tex1, tex2 }
+    }
 }
 
 impl Texture<f64> for ScaleTexture<f64, f64> {
@@ -0,0 +1,21 @@
+use super::{Blend, Texture, mapping::TextureMapping3D, noise::turbulence};
+
+/// the sum of the absolute values of the octaves of noise, the wrinkled texture of pbrt
+pub struct TurbulenceTexture {
+    mapping: Box<dyn TextureMapping3D>,
+    omega: f64,
+    octaves: usize,
+}
+
+impl TurbulenceTexture {
+    pub fn new(mapping: Box<dyn TextureMapping3D>, omega: f64, octaves: usize) -> Self {
+        TurbulenceTexture { mapping, omega, octaves }
+    }
+}
+
+impl<T: Blend> Texture<T> for TurbulenceTexture {
+    fn evaluate(&self, isect: &crate::geometry::interaction::SurfaceInteraction) -> T {
+        let (p, dpdx, dpdy) = self.mapping.map(isect);
+        T::from(turbulence(p, dpdx, dpdy, self.omega, self.octaves))
+    }
+}
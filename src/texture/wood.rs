@@ -0,0 +1,53 @@
+use std::sync::Arc;
+
+use cgmath::InnerSpace;
+
+use super::{Blend, Texture, mapping::TextureMapping3D, noise::{fbm, smooth_step}};
+
+/// growth rings around the z axis of the texture space, going from the early wood tex1 to the
+/// late wood tex2 within every ring, and bent by fbm
+pub struct WoodTexture<T> {
+    mapping: Box<dyn TextureMapping3D>,
+    tex1: Arc<dyn Texture<T>>,
+    tex2: Arc<dyn Texture<T>>,
+    rings: f64, // rings per unit distance
+    distortion: f64,
+    omega: f64,
+    octaves: usize,
+}
+
+impl<T> WoodTexture<T> {
+    pub fn new(
+        mapping: Box<dyn TextureMapping3D>,
+        tex1: Arc<dyn Texture<T>>,
+        tex2: Arc<dyn Texture<T>>,
+        rings: f64,
+        distortion: f64,
+        omega: f64,
+        octaves: usize,
+    ) -> Self {
+        WoodTexture { mapping, tex1, tex2, rings, distortion, omega, octaves }
+    }
+}
+
+// the late wood amount across a ring, which darkens towards its end and fades back
+fn ring_profile(x: f64) -> f64 {
+    smooth_step(0.55, 0.9, x) - smooth_step(0.92, 1.0, x)
+}
+
+// the average of ring_profile over a ring
+const RING_AVERAGE: f64 = 0.235;
+
+impl<T: Blend> Texture<T> for WoodTexture<T> {
+    fn evaluate(&self, isect: &crate::geometry::interaction::SurfaceInteraction) -> T {
+        let (p, dpdx, dpdy) = self.mapping.map(isect);
+        let r = (p.x * p.x + p.y * p.y).sqrt() + self.distortion * fbm(p, dpdx, dpdy, self.omega, self.octaves);
+        let ring = r * self.rings;
+
+        // once the footprint spans a whole ring only the average is left
+        let width = dpdx.magnitude().max(dpdy.magnitude()) * self.rings;
+        let t = ring_profile(ring - ring.floor());
+        let t = t + smooth_step(0.5, 1.0, width) * (RING_AVERAGE - t);
+        T::lerp(t, self.tex1.evaluate(isect), self.tex2.evaluate(isect))
+    }
+}
@@ -70,7 +70,7 @@ pub fn perpendicular(n: Vector3<f64>) -> (Vector3<f64>, Vector3<f64>) {
     assert!(n.magnitude2() != 0.0);
     let n = n.normalize();
 
-    if n.x != 1.0 {
+    if n.x.abs() != 1.0 {
         let v = Vector3::new(0.0, -n.z, n.y).normalize();
         let u = v.cross(n);
         (u, v)
@@ -98,6 +98,7 @@ pub fn perpendicular(n: Vector3<f64>) -> (Vector3<f64>, Vector3<f64>) {
     // (u, v)
 }
 
+#[allow(unused)]
 pub fn sphere_tangent(n: Vector3<f64>) -> (Vector3<f64>, Vector3<f64>) {
     // n is a normal vector
     assert!(n.magnitude2() != 0.0);
@@ -125,6 +126,51 @@ pub fn reflect(wo: Vector3<f64>, n: Vector3<f64>) -> Vector3<f64> {
     -wo + 2.0 * n * cosine
 }
 
+/// refract wi through the interface of normal n on the side of wi, eta is the ratio of the index
+/// on the side of wi over the other one, None on total internal reflection
+pub fn refract(wi: Vector3<f64>, n: Vector3<f64>, eta: f64) -> Option<Vector3<f64>> {
+    let cos_theta_i = n.dot(wi);
+    let sin2_theta_i = (1.0 - cos_theta_i * cos_theta_i).max(0.0);
+    let sin2_theta_t = eta * eta * sin2_theta_i;
+    if sin2_theta_t >= 1.0 {
+        return None;
+    }
+
+    let cos_theta_t = (1.0 - sin2_theta_t).sqrt();
+    Some(-wi * eta + n * (eta * cos_theta_i - cos_theta_t))
+}
+
+/// the error function, with the approximation of Abramowitz and Stegun
+pub fn erf(x: f64) -> f64 {
+    let (a1, a2, a3, a4, a5, p) = (0.254829592, -0.284496736, 1.421413741, -1.453152027, 1.061405429, 0.3275911);
+    let sign = x.signum();
+    let x = x.abs();
+
+    let t = 1.0 / (1.0 + p * x);
+    let y = 1.0 - (((((a5 * t + a4) * t) + a3) * t + a2) * t + a1) * t * (-x * x).exp();
+    sign * y
+}
+
+/// the inverse of the error function, with the polynomial fits of Giles
+pub fn erf_inv(x: f64) -> f64 {
+    let x = x.clamp(-0.99999, 0.99999);
+    let mut w = -((1.0 - x) * (1.0 + x)).ln();
+    let p = if w < 5.0 {
+        w -= 2.5;
+        [
+            2.81022636e-08, 3.43273939e-07, -3.5233877e-06, -4.39150654e-06, 0.00021858087,
+            -0.00125372503, -0.00417768164, 0.246640727, 1.50140941,
+        ].iter().fold(0.0, |p, c| c + p * w)
+    } else {
+        w = w.sqrt() - 3.0;
+        [
+            -0.000200214257, 0.000100950558, 0.00134934322, -0.00367342844, 0.00573950773,
+            -0.0076224613, 0.00943887047, 1.00167406, 2.83297682,
+        ].iter().fold(0.0, |p, c| c + p * w)
+    };
+    p * x
+}
+
 // pub fn random_2d() -> Point2<f64> {
 //     Point2::new(random(), random())
 // }